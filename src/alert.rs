@@ -0,0 +1,373 @@
+//! Routing of results to notification outputs, based on the status reported by the
+//! monitoring-plugin digest
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+use crate::clock;
+use crate::item::ItemResult;
+use crate::keys::Key;
+
+/// The status of a monitoring plugin, as written by the monitoring-plugin digest
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok = 0,
+    Warning = 1,
+    Critical = 2,
+    Unknown = 3,
+}
+
+impl Status {
+    pub fn from_value(value: f64) -> Option<Self> {
+        match value as i64 {
+            0 => Some(Self::Ok),
+            1 => Some(Self::Warning),
+            2 => Some(Self::Critical),
+            3 => Some(Self::Unknown),
+            _ => None,
+        }
+    }
+
+    /// The status of a result, taken from its `<key>.status` value
+    pub fn of(result: &ItemResult) -> Option<Self> {
+        result
+            .values
+            .get(format!("{}.status", result.key).as_str())
+            .and_then(|v| Self::from_value(*v))
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Ok => "OK",
+            Self::Warning => "WARNING",
+            Self::Critical => "CRITICAL",
+            Self::Unknown => "UNKNOWN",
+        }
+    }
+}
+
+/// Which results an output is notified about
+#[derive(Debug, Clone, Deserialize)]
+pub struct AlertRule {
+    #[serde(default = "statuses_default")]
+    pub statuses: Vec<Status>,
+    /// Also notify once the status of an item went back to OK
+    #[serde(default)]
+    pub notify_recovery: bool,
+    /// Seconds in which the same status of an item is not notified again
+    #[serde(default = "dedup_window_default")]
+    pub dedup_window: u64,
+    /// Notify when the output of an item with `track_changes` changed
+    #[serde(default)]
+    pub changes: bool,
+}
+
+impl Default for AlertRule {
+    fn default() -> Self {
+        Self {
+            statuses: statuses_default(),
+            notify_recovery: false,
+            dedup_window: dedup_window_default(),
+            changes: false,
+        }
+    }
+}
+
+fn statuses_default() -> Vec<Status> {
+    vec![Status::Warning, Status::Critical]
+}
+
+fn dedup_window_default() -> u64 {
+    3600
+}
+
+/// Suppresses alerts (but not data collection) for matching items, e.g. during maintenance
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Silence {
+    /// Glob matched against the item keys
+    pub keys: String,
+    /// Silences without a start are active right away
+    #[serde(default)]
+    pub start: Option<DateTime<Utc>>,
+    pub end: DateTime<Utc>,
+    #[serde(default)]
+    pub comment: String,
+}
+
+impl Silence {
+    pub fn is_active(&self, now: &DateTime<Utc>) -> bool {
+        self.start.map(|s| s <= *now).unwrap_or(true) && *now < self.end
+    }
+
+    pub fn matches(&self, key: &str) -> bool {
+        glob::Pattern::new(&self.keys)
+            .map(|p| p.matches(key))
+            .unwrap_or(false)
+    }
+}
+
+impl std::fmt::Display for Silence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.keys)?;
+        if let Some(start) = self.start {
+            write!(
+                f,
+                " from {}",
+                start.to_rfc3339_opts(SecondsFormat::Secs, true)
+            )?;
+        }
+        write!(
+            f,
+            " until {}",
+            self.end.to_rfc3339_opts(SecondsFormat::Secs, true)
+        )?;
+        if !self.comment.is_empty() {
+            write!(f, " ({})", self.comment)?;
+        }
+        Ok(())
+    }
+}
+
+/// All silences known to the daemon; the ones from the configuration and the ones added
+/// at runtime, which are persisted in the state directory. Clones share both, also across
+/// reloads of the configuration.
+#[derive(Debug, Clone, Default)]
+pub struct Silences {
+    configured: Arc<RwLock<Vec<Silence>>>,
+    runtime: Arc<RwLock<Vec<Silence>>>,
+    path: Option<PathBuf>,
+}
+
+impl Silences {
+    pub fn new(configured: Vec<Silence>, state_dir: &std::path::Path) -> Self {
+        let path = state_dir.join("silences.json");
+        let runtime = match std::fs::read(&path) {
+            Ok(content) => serde_json::from_slice(&content).unwrap_or_else(|e| {
+                warn!("Ignoring unreadable silences in {}: {}", path.display(), e);
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        };
+        Self {
+            configured: Arc::new(RwLock::new(configured)),
+            runtime: Arc::new(RwLock::new(runtime)),
+            path: Some(path),
+        }
+    }
+
+    /// Replace the silences from the configuration, e.g. with those of a reloaded one
+    pub fn configure(&self, configured: Vec<Silence>) {
+        *self.configured.write().expect("silences lock poisoned") = configured;
+    }
+
+    pub fn is_silenced(&self, key: &str) -> bool {
+        let now = clock::utc();
+        let configured = self.configured.read().expect("silences lock poisoned");
+        let runtime = self.runtime.read().expect("silences lock poisoned");
+        configured
+            .iter()
+            .chain(runtime.iter())
+            .any(|s| s.is_active(&now) && s.matches(key))
+    }
+
+    /// All silences that did not end yet
+    pub fn list(&self) -> Vec<Silence> {
+        let now = clock::utc();
+        let configured = self.configured.read().expect("silences lock poisoned");
+        let runtime = self.runtime.read().expect("silences lock poisoned");
+        configured
+            .iter()
+            .chain(runtime.iter())
+            .filter(|s| now < s.end)
+            .cloned()
+            .collect()
+    }
+
+    pub fn add(&self, silence: Silence) -> Result<()> {
+        glob::Pattern::new(&silence.keys)?;
+        let mut runtime = self.runtime.write().expect("silences lock poisoned");
+        runtime.push(silence);
+        self.persist(&mut runtime)
+    }
+
+    /// Remove all runtime silences with exactly the given glob, returns how many were removed
+    pub fn remove(&self, keys: &str) -> Result<usize> {
+        let mut runtime = self.runtime.write().expect("silences lock poisoned");
+        let before = runtime.len();
+        runtime.retain(|s| s.keys != keys);
+        let removed = before - runtime.len();
+        self.persist(&mut runtime)?;
+        Ok(removed)
+    }
+
+    /// Write the runtime silences to disk, dropping the ones that already ended
+    fn persist(&self, runtime: &mut Vec<Silence>) -> Result<()> {
+        let now = clock::utc();
+        runtime.retain(|s| now < s.end);
+        if let Some(path) = &self.path {
+            std::fs::write(path, serde_json::to_vec_pretty(runtime)?)
+                .with_context(|| format!("Failed persisting silences to {}", path.display()))?;
+        }
+        Ok(())
+    }
+}
+
+/// Applies an AlertRule, remembering what was notified last for each item
+#[derive(Debug, Clone)]
+pub struct AlertFilter {
+    rule: AlertRule,
+    silences: Silences,
+    notified: HashMap<Key, (Status, Instant)>,
+}
+
+impl AlertFilter {
+    pub fn new(rule: AlertRule, silences: Silences) -> Self {
+        Self {
+            rule,
+            silences,
+            notified: HashMap::new(),
+        }
+    }
+
+    /// Whether the result should be passed on to the output, updates the
+    /// deduplication state if so
+    pub fn should_notify(&mut self, result: &ItemResult) -> bool {
+        let changed = self.rule.changes
+            && result
+                .values
+                .get(format!("{}.changed", result.key).as_str())
+                == Some(&1.0);
+        let status = match Status::of(result) {
+            Some(s) => s,
+            None if changed => Status::Unknown,
+            None => return false,
+        };
+        if self.silences.is_silenced(&result.key) {
+            debug!("alert: item {} is silenced", result.key);
+            return false;
+        }
+        if changed {
+            debug!("alert: notifying change of item {}", result.key);
+            return true;
+        }
+        let previous = self.notified.get(&result.key);
+        let notify = if self.rule.statuses.contains(&status) {
+            match previous {
+                Some((s, at)) if *s == status => {
+                    at.elapsed() >= Duration::from_secs(self.rule.dedup_window)
+                }
+                _ => true,
+            }
+        } else {
+            // A recovery is only interesting if there was an alert before
+            self.rule.notify_recovery
+                && status == Status::Ok
+                && matches!(previous, Some((s, _)) if *s != Status::Ok)
+        };
+        if notify {
+            debug!(
+                "alert: notifying {} for item {}",
+                status.as_str(),
+                result.key
+            );
+            self.notified
+                .insert(result.key.clone(), (status, Instant::now()));
+        } else if !self.rule.statuses.contains(&status) && !self.rule.notify_recovery {
+            self.notified.remove(&result.key);
+        }
+        notify
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+    use std::time::Duration;
+
+    use crate::alert::{AlertFilter, AlertRule, Silence, Silences, Status};
+    use crate::item::ItemResult;
+
+    fn result(status: f64) -> ItemResult {
+        ItemResult {
+            time: Duration::from_secs(0),
+            key: "check".into(),
+            raw: String::new(),
+            values: HashMap::from([("check.status".into(), status)]),
+            tags: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn dedup_and_recovery() {
+        let mut filter = AlertFilter::new(
+            AlertRule {
+                statuses: vec![Status::Warning, Status::Critical],
+                notify_recovery: true,
+                dedup_window: 3600,
+                changes: false,
+            },
+            Silences::default(),
+        );
+        assert!(!filter.should_notify(&result(0.0)));
+        assert!(filter.should_notify(&result(1.0)));
+        assert!(!filter.should_notify(&result(1.0)));
+        assert!(filter.should_notify(&result(2.0)));
+        assert!(filter.should_notify(&result(0.0)));
+        assert!(!filter.should_notify(&result(0.0)));
+    }
+
+    #[test]
+    fn changes() {
+        let mut filter = AlertFilter::new(
+            AlertRule {
+                statuses: vec![Status::Critical],
+                notify_recovery: false,
+                dedup_window: 3600,
+                changes: true,
+            },
+            Silences::default(),
+        );
+        let mut changed = result(0.0);
+        changed.values.clear();
+        changed.values.insert("check.changed".into(), 1.0);
+        assert!(filter.should_notify(&changed));
+        assert!(filter.should_notify(&changed));
+        changed.values.insert("check.changed".into(), 0.0);
+        assert!(!filter.should_notify(&changed));
+    }
+
+    #[test]
+    fn silenced() {
+        let silences = Silences::default();
+        let mut filter = AlertFilter::new(
+            AlertRule {
+                statuses: vec![Status::Critical],
+                notify_recovery: false,
+                dedup_window: 0,
+                changes: false,
+            },
+            silences.clone(),
+        );
+        assert!(filter.should_notify(&result(2.0)));
+        silences
+            .add(Silence {
+                keys: String::from("ch*"),
+                start: None,
+                end: chrono::Utc::now() + chrono::Duration::hours(1),
+                comment: String::new(),
+            })
+            .unwrap();
+        assert!(!filter.should_notify(&result(2.0)));
+        silences.remove("ch*").unwrap();
+        assert!(filter.should_notify(&result(2.0)));
+    }
+}
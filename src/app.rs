@@ -1,57 +1,515 @@
 //! Main application code of antikoerper
 
-use tokio::task::JoinHandle;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::Duration;
 
-use anyhow::Result;
-use log::{debug, info, warn};
-use tokio::sync::broadcast;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+use tokio::task::{AbortHandle, JoinSet};
 
-use crate::conf::{Config, General};
-use crate::item::Item;
-use crate::output::{AKOutput, Output};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use log::{debug, error, info, warn};
+
+use crate::alert::Silences;
+use crate::conf::{self, Config, General};
+use crate::control::{Access, ControlServer};
+use crate::dispatcher::Dispatcher;
+use crate::item::{now, Item, ItemResult};
+use crate::output::buffer::contents;
+use crate::output::{AKEventOutput, AKOutput, ConfiguredOutput};
+use crate::state::Runs;
+use crate::workers::Workers;
+
+/// Results queued for the outputs
+const CAPACITY: usize = 100;
+/// Time the outputs restarted by a reload have to write the results they hold
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
 
 pub struct App {
     general: General,
     items: Vec<Item>,
-    outputs: Vec<Output>,
+    outputs: Vec<ConfiguredOutput>,
+    silences: Silences,
+    access: Access,
+    /// Fingerprint of what the configuration was read from
+    source: Option<toml::Value>,
+    /// Counted up with every reload
+    generation: u64,
+    started: DateTime<Utc>,
+}
+
+/// Shared with the running tasks, for the state dump, and the tasks to stop on a reload
+struct Running {
+    dispatcher: Dispatcher,
+    history: Runs,
+    /// Items, health reports and the control socket
+    tasks: Vec<AbortHandle>,
+    /// By the name of the output
+    outputs: HashMap<String, RunningOutput>,
+}
+
+/// The tasks of an output, and how to tell them to write what they hold and end
+struct RunningOutput {
+    stop: watch::Sender<()>,
+    tasks: Vec<AbortHandle>,
 }
 
 impl App {
-    pub async fn start(&self) -> Result<()> {
+    /// The application of `config`, with `silences` instead of those configured in it
+    fn with_silences(config: Config, silences: Silences) -> Result<Self> {
+        if let Err(e) = std::fs::create_dir_all(&config.general.state_dir) {
+            warn!(
+                "Failed creating state directory {}: {}",
+                config.general.state_dir.display(),
+                e
+            );
+        }
+        let mut names = HashSet::new();
+        let outputs = config
+            .output
+            .into_iter()
+            .map(|oc| {
+                // Several outputs of the same type are numbered
+                let base = oc
+                    .name
+                    .clone()
+                    .unwrap_or_else(|| oc.kind.type_name().to_owned());
+                let mut name = base.clone();
+                let mut n = 1;
+                while !names.insert(name.clone()) {
+                    name = format!("{}_{}", base, n);
+                    n += 1;
+                }
+                ConfiguredOutput::new(name, oc, &config.general, &silences)
+            })
+            .collect::<Result<_>>()?;
+        Ok(App {
+            access: Access::new(&config.general.control)?,
+            general: config.general,
+            items: config.items,
+            outputs,
+            silences,
+            source: config.source,
+            generation: 1,
+            started: Utc::now(),
+        })
+    }
+
+    pub fn outputs(&self) -> &[ConfiguredOutput] {
+        &self.outputs
+    }
+
+    /// Keys of the sensitive items
+    fn sensitive(&self) -> HashSet<String> {
+        self.items
+            .iter()
+            .filter(|item| item.sensitive)
+            .map(|item| item.key.clone())
+            .collect()
+    }
+
+    /// Prepare the outputs to be started, all but those in `running`
+    fn prepare(&self, running: &HashSet<String>) -> Result<()> {
+        for output in &self.outputs {
+            if !running.contains(&output.name) {
+                output
+                    .output
+                    .prepare()
+                    .with_context(|| format!("Failed preparing output {}", output.name))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Start the tasks of items, outputs, health reports and the control socket. The
+    /// outputs in `running` are still running from before a reload and are kept, the
+    /// others must have been prepared.
+    fn spawn(
+        &self,
+        tasks: &mut JoinSet<()>,
+        dispatcher: Dispatcher,
+        mut running: HashMap<String, RunningOutput>,
+    ) -> Result<Running> {
         info!("Starting up antikoerper!");
-        let (sender, _receiver) = broadcast::channel(100);
-        let mut join_handles: Vec<JoinHandle<_>> = Vec::new();
+        if let Err(e) = self.general.priority.apply() {
+            warn!("{:#}", e);
+        }
+        let workers = Workers::new(self.general.digest_workers);
+        let history = Runs::default();
+        let mut handles = Vec::new();
         for item in &self.items {
             debug!("spawning item task {}", item.key);
-            let s = sender.clone();
-            let shell = self.general.shell.clone();
+            let d = dispatcher.clone();
+            let general = self.general.clone();
             let item = item.clone();
-            join_handles.push(tokio::spawn(item.start(shell, s)));
+            handles.push(tasks.spawn(item.start(general, d, workers.clone(), history.clone())));
+        }
+        for output in &self.outputs {
+            if running.contains_key(&output.name) {
+                debug!("output {} keeps running", output.name);
+                continue;
+            }
+            debug!("spawning output tasks for {}", output.name);
+            let (stop, stopped) = watch::channel(());
+            let mut output_handles = Vec::new();
+            for r in output.result_pool(&dispatcher, &stopped) {
+                let op = output.output.clone();
+                output_handles.push(tasks.spawn(op.start(r)));
+            }
+            if output.output.handles_events() {
+                let r = output.events(&dispatcher, &stopped);
+                let op = output.output.clone();
+                output_handles.push(tasks.spawn(op.start_events(r)));
+            }
+            running.insert(
+                output.name.clone(),
+                RunningOutput {
+                    stop,
+                    tasks: output_handles,
+                },
+            );
         }
+        if self.general.health_interval > 0 {
+            let breakers = self
+                .outputs
+                .iter()
+                .map(|output| output.breaker.clone())
+                .collect::<Vec<_>>();
+            let d = dispatcher.clone();
+            let period = Duration::from_secs(self.general.health_interval);
+            let generation = self.generation;
+            handles.push(tasks.spawn(async move {
+                let mut interval = tokio::time::interval(period);
+                loop {
+                    interval.tick().await;
+                    for breaker in &breakers {
+                        // Nobody listening is no reason to complain
+                        let _ = d.send(breaker.health());
+                    }
+                    let _ = d.send(ItemResult {
+                        time: now(),
+                        key: "antikoerper.config".into(),
+                        raw: String::new(),
+                        values: HashMap::from([(
+                            "antikoerper.config.generation".into(),
+                            generation as f64,
+                        )]),
+                        tags: BTreeMap::new(),
+                    });
+                }
+            }));
+        }
+        let control = ControlServer {
+            socket: self.general.control_socket.clone(),
+            access: self.access.clone(),
+            started: self.started,
+            generation: self.generation,
+            items: self.items.len(),
+            outputs: self.outputs.len(),
+            silences: self.silences.clone(),
+            dispatcher: dispatcher.clone(),
+        };
+        handles.push(tasks.spawn(control.start()));
+        Ok(Running {
+            dispatcher,
+            history,
+            tasks: handles,
+            outputs: running,
+        })
+    }
+
+    /// Log the state of the items and outputs, or write it to `dump_file`
+    fn dump(&self, running: &Running) {
+        let mut lines = vec![format!(
+            "State at {}, running since {}, configuration generation {}:",
+            Utc::now().format("%Y-%m-%d %H:%M:%S"),
+            self.started.format("%Y-%m-%d %H:%M:%S"),
+            self.generation
+        )];
+        let keys = self
+            .items
+            .iter()
+            .map(|item| item.key.clone())
+            .collect::<Vec<_>>();
+        lines.extend(running.history.describe(&keys));
         for output in &self.outputs {
-            debug!("spawning output tasks");
-            output.prepare()?;
-            let r = sender.subscribe();
-            let op = output.clone();
-            join_handles.push(tokio::spawn(op.start(r)));
-        }
-        for jh in join_handles {
-            if let Err(e) = jh.await {
+            let mut line = format!("output {}: {}", output.name, output.breaker.describe());
+            if let Some(buffer) = output.spool() {
+                match contents(buffer.path()) {
+                    Ok(buffered) => line.push_str(&format!(
+                        ", {} results buffered ({} bytes)",
+                        buffered.results, buffered.bytes
+                    )),
+                    Err(e) => line.push_str(&format!(", buffer unreadable: {:#}", e)),
+                }
+            }
+            lines.push(line);
+        }
+        lines.push(format!(
+            "{} of at most {} results queued for the slowest output",
+            running.dispatcher.queued(),
+            CAPACITY
+        ));
+        let dump = lines.join("\n  ");
+        match &self.general.dump_file {
+            Some(path) => match std::fs::write(path, format!("{}\n", dump)) {
+                Ok(()) => info!("State written to {}", path.display()),
+                Err(e) => error!("Failed writing the state to {}: {}", path.display(), e),
+            },
+            None => info!("{}", dump),
+        }
+    }
+
+    async fn join(tasks: &mut JoinSet<()>) -> bool {
+        match tasks.join_next().await {
+            Some(Err(e)) if e.is_cancelled() => true,
+            Some(Err(e)) => {
                 warn!("Waiting on a thread failed");
                 warn!("{}", e);
+                true
+            }
+            Some(Ok(())) => true,
+            None => {
+                debug!("all tasks have rejoined. Exiting.");
+                false
             }
         }
-        debug!("all tasks have rejoined. Exiting.");
+    }
+
+    /// Wait until the tasks ended, aborting them after `grace`, joining other tasks ending
+    /// meanwhile
+    async fn stop(tasks: &mut JoinSet<()>, handles: Vec<AbortHandle>, grace: Duration) {
+        let mut pending = handles.iter().map(AbortHandle::id).collect::<HashSet<_>>();
+        let deadline = tokio::time::sleep(grace);
+        tokio::pin!(deadline);
+        let mut aborted = false;
+        while !pending.is_empty() {
+            tokio::select! {
+                joined = tasks.join_next_with_id() => match joined {
+                    Some(Ok((id, ()))) => {
+                        pending.remove(&id);
+                    }
+                    Some(Err(e)) => {
+                        pending.remove(&e.id());
+                    }
+                    None => break,
+                },
+                () = &mut deadline, if !aborted => {
+                    if !grace.is_zero() {
+                        warn!(
+                            "Outputs still writing after {}s, aborting them",
+                            grace.as_secs()
+                        );
+                    }
+                    handles.iter().for_each(AbortHandle::abort);
+                    aborted = true;
+                }
+            }
+        }
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        let mut tasks = JoinSet::new();
+        let dispatcher = Dispatcher::new(CAPACITY, self.sensitive());
+        self.prepare(&HashSet::new())?;
+        self.spawn(&mut tasks, dispatcher, HashMap::new())?;
+        while Self::join(&mut tasks).await {}
         Ok(())
     }
+
+    /// The configuration returned by `reload` with its outputs prepared, and the names of
+    /// the outputs that keep running, if it can be used and changed
+    fn reload(&self, reload: &impl Fn() -> Result<Config>) -> Option<(App, HashSet<String>)> {
+        let mut config = match reload() {
+            Ok(config) => config,
+            Err(e) => {
+                error!("Failed reloading the configuration, keeping the running one");
+                error!("{:#}", e);
+                return None;
+            }
+        };
+        let changes = conf::diff(self.source.as_ref(), config.source.as_ref());
+        if changes.is_empty() {
+            info!("Configuration unchanged, nothing to reload");
+            return None;
+        }
+        // The outputs keeping running check the silences of the reloaded configuration, and
+        // those added at runtime, through the same list. None keep running with another
+        // state directory, where the silences added at runtime are read from.
+        let configured = std::mem::take(&mut config.silences);
+        let silences = if config.general.state_dir == self.general.state_dir {
+            self.silences.clone()
+        } else {
+            Silences::new(Vec::new(), &config.general.state_dir)
+        };
+        let mut app = match App::with_silences(config, silences) {
+            Ok(app) => app,
+            Err(e) => {
+                error!("Failed setting up the reloaded configuration, keeping the running one");
+                error!("{:#}", e);
+                return None;
+            }
+        };
+        let keep = conf::unchanged_outputs(self.source.as_ref(), app.source.as_ref());
+        // With the circuit breakers of the running tasks
+        for output in app.outputs.iter_mut() {
+            if let Some(running) = self
+                .outputs
+                .iter()
+                .find(|o| o.name == output.name && keep.contains(&o.name))
+            {
+                *output = running.clone();
+            }
+        }
+        // Before anything is stopped, so that the running configuration is kept if it fails
+        if let Err(e) = app.prepare(&keep) {
+            error!("Failed setting up the reloaded configuration, keeping the running one");
+            error!("{:#}", e);
+            return None;
+        }
+        app.silences.configure(configured);
+        app.generation = self.generation + 1;
+        app.started = self.started;
+        info!("Reloading configuration, generation {}:", app.generation);
+        for change in changes {
+            info!("  {}", change);
+        }
+        Some((app, keep))
+    }
+
+    /// Like `start`, restarting the tasks with the configuration returned by `reload` on
+    /// SIGHUP, and dumping the state on SIGUSR1. Outputs configured the same way keep
+    /// running across reloads, with their connections and buffers, so their results are
+    /// only closed when shutting down.
+    pub async fn run(mut self, reload: impl Fn() -> Result<Config>) -> Result<()> {
+        let mut hangup = signal(SignalKind::hangup())?;
+        let mut user1 = signal(SignalKind::user_defined1())?;
+        let mut tasks = JoinSet::new();
+        let dispatcher = Dispatcher::new(CAPACITY, self.sensitive());
+        self.prepare(&HashSet::new())?;
+        let mut state = self.spawn(&mut tasks, dispatcher, HashMap::new())?;
+        loop {
+            tokio::select! {
+                running = Self::join(&mut tasks) => if !running {
+                    return Ok(());
+                },
+                _ = hangup.recv() => if let Some((app, keep)) = self.reload(&reload) {
+                    Self::stop(&mut tasks, state.tasks, Duration::ZERO).await;
+                    // The items are stopped, the outputs restarted write the results they
+                    // hold, e.g. their batches, before their replacements start
+                    let mut stopped = Vec::new();
+                    let mut kept = HashMap::new();
+                    for (name, output) in state.outputs {
+                        if keep.contains(&name) {
+                            kept.insert(name, output);
+                        } else {
+                            output.stop.send_replace(());
+                            stopped.extend(output.tasks);
+                        }
+                    }
+                    Self::stop(&mut tasks, stopped, STOP_TIMEOUT).await;
+                    self = app;
+                    let dispatcher = state.dispatcher.with_sensitive(self.sensitive());
+                    state = self.spawn(&mut tasks, dispatcher, kept)?;
+                },
+                _ = user1.recv() => self.dump(&state),
+            }
+        }
+    }
 }
 
-impl From<Config> for App {
-    fn from(config: Config) -> Self {
-        App {
-            general: config.general,
-            items: config.items,
-            outputs: config.output.into_iter().map(Output::from).collect(),
+impl TryFrom<Config> for App {
+    type Error = anyhow::Error;
+
+    fn try_from(mut config: Config) -> Result<Self> {
+        let silences = Silences::new(
+            std::mem::take(&mut config.silences),
+            &config.general.state_dir,
+        );
+        App::with_silences(config, silences)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use tokio::net::TcpListener;
+    use tokio::sync::broadcast;
+
+    use crate::alert::Silence;
+    use crate::app::App;
+    use crate::conf::Config;
+    use crate::item::ItemResult;
+    use crate::output::AKOutput;
+    use crate::testing::{receive_request, ConfigBuilder};
+
+    const ITEM: &str = r#"
+        key = "check"
+        interval = 60
+        input = { type = "shell", script = "true" }
+        "#;
+
+    fn silence(keys: &str) -> Silence {
+        Silence {
+            keys: keys.to_owned(),
+            start: None,
+            end: chrono::Utc::now() + chrono::Duration::hours(1),
+            comment: String::new(),
+        }
+    }
+
+    fn critical(key: &str) -> Arc<ItemResult> {
+        Arc::new(ItemResult {
+            time: Duration::from_secs(0),
+            key: key.into(),
+            raw: String::new(),
+            values: HashMap::from([(format!("{}.status", key).into(), 2.0)]),
+            tags: BTreeMap::new(),
+        })
+    }
+
+    #[tokio::test]
+    async fn reload_keeps_silences() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let webhook = format!(
+            "type = \"webhook\"\nurl = \"{}\"\nalert = {{ dedup_window = 0 }}",
+            url
+        );
+        let config = |items: &[&str]| -> anyhow::Result<Config> {
+            items
+                .iter()
+                .fold(ConfigBuilder::new(), |builder, item| builder.item(item))
+                .output_toml(&webhook)
+                .build()
+        };
+        let app = App::try_from(config(&[ITEM]).unwrap()).unwrap();
+        let state_dir = app.general.state_dir.clone();
+        let (app, keep) = app
+            .reload(&|| {
+                let mut config = config(&[ITEM, &ITEM.replace("check", "other")])?;
+                config.general.state_dir = state_dir.clone();
+                config.silences = vec![silence("configured")];
+                Ok(config)
+            })
+            .unwrap();
+        assert!(keep.contains("webhook"));
+
+        // As by `antikoerper ctl silence`, after the reload
+        app.silences.add(silence("silenced")).unwrap();
+        let (sender, receiver) = broadcast::channel(8);
+        let kept = tokio::spawn(app.outputs()[0].output.clone().start(receiver));
+        for key in ["silenced", "configured", "check"] {
+            assert!(sender.send(critical(key)).is_ok());
         }
+        let body = receive_request(&listener).await;
+        assert!(body.contains(r#""key":"check""#), "{}", body);
+        drop(sender);
+        kept.await.unwrap();
+        std::fs::remove_dir_all(state_dir).unwrap();
     }
 }
@@ -0,0 +1,110 @@
+//! Development mode injecting item failures, slow outputs and bursts of results, to see
+//! the supervision, buffering and backpressure cope with them. Enabled with the hidden
+//! `--chaos` flag.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use log::warn;
+use tokio::sync::broadcast;
+
+use crate::item::ItemResult;
+
+/// Chance of a run of an item failing
+pub const ITEM_FAILURE: f64 = 0.1;
+/// Chance of a result being sent again many times, more than the channels hold
+pub const BURST: f64 = 0.02;
+pub const BURST_SIZE: usize = 500;
+/// Chance of an output taking up to `SLOW_OUTPUT_DELAY` for a result
+const SLOW_OUTPUT: f64 = 0.05;
+const SLOW_OUTPUT_DELAY: Duration = Duration::from_secs(5);
+/// Capacity of the channel between the delay and the output
+const CAPACITY: usize = 100;
+
+/// Random decisions, not suitable for anything but this
+#[derive(Debug)]
+pub struct Chaos {
+    state: u64,
+}
+
+impl Chaos {
+    pub fn new() -> Self {
+        static CREATED: AtomicU64 = AtomicU64::new(0);
+        let nanos = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        Self {
+            // xorshift must not start at 0
+            state: (nanos ^ CREATED.fetch_add(1, Ordering::Relaxed).rotate_left(32)) | 1,
+        }
+    }
+
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    /// Uniformly in [0, 1)
+    fn fraction(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    pub fn happens(&mut self, chance: f64) -> bool {
+        self.fraction() < chance
+    }
+
+    fn delay(&mut self, max: Duration) -> Duration {
+        max.mul_f64(self.fraction())
+    }
+}
+
+impl Default for Chaos {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Results passed on with random delays, as if the output was slow at times
+pub fn slow(
+    mut receiver: broadcast::Receiver<Arc<ItemResult>>,
+) -> broadcast::Receiver<Arc<ItemResult>> {
+    let (sender, delayed) = broadcast::channel(CAPACITY);
+    tokio::spawn(async move {
+        let mut chaos = Chaos::new();
+        loop {
+            match receiver.recv().await {
+                Err(broadcast::error::RecvError::Closed) => break,
+                Err(broadcast::error::RecvError::Lagged(count)) => {
+                    warn!("Chaos: slow output is lagging behind, {} skipped", count)
+                }
+                Ok(result) => {
+                    if chaos.happens(SLOW_OUTPUT) {
+                        tokio::time::sleep(chaos.delay(SLOW_OUTPUT_DELAY)).await;
+                    }
+                    if sender.send(result).is_err() {
+                        // The output is gone
+                        break;
+                    }
+                }
+            }
+        }
+    });
+    delayed
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chaos::Chaos;
+
+    #[test]
+    fn chances() {
+        let mut chaos = Chaos::new();
+        let happened = (0..10_000).filter(|_| chaos.happens(0.1)).count();
+        assert!((800..1200).contains(&happened), "{}", happened);
+        assert!(!(0..100).any(|_| chaos.happens(0.0)));
+    }
+}
@@ -0,0 +1,49 @@
+//! `antikoerper output-check`, writing a probe to the configured outputs to catch broken
+//! credentials, permissions or addresses before the daemon runs with them
+
+use std::fmt::Write;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+
+use crate::app::App;
+use crate::conf::Config;
+
+/// Time each output has to write its probe
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// What to print, or an error once all outputs were checked
+pub async fn run(config: Config, name: Option<String>) -> Result<String> {
+    let app = App::try_from(config)?;
+    let outputs = app
+        .outputs()
+        .iter()
+        .filter(|output| name.as_ref().is_none_or(|name| output.name == *name))
+        .collect::<Vec<_>>();
+    if let (Some(name), true) = (&name, outputs.is_empty()) {
+        bail!("No output {}", name);
+    }
+    let mut out = String::new();
+    let mut failed = false;
+    for output in outputs {
+        match tokio::time::timeout(TIMEOUT, output.output.check()).await {
+            Ok(Ok(done)) => writeln!(out, "{}: ok, {}", output.name, done)?,
+            Ok(Err(e)) => {
+                failed = true;
+                writeln!(out, "{}: failed, {:#}", output.name, e)?
+            }
+            Err(_) => {
+                failed = true;
+                writeln!(
+                    out,
+                    "{}: failed, timed out after {:?}",
+                    output.name, TIMEOUT
+                )?
+            }
+        }
+    }
+    if failed {
+        bail!("{}", out.trim_end());
+    }
+    Ok(out)
+}
@@ -0,0 +1,70 @@
+//! Wall-clock time of results and events, replaceable in tests. Durations between two
+//! points in time are measured with `tokio::time::Instant`, which tests can pause and
+//! advance.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, Utc};
+
+pub trait Clock {
+    /// Time since the UNIX epoch
+    fn now(&self) -> Duration;
+}
+
+/// The system's clock
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .expect("SystemTime before UNIX EPOCH!")
+    }
+}
+
+/// Starts at a fixed time and advances with tokio's clock, so tests pausing and
+/// advancing it get predictable timestamps
+#[cfg(test)]
+pub struct TokioClock {
+    start: Duration,
+    origin: tokio::time::Instant,
+}
+
+#[cfg(test)]
+impl TokioClock {
+    pub fn new(start: Duration) -> Self {
+        Self {
+            start,
+            origin: tokio::time::Instant::now(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl Clock for TokioClock {
+    fn now(&self) -> Duration {
+        self.start + self.origin.elapsed()
+    }
+}
+
+thread_local! {
+    static CLOCK: RefCell<Rc<dyn Clock>> = RefCell::new(Rc::new(SystemClock));
+}
+
+pub fn now() -> Duration {
+    CLOCK.with(|clock| clock.borrow().now())
+}
+
+pub fn utc() -> DateTime<Utc> {
+    let now = now();
+    DateTime::from_timestamp(now.as_secs() as i64, now.subsec_nanos()).unwrap_or_default()
+}
+
+/// Replace the clock of the current thread. With the current thread runtime of
+/// `#[tokio::test]` that is the clock of all tasks, but not of blocking threads.
+#[cfg(test)]
+pub fn set(clock: impl Clock + 'static) {
+    CLOCK.with(|current| *current.borrow_mut() = Rc::new(clock));
+}
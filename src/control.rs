@@ -0,0 +1,394 @@
+//! Control socket of the running daemon, and the client side used by `antikoerper ctl`
+//!
+//! Requests and responses are single lines of JSON. Requests are allowed by the scope of
+//! the peer's user, or of a token sent along.
+
+use std::collections::HashSet;
+use std::ffi::CString;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Utc};
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+
+use crate::alert::{Silence, Silences};
+use crate::conf::{ControlAccess, Scope};
+use crate::dispatcher::Dispatcher;
+use crate::event::{Event, Severity};
+use crate::item::{now, ItemResult};
+use crate::secret::Secret;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "lowercase")]
+pub enum Request {
+    Status,
+    Silence {
+        keys: String,
+        /// Seconds
+        duration: u64,
+        #[serde(default)]
+        comment: String,
+    },
+    Unsilence {
+        keys: String,
+    },
+    /// A manual data point, sent to all outputs like a result of an item
+    Annotate {
+        key: String,
+        value: String,
+        #[serde(default)]
+        note: Option<String>,
+    },
+}
+
+impl Request {
+    fn scope(&self) -> Scope {
+        match self {
+            Request::Status => Scope::Read,
+            _ => Scope::Write,
+        }
+    }
+}
+
+/// A request as sent over the socket
+#[derive(Serialize, Deserialize)]
+struct Envelope<'a> {
+    #[serde(flatten)]
+    request: Request,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    token: Option<std::borrow::Cow<'a, str>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Response {
+    pub ok: bool,
+    pub message: String,
+}
+
+impl Response {
+    fn ok(message: String) -> Self {
+        Self { ok: true, message }
+    }
+    fn error(message: String) -> Self {
+        Self { ok: false, message }
+    }
+}
+
+/// The uid of a user given by name or uid
+fn uid(user: &str) -> Result<u32> {
+    if let Ok(uid) = user.parse() {
+        return Ok(uid);
+    }
+    let name = CString::new(user).map_err(|_| anyhow!("Invalid user name {:?}", user))?;
+    // SAFETY: all zeros is a valid passwd, filled in by getpwnam_r
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; 16384];
+    let mut found = std::ptr::null_mut();
+    // SAFETY: the pointers are valid for the duration of the call, with the buffer's length
+    let rc = unsafe {
+        libc::getpwnam_r(
+            name.as_ptr(),
+            &mut passwd,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut found,
+        )
+    };
+    if rc != 0 || found.is_null() {
+        bail!("Unknown user {}", user);
+    }
+    Ok(passwd.pw_uid)
+}
+
+/// Compared in constant time, to not reveal how much of a token was right
+fn same(given: &str, token: &Secret) -> bool {
+    let (given, token) = (given.as_bytes(), token.expose().as_bytes());
+    given.len() == token.len() && given.iter().zip(token).fold(0, |d, (a, b)| d | (a ^ b)) == 0
+}
+
+/// `ControlAccess` with users resolved
+#[derive(Debug, Clone)]
+pub struct Access {
+    read: HashSet<u32>,
+    write: HashSet<u32>,
+    token: Option<Secret>,
+    read_token: Option<Secret>,
+    others: Scope,
+}
+
+impl Access {
+    pub fn new(config: &ControlAccess) -> Result<Self> {
+        let resolve = |users: &[String]| {
+            users
+                .iter()
+                .map(|user| uid(user).context("control access"))
+                .collect::<Result<_>>()
+        };
+        let mut write: HashSet<u32> = resolve(&config.write)?;
+        write.insert(0);
+        // SAFETY: geteuid cannot fail
+        write.insert(unsafe { libc::geteuid() });
+        Ok(Self {
+            read: resolve(&config.read)?,
+            write,
+            token: config.token.clone(),
+            read_token: config.read_token.clone(),
+            others: config.others,
+        })
+    }
+
+    /// The widest scope granted by the peer's user or the token
+    fn scope(&self, uid: Option<u32>, token: Option<&str>) -> Scope {
+        let by_user = match uid {
+            Some(uid) if self.write.contains(&uid) => Scope::Write,
+            Some(uid) if self.read.contains(&uid) => Scope::Read,
+            _ => self.others,
+        };
+        let by_token = match token {
+            Some(t) if self.token.as_ref().is_some_and(|token| same(t, token)) => Scope::Write,
+            Some(t) if self.read_token.as_ref().is_some_and(|token| same(t, token)) => Scope::Read,
+            _ => Scope::None,
+        };
+        by_user.max(by_token)
+    }
+}
+
+/// What the control server needs to know about the running daemon
+#[derive(Clone)]
+pub struct ControlServer {
+    pub socket: PathBuf,
+    pub access: Access,
+    pub started: DateTime<Utc>,
+    /// Of the configuration, counted up with every reload
+    pub generation: u64,
+    pub items: usize,
+    pub outputs: usize,
+    pub silences: Silences,
+    pub dispatcher: Dispatcher,
+}
+
+impl ControlServer {
+    pub async fn start(self) {
+        if let Some(parent) = self.socket.parent() {
+            if let Err(e) = std::fs::create_dir_all(parent) {
+                warn!("Failed creating directory for control socket: {}", e);
+            }
+        }
+        // A socket left behind by a previous run would make binding fail
+        let _ = std::fs::remove_file(&self.socket);
+        let listener = match UnixListener::bind(&self.socket) {
+            Ok(l) => l,
+            Err(e) => {
+                warn!(
+                    "Control socket {} not available, `antikoerper ctl` will not work: {}",
+                    self.socket.display(),
+                    e
+                );
+                return;
+            }
+        };
+        info!("Listening on control socket {}", self.socket.display());
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let server = self.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = server.handle(stream).await {
+                            error!("Control connection failed: {}", e);
+                        }
+                    });
+                }
+                Err(e) => error!("Failed accepting control connection: {}", e),
+            }
+        }
+    }
+
+    async fn handle(&self, stream: UnixStream) -> Result<()> {
+        let uid = stream.peer_cred().ok().map(|credentials| credentials.uid());
+        let (read, mut write) = stream.into_split();
+        let mut lines = BufReader::new(read).lines();
+        while let Some(line) = lines.next_line().await? {
+            let response = match serde_json::from_str::<Envelope>(&line) {
+                Ok(Envelope { request, token }) => {
+                    debug!("control: {:?} from uid {:?}", request, uid);
+                    let needed = request.scope();
+                    if self.access.scope(uid, token.as_deref()) < needed {
+                        warn!("control: denied {:?} to uid {:?}", request, uid);
+                        Response::error(format!(
+                            "Permission denied, {} access is needed",
+                            needed.as_str()
+                        ))
+                    } else {
+                        self.respond(request)
+                            .unwrap_or_else(|e| Response::error(e.to_string()))
+                    }
+                }
+                Err(e) => Response::error(format!("Invalid request: {}", e)),
+            };
+            let mut out = serde_json::to_vec(&response)?;
+            out.push(b'\n');
+            write.write_all(&out).await?;
+        }
+        Ok(())
+    }
+
+    fn respond(&self, request: Request) -> Result<Response> {
+        Ok(match request {
+            Request::Status => Response::ok(self.status()),
+            Request::Silence {
+                keys,
+                duration,
+                comment,
+            } => {
+                let Some(end) = silence_end(duration) else {
+                    return Ok(Response::error(format!(
+                        "A silence of {}s ends too far in the future",
+                        duration
+                    )));
+                };
+                let silence = Silence {
+                    keys,
+                    start: None,
+                    end,
+                    comment,
+                };
+                let message = format!("Silenced {}", silence);
+                self.silences.add(silence)?;
+                Response::ok(message)
+            }
+            Request::Unsilence { keys } => match self.silences.remove(&keys)? {
+                0 => Response::error(format!("No silence for {} was added at runtime", keys)),
+                n => Response::ok(format!("Removed {} silence(s) for {}", n, keys)),
+            },
+            Request::Annotate { key, value, note } => self.annotate(key, value, note)?,
+        })
+    }
+
+    /// Numeric values are written as `<key>`, everything else only as raw value. The
+    /// note is attached as tag, and sent as event.
+    fn annotate(&self, key: String, value: String, note: Option<String>) -> Result<Response> {
+        let mut result = ItemResult {
+            time: now(),
+            key: key.as_str().into(),
+            raw: value.clone(),
+            values: Default::default(),
+            tags: Default::default(),
+        };
+        if let Ok(number) = value.trim().parse::<f64>() {
+            result.values.insert(result.key.clone(), number);
+        }
+        let mut message = format!("{} = {}", key, value);
+        if let Some(note) = note {
+            message = format!("{}: {}", message, note);
+            result.tags.insert(String::from("note"), note);
+        }
+        let event = Event::new(&key, Severity::Info, message.clone()).tags(&result.tags);
+        self.dispatcher.send(result)?;
+        self.dispatcher.event(event);
+        info!("control: annotated {}", message);
+        Ok(Response::ok(format!("Recorded {}", message)))
+    }
+
+    fn status(&self) -> String {
+        let mut status = format!(
+            "running since {}, configuration generation {}\n{} items, {} outputs\n",
+            self.started
+                .to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            self.generation,
+            self.items,
+            self.outputs
+        );
+        let silences = self.silences.list();
+        if silences.is_empty() {
+            status.push_str("no silences\n");
+        } else {
+            status.push_str("silences:\n");
+            for silence in silences {
+                status.push_str(&format!("  {}\n", silence));
+            }
+        }
+        status
+    }
+}
+
+/// Send a single request to the daemon listening on `socket`, with a token if the user
+/// alone has no access
+pub async fn request(socket: &Path, request: Request, token: Option<&str>) -> Result<Response> {
+    let stream = tokio::time::timeout(Duration::from_secs(5), UnixStream::connect(socket))
+        .await
+        .context("Timed out connecting to the control socket")?
+        .with_context(|| {
+            format!(
+                "Failed connecting to {}, is antikoerper running?",
+                socket.display()
+            )
+        })?;
+    let (read, mut write) = stream.into_split();
+    let mut line = serde_json::to_vec(&Envelope {
+        request,
+        token: token.map(Into::into),
+    })?;
+    line.push(b'\n');
+    write.write_all(&line).await?;
+    match BufReader::new(read).lines().next_line().await? {
+        Some(response) => Ok(serde_json::from_str(&response)?),
+        None => bail!("The daemon closed the connection without responding"),
+    }
+}
+
+/// When a silence of `duration` seconds from now ends, if that can be told
+fn silence_end(duration: u64) -> Option<DateTime<Utc>> {
+    i64::try_from(duration)
+        .ok()
+        .and_then(chrono::Duration::try_seconds)
+        .and_then(|duration| Utc::now().checked_add_signed(duration))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::conf::{ControlAccess, Scope};
+    use crate::control::{silence_end, Access, Envelope, Request};
+    use crate::secret::Secret;
+
+    #[test]
+    fn access() {
+        let access = Access::new(&ControlAccess {
+            read: vec![String::from("4242")],
+            write: vec![String::from("root")],
+            token: Some(Secret::new(String::from("hunter2"))),
+            read_token: None,
+            others: Scope::None,
+        })
+        .unwrap();
+        assert_eq!(access.scope(Some(0), None), Scope::Write);
+        assert_eq!(access.scope(Some(4242), None), Scope::Read);
+        assert_eq!(access.scope(Some(4243), None), Scope::None);
+        assert_eq!(access.scope(None, Some("hunter2")), Scope::Write);
+        assert_eq!(access.scope(Some(4242), Some("hunter")), Scope::Read);
+
+        let envelope: Envelope = serde_json::from_str(
+            r#"{"command":"silence","keys":"a.*","duration":60,"token":"hunter2"}"#,
+        )
+        .unwrap();
+        assert_eq!(envelope.token.as_deref(), Some("hunter2"));
+        assert_eq!(envelope.request.scope(), Scope::Write);
+        assert!(matches!(
+            serde_json::from_str::<Envelope>(r#"{"command":"status"}"#)
+                .unwrap()
+                .request,
+            Request::Status
+        ));
+    }
+
+    #[test]
+    fn silence_ends() {
+        let end = silence_end(3600).unwrap();
+        assert!((end - chrono::Utc::now()).num_seconds() > 3590);
+        assert!(silence_end(u64::MAX).is_none());
+        assert!(silence_end(i64::MAX as u64).is_none());
+    }
+}
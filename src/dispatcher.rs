@@ -0,0 +1,132 @@
+//! Distribution of results and events from the items to all outputs
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use tokio::sync::broadcast;
+
+use crate::event::Event;
+use crate::item::ItemResult;
+
+#[derive(Clone)]
+pub struct Dispatcher {
+    results: broadcast::Sender<Arc<ItemResult>>,
+    events: broadcast::Sender<Event>,
+    /// Keys of sensitive items, their raw output never reaches an output
+    sensitive: Arc<HashSet<String>>,
+}
+
+/// Tags holding raw output, see `track_changes`
+const RAW_TAGS: [&str; 2] = ["old", "new"];
+
+impl Dispatcher {
+    pub fn new(capacity: usize, sensitive: HashSet<String>) -> Self {
+        Self {
+            results: broadcast::channel(capacity).0,
+            events: broadcast::channel(capacity).0,
+            sensitive: Arc::new(sensitive),
+        }
+    }
+
+    /// Sending to the same outputs, for the items of a reloaded configuration
+    pub fn with_sensitive(&self, sensitive: HashSet<String>) -> Self {
+        Self {
+            results: self.results.clone(),
+            events: self.events.clone(),
+            sensitive: Arc::new(sensitive),
+        }
+    }
+
+    /// Native inputs of a sensitive item produce results below its key
+    fn is_sensitive(&self, key: &str) -> bool {
+        self.sensitive.iter().any(|sensitive| {
+            key.strip_prefix(sensitive.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+        })
+    }
+
+    pub fn send(&self, mut result: ItemResult) -> Result<usize> {
+        if self.is_sensitive(&result.key) {
+            result.raw.clear();
+            result
+                .tags
+                .retain(|name, _| !RAW_TAGS.contains(&name.as_str()));
+        }
+        // Shared by all outputs, instead of a copy for each
+        self.results
+            .send(Arc::new(result))
+            .map_err(|_| anyhow!("No output is receiving results"))
+    }
+
+    /// Events are optional for outputs, it is fine if nobody listens
+    pub fn event(&self, mut event: Event) {
+        if self.is_sensitive(&event.key) {
+            event
+                .tags
+                .retain(|name, _| !RAW_TAGS.contains(&name.as_str()));
+        }
+        if self.events.send(event).is_err() {
+            log::trace!("no output handles events");
+        }
+    }
+
+    /// Results not yet received by the slowest output
+    pub fn queued(&self) -> usize {
+        self.results.len()
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<ItemResult>> {
+        self.results.subscribe()
+    }
+
+    pub fn subscribe_events(&self) -> broadcast::Receiver<Event> {
+        self.events.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap, HashSet};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::dispatcher::Dispatcher;
+    use crate::item::ItemResult;
+
+    fn result(key: &str) -> ItemResult {
+        ItemResult {
+            time: Duration::from_secs(0),
+            key: key.into(),
+            raw: String::from("s3cr3t"),
+            values: HashMap::from([(format!("{}.parsed", key).into(), 1.0)]),
+            tags: BTreeMap::from([(String::from("new"), String::from("s3cr3t"))]),
+        }
+    }
+
+    #[test]
+    fn sensitive_raw() {
+        let dispatcher = Dispatcher::new(10, HashSet::from([String::from("vpn")]));
+        let mut receiver = dispatcher.subscribe();
+        for key in ["vpn", "vpn.wg0", "vpnstatus"] {
+            dispatcher.send(result(key)).unwrap();
+        }
+        for hidden in [true, true, false] {
+            let received = receiver.try_recv().unwrap();
+            assert_eq!(received.raw.is_empty(), hidden);
+            assert_eq!(received.tags.is_empty(), hidden);
+            assert_eq!(received.values.len(), 1);
+        }
+    }
+
+    #[test]
+    fn shared() {
+        let dispatcher = Dispatcher::new(10, HashSet::new());
+        let (mut first, mut second) = (dispatcher.subscribe(), dispatcher.subscribe());
+        dispatcher.send(result("load")).unwrap();
+        assert!(Arc::ptr_eq(
+            &first.try_recv().unwrap(),
+            &second.try_recv().unwrap()
+        ));
+    }
+}
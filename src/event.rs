@@ -0,0 +1,68 @@
+//! Events, for things that happened rather than measured values: failing items, changed
+//! statuses and outputs. They are handled by outputs configured to receive events.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::alert::Status;
+use crate::item::{duration_millis, now};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    Warning,
+    Critical,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Info => "INFO",
+            Self::Warning => "WARNING",
+            Self::Critical => "CRITICAL",
+        }
+    }
+}
+
+impl From<Status> for Severity {
+    fn from(status: Status) -> Self {
+        match status {
+            Status::Ok => Self::Info,
+            Status::Warning | Status::Unknown => Self::Warning,
+            Status::Critical => Self::Critical,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Event {
+    /// Milliseconds since the UNIX epoch when serialized
+    #[serde(with = "duration_millis")]
+    pub time: Duration,
+    /// Key of the item the event is about
+    pub key: String,
+    pub severity: Severity,
+    pub message: String,
+    pub tags: BTreeMap<String, String>,
+}
+
+impl Event {
+    pub fn new(key: &str, severity: Severity, message: String) -> Self {
+        Self {
+            time: now(),
+            key: key.to_owned(),
+            severity,
+            message,
+            tags: BTreeMap::new(),
+        }
+    }
+
+    pub fn tags(mut self, tags: &BTreeMap<String, String>) -> Self {
+        self.tags
+            .extend(tags.iter().map(|(k, v)| (k.clone(), v.clone())));
+        self
+    }
+}
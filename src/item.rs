@@ -1,48 +1,1017 @@
 use std::collections::BTreeMap;
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::Duration;
-use std::time::SystemTime;
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use log::{debug, error, info, warn};
-use serde::Deserialize;
-use tokio::io::AsyncReadExt;
-use tokio::sync::broadcast;
+use serde::de::value::{MapAccessDeserializer, SeqAccessDeserializer};
+use serde::de::{MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
+use sha2::{Digest as _, Sha256};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt};
+use tokio::time::{Instant, MissedTickBehavior};
+
+use crate::alert::Status;
+use crate::chaos::{self, Chaos};
+use crate::conf::General;
+use crate::dispatcher::Dispatcher;
+use crate::event::{Event, Severity};
+use crate::keys::{Key, KeyCache};
+use crate::priority::Priority;
+use crate::secret::{Redacted, Secret};
+use crate::state::Runs;
+use crate::workers::Workers;
 
 /// A single item, knowing when it is supposed to run next, what should be done and its key.
 #[derive(Debug, Clone, Deserialize)]
 pub struct Item {
+    /// Seconds between runs, not needed by inputs pushing their output
+    #[serde(default)]
     pub interval: u64,
+    /// Only run the input every nth interval, repeating the last result in between
+    #[serde(default = "sample_every_default")]
+    pub sample_every: u64,
+    /// What happens when a run takes longer than the interval
+    #[serde(default)]
+    pub overlap: Overlap,
+    /// Shrink the interval while the values change quickly, grow it while they are stable
+    pub adaptive: Option<Adaptive>,
     pub key: String,
+    /// Hidden from logs, like the configured credentials
+    #[serde(default)]
+    pub env: BTreeMap<String, Secret>,
+    /// The raw output is never logged
+    #[serde(default)]
+    pub sensitive: bool,
+    /// Additional information attached to every result, for outputs supporting it
     #[serde(default)]
-    pub env: BTreeMap<String, String>,
+    pub tags: BTreeMap<String, String>,
+    /// Also write a hash of the raw output as `<key>.raw_hash`, to detect changes
+    #[serde(default)]
+    pub raw_hash: bool,
+    /// Remember the raw output and report when it changed, with the old and new output as tags
+    #[serde(default)]
+    pub track_changes: bool,
     #[serde(rename = "input")]
     pub kind: ItemKind,
-    #[serde(default)]
-    pub digest: DigestKind,
+    /// One or more digests, each producing values from the same input
+    #[serde(
+        default = "digests_default",
+        rename = "digest",
+        deserialize_with = "one_or_many"
+    )]
+    pub digests: Vec<Digest>,
+    /// The keys of the values its digests parse, formatted once
+    #[serde(skip)]
+    pub keys: KeyCache,
+}
+
+/// A digest, and how often it is applied to the result of the input
+#[derive(Debug, Clone, Deserialize)]
+pub struct Digest {
+    #[serde(flatten)]
+    pub kind: DigestKind,
+    /// Only apply the digest to every nth result of the input
+    #[serde(default = "every_default")]
+    pub every: u64,
+}
+
+fn digests_default() -> Vec<Digest> {
+    vec![Digest {
+        kind: DigestKind::default(),
+        every: every_default(),
+    }]
+}
+
+fn every_default() -> u64 {
+    1
+}
+
+/// `digest` is either a single table or a list of them
+fn one_or_many<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Vec<Digest>, D::Error> {
+    // Rather than an untagged enum, which hides why a digest is invalid
+    struct OneOrMany;
+
+    impl<'de> Visitor<'de> for OneOrMany {
+        type Value = Vec<Digest>;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.write_str("a digest or a list of digests")
+        }
+
+        fn visit_map<A: MapAccess<'de>>(
+            self,
+            map: A,
+        ) -> std::result::Result<Self::Value, A::Error> {
+            Digest::deserialize(MapAccessDeserializer::new(map)).map(|digest| vec![digest])
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(
+            self,
+            seq: A,
+        ) -> std::result::Result<Self::Value, A::Error> {
+            Vec::deserialize(SeqAccessDeserializer::new(seq))
+        }
+    }
+
+    deserializer.deserialize_any(OneOrMany)
+}
+
+fn sample_every_default() -> u64 {
+    1
+}
+
+/// Runs of an item never overlap, this decides about the ticks missed by a slow run
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Overlap {
+    /// Missed ticks are dropped, the next run happens on schedule
+    #[default]
+    Skip,
+    /// The next run starts right after the slow one, later ones are scheduled from there
+    Queue,
+    /// The run is cancelled when the next one is due, commands are killed
+    Kill,
+}
+
+impl Overlap {
+    fn missed_ticks(self) -> MissedTickBehavior {
+        match self {
+            Overlap::Skip | Overlap::Kill => MissedTickBehavior::Skip,
+            Overlap::Queue => MissedTickBehavior::Delay,
+        }
+    }
+}
+
+/// Bounds of the interval of an item scheduled by the volatility of its values, starting
+/// with `interval`
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Adaptive {
+    /// Seconds
+    #[serde(default = "min_interval_default")]
+    pub min_interval: u64,
+    /// Seconds
+    pub max_interval: u64,
+    /// The relative change of a value since the last run, e.g. 0.05 for 5%, beyond which
+    /// the values are volatile
+    #[serde(default = "change_default")]
+    pub change: f64,
+}
+
+fn min_interval_default() -> u64 {
+    1
+}
+
+fn change_default() -> f64 {
+    0.05
+}
+
+impl Adaptive {
+    /// The interval after a run, halved if any value changed by more than `change` since
+    /// the last run, otherwise grown by half. Values without a previous one are not
+    /// compared, the interval stays if there are none.
+    fn next(
+        &self,
+        period: Duration,
+        previous: &mut HashMap<Key, f64>,
+        results: &[ItemResult],
+    ) -> Duration {
+        let mut compared = false;
+        let mut volatile = false;
+        for (key, value) in results.iter().flat_map(|result| &result.values) {
+            if let Some(old) = previous.insert(key.clone(), *value) {
+                compared = true;
+                volatile |= (value - old).abs() / old.abs().max(f64::EPSILON) > self.change;
+            }
+        }
+        let next = match (compared, volatile) {
+            (false, _) => period,
+            (true, true) => period / 2,
+            (true, false) => period * 3 / 2,
+        };
+        next.clamp(
+            Duration::from_secs(self.min_interval),
+            Duration::from_secs(self.max_interval),
+        )
+    }
 }
 
 impl Item {
-    pub async fn start(self, shell: String, sender: broadcast::Sender<ItemResult>) {
+    pub async fn start(
+        self,
+        general: General,
+        dispatcher: Dispatcher,
+        workers: Workers,
+        history: Runs,
+    ) {
+        if let ItemKind::MqttSubscribe(subscription) = &self.kind {
+            let subscription = subscription.clone();
+            return self
+                .subscribe(subscription, general, dispatcher, workers, history)
+                .await;
+        }
+        if let ItemKind::Serial(port) = &self.kind {
+            let port = port.clone();
+            return self
+                .read_serial(port, general, dispatcher, workers, history)
+                .await;
+        }
+        if let ItemKind::Tail(tail) = &self.kind {
+            let tail = tail.clone();
+            return self
+                .follow(tail, general, dispatcher, workers, history)
+                .await;
+        }
+        if let ItemKind::Listen(listener) = &self.kind {
+            let listener = listener.clone();
+            return self.listen(listener, general, dispatcher, history).await;
+        }
+        if let ItemKind::Socket(socket) = &self.kind {
+            let socket = socket.clone();
+            return self.ingest(socket, general, dispatcher, history).await;
+        }
         debug!("item {}: starting loop", self.key);
-        let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(self.interval));
-        loop {
-            interval.tick().await;
-            match self.kind.produce_result(&shell, &self.env).await {
+        // Shared with the digest workers
+        let item = Arc::new(self.clone());
+        let shell = general.shell;
+        let priority = general.command_priority;
+        // Changes of the public address are what it is watched for
+        let mut tracker = (self.track_changes || matches!(self.kind, ItemKind::PublicIp(_)))
+            .then(|| ChangeTracker::new(&general.state_dir, &self.key, self.sensitive));
+        let mut period = Duration::from_secs(self.interval);
+        let mut previous = HashMap::new();
+        let mut interval = tokio::time::interval(period);
+        interval.set_missed_tick_behavior(self.overlap.missed_ticks());
+        let mut kind = self.kind.clone();
+        if let ItemKind::PublicIp(ip) = &mut kind {
+            ip.restore(general.state_dir.join("publicip").join(&self.key));
+        }
+        let mut last: Vec<ItemResult> = Vec::new();
+        let mut runs = 0u64;
+        let mut status: Option<Status> = None;
+        let mut overruns = 0u64;
+        let mut chaos = general.chaos.then(Chaos::new);
+        for tick in 0u64.. {
+            let scheduled = interval.tick().await;
+            let results = if !last.is_empty() && !tick.is_multiple_of(self.sample_every) {
+                debug!("item {}: repeating last result", self.key);
+                let time = now();
+                last.iter()
+                    .map(|cached| ItemResult {
+                        time,
+                        ..cached.clone()
+                    })
+                    .collect()
+            } else {
+                let run = runs;
+                runs += 1;
+                let started = Instant::now();
+                let produced = match self.overlap {
+                    Overlap::Kill => tokio::time::timeout(
+                        period,
+                        kind.produce_result(&self.key, &shell, priority, &self.env),
+                    )
+                    .await
+                    .unwrap_or_else(|_| {
+                        Err(anyhow!(
+                            "Cancelled, the run did not finish within the interval of {}s",
+                            self.interval
+                        ))
+                    }),
+                    _ => {
+                        kind.produce_result(&self.key, &shell, priority, &self.env)
+                            .await
+                    }
+                };
+                let produced = if chaos
+                    .as_mut()
+                    .is_some_and(|c| c.happens(chaos::ITEM_FAILURE))
+                {
+                    Err(anyhow!("Chaos: injected failure"))
+                } else {
+                    produced
+                };
+                history.finished(
+                    &self.key,
+                    produced.as_ref().err().map(|e| format!("{:#}", e)),
+                    Some(period.saturating_sub(scheduled.elapsed())),
+                );
+                if scheduled.elapsed() >= period {
+                    overruns += 1;
+                    warn!(
+                        "item {}: cannot keep up with its interval of {}s, the run took {:.1}s",
+                        self.key,
+                        self.interval,
+                        started.elapsed().as_secs_f64()
+                    );
+                    // Nobody listening is no reason to complain
+                    let _ = dispatcher.send(self.overrun(
+                        started.elapsed(),
+                        started - scheduled,
+                        overruns,
+                    ));
+                }
+                let results = match produced {
+                    Err(e) => {
+                        error!("Item {} failed to produce a result", self.key);
+                        error!("{}", e);
+                        dispatcher.event(
+                            Event::new(&self.key, Severity::Warning, format!("{:#}", e))
+                                .tags(&self.tags),
+                        );
+                        continue;
+                    }
+                    Ok(Reading::Raw(r)) => {
+                        let item = item.clone();
+                        // Taken here, the workers do not follow the clock of tests
+                        let time = now();
+                        match workers.run(move || item.digest(&r, run, time)).await {
+                            Ok(result) => vec![result],
+                            Err(e) => {
+                                error!("Item {} failed digesting its output", self.key);
+                                error!("{:#}", e);
+                                continue;
+                            }
+                        }
+                    }
+                    Ok(Reading::Results(results)) => results
+                        .into_iter()
+                        .map(|mut result| {
+                            let mut tags = self.tags.clone();
+                            tags.append(&mut result.tags);
+                            result.tags = tags;
+                            result
+                        })
+                        .collect(),
+                    // Pushed inputs are started above and never get here
+                    Ok(Reading::Pushed) => continue,
+                };
+                if self.sample_every > 1 {
+                    last = results.clone();
+                }
+                if let Some(adaptive) = &self.adaptive {
+                    let next = adaptive.next(period, &mut previous, &results);
+                    if next != period || run == 0 {
+                        debug!("item {}: running every {:?}", self.key, next);
+                        period = next;
+                        interval = tokio::time::interval_at(scheduled + period, period);
+                        interval.set_missed_tick_behavior(self.overlap.missed_ticks());
+                        let _ = dispatcher.send(self.effective_interval(period));
+                    }
+                }
+                results
+            };
+            for result in results {
+                self.deliver(result, &mut tracker, &mut status, &mut chaos, &dispatcher);
+            }
+        }
+    }
+
+    /// Digest every message published to the topic
+    async fn subscribe(
+        self,
+        subscription: mqtt::Subscription,
+        general: General,
+        dispatcher: Dispatcher,
+        workers: Workers,
+        history: Runs,
+    ) {
+        debug!("item {}: subscribing to {}", self.key, subscription.topic);
+        let item = Arc::new(self.clone());
+        let mut tracker = self
+            .track_changes
+            .then(|| ChangeTracker::new(&general.state_dir, &self.key, self.sensitive));
+        let mut status: Option<Status> = None;
+        let mut chaos = general.chaos.then(Chaos::new);
+        let (client, mut eventloop) =
+            match subscription.connect(format!("antikoerper-{}-{}", general.hostname, self.key)) {
+                Ok(connection) => connection,
                 Err(e) => {
-                    error!("Item {} failed to produce a result", self.key);
-                    error!("{}", e);
+                    error!("Item {} cannot subscribe: {:#}", self.key, e);
+                    return;
                 }
-                Ok(r) => {
-                    if let Err(e) = sender.send(self.digest.digest(&r, &self.key)) {
-                        error!("Result of Item {} could not be send via channel", self.key);
+            };
+        for run in 0u64.. {
+            let publish = loop {
+                match eventloop.poll().await {
+                    // Subscriptions do not survive reconnecting with a clean session
+                    Ok(rumqttc::Event::Incoming(rumqttc::Incoming::ConnAck(_))) => {
+                        if let Err(e) =
+                            client.try_subscribe(&subscription.topic, rumqttc::QoS::AtMostOnce)
+                        {
+                            error!("Item {} failed subscribing: {}", self.key, e);
+                        }
+                    }
+                    Ok(rumqttc::Event::Incoming(rumqttc::Incoming::Publish(publish))) => {
+                        break publish
+                    }
+                    Ok(_) => (),
+                    Err(e) => {
+                        error!("Item {} lost the connection to the broker", self.key);
                         error!("{}", e);
+                        history.finished(&self.key, Some(e.to_string()), None);
+                        dispatcher.event(
+                            Event::new(&self.key, Severity::Warning, e.to_string())
+                                .tags(&self.tags),
+                        );
+                        tokio::time::sleep(Duration::from_secs(5)).await;
+                    }
+                }
+            };
+            let raw = String::from_utf8_lossy(&publish.payload).into_owned();
+            let item = item.clone();
+            let time = now();
+            match workers.run(move || item.digest(&raw, run, time)).await {
+                Ok(mut result) => {
+                    result.tags.insert(String::from("topic"), publish.topic);
+                    history.finished(&self.key, None, None);
+                    self.deliver(result, &mut tracker, &mut status, &mut chaos, &dispatcher);
+                }
+                Err(e) => {
+                    error!("Item {} failed digesting its output", self.key);
+                    error!("{:#}", e);
+                }
+            }
+        }
+    }
+
+    /// Digest every line read from the device, opening it again if it went away, e.g.
+    /// when unplugged
+    async fn read_serial(
+        self,
+        port: serial::Port,
+        general: General,
+        dispatcher: Dispatcher,
+        workers: Workers,
+        history: Runs,
+    ) {
+        debug!("item {}: reading {}", self.key, port.device.display());
+        let item = Arc::new(self.clone());
+        let mut tracker = self
+            .track_changes
+            .then(|| ChangeTracker::new(&general.state_dir, &self.key, self.sensitive));
+        let mut status: Option<Status> = None;
+        let mut chaos = general.chaos.then(Chaos::new);
+        let mut connection = None;
+        for run in 0u64.. {
+            let lines = match connection.take().map_or_else(|| port.open(), Ok) {
+                Ok(mut opened) => {
+                    let lines = opened.read().await;
+                    // Opened again after failures
+                    if lines.is_ok() {
+                        connection = Some(opened);
+                    }
+                    lines
+                }
+                Err(e) => Err(e),
+            };
+            let lines = match lines {
+                Ok(lines) => lines,
+                Err(e) => {
+                    error!("Item {} failed reading {}", self.key, port.device.display());
+                    error!("{:#}", e);
+                    history.finished(&self.key, Some(format!("{:#}", e)), None);
+                    dispatcher.event(
+                        Event::new(&self.key, Severity::Warning, format!("{:#}", e))
+                            .tags(&self.tags),
+                    );
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                    continue;
+                }
+            };
+            for raw in lines.into_iter().filter(|line| !line.trim().is_empty()) {
+                let item = item.clone();
+                let time = now();
+                match workers.run(move || item.digest(&raw, run, time)).await {
+                    Ok(result) => {
+                        history.finished(&self.key, None, None);
+                        self.deliver(result, &mut tracker, &mut status, &mut chaos, &dispatcher);
+                    }
+                    Err(e) => {
+                        error!("Item {} failed digesting its output", self.key);
+                        error!("{:#}", e);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Digest the lines appended to a file, one by one or those read at once together
+    async fn follow(
+        self,
+        tail: tail::Tail,
+        general: General,
+        dispatcher: Dispatcher,
+        workers: Workers,
+        history: Runs,
+    ) {
+        debug!("item {}: following {}", self.key, tail.path.display());
+        let item = Arc::new(self.clone());
+        let mut tracker = self
+            .track_changes
+            .then(|| ChangeTracker::new(&general.state_dir, &self.key, self.sensitive));
+        let mut status: Option<Status> = None;
+        let mut chaos = general.chaos.then(Chaos::new);
+        let mut follower = None;
+        for run in 0u64.. {
+            let lines = match follower.take().map_or_else(|| tail.follow(), Ok) {
+                Ok(mut following) => {
+                    let lines = following.read().await;
+                    // Started again after failures
+                    if lines.is_ok() {
+                        follower = Some(following);
+                    }
+                    lines
+                }
+                Err(e) => Err(e),
+            };
+            let lines = match lines {
+                Ok(lines) if tail.batch => vec![lines.join("\n")],
+                Ok(lines) => lines,
+                Err(e) => {
+                    error!("Item {} failed reading {}", self.key, tail.path.display());
+                    error!("{:#}", e);
+                    history.finished(&self.key, Some(format!("{:#}", e)), None);
+                    dispatcher.event(
+                        Event::new(&self.key, Severity::Warning, format!("{:#}", e))
+                            .tags(&self.tags),
+                    );
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                    continue;
+                }
+            };
+            for raw in lines.into_iter().filter(|line| !line.trim().is_empty()) {
+                let item = item.clone();
+                let time = now();
+                match workers.run(move || item.digest(&raw, run, time)).await {
+                    Ok(result) => {
+                        history.finished(&self.key, None, None);
+                        self.deliver(result, &mut tracker, &mut status, &mut chaos, &dispatcher);
+                    }
+                    Err(e) => {
+                        error!("Item {} failed digesting its output", self.key);
+                        error!("{:#}", e);
                     }
                 }
             }
         }
     }
+
+    /// Turn datagrams into results, as they arrive or aggregated for statsd
+    async fn listen(
+        self,
+        listener: listen::Listener,
+        general: General,
+        dispatcher: Dispatcher,
+        history: Runs,
+    ) {
+        debug!("item {}: listening on {}", self.key, listener.bind);
+        let mut tracker = self
+            .track_changes
+            .then(|| ChangeTracker::new(&general.state_dir, &self.key, self.sensitive));
+        let mut status: Option<Status> = None;
+        let mut chaos = general.chaos.then(Chaos::new);
+        let socket = match listener.bind().await {
+            Ok(socket) => socket,
+            Err(e) => {
+                error!("Item {} cannot listen: {:#}", self.key, e);
+                return;
+            }
+        };
+        let statsd = listener.format == listen::Format::Statsd;
+        let mut aggregated = listen::Statsd::default();
+        let mut flush = tokio::time::interval(Duration::from_secs(listener.flush_interval.max(1)));
+        // The largest datagram possible
+        let mut buffer = vec![0; 65536];
+        loop {
+            let results = tokio::select! {
+                received = socket.recv_from(&mut buffer) => {
+                    let (length, peer) = match received {
+                        Ok(received) => received,
+                        Err(e) => {
+                            error!("Item {} failed receiving: {}", self.key, e);
+                            continue;
+                        }
+                    };
+                    let datagram = String::from_utf8_lossy(&buffer[..length]);
+                    let parsed = if statsd {
+                        aggregated.add(&datagram).map(|_| Vec::new())
+                    } else {
+                        listen::plain(&self.key, &datagram)
+                    };
+                    match parsed {
+                        Ok(results) => results,
+                        Err(e) => {
+                            let error = Redacted::new(&format!("{:#}", e), self.sensitive).to_string();
+                            warn!(
+                                "Item {}: ignoring a datagram from {}: {}",
+                                self.key, peer, error
+                            );
+                            history.finished(&self.key, Some(error), None);
+                            continue;
+                        }
+                    }
+                }
+                _ = flush.tick(), if statsd => aggregated.flush(&self.key).into_iter().collect(),
+            };
+            for mut result in results {
+                let mut tags = self.tags.clone();
+                tags.append(&mut result.tags);
+                result.tags = tags;
+                history.finished(&self.key, None, None);
+                self.deliver(result, &mut tracker, &mut status, &mut chaos, &dispatcher);
+            }
+        }
+    }
+
+    /// Accept results as JSON lines from any number of connections
+    async fn ingest(
+        self,
+        socket: ingest::Socket,
+        general: General,
+        dispatcher: Dispatcher,
+        history: Runs,
+    ) {
+        debug!("item {}: listening on {}", self.key, socket.path.display());
+        let mut tracker = self
+            .track_changes
+            .then(|| ChangeTracker::new(&general.state_dir, &self.key, self.sensitive));
+        let mut status: Option<Status> = None;
+        let mut chaos = general.chaos.then(Chaos::new);
+        let listener = match socket.bind() {
+            Ok(listener) => listener,
+            Err(e) => {
+                error!("Item {} cannot listen: {:#}", self.key, e);
+                return;
+            }
+        };
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(100);
+        loop {
+            let mut result: ItemResult = tokio::select! {
+                accepted = listener.accept() => {
+                    match accepted {
+                        Ok((stream, _)) => {
+                            let key = self.key.clone();
+                            let sensitive = self.sensitive;
+                            let sender = sender.clone();
+                            let history = history.clone();
+                            tokio::spawn(async move {
+                                let mut lines =
+                                    tokio::io::BufReader::new(stream).lines();
+                                while let Ok(Some(line)) = lines.next_line().await {
+                                    if line.trim().is_empty() {
+                                        continue;
+                                    }
+                                    match ingest::result(&key, &line) {
+                                        Ok(result) => {
+                                            if sender.send(result).await.is_err() {
+                                                break;
+                                            }
+                                        }
+                                        Err(e) => {
+                                            let error = Redacted::new(&e, sensitive).to_string();
+                                            warn!(
+                                                "Item {}: ignoring an invalid line: {}",
+                                                key, error
+                                            );
+                                            history.finished(&key, Some(error), None);
+                                        }
+                                    }
+                                }
+                            });
+                        }
+                        Err(e) => error!("Item {} failed accepting a connection: {}", self.key, e),
+                    }
+                    continue;
+                }
+                Some(result) = receiver.recv() => result,
+            };
+            let mut tags = self.tags.clone();
+            tags.append(&mut result.tags);
+            result.tags = tags;
+            history.finished(&self.key, None, None);
+            self.deliver(result, &mut tracker, &mut status, &mut chaos, &dispatcher);
+        }
+    }
+
+    /// Send a result, after tracking changes and statuses
+    fn deliver(
+        &self,
+        mut result: ItemResult,
+        tracker: &mut Option<ChangeTracker>,
+        status: &mut Option<Status>,
+        chaos: &mut Option<Chaos>,
+        dispatcher: &Dispatcher,
+    ) {
+        // Changes and statuses are tracked for the item's own result only
+        if *result.key == *self.key {
+            if let Some(tracker) = tracker.as_mut() {
+                if let Some(event) = tracker.apply(&mut result) {
+                    dispatcher.event(event);
+                }
+            }
+            let new_status = Status::of(&result);
+            if new_status != *status {
+                if let Some(s) = new_status.filter(|s| status.is_some() || *s != Status::Ok) {
+                    let message = format!(
+                        "Status changed from {} to {}: {}",
+                        status.map(|s| s.as_str()).unwrap_or("none"),
+                        s.as_str(),
+                        Redacted::new(
+                            result.raw.split('|').next().unwrap_or_default().trim(),
+                            self.sensitive
+                        )
+                    );
+                    dispatcher.event(Event::new(&self.key, s.into(), message).tags(&self.tags));
+                }
+                *status = new_status;
+            }
+        }
+        if chaos.as_mut().is_some_and(|c| c.happens(chaos::BURST)) {
+            for _ in 0..chaos::BURST_SIZE {
+                let _ = dispatcher.send(result.clone());
+            }
+        }
+        if let Err(e) = dispatcher.send(result) {
+            error!("Result of Item {} could not be send via channel", self.key);
+            error!("{}", e);
+        }
+    }
+}
+
+impl Item {
+    /// `antikoerper.item.<key>` with `.duration` and `.drift`, the seconds the run took and
+    /// started late, and `.overruns` since the start
+    fn overrun(&self, duration: Duration, drift: Duration, overruns: u64) -> ItemResult {
+        let key = format!("antikoerper.item.{}", self.key);
+        ItemResult {
+            time: now(),
+            values: HashMap::from([
+                (format!("{}.duration", key).into(), duration.as_secs_f64()),
+                (format!("{}.drift", key).into(), drift.as_secs_f64()),
+                (format!("{}.overruns", key).into(), overruns as f64),
+            ]),
+            key: key.into(),
+            raw: String::new(),
+            tags: BTreeMap::from([(String::from("item"), self.key.clone())]),
+        }
+    }
+
+    /// `antikoerper.item.<key>` with `.interval`, the seconds between runs of adaptive items
+    fn effective_interval(&self, period: Duration) -> ItemResult {
+        let key = format!("antikoerper.item.{}", self.key);
+        ItemResult {
+            time: now(),
+            values: HashMap::from([(format!("{}.interval", key).into(), period.as_secs_f64())]),
+            key: key.into(),
+            raw: String::new(),
+            tags: BTreeMap::from([(String::from("item"), self.key.clone())]),
+        }
+    }
+
+    /// Capture group names of regex digests that would overwrite other values of the
+    /// item: `raw`, written by outputs, the values of the other digests, of `raw_hash` and
+    /// of `track_changes`, and groups of the same name in several digests
+    pub fn colliding_groups(&self) -> Vec<String> {
+        let mut taken = HashSet::from(["raw"]);
+        if self.raw_hash {
+            taken.insert("raw_hash");
+        }
+        if self.track_changes {
+            taken.insert("changed");
+        }
+        for digest in &self.digests {
+            match &digest.kind {
+                DigestKind::Raw => taken.insert("parsed"),
+                DigestKind::MonitoringPlugin { .. } => taken.insert("status"),
+                DigestKind::Regex { .. } | DigestKind::Json { .. } => false,
+            };
+        }
+        let mut collisions = Vec::new();
+        for digest in &self.digests {
+            let names = match &digest.kind {
+                DigestKind::Regex { regex } => regex.capture_names().flatten().collect(),
+                DigestKind::Json { paths } => paths.keys().map(String::as_str).collect(),
+                _ => Vec::new(),
+            };
+            for name in names {
+                if !taken.insert(name) {
+                    collisions.push(name.to_owned());
+                }
+            }
+        }
+        collisions
+    }
+
+    /// The keys of the values the item writes, as far as they are known before running it:
+    /// `raw` and those of the digests, `raw_hash` and `track_changes`. Native inputs name
+    /// their values while running, below the item's key.
+    pub fn value_keys(&self) -> Vec<String> {
+        let mut names = vec![String::from("raw")];
+        if self.kind.is_native() {
+            return vec![format!("{}.raw", self.key)];
+        }
+        if self.raw_hash {
+            names.push(String::from("raw_hash"));
+        }
+        if self.track_changes {
+            names.push(String::from("changed"));
+        }
+        for digest in &self.digests {
+            match &digest.kind {
+                DigestKind::Raw => names.push(String::from("parsed")),
+                DigestKind::MonitoringPlugin { .. } => names.push(String::from("status")),
+                DigestKind::Regex { regex } => {
+                    names.extend(regex.capture_names().flatten().map(String::from))
+                }
+                DigestKind::Json { paths } => names.extend(paths.keys().cloned()),
+            }
+        }
+        names
+            .into_iter()
+            .map(|name| format!("{}.{}", self.key, name))
+            .collect()
+    }
+
+    /// Run the input once and digest its output, without delivering the results, for
+    /// `antikoerper selftest`
+    pub async fn run_once(&self, general: &General) -> Result<Vec<ItemResult>> {
+        let mut kind = self.kind.clone();
+        let reading = kind
+            .produce_result(
+                &self.key,
+                &general.shell,
+                general.command_priority,
+                &self.env,
+            )
+            .await?;
+        Ok(match reading {
+            Reading::Raw(raw) => vec![self.digest(&raw, 0, now())],
+            Reading::Results(results) => results,
+            Reading::Pushed => Vec::new(),
+        })
+    }
+
+    /// Apply all digests due for the given run of the input, merging their values
+    fn digest(&self, raw: &str, run: u64, time: Duration) -> ItemResult {
+        let mut result = ItemResult {
+            time,
+            key: self.keys.item(&self.key),
+            raw: String::from(raw.trim()),
+            values: HashMap::new(),
+            tags: self.tags.clone(),
+        };
+        for digest in self.digests.iter().filter(|d| run.is_multiple_of(d.every)) {
+            result.values.extend(
+                digest
+                    .kind
+                    .digest(raw, &self.keys, &self.key, self.sensitive)
+                    .values,
+            );
+        }
+        if self.raw_hash {
+            result.values.insert(
+                self.keys.value(&self.key, "raw_hash"),
+                raw_hash(&result.raw),
+            );
+        }
+        result
+    }
+}
+
+/// Remembers the last raw output of an item across restarts, for `track_changes`
+struct ChangeTracker {
+    path: PathBuf,
+    previous: Option<String>,
+    /// The outputs are left out of the event
+    sensitive: bool,
+}
+
+impl ChangeTracker {
+    fn new(state_dir: &Path, key: &str, sensitive: bool) -> Self {
+        let path = state_dir
+            .join("changes")
+            .join(key.replace(std::path::MAIN_SEPARATOR, "_"));
+        let previous = std::fs::read_to_string(&path).ok();
+        Self {
+            path,
+            previous,
+            sensitive,
+        }
+    }
+
+    /// Add `<key>.changed`, and the `old` and `new` tags if the raw output changed. Returns
+    /// the event to send about the change.
+    fn apply(&mut self, result: &mut ItemResult) -> Option<Event> {
+        let changed = match &self.previous {
+            Some(previous) if *previous != result.raw => {
+                info!("item {}: output changed", result.key);
+                result.tags.insert(String::from("old"), previous.clone());
+                result.tags.insert(String::from("new"), result.raw.clone());
+                true
+            }
+            // Unchanged, or nothing to compare with the first time
+            _ => false,
+        };
+        result.values.insert(
+            format!("{}.changed", result.key).into(),
+            if changed { 1.0 } else { 0.0 },
+        );
+        let event = changed.then(|| {
+            Event::new(
+                &result.key,
+                Severity::Info,
+                format!(
+                    "Output changed from {} to {}",
+                    Redacted::new(self.previous.as_deref().unwrap_or_default(), self.sensitive),
+                    Redacted::new(&result.raw, self.sensitive)
+                ),
+            )
+            .tags(&result.tags)
+        });
+        if changed || self.previous.is_none() {
+            self.previous = Some(result.raw.clone());
+            if let Err(e) = self.persist() {
+                warn!("item {}: failed persisting output: {}", result.key, e);
+            }
+        }
+        event
+    }
+
+    fn persist(&self) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&self.path, self.previous.as_deref().unwrap_or_default())
+            .with_context(|| format!("Failed writing {}", self.path.display()))
+    }
+}
+
+/// First 53 bits of the SHA-256 of the raw output, so the hash is exactly representable as f64
+fn raw_hash(raw: &str) -> f64 {
+    let digest = Sha256::digest(raw.as_bytes());
+    let bytes: [u8; 8] = digest[..8]
+        .try_into()
+        .expect("SHA-256 is longer than 8 bytes");
+    (u64::from_be_bytes(bytes) >> 11) as f64
+}
+
+pub use crate::clock::now;
+
+mod asn1;
+mod battery;
+mod certificate;
+mod directory;
+mod dns;
+mod docker;
+mod filesystems;
+mod gpu;
+mod http;
+mod hwmon;
+mod ingest;
+mod ipmi;
+mod journal;
+mod jsonpath;
+mod kernel;
+mod libvirt;
+mod listen;
+mod modbus;
+mod mpris;
+mod mqtt;
+mod network;
+mod pressure;
+mod probe;
+mod process;
+mod processes;
+mod prometheus;
+mod publicip;
+mod serial;
+mod smart;
+mod snmp;
+mod sql;
+mod system;
+mod systemd;
+mod tail;
+mod tcp;
+mod thermal;
+mod timetracking;
+mod wifi;
+mod wireguard;
+
+/// What an input produced
+pub enum Reading {
+    /// Output of an external source, to be digested
+    Raw(String),
+    /// Results of inputs reading the values themselves, the digests are not used
+    Results(Vec<ItemResult>),
+    /// Nothing, the input pushes its output when it arrives instead of being run
+    Pushed,
 }
 
 /// The different kinds of items one can use
@@ -59,37 +1028,265 @@ pub enum ItemKind {
     },
     /// A string to be executed as a shell script
     Shell { script: String },
+    /// The body of a response to an HTTP request
+    Http(http::Request),
+    /// Messages published to an MQTT topic, digested as they arrive
+    MqttSubscribe(mqtt::Subscription),
+    /// Lines read from a serial device, digested as they arrive
+    Serial(serial::Port),
+    /// Lines appended to a file, digested as they are written
+    Tail(tail::Tail),
+    /// Metrics other programs send as UDP datagrams, one result per datagram or flush
+    Listen(listen::Listener),
+    /// Results local programs write as JSON lines to a unix socket
+    Socket(ingest::Socket),
+    /// Status, size and latencies per phase of an HTTP request
+    Probe(probe::Probe),
+    /// Days until the TLS certificate of a server expires
+    Certificate(certificate::Endpoint),
+    /// Whether a TCP port accepts connections, and how fast
+    Tcp(tcp::Target),
+    /// Query time and answers of a DNS resolver for a name
+    Dns(dns::Query),
+    /// Values of an SNMP agent, read by OID
+    Snmp(snmp::Agent),
+    /// Numeric columns of the rows a database query returns, one result per row
+    Sql(sql::Query),
+    /// Metrics scraped from a Prometheus exporter, one result per set of labels
+    Prometheus(prometheus::Scrape),
+    /// The public IP address of the connection and for how long it has had it
+    PublicIp(publicip::PublicIp),
+    /// CPU, memory and network usage per running container of Docker or Podman
+    Docker(docker::Engine),
+    /// Health, temperature and worn out sectors of disks read with smartctl, one result per disk
+    Smart(smart::Smart),
+    /// Size, number and ages of the files in a directory
+    Directory(directory::Directory),
+    /// Space and inode usage, one result per mounted filesystem
+    Filesystems {
+        /// Mount points to report, all if empty
+        #[serde(default)]
+        mounts: Vec<PathBuf>,
+    },
+    /// Kernel statistics like entropy or file descriptors, all if none are selected
+    Kernel {
+        #[serde(default)]
+        metrics: Vec<kernel::KernelMetric>,
+        #[serde(skip)]
+        counters: kernel::Counters,
+    },
+    /// CPU, memory, disk and network usage and load averages, all if none are selected
+    System {
+        #[serde(default)]
+        metrics: Vec<system::SystemMetric>,
+        #[serde(skip)]
+        counters: system::Counters,
+    },
+    /// Bytes, packets, errors and drops per second of network interfaces, one result per
+    /// interface
+    Network(network::Network),
+    /// Playback of media players over MPRIS, one result per player
+    Mpris {
+        #[serde(skip)]
+        playback: mpris::Playback,
+    },
+    /// State, restarts and memory of systemd units, one result per unit
+    Systemd(systemd::Units),
+    /// Journal entries matching a filter since the last run
+    Journal(journal::Query),
+    /// Pressure stall information, all resources if none are selected
+    Pressure {
+        #[serde(default)]
+        resources: Vec<pressure::Resource>,
+        #[serde(skip)]
+        totals: pressure::Totals,
+    },
+    /// CPU, memory, file descriptors and threads of the processes of a program
+    Process(process::Process),
+    /// The top processes by CPU or memory usage, one result per rank
+    Processes {
+        #[serde(default = "processes_top_default")]
+        top: usize,
+        #[serde(default)]
+        sort_by: processes::SortBy,
+        #[serde(skip)]
+        snapshot: processes::Snapshot,
+    },
+    /// CPU frequencies, thermal throttling counters and temperatures of thermal zones
+    Thermal,
+    /// Charge, power draw and remaining time of batteries, and power adapters
+    Battery,
+    /// Temperatures, fan speeds, voltages, currents and power of hardware monitoring chips
+    Hwmon {
+        /// Names of the chips to report, e.g. `coretemp`, all if empty
+        #[serde(default)]
+        chips: Vec<String>,
+    },
+    /// Temperatures, fan speeds, voltages and power supplies of a BMC over IPMI
+    Ipmi(ipmi::Ipmi),
+    /// Utilization, memory, temperature and power of NVIDIA and AMD GPUs, one result per GPU
+    Gpu(gpu::Gpu),
+    /// Network, signal and bitrates of the Wi-Fi connection, and whether a host is reachable
+    Wifi(wifi::Wifi),
+    /// Handshakes and transfer of WireGuard peers, one result per peer
+    WireGuard(wireguard::WireGuard),
+    /// CPU, memory and I/O of libvirt's virtual machines, one result per domain
+    Libvirt(libvirt::Libvirt),
+    /// Holding and input registers of a Modbus TCP device
+    Modbus(modbus::Device),
+    /// Minutes per tag tracked today with Timewarrior
+    Timewarrior {
+        #[serde(default = "timewarrior_path_default")]
+        path: PathBuf,
+    },
+    /// Minutes per application today, from a local ActivityWatch server
+    ActivityWatch {
+        #[serde(default = "activitywatch_url_default")]
+        url: String,
+        /// The window watcher's bucket, found automatically if not given
+        bucket: Option<String>,
+    },
+}
+
+fn timewarrior_path_default() -> PathBuf {
+    PathBuf::from("timew")
+}
+
+fn activitywatch_url_default() -> String {
+    String::from("http://localhost:5600")
+}
+
+fn processes_top_default() -> usize {
+    5
 }
 
 impl ItemKind {
-    /// Generate a single result (raw, String)
+    /// Whether the input produces results itself, rather than output for the digests
+    pub fn is_native(&self) -> bool {
+        !matches!(
+            self,
+            ItemKind::File { .. }
+                | ItemKind::Command { .. }
+                | ItemKind::Shell { .. }
+                | ItemKind::Http(_)
+                | ItemKind::MqttSubscribe(_)
+                | ItemKind::Serial(_)
+                | ItemKind::Tail(_)
+        )
+    }
+
+    /// Whether the input pushes its output instead of being run on an interval
+    pub fn is_pushed(&self) -> bool {
+        matches!(
+            self,
+            ItemKind::MqttSubscribe(_)
+                | ItemKind::Serial(_)
+                | ItemKind::Tail(_)
+                | ItemKind::Listen(_)
+                | ItemKind::Socket(_)
+        )
+    }
+
+    /// Generate a single raw result, or the results of native inputs. Every input is
+    /// matched here, so that new ones can not be forgotten.
     pub async fn produce_result(
-        &self,
-        shell: &str,
-        env: &BTreeMap<String, String>,
-    ) -> Result<String> {
-        match &self {
-            ItemKind::File { ref path } => {
-                let mut file = tokio::fs::File::open(path)
+        &mut self,
+        key: &str,
+        shell: &[String],
+        priority: Priority,
+        env: &BTreeMap<String, Secret>,
+    ) -> Result<Reading> {
+        match self {
+            ItemKind::Processes {
+                top,
+                sort_by,
+                snapshot,
+            } => Ok(Reading::Results(
+                processes::produce(key, *top, *sort_by, snapshot).await?,
+            )),
+            ItemKind::Process(process) => Ok(Reading::Results(vec![process.produce(key).await?])),
+            ItemKind::Directory(directory) => {
+                Ok(Reading::Results(vec![directory.produce(key).await?]))
+            }
+            ItemKind::Filesystems { mounts } => {
+                Ok(Reading::Results(filesystems::produce(key, mounts).await?))
+            }
+            ItemKind::Kernel { metrics, counters } => Ok(Reading::Results(vec![kernel::produce(
+                key, metrics, counters,
+            )?])),
+            ItemKind::System { metrics, counters } => Ok(Reading::Results(vec![system::produce(
+                key, metrics, counters,
+            )?])),
+            ItemKind::Pressure { resources, totals } => {
+                Ok(Reading::Results(vec![pressure::produce(
+                    key, resources, totals,
+                )?]))
+            }
+            ItemKind::Thermal => Ok(Reading::Results(vec![thermal::produce(key)?])),
+            ItemKind::Battery => Ok(Reading::Results(vec![battery::produce(key)?])),
+            ItemKind::Hwmon { chips } => Ok(Reading::Results(vec![hwmon::produce(key, chips)?])),
+            ItemKind::Ipmi(ipmi) => Ok(Reading::Results(vec![ipmi.produce(key, priority).await?])),
+            ItemKind::Gpu(gpu) => Ok(Reading::Results(gpu.produce(key, priority).await?)),
+            ItemKind::Smart(smart) => Ok(Reading::Results(smart.produce(key, priority).await?)),
+            ItemKind::WireGuard(wireguard) => {
+                Ok(Reading::Results(wireguard.produce(key, priority).await?))
+            }
+            ItemKind::Wifi(wifi) => Ok(Reading::Results(vec![wifi.produce(key, priority).await?])),
+            ItemKind::Libvirt(libvirt) => {
+                Ok(Reading::Results(libvirt.produce(key, priority).await?))
+            }
+            ItemKind::Probe(probe) => Ok(Reading::Results(vec![probe.produce(key).await])),
+            ItemKind::Tcp(target) => Ok(Reading::Results(vec![target.produce(key).await])),
+            ItemKind::Dns(query) => Ok(Reading::Results(vec![query.produce(key).await])),
+            ItemKind::Snmp(agent) => Ok(Reading::Results(vec![agent.produce(key).await?])),
+            ItemKind::Modbus(device) => Ok(Reading::Results(vec![device.produce(key).await?])),
+            ItemKind::Sql(query) => Ok(Reading::Results(query.produce(key).await?)),
+            ItemKind::Prometheus(scrape) => Ok(Reading::Results(scrape.produce(key).await?)),
+            ItemKind::PublicIp(ip) => Ok(Reading::Results(vec![ip.produce(key).await?])),
+            ItemKind::Docker(engine) => Ok(Reading::Results(engine.produce(key).await?)),
+            ItemKind::Certificate(endpoint) => {
+                Ok(Reading::Results(vec![endpoint.produce(key).await?]))
+            }
+            ItemKind::Timewarrior { path } => Ok(Reading::Results(vec![
+                timetracking::timewarrior(key, path, priority, env).await?,
+            ])),
+            ItemKind::ActivityWatch { url, bucket } => Ok(Reading::Results(vec![
+                timetracking::activitywatch(key, url, bucket.as_deref()).await?,
+            ])),
+            ItemKind::Network(network) => Ok(Reading::Results(network.produce(key)?)),
+            ItemKind::Mpris { playback } => {
+                Ok(Reading::Results(mpris::produce(key, playback).await?))
+            }
+            ItemKind::Systemd(units) => Ok(Reading::Results(units.produce(key).await?)),
+            ItemKind::Journal(query) => Ok(Reading::Results(vec![
+                query.produce(key, priority, env).await?,
+            ])),
+            ItemKind::File { path } => {
+                let mut file = tokio::fs::File::open(&path)
                     .await
                     .with_context(|| format!("Failed to open file {}", path.display()))?;
                 let mut buffer = String::new();
                 file.read_to_string(&mut buffer)
                     .await
                     .with_context(|| format!("Failed to read from file {}", path.display()))?;
-                Ok(buffer)
-            }
-            ItemKind::Command { path, args } => {
-                run_cmd_capture_output(path, args.as_slice(), env).await
+                Ok(Reading::Raw(buffer))
             }
+            ItemKind::Command { path, args } => Ok(Reading::Raw(
+                run_cmd_capture_output(path, args.as_slice(), priority, env).await?,
+            )),
             ItemKind::Shell { script } => {
-                run_cmd_capture_output(
-                    &PathBuf::from(shell),
-                    &["-c".into(), script.to_owned()],
-                    env,
-                )
-                .await
+                let mut args = shell[1..].to_vec();
+                args.push(script.to_owned());
+                Ok(Reading::Raw(
+                    run_cmd_capture_output(&PathBuf::from(&shell[0]), &args, priority, env).await?,
+                ))
             }
+            ItemKind::Http(request) => Ok(Reading::Raw(request.fetch().await?)),
+            ItemKind::MqttSubscribe(_)
+            | ItemKind::Serial(_)
+            | ItemKind::Tail(_)
+            | ItemKind::Listen(_)
+            | ItemKind::Socket(_) => Ok(Reading::Pushed),
         }
     }
 }
@@ -99,11 +1296,15 @@ impl ItemKind {
 async fn run_cmd_capture_output(
     path: &PathBuf,
     args: &[String],
-    env: &BTreeMap<String, String>,
+    priority: Priority,
+    env: &BTreeMap<String, Secret>,
 ) -> Result<String> {
-    tokio::process::Command::new(path)
+    let mut command = tokio::process::Command::new(path);
+    priority.command(&mut command);
+    command
         .args(args)
-        .envs(env.clone())
+        .envs(env.iter().map(|(key, value)| (key, value.expose())))
+        .kill_on_drop(true)
         .output()
         .await
         .with_context(|| format!("Failed running command {} {:#?}", path.display(), args))
@@ -140,7 +1341,13 @@ pub enum DigestKind {
     MonitoringPlugin {
         #[serde(skip, default = "monitoring_plugin_regex")]
         regex: (::regex::Regex, ::regex::Regex),
-    }, // Maybe later more?
+    },
+    /// Parse the output as JSON, selecting the values with JSON pointers like
+    /// `/sensors/0/temp` or JSONPaths like `$.sensors[*].temp`
+    Json {
+        /// Names of the values, and the paths selecting them
+        paths: BTreeMap<String, jsonpath::JsonPath>,
+    },
 }
 
 fn monitoring_plugin_regex() -> (::regex::Regex, ::regex::Regex) {
@@ -173,34 +1380,44 @@ fn monitoring_plugin_regex() -> (::regex::Regex, ::regex::Regex) {
 impl DigestKind {
     /// If configured, parse a raw result (String) into one or more f64 values,
     /// and produce an ItemResult
-    pub fn digest(&self, result: &str, itemkey: &str) -> ItemResult {
+    pub fn digest(
+        &self,
+        result: &str,
+        keys: &KeyCache,
+        itemkey: &str,
+        sensitive: bool,
+    ) -> ItemResult {
         let result = result.trim();
-        let mut values = HashMap::<String, f64>::new();
+        let mut values = HashMap::<Key, f64>::new();
         match self {
             DigestKind::Raw => match result.parse::<f64>() {
                 Ok(f) => {
-                    values.insert(format!("{}.parsed", itemkey), f);
+                    values.insert(keys.value(itemkey, "parsed"), f);
                 }
-                Err(_) => info!("Value could not be parsed as f64: {}", result),
+                Err(_) => info!(
+                    "Value could not be parsed as f64: {}",
+                    Redacted::new(result, sensitive)
+                ),
             },
 
             // digest using regexes, and write the extracted values
             DigestKind::Regex { ref regex } => {
                 debug!("item {}: regex digest", itemkey);
                 if let Some(captures) = regex.captures(result) {
-                    debug!("regex captures: {:#?}", captures);
+                    debug!("regex captures: {:#?}", Redacted::new(&captures, sensitive));
                     for cn in regex.capture_names().flatten() {
                         let value = captures[cn].parse::<f64>().unwrap_or(f64::NAN);
                         debug!(
                             "item {}: parsed value {} for capture group {}",
                             itemkey, value, cn
                         );
-                        values.insert(format!("{}.{}", itemkey, &cn), value);
+                        values.insert(keys.value(itemkey, cn), value);
                     }
                 } else {
                     warn!(
                         "Provided regex did not match the output: {}\n{}",
-                        regex, result
+                        regex,
+                        Redacted::new(result, sensitive)
                     );
                 }
             }
@@ -208,9 +1425,12 @@ impl DigestKind {
                 regex: (output_regex, performance_regex),
             } => {
                 debug!("item {}: monitoring-plugin-digest", itemkey);
-                debug!("item {}: {}", itemkey, result);
+                debug!("item {}: {}", itemkey, Redacted::new(result, sensitive));
                 if let Some(output_matches) = output_regex.captures(result) {
-                    debug!("monitoring plugin matches: {:#?}", output_matches);
+                    debug!(
+                        "monitoring plugin matches: {:#?}",
+                        Redacted::new(&output_matches, sensitive)
+                    );
                     output_matches.name("status").and_then(|status| {
                         let status_val = match status.as_str() {
                             "OK" => 0f64,
@@ -219,12 +1439,12 @@ impl DigestKind {
                             "UNKNOWN" => 3f64,
                             _ => return None,
                         };
-                        values.insert(format!("{}.status", itemkey), status_val)
+                        values.insert(keys.value(itemkey, "status"), status_val)
                     });
                     if let Some(perf_metrics) = output_matches.name("performance") {
                         debug!(
                             "monitoring plugin performance metric matches: {:#?}",
-                            perf_metrics
+                            Redacted::new(&perf_metrics, sensitive)
                         );
                         for capture in performance_regex.captures_iter(perf_metrics.as_str()) {
                             let label = match capture.name("label") {
@@ -243,14 +1463,14 @@ impl DigestKind {
                                 _ => 1f64,
                             };
                             value *= value_factor;
-                            values.insert(format!("{}.{}", itemkey, label), value);
+                            values.insert(keys.value(itemkey, label), value);
                             for extra in ["warn", "crit", "min", "max"] {
                                 capture
                                     .name(extra)
                                     .and_then(|v| v.as_str().parse::<f64>().ok())
                                     .and_then(|v| {
                                         values.insert(
-                                            format!("{}.{}.{}", itemkey, label, extra),
+                                            keys.value(itemkey, &format!("{}.{}", label, extra)),
                                             v * value_factor,
                                         )
                                     });
@@ -259,29 +1479,176 @@ impl DigestKind {
                     }
                 }
             }
+            DigestKind::Json { paths } => {
+                debug!("item {}: json digest", itemkey);
+                match serde_json::from_str::<serde_json::Value>(result) {
+                    Ok(document) => {
+                        for (name, path) in paths {
+                            let selected = path.select(&document, name);
+                            if selected.is_empty() {
+                                warn!("item {}: the path of {} selected nothing", itemkey, name);
+                            }
+                            for (name, value) in selected {
+                                values.insert(keys.value(itemkey, &name), jsonpath::number(value));
+                            }
+                        }
+                    }
+                    Err(e) => warn!(
+                        "Output is not JSON, {}: {}",
+                        e,
+                        Redacted::new(result, sensitive)
+                    ),
+                }
+            }
         };
         ItemResult {
-            time: SystemTime::now()
-                .duration_since(SystemTime::UNIX_EPOCH)
-                .expect("SystemTime before UNIX EPOCH!"),
-            key: itemkey.into(),
+            time: now(),
+            key: keys.item(itemkey),
             raw: String::from(result),
             values,
+            tags: BTreeMap::new(),
         }
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct ItemResult {
+    /// Milliseconds since the UNIX epoch when serialized
+    #[serde(with = "duration_millis")]
     pub time: Duration,
-    pub key: String,
+    pub key: Key,
     pub raw: String,
-    pub values: HashMap<String, f64>,
+    pub values: HashMap<Key, f64>,
+    pub tags: BTreeMap<String, String>,
+}
+
+pub mod duration_millis {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(time: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(time.as_millis() as u64)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        u64::deserialize(deserializer).map(Duration::from_millis)
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::item::monitoring_plugin_regex;
+    use std::collections::HashSet;
+    use std::time::Duration;
+
+    use crate::clock::{self, TokioClock};
+    use crate::dispatcher::Dispatcher;
+    use crate::item::{monitoring_plugin_regex, now, raw_hash, Adaptive, Item, ItemResult};
+    use crate::state::Runs;
+    use crate::workers::Workers;
+
+    #[test]
+    fn multiple_digests() {
+        let item: Item = toml::from_str(
+            r#"
+            key = "load"
+            interval = 10
+            input = { type = "shell", script = "uptime" }
+            [[digest]]
+            type = "regex"
+            regex = '(?P<load1>[\d.]+)'
+            [[digest]]
+            type = "none"
+            every = 6
+            "#,
+        )
+        .unwrap();
+        let result = item.digest("0.5", 0, now());
+        assert_eq!(result.values.get("load.load1"), Some(&0.5));
+        assert_eq!(result.values.get("load.parsed"), Some(&0.5));
+        let result = item.digest("0.5", 1, now());
+        assert_eq!(result.values.len(), 1);
+    }
+
+    #[test]
+    fn digest_errors() {
+        let item = |digest: &str| {
+            toml::from_str::<Item>(&format!(
+                "key = \"load\"\ninterval = 10\ninput = {{ type = \"shell\", script = \"uptime\" }}\n{}",
+                digest
+            ))
+            .map_err(|e| e.to_string())
+        };
+        assert!(item("digest = { type = \"none\" }").is_ok());
+        let error = item("digest = { type = \"regexp\" }").unwrap_err();
+        assert!(error.contains("unknown variant `regexp`"), "{}", error);
+        let error = item("[[digest]]\ntype = \"regex\"\nregex = '(?P<load1>'").unwrap_err();
+        assert!(error.contains("regex parse error"), "{}", error);
+        let error = item("digest = \"none\"").unwrap_err();
+        assert!(error.contains("a digest or a list of digests"), "{}", error);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn scheduled() {
+        clock::set(TokioClock::new(Duration::from_secs(1_000_000)));
+        let path = std::env::temp_dir().join(format!("antikoerper-clock-{}", std::process::id()));
+        std::fs::write(&path, "0.5").unwrap();
+        let item: Item = toml::from_str(&format!(
+            r#"
+            key = "load"
+            interval = 10
+            input = {{ type = "file", path = "{}" }}
+            "#,
+            path.display()
+        ))
+        .unwrap();
+        let dispatcher = Dispatcher::new(10, HashSet::new());
+        let mut results = dispatcher.subscribe();
+        tokio::spawn(item.start(
+            toml::from_str("").unwrap(),
+            dispatcher,
+            Workers::new(1),
+            Runs::default(),
+        ));
+        for tick in 0..3 {
+            let result = results.recv().await.unwrap();
+            assert_eq!(result.time, Duration::from_secs(1_000_000 + tick * 10));
+            assert_eq!(result.values["load.parsed"], 0.5);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn adaptive_interval() {
+        let adaptive = Adaptive {
+            min_interval: 5,
+            max_interval: 60,
+            change: 0.1,
+        };
+        let result = |load: f64| ItemResult {
+            time: now(),
+            key: "load".into(),
+            raw: String::new(),
+            values: [("load.parsed".into(), load)].into(),
+            tags: Default::default(),
+        };
+        let mut previous = Default::default();
+        let secs = Duration::from_secs;
+        let mut next = |period, load| adaptive.next(secs(period), &mut previous, &[result(load)]);
+        assert_eq!(next(20, 1.0), secs(20));
+        assert_eq!(next(20, 1.05), secs(30));
+        assert_eq!(next(30, 2.0), secs(15));
+        assert_eq!(next(15, 0.0), secs(7) + Duration::from_millis(500));
+        assert_eq!(next(8, 1.0), secs(5));
+        assert_eq!(next(50, 1.0), secs(60));
+    }
+
+    #[test]
+    fn raw_hash_changes() {
+        assert_eq!(raw_hash("1.2.3"), raw_hash("1.2.3\n".trim()));
+        assert_ne!(raw_hash("1.2.3"), raw_hash("1.2.4"));
+        assert!(raw_hash("1.2.3") < 2f64.powi(53));
+    }
 
     #[test]
     fn monitoring_plugin_regex_match() {
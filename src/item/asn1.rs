@@ -0,0 +1,48 @@
+//! Parsing the tag-length-value encoding of ASN.1, the DER of X.509 certificates and the
+//! BER of SNMP messages
+
+use anyhow::{anyhow, bail, Result};
+
+/// A tag, its content and what follows it
+pub fn tlv(input: &[u8]) -> Result<(u8, &[u8], &[u8])> {
+    let (&tag, input) = input.split_first().ok_or_else(|| anyhow!("Truncated"))?;
+    let (&first, mut input) = input.split_first().ok_or_else(|| anyhow!("Truncated"))?;
+    let length = if first < 0x80 {
+        first as usize
+    } else {
+        let count = (first & 0x7f) as usize;
+        if count == 0 || count > std::mem::size_of::<usize>() || input.len() < count {
+            bail!("Unsupported length");
+        }
+        let (bytes, rest) = input.split_at(count);
+        input = rest;
+        bytes
+            .iter()
+            .fold(0, |length, b| (length << 8) | *b as usize)
+    };
+    if input.len() < length {
+        bail!("Truncated");
+    }
+    let (content, rest) = input.split_at(length);
+    Ok((tag, content, rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::item::asn1::tlv;
+
+    #[test]
+    fn lengths() {
+        assert_eq!(
+            tlv(&[0x02, 0x01, 0x05, 0x04]).unwrap(),
+            (0x02, &[0x05][..], &[0x04][..])
+        );
+        let mut long = vec![0x04, 0x81, 0x80];
+        long.extend([0x61; 0x80]);
+        let (tag, content, rest) = tlv(&long).unwrap();
+        assert_eq!((tag, content.len(), rest.len()), (0x04, 0x80, 0));
+        assert!(tlv(&[0x04, 0x02, 0x61]).is_err());
+        assert!(tlv(&[0x04, 0x80]).is_err());
+        assert!(tlv(&[0x04]).is_err());
+    }
+}
@@ -0,0 +1,245 @@
+//! Charge, power draw and remaining time of batteries, and whether power adapters are
+//! connected, from /sys/class/power_supply
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use anyhow::{bail, Result};
+
+use crate::item::hwmon::sanitize;
+use crate::item::thermal::read_number;
+use crate::item::{now, ItemResult};
+use crate::keys::Key;
+
+/// A battery in Wh and W, whichever of energy, charge, power and current it reports
+struct Battery {
+    name: String,
+    status: String,
+    /// Of the system, not of a device like a mouse
+    system: bool,
+    capacity: Option<f64>,
+    energy: Option<f64>,
+    energy_full: Option<f64>,
+    energy_full_design: Option<f64>,
+    power: Option<f64>,
+}
+
+impl Battery {
+    fn read(supply: &Path, name: String) -> Self {
+        let number = |file: &str| read_number(&supply.join(file));
+        let text = |file: &str| {
+            std::fs::read_to_string(supply.join(file))
+                .map(|text| text.trim().to_owned())
+                .unwrap_or_default()
+        };
+        // µV, µAh and µA of batteries reporting their charge instead of their energy
+        let voltage = number("voltage_min_design").or_else(|| number("voltage_now"));
+        let energy = |energy: &str, charge: &str| {
+            number(energy)
+                .or_else(|| Some(number(charge)? * voltage? / 1_000_000.0))
+                .map(|microwatthours| microwatthours / 1_000_000.0)
+        };
+        let energy_full = energy("energy_full", "charge_full");
+        let energy_now = energy("energy_now", "charge_now");
+        // Negative while discharging with some firmware
+        let power = number("power_now")
+            .or_else(|| Some(number("current_now")? * number("voltage_now")? / 1_000_000.0))
+            .map(|microwatts| microwatts.abs() / 1_000_000.0);
+        Self {
+            name,
+            status: text("status"),
+            system: text("scope") != "Device",
+            capacity: number("capacity").or_else(|| Some(energy_now? / energy_full? * 100.0)),
+            energy: energy_now,
+            energy_full,
+            energy_full_design: energy("energy_full_design", "charge_full_design"),
+            power,
+        }
+    }
+
+    fn charging(&self) -> bool {
+        self.status == "Charging"
+    }
+
+    fn discharging(&self) -> bool {
+        self.status == "Discharging"
+    }
+
+    /// Percent of the design capacity left
+    fn health(&self) -> Option<f64> {
+        Some(self.energy_full? / self.energy_full_design? * 100.0)
+    }
+
+    /// Seconds until empty while discharging, or until full while charging
+    fn remaining(&self) -> Option<f64> {
+        let power = self.power.filter(|power| *power > 0.0)?;
+        let energy = if self.charging() {
+            self.energy_full? - self.energy?
+        } else {
+            self.energy?
+        };
+        Some(energy / power * 3600.0)
+    }
+}
+
+fn flag(set: bool) -> f64 {
+    if set {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+/// The values below `key`, and the status of each battery
+pub fn collect(sys: &Path, key: &str) -> (HashMap<Key, f64>, Vec<String>) {
+    let mut values: HashMap<Key, f64> = HashMap::new();
+    let mut batteries = Vec::new();
+    let mut adapters = Vec::new();
+    let mut supplies = std::fs::read_dir(sys.join("class/power_supply"))
+        .map(|entries| entries.flatten().map(|entry| entry.path()).collect())
+        .unwrap_or_else(|_| Vec::new());
+    supplies.sort();
+    for supply in supplies {
+        let name = sanitize(&supply.file_name().unwrap_or_default().to_string_lossy());
+        let kind = std::fs::read_to_string(supply.join("type")).unwrap_or_default();
+        match kind.trim() {
+            "Battery" => batteries.push(Battery::read(&supply, name)),
+            // Mains, USB or Wireless
+            _ => {
+                if let Some(online) = read_number(&supply.join("online")) {
+                    values.insert(format!("{}.{}.online", key, name).into(), online);
+                    if kind.trim() == "Mains" {
+                        adapters.push(online);
+                    }
+                }
+            }
+        }
+    }
+    if !adapters.is_empty() {
+        values.insert(
+            format!("{}.adapter_online", key).into(),
+            flag(adapters.iter().any(|online| *online > 0.0)),
+        );
+    }
+    let mut statuses = Vec::new();
+    for battery in &batteries {
+        statuses.push(format!("{}: {}", battery.name, battery.status));
+        let remaining = battery.remaining();
+        for (name, value) in [
+            ("capacity", battery.capacity),
+            ("charging", Some(flag(battery.charging()))),
+            ("power", battery.power),
+            ("energy", battery.energy),
+            ("energy_full", battery.energy_full),
+            ("health", battery.health()),
+            ("time_to_empty", remaining.filter(|_| battery.discharging())),
+            ("time_to_full", remaining.filter(|_| battery.charging())),
+        ] {
+            if let Some(value) = value {
+                values.insert(format!("{}.{}.{}", key, battery.name, name).into(), value);
+            }
+        }
+    }
+    // All batteries of the system together, like one
+    let system = batteries.iter().filter(|b| b.system).collect::<Vec<_>>();
+    if !system.is_empty() {
+        let sum = |value: fn(&Battery) -> Option<f64>| {
+            system.iter().map(|b| value(b)).sum::<Option<f64>>()
+        };
+        let (energy, energy_full, power) =
+            (sum(|b| b.energy), sum(|b| b.energy_full), sum(|b| b.power));
+        let capacity = match (energy, energy_full) {
+            (Some(energy), Some(full)) if full > 0.0 => Some(energy / full * 100.0),
+            _ => sum(|b| b.capacity).map(|sum| sum / system.len() as f64),
+        };
+        let discharging = system.iter().any(|b| b.discharging());
+        let time_to_empty = match (energy, power) {
+            (Some(energy), Some(power)) if discharging && power > 0.0 => {
+                Some(energy / power * 3600.0)
+            }
+            _ => None,
+        };
+        for (name, value) in [
+            ("capacity", capacity),
+            ("charging", Some(flag(system.iter().any(|b| b.charging())))),
+            ("power", power),
+            ("time_to_empty", time_to_empty),
+        ] {
+            if let Some(value) = value {
+                values.insert(format!("{}.{}", key, name).into(), value);
+            }
+        }
+    }
+    (values, statuses)
+}
+
+pub fn produce(key: &str) -> Result<ItemResult> {
+    let (values, statuses) = collect(Path::new("/sys"), key);
+    if values.is_empty() {
+        bail!("No batteries or power adapters found in /sys/class/power_supply");
+    }
+    Ok(ItemResult {
+        time: now(),
+        key: key.into(),
+        raw: statuses.join(", "),
+        values,
+        tags: BTreeMap::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::item::battery::collect;
+
+    fn write(root: &Path, path: &str, content: &str) {
+        let path = root.join("class/power_supply").join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+
+    #[test]
+    fn sysfs() {
+        let root = std::env::temp_dir().join(format!("antikoerper-battery-{}", std::process::id()));
+        write(&root, "AC/type", "Mains\n");
+        write(&root, "AC/online", "0\n");
+        // Reports energy and power
+        write(&root, "BAT0/type", "Battery\n");
+        write(&root, "BAT0/status", "Discharging\n");
+        write(&root, "BAT0/capacity", "50\n");
+        write(&root, "BAT0/energy_now", "20000000\n");
+        write(&root, "BAT0/energy_full", "40000000\n");
+        write(&root, "BAT0/energy_full_design", "50000000\n");
+        write(&root, "BAT0/power_now", "10000000\n");
+        // Reports charge and current
+        write(&root, "BAT1/type", "Battery\n");
+        write(&root, "BAT1/status", "Discharging\n");
+        write(&root, "BAT1/charge_now", "2000000\n");
+        write(&root, "BAT1/charge_full", "4000000\n");
+        write(&root, "BAT1/voltage_min_design", "10000000\n");
+        write(&root, "BAT1/voltage_now", "12500000\n");
+        write(&root, "BAT1/current_now", "800000\n");
+        write(&root, "hidpp_battery_0/type", "Battery\n");
+        write(&root, "hidpp_battery_0/scope", "Device\n");
+        write(&root, "hidpp_battery_0/status", "Discharging\n");
+        write(&root, "hidpp_battery_0/capacity", "5\n");
+        let (values, statuses) = collect(&root, "bat");
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(values["bat.adapter_online"], 0.0);
+        assert_eq!(values["bat.ac.online"], 0.0);
+        assert_eq!(values["bat.bat0.health"], 80.0);
+        assert_eq!(values["bat.bat0.power"], 10.0);
+        assert_eq!(values["bat.bat0.time_to_empty"], 7200.0);
+        assert!(!values.contains_key("bat.bat0.time_to_full"));
+        assert_eq!(values["bat.bat1.capacity"], 50.0);
+        assert_eq!(values["bat.bat1.energy"], 20.0);
+        assert_eq!(values["bat.bat1.power"], 10.0);
+        assert_eq!(values["bat.hidpp_battery_0.capacity"], 5.0);
+        assert_eq!(values["bat.capacity"], 50.0);
+        assert_eq!(values["bat.charging"], 0.0);
+        assert_eq!(values["bat.power"], 20.0);
+        assert_eq!(values["bat.time_to_empty"], 7200.0);
+        assert_eq!(statuses[0], "bat0: Discharging");
+    }
+}
@@ -0,0 +1,209 @@
+//! Expiry of the TLS certificate a server presents
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use rustls::client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier};
+use rustls::{ClientConfig, OwnedTrustAnchor, RootCertStore, ServerName};
+use serde::Deserialize;
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+
+use crate::item::asn1::tlv;
+use crate::item::{now, ItemResult};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Endpoint {
+    host: String,
+    #[serde(default = "port_default")]
+    port: u16,
+    /// Sent to the server and verified against the certificate, `host` if not given
+    server_name: Option<String>,
+    /// Seconds
+    #[serde(default = "timeout_default")]
+    timeout: u64,
+}
+
+fn port_default() -> u16 {
+    443
+}
+
+fn timeout_default() -> u64 {
+    10
+}
+
+/// The Mozilla root certificates, also used by the probe
+pub fn roots() -> &'static RootCertStore {
+    static ROOTS: OnceLock<RootCertStore> = OnceLock::new();
+    ROOTS.get_or_init(|| {
+        let mut roots = RootCertStore::empty();
+        roots.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|anchor| {
+            OwnedTrustAnchor::from_subject_spki_name_constraints(
+                anchor.subject,
+                anchor.spki,
+                anchor.name_constraints,
+            )
+        }));
+        roots
+    })
+}
+
+/// Accepts every certificate, so expired or untrusted ones can still be reported, and
+/// remembers whether the usual verification would have passed
+struct Recorder {
+    verifier: WebPkiVerifier,
+    verified: Mutex<Option<bool>>,
+}
+
+impl ServerCertVerifier for Recorder {
+    fn verify_server_cert(
+        &self,
+        end_entity: &rustls::Certificate,
+        intermediates: &[rustls::Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> std::result::Result<ServerCertVerified, rustls::Error> {
+        let verified = self.verifier.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            scts,
+            ocsp_response,
+            now,
+        );
+        *self.verified.lock().expect("verifier lock poisoned") = Some(verified.is_ok());
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+fn parse_time(tag: u8, time: &[u8]) -> Result<DateTime<Utc>> {
+    let time = std::str::from_utf8(time)?;
+    let time = match tag {
+        // UTCTime, two-digit years from 50 on are in the 20th century
+        0x17 if time.get(..2).is_some_and(|year| year >= "50") => format!("19{}", time),
+        0x17 => format!("20{}", time),
+        // GeneralizedTime
+        0x18 => time.to_owned(),
+        _ => bail!("Unexpected time type {}", tag),
+    };
+    Ok(NaiveDateTime::parse_from_str(&time, "%Y%m%d%H%M%SZ")?.and_utc())
+}
+
+/// The end of the validity period of a DER encoded X.509 certificate
+fn not_after(certificate: &[u8]) -> Result<DateTime<Utc>> {
+    let (_, certificate, _) = tlv(certificate)?;
+    let (_, mut fields, _) = tlv(certificate)?;
+    // The explicitly tagged version is optional
+    if fields.first() == Some(&0xa0) {
+        fields = tlv(fields)?.2;
+    }
+    // Serial number, signature algorithm and issuer come before the validity
+    for _ in 0..3 {
+        fields = tlv(fields)?.2;
+    }
+    let (_, validity, _) = tlv(fields)?;
+    let (_, _, validity) = tlv(validity)?;
+    let (tag, time, _) = tlv(validity)?;
+    parse_time(tag, time)
+}
+
+impl Endpoint {
+    /// `<key>` with `.days_left` of the server's certificate, `.chain_length` and `.valid`
+    pub async fn produce(&self, key: &str) -> Result<ItemResult> {
+        let server_name = self.server_name.as_deref().unwrap_or(&self.host);
+        let recorder = Arc::new(Recorder {
+            verifier: WebPkiVerifier::new(roots().clone(), None),
+            verified: Mutex::new(None),
+        });
+        let config = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_custom_certificate_verifier(recorder.clone())
+            .with_no_client_auth();
+        let name = ServerName::try_from(server_name)
+            .with_context(|| format!("Invalid server name {}", server_name))?;
+        let address = format!("{}:{}", self.host, self.port);
+        let handshake = async {
+            let stream = TcpStream::connect(&address)
+                .await
+                .with_context(|| format!("Failed connecting to {}", address))?;
+            TlsConnector::from(Arc::new(config))
+                .connect(name, stream)
+                .await
+                .with_context(|| format!("TLS handshake with {} failed", address))
+        };
+        let stream = tokio::time::timeout(Duration::from_secs(self.timeout), handshake)
+            .await
+            .map_err(|_| anyhow!("TLS handshake with {} timed out", address))??;
+        let chain = stream
+            .get_ref()
+            .1
+            .peer_certificates()
+            .ok_or_else(|| anyhow!("{} presented no certificate", address))?;
+        let leaf = chain
+            .first()
+            .ok_or_else(|| anyhow!("{} presented no certificate", address))?;
+        let expires = not_after(&leaf.0)
+            .with_context(|| format!("Failed parsing the certificate of {}", address))?;
+        let valid = recorder
+            .verified
+            .lock()
+            .expect("verifier lock poisoned")
+            .unwrap_or_default();
+        Ok(ItemResult {
+            time: now(),
+            key: key.into(),
+            raw: String::new(),
+            values: HashMap::from([
+                (
+                    format!("{}.days_left", key).into(),
+                    (expires - Utc::now()).num_seconds() as f64 / 86400.0,
+                ),
+                (format!("{}.chain_length", key).into(), chain.len() as f64),
+                (
+                    format!("{}.valid", key).into(),
+                    if valid { 1.0 } else { 0.0 },
+                ),
+            ]),
+            tags: BTreeMap::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::item::certificate::{not_after, parse_time};
+
+    #[test]
+    fn validity() {
+        assert_eq!(
+            parse_time(0x17, b"491231235959Z").unwrap().to_rfc3339(),
+            "2049-12-31T23:59:59+00:00"
+        );
+        assert_eq!(
+            parse_time(0x17, b"500101000000Z").unwrap().to_rfc3339(),
+            "1950-01-01T00:00:00+00:00"
+        );
+        // A certificate stripped down to the fields before the validity
+        let certificate = [
+            0x30, 0x2e, // Certificate
+            0x30, 0x2c, // TBSCertificate
+            0xa0, 0x03, 0x02, 0x01, 0x02, // version 3
+            0x02, 0x01, 0x01, // serial number
+            0x30, 0x00, // signature algorithm
+            0x30, 0x00, // issuer
+            0x30, 0x1e, // validity
+            0x17, 0x0d, b'2', b'4', b'0', b'1', b'0', b'1', b'0', b'0', b'0', b'0', b'0', b'0',
+            b'Z', 0x17, 0x0d, b'2', b'5', b'0', b'4', b'0', b'1', b'1', b'2', b'0', b'0', b'0',
+            b'0', b'Z',
+        ];
+        assert_eq!(
+            not_after(&certificate).unwrap().to_rfc3339(),
+            "2025-04-01T12:00:00+00:00"
+        );
+    }
+}
@@ -0,0 +1,163 @@
+//! Size, number and ages of the files in a directory, e.g. of a backup target or a spool
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::{Context, Result};
+use log::debug;
+use serde::{Deserialize, Deserializer};
+
+use crate::item::{now, ItemResult};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Directory {
+    path: PathBuf,
+    /// Also the files in subdirectories
+    #[serde(default = "recursive_default")]
+    recursive: bool,
+    /// Only files with a matching name are counted, e.g. `*.tar.gz`
+    #[serde(default, deserialize_with = "pattern")]
+    pattern: Option<glob::Pattern>,
+}
+
+fn recursive_default() -> bool {
+    true
+}
+
+fn pattern<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<glob::Pattern>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|pattern| glob::Pattern::new(&pattern).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+#[derive(Debug, Default)]
+struct Stats {
+    /// Bytes
+    size: u64,
+    files: u64,
+    directories: u64,
+    /// Entries that could not be read
+    unreadable: u64,
+    newest: Option<SystemTime>,
+    oldest: Option<SystemTime>,
+}
+
+impl Directory {
+    /// Symbolic links are not followed
+    fn walk(&self) -> Result<Stats> {
+        let mut stats = Stats::default();
+        let mut pending = vec![self.path.clone()];
+        let mut root = true;
+        while let Some(dir) = pending.pop() {
+            let entries = match std::fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(e) if root => {
+                    return Err(e).with_context(|| format!("Failed reading {}", dir.display()))
+                }
+                Err(e) => {
+                    debug!("Failed reading {}: {}", dir.display(), e);
+                    stats.unreadable += 1;
+                    continue;
+                }
+            };
+            root = false;
+            for entry in entries {
+                let Ok((entry, metadata)) = entry.and_then(|e| Ok((e.path(), e.metadata()?)))
+                else {
+                    stats.unreadable += 1;
+                    continue;
+                };
+                if metadata.is_dir() {
+                    stats.directories += 1;
+                    if self.recursive {
+                        pending.push(entry);
+                    }
+                } else if metadata.is_file() && self.matches(&entry) {
+                    stats.files += 1;
+                    stats.size += metadata.len();
+                    if let Ok(modified) = metadata.modified() {
+                        stats.newest = stats.newest.max(Some(modified));
+                        stats.oldest = Some(stats.oldest.map_or(modified, |o| o.min(modified)));
+                    }
+                }
+            }
+        }
+        Ok(stats)
+    }
+
+    fn matches(&self, file: &Path) -> bool {
+        let name = file.file_name().unwrap_or_default().to_string_lossy();
+        self.pattern
+            .as_ref()
+            .is_none_or(|pattern| pattern.matches(&name))
+    }
+
+    pub async fn produce(&self, key: &str) -> Result<ItemResult> {
+        let directory = self.clone();
+        // Large trees take a while
+        let stats = tokio::task::spawn_blocking(move || directory.walk()).await??;
+        let age = |time: Option<SystemTime>| {
+            time.map(|time| {
+                SystemTime::now()
+                    .duration_since(time)
+                    .unwrap_or_default()
+                    .as_secs_f64()
+            })
+        };
+        let values = [
+            ("size", Some(stats.size as f64)),
+            ("files", Some(stats.files as f64)),
+            ("directories", Some(stats.directories as f64)),
+            ("unreadable", Some(stats.unreadable as f64)),
+            ("newest_age", age(stats.newest)),
+            ("oldest_age", age(stats.oldest)),
+        ];
+        Ok(ItemResult {
+            time: now(),
+            key: key.into(),
+            raw: self.path.display().to_string(),
+            values: values
+                .into_iter()
+                .filter_map(|(name, value)| Some((format!("{}.{}", key, name).into(), value?)))
+                .collect::<HashMap<_, _>>(),
+            tags: BTreeMap::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use crate::item::directory::Directory;
+
+    #[test]
+    fn walk() {
+        let dir = std::env::temp_dir().join(format!("antikoerper-dir-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("daily")).unwrap();
+        std::fs::write(dir.join("a.tar.gz"), "12345").unwrap();
+        std::fs::write(dir.join("notes.txt"), "1").unwrap();
+        std::fs::write(dir.join("daily/b.tar.gz"), "123").unwrap();
+        let old = SystemTime::now() - Duration::from_secs(3600);
+        std::fs::File::options()
+            .write(true)
+            .open(dir.join("daily/b.tar.gz"))
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+
+        let directory: Directory =
+            toml::from_str(&format!("path = {:?}\npattern = \"*.tar.gz\"", dir)).unwrap();
+        let stats = directory.walk().unwrap();
+        assert_eq!((stats.files, stats.size, stats.directories), (2, 8, 1));
+        assert_eq!(stats.oldest, Some(old));
+        assert!(stats.newest > Some(old));
+
+        let flat: Directory =
+            toml::from_str(&format!("path = {:?}\nrecursive = false", dir)).unwrap();
+        assert_eq!(flat.walk().unwrap().files, 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(flat.walk().is_err());
+    }
+}
@@ -0,0 +1,272 @@
+//! Resolving a name against a resolver, for monitoring local resolvers and DNS health
+
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::{anyhow, bail, Context, Result};
+use log::warn;
+use serde::Deserialize;
+use tokio::net::UdpSocket;
+
+use crate::item::{now, ItemResult};
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum RecordType {
+    #[default]
+    A,
+    Aaaa,
+}
+
+impl RecordType {
+    fn code(self) -> u16 {
+        match self {
+            RecordType::A => 1,
+            RecordType::Aaaa => 28,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Query {
+    name: String,
+    #[serde(default)]
+    record_type: RecordType,
+    /// Address of the resolver, optionally with port, the first nameserver of
+    /// /etc/resolv.conf if not given
+    resolver: Option<String>,
+    /// Addresses of which at least one should be in the answer
+    #[serde(default)]
+    expected: Vec<IpAddr>,
+    /// Seconds
+    #[serde(default = "timeout_default")]
+    timeout: u64,
+}
+
+fn timeout_default() -> u64 {
+    10
+}
+
+/// What the resolver answered
+#[derive(Debug, PartialEq)]
+struct Answer {
+    rcode: u8,
+    count: u16,
+    addresses: Vec<IpAddr>,
+}
+
+fn system_resolver() -> Result<String> {
+    let conf =
+        std::fs::read_to_string("/etc/resolv.conf").context("Failed reading /etc/resolv.conf")?;
+    conf.lines()
+        .filter_map(|line| line.trim().strip_prefix("nameserver"))
+        .map(str::trim)
+        .next()
+        .map(str::to_owned)
+        .ok_or_else(|| anyhow!("No nameserver in /etc/resolv.conf"))
+}
+
+fn resolver_address(resolver: &str) -> Result<SocketAddr> {
+    resolver
+        .parse::<SocketAddr>()
+        .or_else(|_| resolver.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, 53)))
+        .with_context(|| format!("Invalid resolver address {}", resolver))
+}
+
+/// A recursive query for one record of `name`
+fn encode_query(id: u16, name: &str, record_type: RecordType) -> Result<Vec<u8>> {
+    let mut query = Vec::with_capacity(name.len() + 18);
+    query.extend_from_slice(&id.to_be_bytes());
+    // Recursion desired, one question
+    query.extend_from_slice(&[0x01, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0]);
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            bail!("Invalid name {}", name);
+        }
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&record_type.code().to_be_bytes());
+    query.extend_from_slice(&1u16.to_be_bytes());
+    Ok(query)
+}
+
+/// The position after a possibly compressed name starting at `position`
+fn skip_name(message: &[u8], mut position: usize) -> Result<usize> {
+    loop {
+        let length = *message.get(position).ok_or_else(|| anyhow!("Truncated"))?;
+        match length {
+            0 => return Ok(position + 1),
+            // A pointer ends the name
+            l if l & 0xc0 == 0xc0 => return Ok(position + 2),
+            l => position += 1 + l as usize,
+        }
+    }
+}
+
+fn u16_at(message: &[u8], position: usize) -> Result<u16> {
+    message
+        .get(position..position + 2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        .ok_or_else(|| anyhow!("Truncated"))
+}
+
+fn decode_answer(id: u16, record_type: RecordType, message: &[u8]) -> Result<Answer> {
+    if u16_at(message, 0)? != id {
+        bail!("Answer to another query");
+    }
+    let rcode = (u16_at(message, 2)? & 0x0f) as u8;
+    let questions = u16_at(message, 4)?;
+    let count = u16_at(message, 6)?;
+    let mut position = 12;
+    for _ in 0..questions {
+        // Type and class follow the name
+        position = skip_name(message, position)? + 4;
+    }
+    let mut addresses = Vec::new();
+    for _ in 0..count {
+        position = skip_name(message, position)?;
+        let kind = u16_at(message, position)?;
+        // Class and TTL come before the length of the data
+        let length = u16_at(message, position + 8)? as usize;
+        position += 10;
+        let data = message
+            .get(position..position + length)
+            .ok_or_else(|| anyhow!("Truncated"))?;
+        if kind == record_type.code() {
+            match data.len() {
+                4 => addresses.push(IpAddr::from(<[u8; 4]>::try_from(data)?)),
+                16 => addresses.push(IpAddr::from(<[u8; 16]>::try_from(data)?)),
+                _ => bail!("Unexpected address length {}", data.len()),
+            }
+        }
+        position += length;
+    }
+    Ok(Answer {
+        rcode,
+        count,
+        addresses,
+    })
+}
+
+impl Query {
+    /// Never fails, resolvers that do not answer are reported with `up` being 0
+    pub async fn produce(&self, key: &str) -> ItemResult {
+        let start = Instant::now();
+        let answer =
+            match tokio::time::timeout(Duration::from_secs(self.timeout), self.resolve()).await {
+                Ok(Ok(answer)) => Some(answer),
+                Ok(Err(e)) => {
+                    warn!("Resolving {} failed: {:#}", self.name, e);
+                    None
+                }
+                Err(_) => {
+                    warn!("Resolving {} timed out after {}s", self.name, self.timeout);
+                    None
+                }
+            };
+        let mut values = HashMap::new();
+        values.insert(
+            format!("{}.up", key).into(),
+            if answer.as_ref().is_some_and(|a| a.rcode == 0) {
+                1.0
+            } else {
+                0.0
+            },
+        );
+        if let Some(answer) = answer {
+            values.insert(
+                format!("{}.query_time", key).into(),
+                start.elapsed().as_secs_f64(),
+            );
+            values.insert(format!("{}.rcode", key).into(), answer.rcode as f64);
+            values.insert(format!("{}.answers", key).into(), answer.count as f64);
+            if !self.expected.is_empty() {
+                let matched = answer
+                    .addresses
+                    .iter()
+                    .any(|address| self.expected.contains(address));
+                values.insert(
+                    format!("{}.expected", key).into(),
+                    if matched { 1.0 } else { 0.0 },
+                );
+            }
+        }
+        ItemResult {
+            time: now(),
+            key: key.into(),
+            raw: String::new(),
+            values,
+            tags: BTreeMap::new(),
+        }
+    }
+
+    async fn resolve(&self) -> Result<Answer> {
+        let resolver = match &self.resolver {
+            Some(resolver) => resolver.clone(),
+            None => system_resolver()?,
+        };
+        let address = resolver_address(&resolver)?;
+        let local = match address {
+            SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+            SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+        };
+        let socket = UdpSocket::bind(local).await?;
+        socket
+            .connect(address)
+            .await
+            .with_context(|| format!("Failed connecting to {}", address))?;
+        let id = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .subsec_nanos() as u16;
+        socket
+            .send(&encode_query(id, &self.name, self.record_type)?)
+            .await
+            .with_context(|| format!("Failed sending the query to {}", address))?;
+        let mut buffer = [0; 4096];
+        loop {
+            let read = socket
+                .recv(&mut buffer)
+                .await
+                .with_context(|| format!("Failed receiving the answer of {}", address))?;
+            // Late answers to earlier queries are skipped
+            if u16_at(&buffer[..read], 0).is_ok_and(|other| other != id) {
+                continue;
+            }
+            return decode_answer(id, self.record_type, &buffer[..read])
+                .with_context(|| format!("Unexpected answer of {}", address));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use crate::item::dns::{decode_answer, encode_query, Answer, RecordType};
+
+    #[test]
+    fn answer() {
+        let query = encode_query(0x1234, "example.org.", RecordType::A).unwrap();
+        assert_eq!(&query[12..25], b"\x07example\x03org\x00");
+        let mut answer = query.clone();
+        // A response with two answers, the first is a CNAME
+        answer[2..4].copy_from_slice(&[0x81, 0x80]);
+        answer[6..8].copy_from_slice(&[0, 2]);
+        answer.extend_from_slice(&[0xc0, 0x0c, 0, 5, 0, 1, 0, 0, 0, 60, 0, 2, 0xc0, 0x0c]);
+        answer.extend_from_slice(&[0xc0, 0x0c, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 192, 0, 2, 1]);
+        assert_eq!(
+            decode_answer(0x1234, RecordType::A, &answer).unwrap(),
+            Answer {
+                rcode: 0,
+                count: 2,
+                addresses: vec!["192.0.2.1".parse::<IpAddr>().unwrap()],
+            }
+        );
+        assert!(decode_answer(0x4321, RecordType::A, &answer).is_err());
+        assert!(decode_answer(0x1234, RecordType::A, &answer[..answer.len() - 2]).is_err());
+    }
+}
@@ -0,0 +1,296 @@
+//! CPU, memory and network usage of running containers, from the API of Docker or Podman
+//! on their unix socket
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use log::warn;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+
+use crate::item::filesystems::percent;
+use crate::item::{now, ItemResult};
+use crate::keys::Key;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Engine {
+    /// `/run/podman/podman.sock` for Podman
+    #[serde(default = "socket_default")]
+    socket: PathBuf,
+    /// Seconds for all requests of a run
+    #[serde(default = "timeout_default")]
+    timeout: u64,
+    /// CPU time of the container and the system per container id at the last run
+    #[serde(skip)]
+    cpu: HashMap<String, (u64, u64)>,
+}
+
+fn socket_default() -> PathBuf {
+    PathBuf::from("/var/run/docker.sock")
+}
+
+fn timeout_default() -> u64 {
+    10
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Container {
+    id: String,
+    #[serde(default)]
+    names: Vec<String>,
+    #[serde(default)]
+    image: String,
+    #[serde(default)]
+    status: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Stats {
+    #[serde(default)]
+    cpu_stats: CpuStats,
+    #[serde(default)]
+    memory_stats: MemoryStats,
+    /// Missing for containers sharing the host's network
+    #[serde(default)]
+    networks: HashMap<String, Network>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct CpuStats {
+    #[serde(default)]
+    cpu_usage: CpuUsage,
+    #[serde(default)]
+    system_cpu_usage: u64,
+    #[serde(default)]
+    online_cpus: u64,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct CpuUsage {
+    #[serde(default)]
+    total_usage: u64,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct MemoryStats {
+    #[serde(default)]
+    usage: u64,
+    #[serde(default)]
+    limit: u64,
+    #[serde(default)]
+    stats: HashMap<String, u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Network {
+    rx_bytes: u64,
+    tx_bytes: u64,
+}
+
+/// A chunked transfer-encoded body
+fn dechunk(mut body: &[u8]) -> Result<Vec<u8>> {
+    let mut decoded = Vec::new();
+    loop {
+        let end = body
+            .windows(2)
+            .position(|w| w == b"\r\n")
+            .ok_or_else(|| anyhow!("Truncated chunk"))?;
+        let size = std::str::from_utf8(&body[..end])?;
+        let size = usize::from_str_radix(size.split(';').next().unwrap_or_default().trim(), 16)
+            .context("Invalid chunk size")?;
+        if size == 0 {
+            return Ok(decoded);
+        }
+        let chunk = body
+            .get(end + 2..end + 2 + size)
+            .ok_or_else(|| anyhow!("Truncated chunk"))?;
+        decoded.extend_from_slice(chunk);
+        body = body.get(end + 4 + size..).unwrap_or_default();
+    }
+}
+
+/// The body of a successful HTTP response
+fn body(response: &[u8]) -> Result<Vec<u8>> {
+    let end = response
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or_else(|| anyhow!("Incomplete response"))?;
+    let head = String::from_utf8_lossy(&response[..end]);
+    let mut lines = head.lines();
+    let status = lines.next().unwrap_or_default();
+    let body = &response[end + 4..];
+    if status.split_whitespace().nth(1) != Some("200") {
+        bail!(
+            "{}: {}",
+            status,
+            String::from_utf8_lossy(&body[..body.len().min(200)]).trim()
+        );
+    }
+    let chunked = lines.any(|line| {
+        line.split_once(':').is_some_and(|(name, value)| {
+            name.eq_ignore_ascii_case("transfer-encoding") && value.trim() == "chunked"
+        })
+    });
+    if chunked {
+        dechunk(body)
+    } else {
+        Ok(body.to_vec())
+    }
+}
+
+/// Values of a container, CPU usage from the second run on
+fn usage(key: &str, stats: &Stats, previous: Option<(u64, u64)>) -> HashMap<Key, f64> {
+    let mut values = HashMap::new();
+    let cpu = &stats.cpu_stats;
+    if let Some((container, system)) = previous {
+        let container = cpu.cpu_usage.total_usage.saturating_sub(container) as f64;
+        let system = cpu.system_cpu_usage.saturating_sub(system) as f64;
+        // Like `docker stats`, 100% per CPU
+        values.insert(
+            format!("{}.cpu_percent", key).into(),
+            percent(container, system) * cpu.online_cpus.max(1) as f64,
+        );
+    }
+    let memory = &stats.memory_stats;
+    // Like `docker stats`, without the page cache that can be reclaimed; the name
+    // differs between cgroup v1 and v2
+    let inactive = ["total_inactive_file", "inactive_file"]
+        .iter()
+        .find_map(|name| memory.stats.get(*name))
+        .copied()
+        .unwrap_or(0);
+    let used = memory.usage.saturating_sub(inactive) as f64;
+    values.insert(format!("{}.memory_usage", key).into(), used);
+    values.insert(format!("{}.memory_limit", key).into(), memory.limit as f64);
+    values.insert(
+        format!("{}.memory_percent", key).into(),
+        percent(used, memory.limit as f64),
+    );
+    let (rx, tx) = stats
+        .networks
+        .values()
+        .fold((0, 0), |(rx, tx), n| (rx + n.rx_bytes, tx + n.tx_bytes));
+    values.insert(format!("{}.net.rx_bytes", key).into(), rx as f64);
+    values.insert(format!("{}.net.tx_bytes", key).into(), tx as f64);
+    values
+}
+
+impl Engine {
+    async fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
+        let mut stream = UnixStream::connect(&self.socket)
+            .await
+            .with_context(|| format!("Failed connecting to {}", self.socket.display()))?;
+        // HTTP/1.0, so the response ends with the connection
+        stream
+            .write_all(format!("GET {} HTTP/1.0\r\nHost: localhost\r\n\r\n", path).as_bytes())
+            .await?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await?;
+        let body = body(&response).with_context(|| format!("Request of {} failed", path))?;
+        serde_json::from_slice(&body).with_context(|| format!("Unexpected response to {}", path))
+    }
+
+    async fn containers(&mut self, key: &str) -> Result<Vec<ItemResult>> {
+        let containers: Vec<Container> = self.get("/containers/json").await?;
+        let mut cpu = HashMap::new();
+        let mut results = Vec::new();
+        for container in containers {
+            let name = container
+                .names
+                .first()
+                .map(|name| name.trim_start_matches('/').to_owned())
+                .unwrap_or_else(|| container.id.chars().take(12).collect());
+            // The engine would otherwise take a second to sample CPU usage itself
+            let path = format!(
+                "/containers/{}/stats?stream=false&one-shot=true",
+                container.id
+            );
+            let stats: Stats = match self.get(&path).await {
+                Ok(stats) => stats,
+                // Stopped in the meantime
+                Err(e) => {
+                    warn!("item {}: no stats of container {}: {:#}", key, name, e);
+                    continue;
+                }
+            };
+            let key = format!("{}.{}", key, name.replace('.', "_"));
+            let values = usage(&key, &stats, self.cpu.get(&container.id).copied());
+            cpu.insert(
+                container.id.clone(),
+                (
+                    stats.cpu_stats.cpu_usage.total_usage,
+                    stats.cpu_stats.system_cpu_usage,
+                ),
+            );
+            results.push(ItemResult {
+                time: now(),
+                key: key.into(),
+                raw: container.status,
+                values,
+                tags: BTreeMap::from([
+                    (String::from("container"), name),
+                    (String::from("image"), container.image),
+                    (String::from("id"), container.id.chars().take(12).collect()),
+                ]),
+            });
+        }
+        // Removed containers are forgotten
+        self.cpu = cpu;
+        Ok(results)
+    }
+
+    /// One result per running container
+    pub async fn produce(&mut self, key: &str) -> Result<Vec<ItemResult>> {
+        let timeout = Duration::from_secs(self.timeout);
+        tokio::time::timeout(timeout, self.containers(key))
+            .await
+            .map_err(|_| {
+                anyhow!(
+                    "No stats from {} within {}s",
+                    self.socket.display(),
+                    self.timeout
+                )
+            })?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::item::docker::{body, usage, Stats};
+
+    #[test]
+    fn stats() {
+        let response = b"HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\
+            Transfer-Encoding: chunked\r\n\r\n5\r\n{\"cpu\r\n12\r\n_stats\":{}, \"x\":1}\r\n0\r\n\r\n";
+        assert_eq!(body(response).unwrap(), b"{\"cpu_stats\":{}, \"x\":1}");
+        assert!(
+            body(b"HTTP/1.0 404 Not Found\r\n\r\n{\"message\":\"no such container\"}")
+                .unwrap_err()
+                .to_string()
+                .contains("no such container")
+        );
+
+        let stats: Stats = serde_json::from_str(
+            r#"{
+                "cpu_stats": {"cpu_usage": {"total_usage": 3000}, "system_cpu_usage": 20000,
+                    "online_cpus": 4},
+                "memory_stats": {"usage": 600, "limit": 2000, "stats": {"inactive_file": 100}},
+                "networks": {"eth0": {"rx_bytes": 10, "tx_bytes": 20},
+                    "eth1": {"rx_bytes": 1, "tx_bytes": 2}}
+            }"#,
+        )
+        .unwrap();
+        let values = usage("c.web", &stats, Some((1000, 10000)));
+        assert_eq!(values["c.web.cpu_percent"], 80.0);
+        assert_eq!(values["c.web.memory_usage"], 500.0);
+        assert_eq!(values["c.web.memory_percent"], 25.0);
+        assert_eq!(values["c.web.net.rx_bytes"], 11.0);
+        assert_eq!(values["c.web.net.tx_bytes"], 22.0);
+        assert!(!usage("c.web", &stats, None).contains_key("c.web.cpu_percent"));
+    }
+}
@@ -0,0 +1,173 @@
+//! Space and inode usage of mounted filesystems, using statvfs
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+
+use crate::item::{now, ItemResult};
+
+struct Mount {
+    device: String,
+    path: PathBuf,
+    fstype: String,
+}
+
+/// /proc/mounts escapes whitespace in paths as octal, e.g. `\040` for a space
+fn unescape(s: &str) -> String {
+    let mut result = Vec::with_capacity(s.len());
+    let bytes = s.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\' && i + 4 <= bytes.len() {
+            let octal = std::str::from_utf8(&bytes[i + 1..i + 4]).unwrap_or_default();
+            if let Ok(c) = u8::from_str_radix(octal, 8) {
+                result.push(c);
+                i += 4;
+                continue;
+            }
+        }
+        result.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&result).into_owned()
+}
+
+fn parse_mounts(content: &str) -> Vec<Mount> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            Some(Mount {
+                device: unescape(fields.next()?),
+                path: PathBuf::from(unescape(fields.next()?)),
+                fstype: fields.next()?.to_owned(),
+            })
+        })
+        .collect()
+}
+
+/// Name of a mount point usable in keys, `/` is `root`, `/var/lib` is `var_lib`
+fn mount_name(path: &Path) -> String {
+    let name = path
+        .to_string_lossy()
+        .trim_matches('/')
+        .replace(['/', '.', ' '], "_");
+    if name.is_empty() {
+        String::from("root")
+    } else {
+        name
+    }
+}
+
+pub fn statvfs(path: &Path) -> Result<libc::statvfs> {
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    // SAFETY: statvfs is plain old data, an all-zero value is valid
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: the path is NUL-terminated and stat is valid for writes
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("statvfs of {} failed", path.display()));
+    }
+    Ok(stat)
+}
+
+pub fn percent(part: f64, total: f64) -> f64 {
+    if total > 0.0 {
+        part / total * 100.0
+    } else {
+        0.0
+    }
+}
+
+fn usage(key: &str, mount: &Mount, stat: &libc::statvfs) -> ItemResult {
+    let fragment = stat.f_frsize as f64;
+    let size = stat.f_blocks as f64 * fragment;
+    let free = stat.f_bfree as f64 * fragment;
+    let available = stat.f_bavail as f64 * fragment;
+    let used = size - free;
+    let inodes = stat.f_files as f64;
+    let inodes_free = stat.f_ffree as f64;
+    let inodes_used = inodes - inodes_free;
+    let key = format!("{}.{}", key, mount_name(&mount.path));
+    let values = [
+        ("size", size),
+        ("used", used),
+        ("available", available),
+        // Like df, relative to what is usable by unprivileged users
+        ("used_percent", percent(used, used + available)),
+        ("inodes", inodes),
+        ("inodes_used", inodes_used),
+        ("inodes_free", inodes_free),
+        ("inodes_used_percent", percent(inodes_used, inodes)),
+    ];
+    ItemResult {
+        time: now(),
+        values: values
+            .into_iter()
+            .map(|(name, value)| (format!("{}.{}", key, name).into(), value))
+            .collect::<HashMap<_, _>>(),
+        raw: mount.path.display().to_string(),
+        tags: BTreeMap::from([
+            (String::from("mount"), mount.path.display().to_string()),
+            (String::from("device"), mount.device.clone()),
+            (String::from("fstype"), mount.fstype.clone()),
+        ]),
+        key: key.into(),
+    }
+}
+
+/// One result per mount; all mounts with blocks if `mounts` is empty, which excludes
+/// pseudo filesystems like proc or sysfs
+pub async fn produce(key: &str, mounts: &[PathBuf]) -> Result<Vec<ItemResult>> {
+    let content = tokio::fs::read_to_string("/proc/mounts")
+        .await
+        .context("Failed reading /proc/mounts")?;
+    let key = key.to_owned();
+    let wanted = mounts.to_vec();
+    // statvfs blocks on unreachable network filesystems
+    tokio::task::spawn_blocking(move || {
+        let mut seen = HashSet::new();
+        let mut results = Vec::new();
+        for mount in parse_mounts(&content) {
+            if !wanted.is_empty() && !wanted.contains(&mount.path) {
+                continue;
+            }
+            // Mounted over, or bind mounts
+            if !seen.insert(mount.path.clone()) {
+                continue;
+            }
+            match statvfs(&mount.path) {
+                Ok(stat) if stat.f_blocks == 0 && wanted.is_empty() => (),
+                Ok(stat) => results.push(usage(&key, &mount, &stat)),
+                Err(e) if wanted.is_empty() => log::debug!("{:#}", e),
+                Err(e) => return Err(e),
+            }
+        }
+        if let Some(missing) = wanted.iter().find(|path| !seen.contains(*path)) {
+            bail!("{} is not mounted", missing.display());
+        }
+        Ok(results)
+    })
+    .await?
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::item::filesystems::{mount_name, parse_mounts};
+
+    #[test]
+    fn mounts() {
+        let mounts = parse_mounts(
+            "/dev/sda1 / ext4 rw,relatime 0 0\n/dev/sdb1 /mnt/my\\040disk vfat rw 0 0\n",
+        );
+        assert_eq!(mounts.len(), 2);
+        assert_eq!(mounts[1].path, Path::new("/mnt/my disk"));
+        assert_eq!(mount_name(&mounts[0].path), "root");
+        assert_eq!(mount_name(&mounts[1].path), "mnt_my_disk");
+    }
+}
@@ -0,0 +1,234 @@
+//! Utilization, memory, temperature and power of GPUs, of NVIDIA ones as told by
+//! nvidia-smi, which reads them with NVML, and of AMD ones from the amdgpu driver in sysfs
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use log::debug;
+use serde::Deserialize;
+
+use crate::item::thermal::{numbered, read_number};
+use crate::item::{now, ItemResult};
+use crate::keys::Key;
+use crate::priority::Priority;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Gpu {
+    /// Only run if installed, like with the NVIDIA driver
+    #[serde(default = "nvidia_smi_default")]
+    nvidia_smi: PathBuf,
+}
+
+fn nvidia_smi_default() -> PathBuf {
+    PathBuf::from("nvidia-smi")
+}
+
+/// Vendor ID of AMD in PCI
+const AMD: &str = "0x1002";
+
+/// Fields queried from nvidia-smi, after `index` and `name`, with the names used in keys
+/// and the factors to the units of amdgpu
+const NVIDIA_FIELDS: [(&str, &str, f64); 5] = [
+    ("utilization.gpu", "utilization", 1.0),
+    // MiB
+    ("memory.used", "memory_used", 1024.0 * 1024.0),
+    ("memory.total", "memory_total", 1024.0 * 1024.0),
+    ("temperature.gpu", "temperature", 1.0),
+    ("power.draw", "power", 1.0),
+];
+
+fn result(key: String, values: Vec<(&str, f64)>, vendor: &str, model: &str) -> ItemResult {
+    ItemResult {
+        time: now(),
+        raw: String::new(),
+        values: values
+            .into_iter()
+            .map(|(name, value)| (Key::from(format!("{}.{}", key, name)), value))
+            .collect(),
+        key: key.into(),
+        tags: BTreeMap::from([
+            (String::from("vendor"), vendor.to_owned()),
+            (String::from("model"), model.to_owned()),
+        ]),
+    }
+}
+
+/// The output of `nvidia-smi --format=csv,noheader,nounits`, one line per GPU, fields a
+/// GPU does not support are `[N/A]` or `[Not Supported]` and left out
+fn parse_nvidia(key: &str, csv: &str) -> Vec<ItemResult> {
+    csv.lines()
+        .filter_map(|line| {
+            let fields = line.split(',').map(str::trim).collect::<Vec<_>>();
+            let [index, model, readings @ ..] = fields.as_slice() else {
+                return None;
+            };
+            let values = NVIDIA_FIELDS
+                .iter()
+                .zip(readings)
+                .filter_map(|((_, name, factor), reading)| {
+                    Some((*name, reading.parse::<f64>().ok()? * factor))
+                })
+                .collect();
+            Some(result(
+                format!("{}.nvidia{}", key, index),
+                values,
+                "nvidia",
+                model,
+            ))
+        })
+        .collect()
+}
+
+/// The cards of the amdgpu driver below `sys`, e.g. `/sys`
+fn amdgpu(sys: &Path, key: &str) -> Vec<ItemResult> {
+    numbered(&sys.join("class/drm"), "card")
+        .into_iter()
+        .filter_map(|(n, card)| {
+            let device = card.join("device");
+            let vendor = std::fs::read_to_string(device.join("vendor")).ok()?;
+            if vendor.trim() != AMD {
+                return None;
+            }
+            // Percent and bytes
+            let mut values = [
+                ("utilization", "gpu_busy_percent"),
+                ("memory_used", "mem_info_vram_used"),
+                ("memory_total", "mem_info_vram_total"),
+            ]
+            .into_iter()
+            .filter_map(|(name, file)| Some((name, read_number(&device.join(file))?)))
+            .collect::<Vec<_>>();
+            if let Some((_, hwmon)) = numbered(&device.join("hwmon"), "hwmon").first() {
+                // Millidegrees Celsius of the edge sensor
+                if let Some(temperature) = read_number(&hwmon.join("temp1_input")) {
+                    values.push(("temperature", temperature / 1000.0));
+                }
+                // Microwatts, averaged by older cards
+                if let Some(power) = read_number(&hwmon.join("power1_average"))
+                    .or_else(|| read_number(&hwmon.join("power1_input")))
+                {
+                    values.push(("power", power / 1_000_000.0));
+                }
+            }
+            let model = std::fs::read_to_string(device.join("product_name"))
+                .map(|name| name.trim().to_owned())
+                .unwrap_or_else(|_| String::from("amdgpu"));
+            Some(result(
+                format!("{}.amdgpu{}", key, n),
+                values,
+                "amd",
+                &model,
+            ))
+        })
+        .collect()
+}
+
+impl Gpu {
+    /// None without nvidia-smi
+    async fn nvidia(&self, key: &str, priority: Priority) -> Result<Vec<ItemResult>> {
+        let query = ["index", "name"]
+            .into_iter()
+            .chain(NVIDIA_FIELDS.iter().map(|(field, _, _)| *field))
+            .collect::<Vec<_>>()
+            .join(",");
+        let mut command = tokio::process::Command::new(&self.nvidia_smi);
+        priority.command(&mut command);
+        let output = match command
+            .arg(format!("--query-gpu={}", query))
+            .arg("--format=csv,noheader,nounits")
+            .kill_on_drop(true)
+            .output()
+            .await
+        {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                debug!("item {}: no {}", key, self.nvidia_smi.display());
+                return Ok(Vec::new());
+            }
+            output => {
+                output.with_context(|| format!("Failed running {}", self.nvidia_smi.display()))?
+            }
+        };
+        if !output.status.success() {
+            bail!(
+                "{} failed: {}",
+                self.nvidia_smi.display(),
+                String::from_utf8_lossy(&output.stdout).trim()
+            );
+        }
+        Ok(parse_nvidia(key, &String::from_utf8_lossy(&output.stdout)))
+    }
+
+    /// One result per GPU
+    pub async fn produce(&self, key: &str, priority: Priority) -> Result<Vec<ItemResult>> {
+        let mut results = self.nvidia(key, priority).await?;
+        results.extend(amdgpu(Path::new("/sys"), key));
+        if results.is_empty() {
+            bail!("No NVIDIA or AMD GPUs found");
+        }
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::Path;
+
+    use crate::item::gpu::{amdgpu, parse_nvidia};
+    use crate::keys::Key;
+
+    fn write(root: &Path, path: &str, content: &str) {
+        let path = root.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+
+    #[test]
+    fn readings() {
+        let nvidia = parse_nvidia(
+            "gpu",
+            "0, NVIDIA GeForce RTX 3080, 42, 2048, 10240, 65, 220.50\n\
+             1, Tesla K80, 0, 0, 11441, 30, [N/A]\n",
+        );
+        assert_eq!(nvidia.len(), 2);
+        assert_eq!(&*nvidia[0].key, "gpu.nvidia0");
+        assert_eq!(nvidia[0].tags["model"], "NVIDIA GeForce RTX 3080");
+        assert_eq!(nvidia[0].values["gpu.nvidia0.memory_used"], 2147483648.0);
+        assert_eq!(nvidia[0].values["gpu.nvidia0.power"], 220.5);
+        assert_eq!(nvidia[1].values.len(), 4);
+
+        let root = std::env::temp_dir().join(format!("antikoerper-gpu-{}", std::process::id()));
+        write(&root, "class/drm/card0/device/vendor", "0x8086\n");
+        write(&root, "class/drm/card1/device/vendor", "0x1002\n");
+        write(&root, "class/drm/card1/device/gpu_busy_percent", "17\n");
+        write(
+            &root,
+            "class/drm/card1/device/mem_info_vram_used",
+            "1073741824\n",
+        );
+        write(
+            &root,
+            "class/drm/card1/device/hwmon/hwmon4/temp1_input",
+            "48000\n",
+        );
+        write(
+            &root,
+            "class/drm/card1/device/hwmon/hwmon4/power1_average",
+            "35000000\n",
+        );
+        write(&root, "class/drm/card1-DP-1/status", "connected\n");
+        let amd = amdgpu(&root, "gpu");
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(amd.len(), 1);
+        assert_eq!(
+            amd[0].values,
+            HashMap::from([
+                (Key::from("gpu.amdgpu1.utilization"), 17.0),
+                (Key::from("gpu.amdgpu1.memory_used"), 1073741824.0),
+                (Key::from("gpu.amdgpu1.temperature"), 48.0),
+                (Key::from("gpu.amdgpu1.power"), 35.0),
+            ])
+        );
+    }
+}
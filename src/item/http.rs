@@ -0,0 +1,85 @@
+//! Fetching a URL, the response body is passed to the digests
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+
+use crate::secret::Secret;
+
+/// Response bodies of failed requests are cut to this many characters in errors
+const ERROR_BODY_LENGTH: usize = 200;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Auth {
+    Basic { username: String, password: Secret },
+    Bearer { token: Secret },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Request {
+    url: String,
+    #[serde(default = "method_default")]
+    method: String,
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+    body: Option<String>,
+    auth: Option<Auth>,
+    /// Seconds
+    #[serde(default = "timeout_default")]
+    timeout: u64,
+    #[serde(skip)]
+    client: reqwest::Client,
+}
+
+fn method_default() -> String {
+    String::from("GET")
+}
+
+fn timeout_default() -> u64 {
+    10
+}
+
+impl Request {
+    pub async fn fetch(&self) -> Result<String> {
+        let method = reqwest::Method::from_bytes(self.method.to_uppercase().as_bytes())
+            .with_context(|| format!("Invalid HTTP method {}", self.method))?;
+        let mut request = self
+            .client
+            .request(method, &self.url)
+            .timeout(Duration::from_secs(self.timeout));
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+        if let Some(body) = &self.body {
+            request = request.body(body.clone());
+        }
+        request = match &self.auth {
+            Some(Auth::Basic { username, password }) => {
+                request.basic_auth(username, Some(password.expose()))
+            }
+            Some(Auth::Bearer { token }) => request.bearer_auth(token.expose()),
+            None => request,
+        };
+        let response = request
+            .send()
+            .await
+            .with_context(|| format!("Failed fetching {}", self.url))?;
+        let status = response.status();
+        let body = response
+            .text()
+            .await
+            .with_context(|| format!("Failed reading the response of {}", self.url))?;
+        if !status.is_success() {
+            bail!(
+                "{} responded with {}: {}",
+                self.url,
+                status,
+                body.chars().take(ERROR_BODY_LENGTH).collect::<String>()
+            );
+        }
+        Ok(body)
+    }
+}
@@ -0,0 +1,146 @@
+//! Temperatures, fan speeds, voltages, currents and power of the hardware monitoring chips
+//! in sysfs, what `sensors` of lm-sensors shows
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+
+use anyhow::{bail, Result};
+
+use crate::item::thermal::{numbered, read_number};
+use crate::item::{now, ItemResult};
+use crate::keys::Key;
+
+/// Prefix of the files, the name used in keys, and the divisor of the file's unit
+const SENSORS: [(&str, &str, f64); 5] = [
+    // millidegrees Celsius to °C
+    ("temp", "temperature", 1000.0),
+    // RPM
+    ("fan", "fan", 1.0),
+    // millivolts to V
+    ("in", "voltage", 1000.0),
+    // milliamperes to A
+    ("curr", "current", 1000.0),
+    // microwatts to W
+    ("power", "power", 1_000_000.0),
+];
+
+/// Lowercase, with other characters than letters and digits replaced by `_`
+pub fn sanitize(name: &str) -> String {
+    name.trim()
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+/// The sensors of a chip, `<kind>.<label>` with the file's name like `temp1` if unlabeled
+fn sensors(chip: &Path) -> BTreeMap<String, f64> {
+    let mut sensors = BTreeMap::new();
+    let Ok(entries) = std::fs::read_dir(chip) else {
+        return sensors;
+    };
+    for entry in entries.flatten() {
+        let file = entry.file_name().to_string_lossy().into_owned();
+        let Some(sensor) = file.strip_suffix("_input") else {
+            continue;
+        };
+        let Some((name, divisor)) = SENSORS.iter().find_map(|(prefix, name, divisor)| {
+            let n = sensor.strip_prefix(prefix)?;
+            n.parse::<u32>().is_ok().then_some((name, divisor))
+        }) else {
+            continue;
+        };
+        // Sensors without a reading, e.g. an unconnected fan header, fail to be read
+        let Some(value) = read_number(&entry.path()) else {
+            continue;
+        };
+        let label = std::fs::read_to_string(chip.join(format!("{}_label", sensor)))
+            .map(|label| sanitize(&label))
+            .ok()
+            .filter(|label| !label.is_empty())
+            .unwrap_or_else(|| sensor.to_owned());
+        sensors.insert(format!("{}.{}", name, label), value / divisor);
+    }
+    sensors
+}
+
+pub fn collect(sys: &Path, key: &str, chips: &[String]) -> HashMap<Key, f64> {
+    let mut values = HashMap::new();
+    let mut names = HashSet::new();
+    for (n, chip) in numbered(&sys.join("class/hwmon"), "hwmon") {
+        let name = std::fs::read_to_string(chip.join("name"))
+            .map(|name| sanitize(&name))
+            .unwrap_or_default();
+        if !chips.is_empty() && !chips.iter().any(|c| sanitize(c) == name) {
+            continue;
+        }
+        // Chips like nvme repeat for every drive
+        let name = if name.is_empty() || !names.insert(name.clone()) {
+            format!("{}_{}", name, n).trim_start_matches('_').to_owned()
+        } else {
+            name
+        };
+        // Older drivers keep the sensors with the device
+        let mut readings = sensors(&chip.join("device"));
+        readings.extend(sensors(&chip));
+        for (sensor, value) in readings {
+            values.insert(format!("{}.{}.{}", key, name, sensor).into(), value);
+        }
+    }
+    values
+}
+
+pub fn produce(key: &str, chips: &[String]) -> Result<ItemResult> {
+    let values = collect(Path::new("/sys"), key, chips);
+    if values.is_empty() {
+        bail!("No hardware monitoring sensors found in /sys/class/hwmon");
+    }
+    Ok(ItemResult {
+        time: now(),
+        key: key.into(),
+        raw: String::new(),
+        values,
+        tags: BTreeMap::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::item::hwmon::collect;
+
+    fn write(root: &Path, path: &str, content: &str) {
+        let path = root.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+
+    #[test]
+    fn sysfs() {
+        let root = std::env::temp_dir().join(format!("antikoerper-hwmon-{}", std::process::id()));
+        write(&root, "class/hwmon/hwmon0/name", "coretemp\n");
+        write(&root, "class/hwmon/hwmon0/temp1_input", "52000\n");
+        write(&root, "class/hwmon/hwmon0/temp1_label", "Package id 0\n");
+        write(&root, "class/hwmon/hwmon0/temp1_crit", "100000\n");
+        write(&root, "class/hwmon/hwmon1/name", "nct6775\n");
+        write(&root, "class/hwmon/hwmon1/fan2_input", "1150\n");
+        write(&root, "class/hwmon/hwmon1/in0_input", "1208\n");
+        write(&root, "class/hwmon/hwmon1/power1_input", "15500000\n");
+        write(&root, "class/hwmon/hwmon2/name", "nvme\n");
+        write(&root, "class/hwmon/hwmon2/device/temp1_input", "38850\n");
+        write(&root, "class/hwmon/hwmon3/name", "nvme\n");
+        write(&root, "class/hwmon/hwmon3/temp1_input", "41850\n");
+        let values = collect(&root, "hw", &[]);
+        let only = collect(&root, "hw", &[String::from("nvme")]);
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(values.len(), 6);
+        assert_eq!(values["hw.coretemp.temperature.package_id_0"], 52.0);
+        assert_eq!(values["hw.nct6775.fan.fan2"], 1150.0);
+        assert_eq!(values["hw.nct6775.voltage.in0"], 1.208);
+        assert_eq!(values["hw.nct6775.power.power1"], 15.5);
+        assert_eq!(values["hw.nvme.temperature.temp1"], 38.85);
+        assert_eq!(values["hw.nvme_3.temperature.temp1"], 41.85);
+        assert_eq!(only.len(), 2);
+    }
+}
@@ -0,0 +1,100 @@
+//! Results local programs write as JSON lines to a unix socket
+
+use std::collections::{BTreeMap, HashMap};
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use tokio::net::UnixListener;
+
+use crate::item::{now, ItemResult};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Socket {
+    pub path: PathBuf,
+    /// Permissions of the socket, e.g. `0o660` so a group can write results
+    mode: Option<u32>,
+}
+
+impl Socket {
+    pub fn bind(&self) -> Result<UnixListener> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed creating {}", parent.display()))?;
+        }
+        // A socket left behind by a previous run would make binding fail
+        let _ = std::fs::remove_file(&self.path);
+        let listener = UnixListener::bind(&self.path)
+            .with_context(|| format!("Failed binding {}", self.path.display()))?;
+        if let Some(mode) = self.mode {
+            std::fs::set_permissions(&self.path, std::fs::Permissions::from_mode(mode))
+                .with_context(|| format!("Failed changing the mode of {}", self.path.display()))?;
+        }
+        Ok(listener)
+    }
+}
+
+/// A result as written by a program, names relative to the item's key
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Line {
+    /// Below the item's key, the item's key itself if missing
+    key: Option<String>,
+    #[serde(default)]
+    values: HashMap<String, f64>,
+    #[serde(default)]
+    raw: String,
+    #[serde(default)]
+    tags: BTreeMap<String, String>,
+    /// Milliseconds since the UNIX epoch, the time received if missing
+    time: Option<u64>,
+}
+
+/// The result of a line, `<key>.<line key>` with the values `<key>.<line key>.<name>`
+pub fn result(key: &str, line: &str) -> Result<ItemResult> {
+    let line: Line = serde_json::from_str(line)?;
+    let key = match line.key {
+        Some(name) => format!("{}.{}", key, name),
+        None => key.to_owned(),
+    };
+    Ok(ItemResult {
+        time: line.time.map(Duration::from_millis).unwrap_or_else(now),
+        values: line
+            .values
+            .into_iter()
+            .map(|(name, value)| (format!("{}.{}", key, name).into(), value))
+            .collect(),
+        key: key.into(),
+        raw: line.raw,
+        tags: line.tags,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::item::ingest::result;
+
+    #[test]
+    fn lines() {
+        let r = result(
+            "apps",
+            r#"{"key": "backup", "values": {"duration": 12.5}, "tags": {"host": "nas"}, "time": 1700000000000}"#,
+        )
+        .unwrap();
+        assert_eq!(&*r.key, "apps.backup");
+        assert_eq!(r.values["apps.backup.duration"], 12.5);
+        assert_eq!(r.tags["host"], "nas");
+        assert_eq!(r.time, Duration::from_secs(1700000000));
+
+        let r = result("apps", r#"{"raw": "started"}"#).unwrap();
+        assert_eq!(&*r.key, "apps");
+        assert_eq!(r.raw, "started");
+        assert!(r.values.is_empty());
+        assert!(result("apps", r#"{"value": 1}"#).is_err());
+        assert!(result("apps", "backup 12.5").is_err());
+    }
+}
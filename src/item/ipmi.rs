@@ -0,0 +1,160 @@
+//! Sensors of the baseboard management controller of servers, read with ipmitool, either
+//! of this host or of a remote one over the network
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+
+use crate::item::hwmon::sanitize;
+use crate::item::{now, ItemResult};
+use crate::keys::Key;
+use crate::priority::Priority;
+use crate::secret::Secret;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Ipmi {
+    /// The BMC to ask over the network with the lanplus interface, the local one if not
+    /// given
+    host: Option<String>,
+    user: Option<String>,
+    password: Option<Secret>,
+    #[serde(default = "path_default")]
+    path: PathBuf,
+}
+
+fn path_default() -> PathBuf {
+    PathBuf::from("ipmitool")
+}
+
+/// Units of the readings and the names used in keys
+const UNITS: [(&str, &str); 5] = [
+    ("degrees C", "temperature"),
+    ("RPM", "fan"),
+    ("Volts", "voltage"),
+    ("Amps", "current"),
+    ("Watts", "power"),
+];
+
+/// Entity ID of power supplies
+const POWER_SUPPLY: &str = "10";
+
+/// States of power supplies telling they do not work
+const PSU_FAILURES: [&str; 4] = ["failure", "ac lost", "config error", "out-of-range"];
+
+/// The values, and the sensors in a bad state with their readings, from the output of
+/// `ipmitool sdr elist`, lines like `CPU Temp | 01h | ok | 3.1 | 45 degrees C`
+fn parse(key: &str, sdr: &str) -> (HashMap<Key, f64>, Vec<String>) {
+    let mut values = HashMap::new();
+    let mut failing = Vec::new();
+    let mut unhealthy = 0;
+    for line in sdr.lines() {
+        let fields = line.split('|').map(str::trim).collect::<Vec<_>>();
+        let [name, _, status, entity, reading] = fields.as_slice() else {
+            continue;
+        };
+        let sensor = sanitize(name);
+        // Non-critical, critical or non-recoverable, `ns` is no reading
+        if matches!(*status, "nc" | "cr" | "nr") {
+            unhealthy += 1;
+            failing.push(format!("{}: {}", name, reading));
+        }
+        let measured = reading.split_once(' ').and_then(|(number, unit)| {
+            let (_, kind) = UNITS.iter().find(|(u, _)| *u == unit)?;
+            Some((kind, number.parse::<f64>().ok()?))
+        });
+        if let Some((kind, number)) = measured {
+            values.insert(format!("{}.{}.{}", key, kind, sensor).into(), number);
+        } else if entity.split('.').next() == Some(POWER_SUPPLY)
+            && !reading.is_empty()
+            && *reading != "No Reading"
+        {
+            // The states asserted, e.g. `Presence detected, Power Supply AC lost`
+            let lower = reading.to_lowercase();
+            let ok = !PSU_FAILURES.iter().any(|f| lower.contains(f));
+            if !ok && *status == "ok" {
+                failing.push(format!("{}: {}", name, reading));
+            }
+            values.insert(
+                format!("{}.psu.{}", key, sensor).into(),
+                if ok { 1.0 } else { 0.0 },
+            );
+        }
+    }
+    values.insert(format!("{}.unhealthy", key).into(), unhealthy as f64);
+    (values, failing)
+}
+
+impl Ipmi {
+    pub async fn produce(&self, key: &str, priority: Priority) -> Result<ItemResult> {
+        let mut command = tokio::process::Command::new(&self.path);
+        priority.command(&mut command);
+        if let Some(host) = &self.host {
+            command.args(["-I", "lanplus", "-H", host]);
+            if let Some(user) = &self.user {
+                command.args(["-U", user]);
+            }
+            // From the environment, not to show it in the process list
+            if let Some(password) = &self.password {
+                command.arg("-E").env("IPMI_PASSWORD", password.expose());
+            }
+        }
+        let output = command
+            .args(["sdr", "elist"])
+            .kill_on_drop(true)
+            .output()
+            .await
+            .with_context(|| format!("Failed running {}", self.path.display()))?;
+        if !output.status.success() {
+            bail!(
+                "{} failed: {}",
+                self.path.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        let (values, failing) = parse(key, &String::from_utf8_lossy(&output.stdout));
+        Ok(ItemResult {
+            time: now(),
+            key: key.into(),
+            raw: failing.join(", "),
+            values,
+            tags: BTreeMap::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::item::ipmi::parse;
+
+    #[test]
+    fn sdr() {
+        let sdr = "CPU1 Temp        | 01h | ok  |  3.1 | 45 degrees C
+System Temp      | 0Bh | nc  |  7.1 | 71 degrees C
+FAN1             | 41h | ok  | 29.1 | 3600 RPM
+FAN2             | 42h | ns  | 29.2 | No Reading
+12V              | 30h | ok  |  7.1 | 12.19 Volts
+PS1 Status       | C8h | ok  | 10.1 | Presence detected
+PS2 Status       | C9h | ok  | 10.2 | Presence detected, Power Supply AC lost
+PS1 Input Power  | 70h | ok  | 10.1 | 120 Watts
+Chassis Intru    | AAh | ok  | 23.1 |
+";
+        let (values, failing) = parse("bmc", sdr);
+        assert_eq!(values.len(), 8);
+        assert_eq!(values["bmc.temperature.cpu1_temp"], 45.0);
+        assert_eq!(values["bmc.fan.fan1"], 3600.0);
+        assert_eq!(values["bmc.voltage.12v"], 12.19);
+        assert_eq!(values["bmc.power.ps1_input_power"], 120.0);
+        assert_eq!(values["bmc.psu.ps1_status"], 1.0);
+        assert_eq!(values["bmc.psu.ps2_status"], 0.0);
+        assert_eq!(values["bmc.unhealthy"], 1.0);
+        assert_eq!(
+            failing,
+            [
+                "System Temp: 71 degrees C",
+                "PS2 Status: Presence detected, Power Supply AC lost"
+            ]
+        );
+    }
+}
@@ -0,0 +1,173 @@
+//! Journal entries matching a filter since the last run, read with journalctl
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::item::{now, ItemResult};
+use crate::priority::Priority;
+use crate::secret::Secret;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Query {
+    /// Entries of any of these units, all if empty
+    #[serde(default)]
+    units: Vec<String>,
+    /// This priority and more severe, e.g. `err` or `warning`
+    priority: Option<String>,
+    /// Matched against the message
+    #[serde(default, with = "serde_regex")]
+    regex: Option<Regex>,
+    /// The user's journal instead of the system's
+    #[serde(default)]
+    user: bool,
+    #[serde(default = "path_default")]
+    path: PathBuf,
+    #[serde(skip)]
+    position: Option<Position>,
+}
+
+fn path_default() -> PathBuf {
+    PathBuf::from("journalctl")
+}
+
+/// Where the next run continues
+#[derive(Debug, Clone, PartialEq)]
+enum Position {
+    /// Seconds since the epoch, until an entry was seen
+    Since(u64),
+    Cursor(String),
+}
+
+/// Matching entries, the cursor of the last entry and the last matching message
+fn count(output: &str, regex: Option<&Regex>) -> Result<(u64, Option<String>, Option<String>)> {
+    let mut count = 0;
+    let mut cursor = None;
+    let mut last = None;
+    for line in output.lines().filter(|line| !line.trim().is_empty()) {
+        let entry: HashMap<String, serde_json::Value> =
+            serde_json::from_str(line).context("Unexpected output of journalctl")?;
+        if let Some(c) = entry.get("__CURSOR").and_then(|c| c.as_str()) {
+            cursor = Some(c.to_owned());
+        }
+        let message = match entry.get("MESSAGE") {
+            Some(serde_json::Value::String(message)) => message.clone(),
+            // Messages that are not valid UTF-8 are arrays of bytes
+            Some(serde_json::Value::Array(bytes)) => String::from_utf8_lossy(
+                &bytes
+                    .iter()
+                    .filter_map(|b| b.as_u64().map(|b| b as u8))
+                    .collect::<Vec<_>>(),
+            )
+            .into_owned(),
+            _ => String::new(),
+        };
+        if regex.is_none_or(|regex| regex.is_match(&message)) {
+            count += 1;
+            last = Some(message);
+        }
+    }
+    Ok((count, cursor, last))
+}
+
+impl Query {
+    fn args(&self, position: &Position) -> Vec<String> {
+        let mut args = vec![
+            String::from("--output=json"),
+            String::from("--output-fields=MESSAGE"),
+            String::from("--no-pager"),
+            String::from("--quiet"),
+        ];
+        if self.user {
+            args.push(String::from("--user"));
+        }
+        for unit in &self.units {
+            args.push(format!("--unit={}", unit));
+        }
+        if let Some(priority) = &self.priority {
+            args.push(format!("--priority={}", priority));
+        }
+        args.push(match position {
+            Position::Since(seconds) => format!("--since=@{}", seconds),
+            Position::Cursor(cursor) => format!("--after-cursor={}", cursor),
+        });
+        args
+    }
+
+    /// `<key>.count`, 0 on the first run, which only remembers where to start
+    pub async fn produce(
+        &mut self,
+        key: &str,
+        priority: Priority,
+        env: &BTreeMap<String, Secret>,
+    ) -> Result<ItemResult> {
+        let (matched, raw) = match self.position.clone() {
+            None => {
+                self.position = Some(Position::Since(now().as_secs()));
+                (0, String::new())
+            }
+            Some(position) => {
+                let args = self.args(&position);
+                let mut command = tokio::process::Command::new(&self.path);
+                priority.command(&mut command);
+                let output = command
+                    .args(&args)
+                    .envs(env.iter().map(|(key, value)| (key, value.expose())))
+                    .kill_on_drop(true)
+                    .output()
+                    .await
+                    .with_context(|| format!("Failed running {}", self.path.display()))?;
+                if !output.status.success() {
+                    bail!(
+                        "{} failed: {}",
+                        self.path.display(),
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    );
+                }
+                let (matched, cursor, last) = count(
+                    &String::from_utf8_lossy(&output.stdout),
+                    self.regex.as_ref(),
+                )?;
+                if let Some(cursor) = cursor {
+                    self.position = Some(Position::Cursor(cursor));
+                }
+                (matched, last.unwrap_or_default())
+            }
+        };
+        Ok(ItemResult {
+            time: now(),
+            key: key.into(),
+            raw,
+            values: HashMap::from([(format!("{}.count", key).into(), matched as f64)]),
+            tags: BTreeMap::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use regex::Regex;
+
+    use crate::item::journal::count;
+
+    #[test]
+    fn entries() {
+        let output = r#"{"__CURSOR":"s=1;i=1","MESSAGE":"Connection refused"}
+{"__CURSOR":"s=1;i=2","MESSAGE":"Started backup"}
+{"__CURSOR":"s=1;i=3","MESSAGE":[114,101,102,117,115,101,100,255]}
+"#;
+        let (matched, cursor, last) = count(output, None).unwrap();
+        assert_eq!(matched, 3);
+        assert_eq!(cursor.as_deref(), Some("s=1;i=3"));
+        assert_eq!(last.as_deref(), Some("refused\u{fffd}"));
+
+        let (matched, cursor, last) = count(output, Some(&Regex::new("refused").unwrap())).unwrap();
+        assert_eq!(matched, 2);
+        assert_eq!(cursor.as_deref(), Some("s=1;i=3"));
+        assert_eq!(last.as_deref(), Some("refused\u{fffd}"));
+        assert_eq!(count("", None).unwrap(), (0, None, None));
+    }
+}
@@ -0,0 +1,176 @@
+//! Selecting values of JSON documents for the `json` digest, with JSON pointers like
+//! `/sensors/0/temp` or a subset of JSONPath like `$.sensors[*].temp`
+
+use anyhow::{anyhow, bail, Result};
+use serde::Deserialize;
+use serde_json::Value;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Step {
+    /// A member of an object, or an element of an array by its index
+    Name(String),
+    /// All members or elements, each selected value is named by its member or index
+    Wildcard,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct JsonPath(Vec<Step>);
+
+impl TryFrom<String> for JsonPath {
+    type Error = anyhow::Error;
+
+    fn try_from(path: String) -> Result<Self> {
+        if path.is_empty() || path.starts_with('/') {
+            return Ok(Self(pointer(&path)));
+        }
+        parse(&path).map_err(|e| anyhow!("Invalid JSONPath {}: {:#}", path, e))
+    }
+}
+
+/// As in RFC 6901, `~1` is `/` and `~0` is `~`
+fn pointer(path: &str) -> Vec<Step> {
+    path.split('/')
+        .skip(1)
+        .map(|token| Step::Name(token.replace("~1", "/").replace("~0", "~")))
+        .collect()
+}
+
+/// `$` followed by `.name`, `['name']`, `[0]`, `.*` or `[*]`
+fn parse(path: &str) -> Result<JsonPath> {
+    let Some(mut rest) = path.strip_prefix('$') else {
+        bail!("JSONPaths start with $, JSON pointers with /");
+    };
+    let mut steps = Vec::new();
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix('.') {
+            let end = after.find(['.', '[']).unwrap_or(after.len());
+            steps.push(match &after[..end] {
+                "" => bail!("Empty name after ."),
+                "*" => Step::Wildcard,
+                name => Step::Name(name.to_owned()),
+            });
+            rest = &after[end..];
+        } else if let Some(after) = rest.strip_prefix('[') {
+            let Some(end) = after.find(']') else {
+                bail!("Unclosed [");
+            };
+            let inner = &after[..end];
+            steps.push(match inner {
+                "*" => Step::Wildcard,
+                _ => match inner
+                    .strip_prefix('\'')
+                    .and_then(|name| name.strip_suffix('\''))
+                {
+                    Some(name) => Step::Name(name.to_owned()),
+                    None if inner.parse::<usize>().is_ok() => Step::Name(inner.to_owned()),
+                    None => bail!("Expected an index, * or a quoted name in [{}]", inner),
+                },
+            });
+            rest = &after[end + 1..];
+        } else {
+            bail!("Expected . or [ before {}", rest);
+        }
+    }
+    Ok(JsonPath(steps))
+}
+
+fn child<'a>(value: &'a Value, name: &str) -> Option<&'a Value> {
+    match value {
+        Value::Object(members) => members.get(name),
+        Value::Array(elements) => elements.get(name.parse::<usize>().ok()?),
+        _ => None,
+    }
+}
+
+impl JsonPath {
+    /// The selected values, named `name` and, below wildcards, `.<member or index>`
+    pub fn select<'a>(&self, value: &'a Value, name: &str) -> Vec<(String, &'a Value)> {
+        let mut selected = vec![(name.to_owned(), value)];
+        for step in &self.0 {
+            selected = selected
+                .into_iter()
+                .flat_map(|(name, value)| match step {
+                    Step::Name(member) => child(value, member)
+                        .map(|value| (name, value))
+                        .into_iter()
+                        .collect::<Vec<_>>(),
+                    Step::Wildcard => match value {
+                        Value::Object(members) => members
+                            .iter()
+                            .map(|(member, value)| (format!("{}.{}", name, member), value))
+                            .collect(),
+                        Value::Array(elements) => elements
+                            .iter()
+                            .enumerate()
+                            .map(|(index, value)| (format!("{}.{}", name, index), value))
+                            .collect(),
+                        _ => Vec::new(),
+                    },
+                })
+                .collect();
+        }
+        selected
+    }
+}
+
+/// Numbers, booleans as 1 and 0 and strings holding a number, NaN for anything else
+pub fn number(value: &Value) -> f64 {
+    match value {
+        Value::Number(number) => number.as_f64().unwrap_or(f64::NAN),
+        Value::Bool(true) => 1.0,
+        Value::Bool(false) => 0.0,
+        Value::String(string) => string.trim().parse().unwrap_or(f64::NAN),
+        _ => f64::NAN,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::item::jsonpath::{number, JsonPath};
+
+    fn select(path: &str, document: &serde_json::Value) -> Vec<(String, f64)> {
+        JsonPath::try_from(path.to_owned())
+            .unwrap()
+            .select(document, "v")
+            .into_iter()
+            .map(|(name, value)| (name, number(value)))
+            .collect()
+    }
+
+    #[test]
+    fn paths() {
+        let document = json!({
+            "uptime": 3600,
+            "a/b": "12.5",
+            "ok": true,
+            "disks": [{"name": "sda", "used": 10}, {"name": "sdb", "used": 20}],
+            "fans": {"cpu": 1200, "case": 800}
+        });
+        assert_eq!(select("/uptime", &document), [(String::from("v"), 3600.0)]);
+        assert_eq!(select("/a~1b", &document), [(String::from("v"), 12.5)]);
+        assert_eq!(
+            select("/disks/1/used", &document),
+            [(String::from("v"), 20.0)]
+        );
+        assert_eq!(select("$.ok", &document), [(String::from("v"), 1.0)]);
+        assert_eq!(select("$['a/b']", &document), [(String::from("v"), 12.5)]);
+        assert_eq!(
+            select("$.disks[*].used", &document),
+            [(String::from("v.0"), 10.0), (String::from("v.1"), 20.0)]
+        );
+        assert_eq!(select("$.fans.*", &document).len(), 2);
+        assert!(select("$.disks[0].name", &document)[0].1.is_nan());
+        assert!(select("$.missing.used", &document).is_empty());
+        assert!(JsonPath::try_from(String::from("uptime")).is_err());
+        assert!(JsonPath::try_from(String::from("$.disks[x]")).is_err());
+        assert_eq!(
+            JsonPath::try_from(String::from("$.disks[0"))
+                .unwrap_err()
+                .to_string(),
+            "Invalid JSONPath $.disks[0: Unclosed ["
+        );
+    }
+}
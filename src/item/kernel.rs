@@ -0,0 +1,140 @@
+//! Commonly wanted kernel statistics, each a one-liner in /proc
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::Instant;
+
+use anyhow::{anyhow, Context, Result};
+use log::debug;
+use serde::Deserialize;
+
+use crate::item::pressure::{self, Resource};
+use crate::item::{now, ItemResult};
+use crate::keys::Key;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KernelMetric {
+    /// Available entropy of the random number generator, in bits
+    Entropy,
+    /// Allocated file handles compared to the maximum
+    FileDescriptors,
+    /// Context switches per second
+    ContextSwitches,
+    /// Averages of the pressure stall information, "some" for cpu, memory and io
+    Pressure,
+}
+
+const ALL: [KernelMetric; 4] = [
+    KernelMetric::Entropy,
+    KernelMetric::FileDescriptors,
+    KernelMetric::ContextSwitches,
+    KernelMetric::Pressure,
+];
+
+/// Counters of the last run, to calculate rates
+#[derive(Debug, Default, Clone)]
+pub struct Counters {
+    context_switches: Option<(u64, Instant)>,
+}
+
+fn read(path: &str) -> Result<String> {
+    std::fs::read_to_string(path).with_context(|| format!("Failed reading {}", path))
+}
+
+fn parse<T: std::str::FromStr>(s: Option<&str>, path: &str) -> Result<T> {
+    s.and_then(|s| s.trim().parse().ok())
+        .ok_or_else(|| anyhow!("Unexpected content of {}", path))
+}
+
+fn collect(
+    metric: KernelMetric,
+    key: &str,
+    counters: &mut Counters,
+    values: &mut HashMap<Key, f64>,
+) -> Result<()> {
+    match metric {
+        KernelMetric::Entropy => {
+            let path = "/proc/sys/kernel/random/entropy_avail";
+            let entropy: f64 = parse(Some(&read(path)?), path)?;
+            values.insert(format!("{}.entropy", key).into(), entropy);
+        }
+        KernelMetric::FileDescriptors => {
+            // allocated, allocated but unused (always 0 since 2.6), maximum
+            let path = "/proc/sys/fs/file-nr";
+            let content = read(path)?;
+            let mut fields = content.split_whitespace();
+            let allocated: f64 = parse(fields.next(), path)?;
+            let _unused: f64 = parse(fields.next(), path)?;
+            let max: f64 = parse(fields.next(), path)?;
+            values.insert(
+                format!("{}.file_descriptors.allocated", key).into(),
+                allocated,
+            );
+            values.insert(format!("{}.file_descriptors.max", key).into(), max);
+            values.insert(
+                format!("{}.file_descriptors.used_percent", key).into(),
+                allocated / max * 100.0,
+            );
+        }
+        KernelMetric::ContextSwitches => {
+            let path = "/proc/stat";
+            let content = read(path)?;
+            let total: u64 = parse(
+                content.lines().find_map(|line| line.strip_prefix("ctxt ")),
+                path,
+            )?;
+            let now = Instant::now();
+            // The rate is known from the second run on
+            if let Some((previous, at)) = counters.context_switches {
+                let elapsed = now.duration_since(at).as_secs_f64();
+                if elapsed > 0.0 {
+                    values.insert(
+                        format!("{}.context_switches", key).into(),
+                        total.saturating_sub(previous) as f64 / elapsed,
+                    );
+                }
+            }
+            counters.context_switches = Some((total, now));
+        }
+        KernelMetric::Pressure => {
+            for resource in [Resource::Cpu, Resource::Memory, Resource::Io] {
+                let lines = pressure::read(resource)?;
+                let some = lines
+                    .into_iter()
+                    .find(|line| line.kind == "some")
+                    .ok_or_else(|| anyhow!("Missing some line for {}", resource.as_str()))?;
+                for (name, value) in some.averages {
+                    values.insert(
+                        format!("{}.pressure.{}.{}", key, resource.as_str(), name).into(),
+                        value,
+                    );
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Without selected metrics, all are collected and the ones not available on this system
+/// are skipped
+pub fn produce(key: &str, metrics: &[KernelMetric], counters: &mut Counters) -> Result<ItemResult> {
+    let mut values = HashMap::new();
+    if metrics.is_empty() {
+        for metric in ALL {
+            if let Err(e) = collect(metric, key, counters, &mut values) {
+                debug!("item {}: skipping {:?}: {:#}", key, metric, e);
+            }
+        }
+    } else {
+        for metric in metrics {
+            collect(*metric, key, counters, &mut values)?;
+        }
+    }
+    Ok(ItemResult {
+        time: now(),
+        key: key.into(),
+        raw: String::new(),
+        values,
+        tags: BTreeMap::new(),
+    })
+}
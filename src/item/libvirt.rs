@@ -0,0 +1,239 @@
+//! CPU, memory and block and network I/O of the virtual machines of libvirt, as told by
+//! `virsh domstats`
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::time::Instant;
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+
+use crate::item::hwmon::sanitize;
+use crate::item::{now, ItemResult};
+use crate::keys::Key;
+use crate::priority::Priority;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Libvirt {
+    /// e.g. `qemu:///system`, the default of virsh if not given
+    uri: Option<String>,
+    #[serde(default = "path_default")]
+    path: PathBuf,
+    /// CPU time in nanoseconds per domain at the last run
+    #[serde(skip)]
+    cpu: HashMap<String, (f64, Instant)>,
+}
+
+fn path_default() -> PathBuf {
+    PathBuf::from("virsh")
+}
+
+/// Names of the states of `state.state`
+const STATES: [&str; 8] = [
+    "no state",
+    "running",
+    "blocked",
+    "paused",
+    "shutdown",
+    "shut off",
+    "crashed",
+    "suspended",
+];
+
+/// Counters summed over the disks and interfaces of a domain, with the names used in keys
+const COUNTERS: [(&str, &str, &str); 8] = [
+    ("block", "rd.bytes", "block.read_bytes"),
+    ("block", "wr.bytes", "block.write_bytes"),
+    ("block", "rd.reqs", "block.read_requests"),
+    ("block", "wr.reqs", "block.write_requests"),
+    ("net", "rx.bytes", "net.rx_bytes"),
+    ("net", "tx.bytes", "net.tx_bytes"),
+    ("net", "rx.pkts", "net.rx_packets"),
+    ("net", "tx.pkts", "net.tx_packets"),
+];
+
+/// The numeric statistics by domain, from blocks like
+///
+/// ```text
+/// Domain: 'web'
+///   state.state=1
+///   block.0.rd.bytes=1024
+/// ```
+fn parse(domstats: &str) -> Vec<(String, HashMap<&str, f64>)> {
+    let mut domains: Vec<(String, HashMap<&str, f64>)> = Vec::new();
+    for line in domstats.lines() {
+        if let Some(name) = line.strip_prefix("Domain: ") {
+            domains.push((name.trim_matches('\'').to_owned(), HashMap::new()));
+        } else if let (Some((_, stats)), Some((name, value))) =
+            (domains.last_mut(), line.trim().split_once('='))
+        {
+            if let Ok(value) = value.parse() {
+                stats.insert(name, value);
+            }
+        }
+    }
+    domains
+}
+
+/// The sum of `<group>.<n>.<name>` over all disks or interfaces
+fn sum(stats: &HashMap<&str, f64>, group: &str, name: &str) -> Option<f64> {
+    let values = stats
+        .iter()
+        .filter(|(stat, _)| {
+            stat.strip_prefix(group)
+                .and_then(|rest| rest.strip_prefix('.'))
+                .and_then(|rest| rest.split_once('.'))
+                .is_some_and(|(n, rest)| n.parse::<u32>().is_ok() && rest == name)
+        })
+        .map(|(_, value)| *value)
+        .collect::<Vec<_>>();
+    (!values.is_empty()).then(|| values.iter().sum())
+}
+
+impl Libvirt {
+    fn result(&mut self, key: &str, name: &str, stats: &HashMap<&str, f64>) -> ItemResult {
+        let key = format!("{}.{}", key, sanitize(name));
+        let mut values = HashMap::new();
+        let mut value = |stat: &str, value: f64| {
+            values.insert(Key::from(format!("{}.{}", key, stat)), value);
+        };
+        let state = stats.get("state.state").copied().unwrap_or_default();
+        value("running", if state == 1.0 { 1.0 } else { 0.0 });
+        // KiB
+        if let Some(memory) = stats.get("balloon.current") {
+            value("memory", memory * 1024.0);
+        }
+        if let Some(rss) = stats.get("balloon.rss") {
+            value("memory_rss", rss * 1024.0);
+        }
+        for (group, stat, name) in COUNTERS {
+            if let Some(sum) = sum(stats, group, stat) {
+                value(name, sum);
+            }
+        }
+        let taken = Instant::now();
+        match stats.get("cpu.time") {
+            Some(cpu) => {
+                let previous = self.cpu.insert(name.to_owned(), (*cpu, taken));
+                if let Some((before, at)) = previous {
+                    let elapsed = taken.duration_since(at).as_secs_f64() * 1e9;
+                    // Less after the domain was restarted
+                    if elapsed > 0.0 && *cpu >= before {
+                        value("cpu_percent", (cpu - before) / elapsed * 100.0);
+                    }
+                }
+            }
+            None => {
+                self.cpu.remove(name);
+            }
+        }
+        ItemResult {
+            time: now(),
+            key: key.into(),
+            raw: STATES
+                .get(state as usize)
+                .copied()
+                .unwrap_or("unknown")
+                .to_owned(),
+            values,
+            tags: BTreeMap::from([(String::from("domain"), name.to_owned())]),
+        }
+    }
+
+    /// One result per domain
+    pub async fn produce(&mut self, key: &str, priority: Priority) -> Result<Vec<ItemResult>> {
+        let mut command = tokio::process::Command::new(&self.path);
+        priority.command(&mut command);
+        if let Some(uri) = &self.uri {
+            command.args(["-c", uri]);
+        }
+        let output = command
+            .args([
+                "domstats",
+                "--state",
+                "--cpu-total",
+                "--balloon",
+                "--block",
+                "--interface",
+            ])
+            .kill_on_drop(true)
+            .output()
+            .await
+            .with_context(|| format!("Failed running {}", self.path.display()))?;
+        if !output.status.success() {
+            bail!(
+                "{} failed: {}",
+                self.path.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        let domstats = String::from_utf8_lossy(&output.stdout);
+        let domains = parse(&domstats);
+        // Of domains that were removed
+        self.cpu
+            .retain(|name, _| domains.iter().any(|(domain, _)| domain == name));
+        Ok(domains
+            .iter()
+            .map(|(name, stats)| self.result(key, name, stats))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::time::{Duration, Instant};
+
+    use crate::item::libvirt::{parse, path_default, Libvirt};
+
+    #[test]
+    fn domstats() {
+        let domstats = "Domain: 'web.example'
+  state.state=1
+  state.reason=1
+  cpu.time=30000000000
+  balloon.current=2097152
+  balloon.rss=1048576
+  block.count=2
+  block.0.name=vda
+  block.0.rd.bytes=1000
+  block.0.wr.bytes=500
+  block.1.name=vdb
+  block.1.rd.bytes=24
+  net.count=1
+  net.0.name=vnet0
+  net.0.rx.bytes=4096
+  net.0.tx.bytes=2048
+
+Domain: 'old'
+  state.state=5
+  state.reason=0
+";
+        let domains = parse(domstats);
+        assert_eq!(domains.len(), 2);
+        let mut libvirt = Libvirt {
+            uri: None,
+            path: path_default(),
+            cpu: HashMap::new(),
+        };
+        libvirt.cpu.insert(
+            String::from("web.example"),
+            (20000000000.0, Instant::now() - Duration::from_secs(10)),
+        );
+        let web = libvirt.result("vm", &domains[0].0, &domains[0].1);
+        assert_eq!(&*web.key, "vm.web_example");
+        assert_eq!(web.raw, "running");
+        assert_eq!(web.values["vm.web_example.running"], 1.0);
+        assert_eq!(web.values["vm.web_example.memory"], 2147483648.0);
+        assert_eq!(web.values["vm.web_example.block.read_bytes"], 1024.0);
+        assert_eq!(web.values["vm.web_example.net.tx_bytes"], 2048.0);
+        assert!(!web
+            .values
+            .contains_key("vm.web_example.block.read_requests"));
+        let cpu = web.values["vm.web_example.cpu_percent"];
+        assert!(cpu > 99.0 && cpu <= 100.0, "{}", cpu);
+        let old = libvirt.result("vm", &domains[1].0, &domains[1].1);
+        assert_eq!(old.raw, "shut off");
+        assert_eq!(old.values.len(), 1);
+    }
+}
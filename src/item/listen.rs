@@ -0,0 +1,217 @@
+//! Metrics other programs send as UDP datagrams, in Graphite's plaintext or the statsd
+//! format, so short-lived scripts need no client of their own
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+use tokio::net::UdpSocket;
+
+use crate::item::{now, ItemResult};
+use crate::keys::Key;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Listener {
+    /// `address:port`, e.g. `127.0.0.1:8125`
+    pub bind: String,
+    #[serde(default)]
+    pub format: Format,
+    /// Seconds between results of statsd metrics
+    #[serde(default = "flush_interval_default")]
+    pub flush_interval: u64,
+}
+
+fn flush_interval_default() -> u64 {
+    10
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// `name value [timestamp]` per line, written as it arrives
+    #[default]
+    Plain,
+    /// `name:value|type[|@rate]` per line, aggregated over the flush interval
+    Statsd,
+}
+
+impl Listener {
+    pub async fn bind(&self) -> Result<UdpSocket> {
+        UdpSocket::bind(&self.bind)
+            .await
+            .with_context(|| format!("Failed binding {}", self.bind))
+    }
+}
+
+/// The results of a plaintext datagram, one per timestamp given, with the values
+/// `<key>.<name>`
+pub fn plain(key: &str, datagram: &str) -> Result<Vec<ItemResult>> {
+    let received = now();
+    let mut times = BTreeMap::<Duration, HashMap<Key, f64>>::new();
+    for line in datagram.lines().filter(|line| !line.trim().is_empty()) {
+        let mut fields = line.split_whitespace();
+        let (Some(name), Some(value)) = (fields.next(), fields.next()) else {
+            bail!("Expected `name value [timestamp]`, got {:?}", line);
+        };
+        let value: f64 = value
+            .parse()
+            .with_context(|| format!("Invalid value in {:?}", line))?;
+        let time = match fields.next() {
+            Some(timestamp) => Duration::from_secs(
+                timestamp
+                    .parse()
+                    .with_context(|| format!("Invalid timestamp in {:?}", line))?,
+            ),
+            None => received,
+        };
+        times
+            .entry(time)
+            .or_default()
+            .insert(format!("{}.{}", key, name).into(), value);
+    }
+    Ok(times
+        .into_iter()
+        .map(|(time, values)| ItemResult {
+            time,
+            key: key.into(),
+            raw: datagram.trim().to_owned(),
+            values,
+            tags: BTreeMap::new(),
+        })
+        .collect())
+}
+
+/// Statsd metrics received since the last flush. Like statsd itself, counters and
+/// gauges seen once are written with every flush.
+#[derive(Debug, Default)]
+pub struct Statsd {
+    counters: HashMap<String, f64>,
+    gauges: HashMap<String, f64>,
+    timers: HashMap<String, Vec<f64>>,
+    sets: HashMap<String, HashSet<String>>,
+}
+
+impl Statsd {
+    /// Add all lines of a datagram
+    pub fn add(&mut self, datagram: &str) -> Result<()> {
+        for line in datagram.lines().filter(|line| !line.trim().is_empty()) {
+            self.add_line(line.trim())
+                .with_context(|| format!("Invalid statsd metric {:?}", line))?;
+        }
+        Ok(())
+    }
+
+    fn add_line(&mut self, line: &str) -> Result<()> {
+        let (name, rest) = line
+            .split_once(':')
+            .ok_or_else(|| anyhow!("Expected `name:value|type`"))?;
+        let mut fields = rest.split('|');
+        let value = fields.next().unwrap_or_default();
+        let kind = fields.next().ok_or_else(|| anyhow!("No type"))?;
+        // Further fields like tags of DogStatsD are ignored
+        let rate = fields
+            .find_map(|field| field.strip_prefix('@'))
+            .map(|rate| rate.parse::<f64>())
+            .transpose()?
+            .filter(|rate| *rate > 0.0)
+            .unwrap_or(1.0);
+        if kind == "s" {
+            self.sets
+                .entry(name.to_owned())
+                .or_default()
+                .insert(value.to_owned());
+            return Ok(());
+        }
+        let number: f64 = value.parse()?;
+        match kind {
+            "c" => *self.counters.entry(name.to_owned()).or_default() += number / rate,
+            // Signed gauges change the last value
+            "g" if value.starts_with(['+', '-']) => {
+                *self.gauges.entry(name.to_owned()).or_default() += number
+            }
+            "g" => {
+                self.gauges.insert(name.to_owned(), number);
+            }
+            "ms" | "h" | "d" => self.timers.entry(name.to_owned()).or_default().push(number),
+            kind => bail!("Unknown type {}", kind),
+        }
+        Ok(())
+    }
+
+    /// The aggregated values, `<key>.<name>` for counters, gauges and sets (as the number of
+    /// unique values) and `<key>.<name>.count`, `.min`, `.max` and `.mean` for timers
+    pub fn flush(&mut self, key: &str) -> Option<ItemResult> {
+        let mut values = HashMap::<Key, f64>::new();
+        for (name, count) in self.counters.iter_mut() {
+            values.insert(format!("{}.{}", key, name).into(), std::mem::take(count));
+        }
+        for (name, value) in &self.gauges {
+            values.insert(format!("{}.{}", key, name).into(), *value);
+        }
+        for (name, timings) in self.timers.drain() {
+            let name = format!("{}.{}", key, name);
+            let count = timings.len() as f64;
+            let min = timings.iter().copied().fold(f64::INFINITY, f64::min);
+            let max = timings.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+            let mean = timings.iter().sum::<f64>() / count;
+            values.insert(format!("{}.count", name).into(), count);
+            values.insert(format!("{}.min", name).into(), min);
+            values.insert(format!("{}.max", name).into(), max);
+            values.insert(format!("{}.mean", name).into(), mean);
+        }
+        for (name, set) in self.sets.drain() {
+            values.insert(format!("{}.{}", key, name).into(), set.len() as f64);
+        }
+        if values.is_empty() {
+            return None;
+        }
+        Some(ItemResult {
+            time: now(),
+            key: key.into(),
+            raw: String::new(),
+            values,
+            tags: BTreeMap::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::item::listen::{plain, Statsd};
+
+    #[test]
+    fn datagrams() {
+        let results = plain("m", "backup.duration 12.5 1700000000\nbackup.files 3\n").unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].time, Duration::from_secs(1700000000));
+        assert_eq!(results[0].values["m.backup.duration"], 12.5);
+        assert_eq!(results[1].values["m.backup.files"], 3.0);
+        assert!(plain("m", "backup.duration twelve").is_err());
+
+        let mut statsd = Statsd::default();
+        statsd
+            .add("jobs:1|c\njobs:1|c|@0.5\nqueue:10|g\nqueue:-3|g\nlatency:20|ms\nlatency:40|ms")
+            .unwrap();
+        statsd
+            .add("users:alice|s\nusers:bob|s\nusers:alice|s")
+            .unwrap();
+        assert!(statsd.add("jobs:1|x").is_err());
+        let values = statsd.flush("s").unwrap().values;
+        assert_eq!(values["s.jobs"], 3.0);
+        assert_eq!(values["s.queue"], 7.0);
+        assert_eq!(values["s.latency.count"], 2.0);
+        assert_eq!(values["s.latency.min"], 20.0);
+        assert_eq!(values["s.latency.max"], 40.0);
+        assert_eq!(values["s.latency.mean"], 30.0);
+        assert_eq!(values["s.users"], 2.0);
+
+        let values = statsd.flush("s").unwrap().values;
+        assert_eq!(values["s.jobs"], 0.0);
+        assert_eq!(values["s.queue"], 7.0);
+        assert!(!values.contains_key("s.latency.count"));
+        assert!(!values.contains_key("s.users"));
+    }
+}
@@ -0,0 +1,253 @@
+//! Registers of Modbus TCP devices like solar inverters, energy meters and industrial
+//! sensors
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::item::{now, ItemResult};
+
+const READ_HOLDING_REGISTERS: u8 = 0x03;
+const READ_INPUT_REGISTERS: u8 = 0x04;
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Table {
+    #[default]
+    Holding,
+    Input,
+}
+
+/// How the registers are read as a number, the 32 bit ones span two registers
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Type {
+    #[default]
+    U16,
+    I16,
+    U32,
+    I32,
+    F32,
+}
+
+impl Type {
+    fn registers(self) -> u16 {
+        match self {
+            Self::U16 | Self::I16 => 1,
+            Self::U32 | Self::I32 | Self::F32 => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Register {
+    address: u16,
+    #[serde(default)]
+    table: Table,
+    #[serde(default, rename = "type")]
+    kind: Type,
+    /// Multiplied with the number read, e.g. 0.1 for registers in tenths
+    #[serde(default = "scale_default")]
+    scale: f64,
+    /// The low register comes first, instead of the high one
+    #[serde(default)]
+    swap_words: bool,
+}
+
+fn scale_default() -> f64 {
+    1.0
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Device {
+    host: String,
+    #[serde(default = "port_default")]
+    port: u16,
+    /// Unit identifier, of the device behind a gateway
+    #[serde(default = "unit_default")]
+    unit: u8,
+    /// Labels of the values, and the registers to read them from
+    registers: BTreeMap<String, Register>,
+    /// Seconds
+    #[serde(default = "timeout_default")]
+    timeout: u64,
+}
+
+fn port_default() -> u16 {
+    502
+}
+
+fn unit_default() -> u8 {
+    1
+}
+
+fn timeout_default() -> u64 {
+    10
+}
+
+/// A read request with its MBAP header
+fn request(transaction: u16, unit: u8, register: &Register) -> Vec<u8> {
+    let function = match register.table {
+        Table::Holding => READ_HOLDING_REGISTERS,
+        Table::Input => READ_INPUT_REGISTERS,
+    };
+    let mut request = Vec::with_capacity(12);
+    request.extend_from_slice(&transaction.to_be_bytes());
+    // Protocol identifier, then the length of what follows
+    request.extend_from_slice(&[0, 0, 0, 6, unit, function]);
+    request.extend_from_slice(&register.address.to_be_bytes());
+    request.extend_from_slice(&register.kind.registers().to_be_bytes());
+    request
+}
+
+/// The value of the register data of a response
+fn decode(register: &Register, data: &[u8]) -> Result<f64> {
+    if data.len() != register.kind.registers() as usize * 2 {
+        bail!(
+            "{} bytes of register data instead of {}",
+            data.len(),
+            register.kind.registers() * 2
+        );
+    }
+    let word = |i: usize| [data[i * 2], data[i * 2 + 1]];
+    // The high word first
+    let double = || {
+        let (high, low) = if register.swap_words { (1, 0) } else { (0, 1) };
+        let ([a, b], [c, d]) = (word(high), word(low));
+        [a, b, c, d]
+    };
+    let value = match register.kind {
+        Type::U16 => u16::from_be_bytes(word(0)) as f64,
+        Type::I16 => i16::from_be_bytes(word(0)) as f64,
+        Type::U32 => u32::from_be_bytes(double()) as f64,
+        Type::I32 => i32::from_be_bytes(double()) as f64,
+        Type::F32 => f32::from_be_bytes(double()) as f64,
+    };
+    Ok(value * register.scale)
+}
+
+/// Names of the exception codes of the protocol
+fn exception(code: u8) -> String {
+    match code {
+        1 => String::from("illegal function"),
+        2 => String::from("illegal data address"),
+        3 => String::from("illegal data value"),
+        4 => String::from("server device failure"),
+        6 => String::from("server device busy"),
+        10 => String::from("gateway path unavailable"),
+        11 => String::from("gateway target device failed to respond"),
+        code => format!("exception {}", code),
+    }
+}
+
+async fn read(
+    stream: &mut TcpStream,
+    transaction: u16,
+    unit: u8,
+    register: &Register,
+) -> Result<f64> {
+    stream
+        .write_all(&request(transaction, unit, register))
+        .await?;
+    let mut header = [0u8; 7];
+    stream.read_exact(&mut header).await?;
+    let length = u16::from_be_bytes([header[4], header[5]]) as usize;
+    if u16::from_be_bytes([header[0], header[1]]) != transaction || !(2..=254).contains(&length) {
+        bail!("Invalid response");
+    }
+    // Function code and the rest of the PDU, the unit identifier was part of the header
+    let mut pdu = vec![0u8; length - 1];
+    stream.read_exact(&mut pdu).await?;
+    if pdu[0] & 0x80 != 0 {
+        bail!("{}", exception(pdu.get(1).copied().unwrap_or_default()));
+    }
+    decode(register, pdu.get(2..).unwrap_or_default())
+}
+
+impl Device {
+    /// All registers, over one connection
+    pub async fn produce(&self, key: &str) -> Result<ItemResult> {
+        let address = format!("{}:{}", self.host, self.port);
+        let timeout = Duration::from_secs(self.timeout);
+        let read_all = async {
+            let mut stream = TcpStream::connect(&address)
+                .await
+                .with_context(|| format!("Failed connecting to {}", address))?;
+            let mut values = HashMap::new();
+            for (transaction, (label, register)) in self.registers.iter().enumerate() {
+                let value = read(&mut stream, transaction as u16, self.unit, register)
+                    .await
+                    .map_err(|e| {
+                        // The device's reason is the interesting part of the error
+                        anyhow!(
+                            "Failed reading {} at address {}: {:#}",
+                            label,
+                            register.address,
+                            e
+                        )
+                    })?;
+                values.insert(format!("{}.{}", key, label).into(), value);
+            }
+            Ok::<_, anyhow::Error>(values)
+        };
+        let values = tokio::time::timeout(timeout, read_all)
+            .await
+            .with_context(|| format!("Timed out reading from {}", address))??;
+        Ok(ItemResult {
+            time: now(),
+            key: key.into(),
+            raw: String::new(),
+            values,
+            tags: BTreeMap::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::item::modbus::{decode, request, Register, Table, Type};
+
+    fn register(kind: Type, scale: f64, swap_words: bool) -> Register {
+        Register {
+            address: 30775,
+            table: Table::Input,
+            kind,
+            scale,
+            swap_words,
+        }
+    }
+
+    #[test]
+    fn registers() {
+        assert_eq!(
+            request(7, 3, &register(Type::I32, 1.0, false)),
+            [0, 7, 0, 0, 0, 6, 3, 4, 0x78, 0x37, 0, 2]
+        );
+        assert_eq!(
+            decode(&register(Type::U16, 0.5, false), &[0x00, 0x65]).unwrap(),
+            50.5
+        );
+        assert_eq!(
+            decode(&register(Type::I16, 1.0, false), &[0xff, 0xfe]).unwrap(),
+            -2.0
+        );
+        assert_eq!(
+            decode(&register(Type::I32, 1.0, false), &[0xff, 0xff, 0xff, 0x9c]).unwrap(),
+            -100.0
+        );
+        assert_eq!(
+            decode(&register(Type::U32, 1.0, true), &[0x00, 0x01, 0x00, 0x02]).unwrap(),
+            131073.0
+        );
+        assert_eq!(
+            decode(&register(Type::F32, 1.0, false), &[0x41, 0x48, 0x00, 0x00]).unwrap(),
+            12.5
+        );
+        assert!(decode(&register(Type::U32, 1.0, false), &[0, 1]).is_err());
+    }
+}
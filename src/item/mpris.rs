@@ -0,0 +1,186 @@
+//! Media playback of MPRIS players on the D-Bus session bus, with the minutes played today
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::Instant;
+
+use anyhow::{Context, Result};
+use chrono::NaiveDate;
+use log::debug;
+use zbus::zvariant::OwnedValue;
+use zbus::{proxy::CacheProperties, Connection, Proxy};
+
+use crate::item::{now, ItemResult};
+
+const PREFIX: &str = "org.mpris.MediaPlayer2.";
+
+#[derive(Debug, Clone)]
+struct Player {
+    playing: bool,
+    at: Instant,
+    day: NaiveDate,
+    /// Seconds played on `day`
+    played: f64,
+    track: String,
+}
+
+/// The bus connection and the playback time of the players seen today
+#[derive(Debug, Default, Clone)]
+pub struct Playback {
+    connection: Option<Connection>,
+    players: HashMap<String, Player>,
+}
+
+/// `firefox` for `org.mpris.MediaPlayer2.firefox.instance_1_42`, instances of the same
+/// player are counted together
+fn player_name(bus_name: &str) -> Option<String> {
+    let name = bus_name.strip_prefix(PREFIX)?;
+    let name = name.split(".instance").next().unwrap_or(name);
+    Some(name.replace(['.', ' '], "_"))
+}
+
+/// `Artist - Title` of the current track, as far as known
+fn track(metadata: &HashMap<String, OwnedValue>) -> String {
+    let title = metadata
+        .get("xesam:title")
+        .and_then(|v| String::try_from(v.try_clone().ok()?).ok())
+        .unwrap_or_default();
+    let artists = metadata
+        .get("xesam:artist")
+        .and_then(|v| Vec::<String>::try_from(v.try_clone().ok()?).ok())
+        .unwrap_or_default();
+    if artists.is_empty() {
+        title
+    } else {
+        format!("{} - {}", artists.join(", "), title)
+    }
+}
+
+/// Whether the players are playing, and their current tracks
+async fn poll(connection: &Connection) -> Result<HashMap<String, (bool, String)>> {
+    let dbus = zbus::fdo::DBusProxy::new(connection).await?;
+    let mut players = HashMap::<String, (bool, String)>::new();
+    for bus_name in dbus.list_names().await? {
+        let Some(name) = player_name(bus_name.as_str()) else {
+            continue;
+        };
+        // Properties are read once per run, caching would only subscribe to changes
+        let proxy = zbus::proxy::Builder::<Proxy>::new(connection)
+            .destination(bus_name.as_str())?
+            .path("/org/mpris/MediaPlayer2")?
+            .interface("org.mpris.MediaPlayer2.Player")?
+            .cache_properties(CacheProperties::No)
+            .build()
+            .await?;
+        let playing = match proxy.get_property::<String>("PlaybackStatus").await {
+            Ok(status) => status == "Playing",
+            Err(e) => {
+                debug!("mpris: skipping {}: {}", bus_name, e);
+                continue;
+            }
+        };
+        let track = proxy
+            .get_property::<HashMap<String, OwnedValue>>("Metadata")
+            .await
+            .map(|metadata| track(&metadata))
+            .unwrap_or_default();
+        let entry = players.entry(name).or_default();
+        // One playing instance is enough
+        if playing || !entry.0 {
+            *entry = (playing, track);
+        }
+    }
+    Ok(players)
+}
+
+/// One result per player, `<key>.<player>`
+pub async fn produce(key: &str, playback: &mut Playback) -> Result<Vec<ItemResult>> {
+    let connection = match &playback.connection {
+        Some(connection) => connection.clone(),
+        None => {
+            let connection = Connection::session()
+                .await
+                .context("Failed connecting to the D-Bus session bus")?;
+            playback.connection = Some(connection.clone());
+            connection
+        }
+    };
+    let current = match poll(&connection).await {
+        Ok(current) => current,
+        Err(e) => {
+            // Reconnect next time, e.g. after the session bus was restarted
+            playback.connection = None;
+            return Err(e);
+        }
+    };
+    let at = Instant::now();
+    let today = chrono::Local::now().date_naive();
+    for player in playback.players.values_mut() {
+        if player.playing {
+            player.played += at.duration_since(player.at).as_secs_f64();
+        }
+        player.at = at;
+        if player.day != today {
+            player.day = today;
+            player.played = 0.0;
+        }
+        player.playing = false;
+        player.track.clear();
+    }
+    for (name, (playing, track)) in &current {
+        let player = playback.players.entry(name.clone()).or_insert(Player {
+            playing: false,
+            at,
+            day: today,
+            played: 0.0,
+            track: String::new(),
+        });
+        player.playing = *playing;
+        player.track = track.clone();
+    }
+    // Players which quit are reported as not playing for the rest of the day
+    playback
+        .players
+        .retain(|name, player| current.contains_key(name) || player.played > 0.0);
+
+    let mut results = Vec::new();
+    for (name, player) in &playback.players {
+        let mut tags = BTreeMap::new();
+        tags.insert(String::from("player"), name.clone());
+        let key = format!("{}.{}", key, name);
+        let mut values = HashMap::new();
+        values.insert(
+            format!("{}.playing", key).into(),
+            if player.playing { 1.0 } else { 0.0 },
+        );
+        values.insert(
+            format!("{}.minutes_today", key).into(),
+            player.played / 60.0,
+        );
+        results.push(ItemResult {
+            time: now(),
+            key: key.into(),
+            raw: player.track.clone(),
+            values,
+            tags,
+        });
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::item::mpris::player_name;
+
+    #[test]
+    fn player_names() {
+        assert_eq!(
+            player_name("org.mpris.MediaPlayer2.firefox.instance_1_42").as_deref(),
+            Some("firefox")
+        );
+        assert_eq!(
+            player_name("org.mpris.MediaPlayer2.spotify").as_deref(),
+            Some("spotify")
+        );
+        assert_eq!(player_name("org.freedesktop.Notifications"), None);
+    }
+}
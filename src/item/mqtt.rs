@@ -0,0 +1,52 @@
+//! Messages published to an MQTT topic, digested as they arrive instead of on an interval
+
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use rumqttc::{AsyncClient, EventLoop, MqttOptions};
+use serde::Deserialize;
+
+use crate::secret::Secret;
+
+/// Requests queued for the broker, only the subscription itself
+const QUEUE_LENGTH: usize = 10;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Subscription {
+    /// `host` or `host:port`, 1883 if no port is given
+    #[serde(default = "broker_default")]
+    broker: String,
+    /// May contain wildcards, `+` for one level and `#` for all below
+    pub topic: String,
+    username: Option<String>,
+    password: Option<Secret>,
+}
+
+fn broker_default() -> String {
+    String::from("localhost")
+}
+
+impl Subscription {
+    pub fn connect(&self, client_id: String) -> Result<(AsyncClient, EventLoop)> {
+        let (host, port) = match self.broker.rsplit_once(':') {
+            Some((host, port)) => (
+                host,
+                port.parse()
+                    .with_context(|| format!("Invalid port in broker {}", self.broker))?,
+            ),
+            None => (self.broker.as_str(), 1883),
+        };
+        let mut options = MqttOptions::new(client_id, host, port);
+        options.set_keep_alive(Duration::from_secs(30));
+        if let Some(username) = &self.username {
+            options.set_credentials(
+                username,
+                self.password
+                    .as_ref()
+                    .map(|p| p.expose().to_owned())
+                    .unwrap_or_default(),
+            );
+        }
+        Ok(AsyncClient::new(options, QUEUE_LENGTH))
+    }
+}
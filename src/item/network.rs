@@ -0,0 +1,137 @@
+//! Traffic of network interfaces per second, from the counters in /proc/net/dev
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::Instant;
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+
+use crate::item::{now, ItemResult};
+use crate::keys::Key;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Network {
+    /// e.g. `eth0`, all but loopback if empty
+    #[serde(default)]
+    interfaces: Vec<String>,
+    /// Counters of the last run by interface, to calculate rates
+    #[serde(skip)]
+    previous: Option<(HashMap<String, Vec<u64>>, Instant)>,
+}
+
+/// Columns of /proc/net/dev, received ones first, then sent ones
+const COUNTERS: [(usize, &str); 8] = [
+    (0, "rx_bytes"),
+    (1, "rx_packets"),
+    (2, "rx_errors"),
+    (3, "rx_dropped"),
+    (8, "tx_bytes"),
+    (9, "tx_packets"),
+    (10, "tx_errors"),
+    (11, "tx_dropped"),
+];
+
+/// The counters by interface
+fn parse(dev: &str) -> HashMap<String, Vec<u64>> {
+    dev.lines()
+        .skip(2)
+        .filter_map(|line| {
+            let (interface, counters) = line.split_once(':')?;
+            let counters = counters
+                .split_whitespace()
+                .map(|c| c.parse().unwrap_or(0))
+                .collect::<Vec<_>>();
+            (counters.len() >= 16).then(|| (interface.trim().to_owned(), counters))
+        })
+        .collect()
+}
+
+/// Per second between two runs, none for counters that were reset, e.g. by recreating the
+/// interface
+fn rates(previous: &[u64], counters: &[u64], elapsed: f64) -> Vec<(&'static str, f64)> {
+    COUNTERS
+        .iter()
+        .filter(|(column, _)| counters[*column] >= previous[*column])
+        .map(|(column, name)| {
+            (
+                *name,
+                (counters[*column] - previous[*column]) as f64 / elapsed,
+            )
+        })
+        .collect()
+}
+
+impl Network {
+    /// One result per interface, from the second run on
+    pub fn produce(&mut self, key: &str) -> Result<Vec<ItemResult>> {
+        let path = "/proc/net/dev";
+        let mut counters = parse(
+            &std::fs::read_to_string(path).with_context(|| format!("Failed reading {}", path))?,
+        );
+        if self.interfaces.is_empty() {
+            counters.remove("lo");
+        } else {
+            counters.retain(|interface, _| self.interfaces.contains(interface));
+            if counters.is_empty() {
+                bail!(
+                    "None of the interfaces {} exist",
+                    self.interfaces.join(", ")
+                );
+            }
+        }
+        let taken = Instant::now();
+        let Some((previous, at)) = self.previous.replace((counters.clone(), taken)) else {
+            return Ok(Vec::new());
+        };
+        let elapsed = taken.duration_since(at).as_secs_f64();
+        if elapsed <= 0.0 {
+            return Ok(Vec::new());
+        }
+        let time = now();
+        let mut results = counters
+            .into_iter()
+            .filter_map(|(interface, counters)| {
+                let rates = rates(previous.get(&interface)?, &counters, elapsed);
+                let key = format!("{}.{}", key, interface.replace('.', "_"));
+                Some(ItemResult {
+                    time,
+                    raw: String::new(),
+                    values: rates
+                        .into_iter()
+                        .map(|(name, rate)| (Key::from(format!("{}.{}", key, name)), rate))
+                        .collect(),
+                    key: key.into(),
+                    tags: BTreeMap::from([(String::from("interface"), interface)]),
+                })
+            })
+            .collect::<Vec<_>>();
+        results.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::item::network::{parse, rates};
+
+    #[test]
+    fn counters() {
+        let dev = "Inter-|   Receive                                                |  Transmit
+ face |bytes    packets errs drop fifo frame compressed multicast|bytes    packets errs drop fifo colls carrier compressed
+    lo: 1000 10 0 0 0 0 0 0 1000 10 0 0 0 0 0 0
+eth0.100: 2000 20 1 2 0 0 0 0 3000 30 0 0 0 0 0 0
+";
+        let counters = parse(dev);
+        assert_eq!(counters.len(), 2);
+        assert_eq!(counters["eth0.100"][8], 3000);
+        let later = [6000, 60, 1, 2, 0, 0, 0, 0, 2000, 40, 0, 0, 0, 0, 0, 0];
+        let rates = rates(&counters["eth0.100"], &later, 2.0);
+        assert_eq!(rates.len(), 7);
+        assert_eq!(rates[0], ("rx_bytes", 2000.0));
+        assert_eq!(rates[1], ("rx_packets", 20.0));
+        assert_eq!(rates[2], ("rx_errors", 0.0));
+        // Reset
+        assert!(!rates.iter().any(|(name, _)| *name == "tx_bytes"));
+        assert_eq!(rates[4], ("tx_packets", 5.0));
+    }
+}
@@ -0,0 +1,158 @@
+//! Pressure stall information from /proc/pressure, with the stall rates since the last run
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::Instant;
+
+use anyhow::{anyhow, Context, Result};
+use log::debug;
+use serde::Deserialize;
+
+use crate::item::{now, ItemResult};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Resource {
+    Cpu,
+    Memory,
+    Io,
+    /// Only available with CONFIG_IRQ_TIME_ACCOUNTING since Linux 6.1
+    Irq,
+}
+
+const ALL: [Resource; 4] = [Resource::Cpu, Resource::Memory, Resource::Io, Resource::Irq];
+
+impl Resource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Cpu => "cpu",
+            Self::Memory => "memory",
+            Self::Io => "io",
+            Self::Irq => "irq",
+        }
+    }
+}
+
+/// A line of a pressure file, like `some avg10=0.00 avg60=0.00 avg300=0.00 total=0`
+#[derive(Debug, PartialEq)]
+pub struct Line {
+    /// `some` or `full`
+    pub kind: String,
+    /// Percentage of time stalled, averaged over 10, 60 and 300 seconds
+    pub averages: Vec<(String, f64)>,
+    /// Microseconds stalled since boot
+    pub total: u64,
+}
+
+pub fn parse(content: &str) -> Result<Vec<Line>> {
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let mut fields = line.split_whitespace();
+            let kind = fields
+                .next()
+                .ok_or_else(|| anyhow!("Empty pressure line"))?
+                .to_owned();
+            let mut averages = Vec::new();
+            let mut total = None;
+            for field in fields {
+                let (name, value) = field
+                    .split_once('=')
+                    .ok_or_else(|| anyhow!("Unexpected pressure field {}", field))?;
+                if name == "total" {
+                    total = Some(value.parse()?);
+                } else {
+                    averages.push((name.to_owned(), value.parse()?));
+                }
+            }
+            Ok(Line {
+                kind,
+                averages,
+                total: total.ok_or_else(|| anyhow!("Missing total in {}", line))?,
+            })
+        })
+        .collect()
+}
+
+pub fn read(resource: Resource) -> Result<Vec<Line>> {
+    let path = format!("/proc/pressure/{}", resource.as_str());
+    let content =
+        std::fs::read_to_string(&path).with_context(|| format!("Failed reading {}", path))?;
+    parse(&content).with_context(|| format!("Unexpected content of {}", path))
+}
+
+/// The totals of the last run, to calculate the stall rates
+#[derive(Debug, Default, Clone)]
+pub struct Totals {
+    previous: HashMap<String, u64>,
+    at: Option<Instant>,
+}
+
+/// Without selected resources, all are collected and the ones not available on this system
+/// are skipped
+pub fn produce(key: &str, resources: &[Resource], totals: &mut Totals) -> Result<ItemResult> {
+    let mut lines = Vec::new();
+    if resources.is_empty() {
+        for resource in ALL {
+            match read(resource) {
+                Ok(l) => lines.push((resource, l)),
+                Err(e) => debug!("item {}: skipping {}: {:#}", key, resource.as_str(), e),
+            }
+        }
+    } else {
+        for resource in resources {
+            lines.push((*resource, read(*resource)?));
+        }
+    }
+    let now_instant = Instant::now();
+    let elapsed = totals
+        .at
+        .map(|at| now_instant.duration_since(at).as_micros() as f64);
+    let mut values = HashMap::new();
+    for (resource, lines) in lines {
+        for line in lines {
+            let prefix = format!("{}.{}.{}", key, resource.as_str(), line.kind);
+            for (name, value) in line.averages {
+                values.insert(format!("{}.{}", prefix, name).into(), value);
+            }
+            values.insert(format!("{}.total", prefix).into(), line.total as f64);
+            // Percentage of time stalled since the last run, known from the second run on
+            if let (Some(previous), Some(elapsed)) = (totals.previous.get(&prefix), elapsed) {
+                if elapsed > 0.0 {
+                    values.insert(
+                        format!("{}.rate", prefix).into(),
+                        line.total.saturating_sub(*previous) as f64 / elapsed * 100.0,
+                    );
+                }
+            }
+            totals.previous.insert(prefix, line.total);
+        }
+    }
+    totals.at = Some(now_instant);
+    Ok(ItemResult {
+        time: now(),
+        key: key.into(),
+        raw: String::new(),
+        values,
+        tags: BTreeMap::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::item::pressure::parse;
+
+    #[test]
+    fn lines() {
+        let lines = parse(
+            "some avg10=2.15 avg60=3.40 avg300=3.15 total=137457826\n\
+             full avg10=0.00 avg60=0.00 avg300=0.00 total=0\n",
+        )
+        .unwrap();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].kind, "some");
+        assert_eq!(lines[0].averages[1], (String::from("avg60"), 3.40));
+        assert_eq!(lines[0].total, 137457826);
+        assert_eq!(lines[1].kind, "full");
+    }
+}
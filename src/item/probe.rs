@@ -0,0 +1,203 @@
+//! Timing of a single HTTP request per phase, for uptime monitoring of websites
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Context, Result};
+use log::warn;
+use serde::Deserialize;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::{ClientConfig, ServerName};
+use tokio_rustls::TlsConnector;
+use url::{Host, Position, Url};
+
+use crate::item::{certificate, now, ItemResult};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Probe {
+    url: String,
+    /// Seconds
+    #[serde(default = "timeout_default")]
+    timeout: u64,
+}
+
+fn timeout_default() -> u64 {
+    10
+}
+
+/// Seconds spent in each phase of the request
+#[derive(Debug, Default)]
+struct Timings {
+    dns: f64,
+    connect: f64,
+    tls: Option<f64>,
+    ttfb: f64,
+    total: f64,
+    status: u16,
+    size: usize,
+}
+
+fn connector() -> TlsConnector {
+    static CONFIG: OnceLock<Arc<ClientConfig>> = OnceLock::new();
+    let config = CONFIG.get_or_init(|| {
+        Arc::new(
+            ClientConfig::builder()
+                .with_safe_defaults()
+                .with_root_certificates(certificate::roots().clone())
+                .with_no_client_auth(),
+        )
+    });
+    TlsConnector::from(config.clone())
+}
+
+/// The status code and the length of the body of a raw HTTP/1.1 response
+fn parse_response(response: &[u8]) -> Result<(u16, usize)> {
+    let status = response
+        .split(|b| *b == b'\n')
+        .next()
+        .and_then(|line| std::str::from_utf8(line).ok())
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| anyhow!("Unexpected response"))?;
+    let body = response
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .map(|end| response.len() - end - 4)
+        .unwrap_or_default();
+    Ok((status, body))
+}
+
+impl Probe {
+    /// Never fails, unreachable websites are reported with `up` being 0
+    pub async fn produce(&self, key: &str) -> ItemResult {
+        let timings =
+            match tokio::time::timeout(Duration::from_secs(self.timeout), self.measure()).await {
+                Ok(Ok(timings)) => Some(timings),
+                Ok(Err(e)) => {
+                    warn!("Probe of {} failed: {:#}", self.url, e);
+                    None
+                }
+                Err(_) => {
+                    warn!("Probe of {} timed out after {}s", self.url, self.timeout);
+                    None
+                }
+            };
+        let mut values = HashMap::new();
+        let up = timings
+            .as_ref()
+            .is_some_and(|t| (200..400).contains(&t.status));
+        values.insert(format!("{}.up", key).into(), if up { 1.0 } else { 0.0 });
+        if let Some(timings) = timings {
+            values.insert(format!("{}.dns", key).into(), timings.dns);
+            values.insert(format!("{}.connect", key).into(), timings.connect);
+            if let Some(tls) = timings.tls {
+                values.insert(format!("{}.tls", key).into(), tls);
+            }
+            values.insert(format!("{}.ttfb", key).into(), timings.ttfb);
+            values.insert(format!("{}.total", key).into(), timings.total);
+            values.insert(format!("{}.status_code", key).into(), timings.status as f64);
+            values.insert(format!("{}.size", key).into(), timings.size as f64);
+        }
+        ItemResult {
+            time: now(),
+            key: key.into(),
+            raw: String::new(),
+            values,
+            tags: BTreeMap::new(),
+        }
+    }
+
+    async fn measure(&self) -> Result<Timings> {
+        let url = Url::parse(&self.url).with_context(|| format!("Invalid URL {}", self.url))?;
+        let host = match url.host() {
+            Some(Host::Domain(domain)) => domain.to_owned(),
+            Some(Host::Ipv4(ip)) => ip.to_string(),
+            Some(Host::Ipv6(ip)) => ip.to_string(),
+            None => bail!("{} has no host", self.url),
+        };
+        let port = url
+            .port_or_known_default()
+            .ok_or_else(|| anyhow!("{} has no port", self.url))?;
+        let mut timings = Timings::default();
+
+        let start = Instant::now();
+        let address = tokio::net::lookup_host((host.as_str(), port))
+            .await
+            .with_context(|| format!("Failed resolving {}", host))?
+            .next()
+            .ok_or_else(|| anyhow!("{} did not resolve", host))?;
+        timings.dns = start.elapsed().as_secs_f64();
+
+        let phase = Instant::now();
+        let stream = TcpStream::connect(address)
+            .await
+            .with_context(|| format!("Failed connecting to {}", address))?;
+        timings.connect = phase.elapsed().as_secs_f64();
+
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: antikoerper\r\nConnection: close\r\n\r\n",
+            &url[Position::BeforePath..Position::AfterQuery],
+            &url[Position::BeforeHost..Position::AfterPort],
+        );
+        let response = match url.scheme() {
+            "http" => exchange(stream, &request, &mut timings).await?,
+            "https" => {
+                let phase = Instant::now();
+                let name = ServerName::try_from(host.as_str())
+                    .with_context(|| format!("Invalid server name {}", host))?;
+                let stream = connector()
+                    .connect(name, stream)
+                    .await
+                    .with_context(|| format!("TLS handshake with {} failed", host))?;
+                timings.tls = Some(phase.elapsed().as_secs_f64());
+                exchange(stream, &request, &mut timings).await?
+            }
+            scheme => bail!("Unsupported scheme {}", scheme),
+        };
+        timings.total = start.elapsed().as_secs_f64();
+        (timings.status, timings.size) = parse_response(&response)?;
+        Ok(timings)
+    }
+}
+
+/// Send the request and read the complete response, the connection is closed by the server
+async fn exchange<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    request: &str,
+    timings: &mut Timings,
+) -> Result<Vec<u8>> {
+    let phase = Instant::now();
+    stream.write_all(request.as_bytes()).await?;
+    let mut response = Vec::new();
+    let mut buffer = [0; 8192];
+    loop {
+        let read = match stream.read(&mut buffer).await {
+            Ok(read) => read,
+            // Servers often close TLS connections without notifying
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof && !response.is_empty() => 0,
+            Err(e) => return Err(e.into()),
+        };
+        if read == 0 {
+            break;
+        }
+        if response.is_empty() {
+            timings.ttfb = phase.elapsed().as_secs_f64();
+        }
+        response.extend_from_slice(&buffer[..read]);
+    }
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::item::probe::parse_response;
+
+    #[test]
+    fn response() {
+        let response = b"HTTP/1.1 301 Moved Permanently\r\nLocation: /\r\n\r\nmoved";
+        assert_eq!(parse_response(response).unwrap(), (301, 5));
+        assert!(parse_response(b"SSH-2.0-OpenSSH_9.6\r\n").is_err());
+    }
+}
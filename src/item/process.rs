@@ -0,0 +1,154 @@
+//! Resource usage of the processes of a specific program, e.g. a daemon, read from /proc
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::item::processes::{self, Snapshot};
+use crate::item::{now, ItemResult};
+use crate::keys::Key;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Process {
+    /// Matched against the command lines, like `ps aux | grep`, or the names of processes
+    /// without one
+    #[serde(default, with = "serde_regex")]
+    name_regex: Option<Regex>,
+    /// The file the process's ID is written to, e.g. `/run/nginx.pid`
+    pidfile: Option<PathBuf>,
+    #[serde(skip)]
+    snapshot: Snapshot,
+}
+
+/// What the process is started with, arguments separated by spaces
+fn command_line(pid: u32) -> Option<String> {
+    let cmdline = std::fs::read(format!("/proc/{}/cmdline", pid)).ok()?;
+    let args = cmdline
+        .split(|b| *b == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(String::from_utf8_lossy)
+        .collect::<Vec<_>>();
+    Some(args.join(" "))
+}
+
+/// Open file descriptors, `None` if not permitted, e.g. for processes of other users
+fn open_fds(pid: u32) -> Option<u64> {
+    Some(std::fs::read_dir(format!("/proc/{}/fd", pid)).ok()?.count() as u64)
+}
+
+/// The process of the ID in the file, none if the file or the process is gone
+fn from_pidfile(pidfile: &Path) -> Result<Vec<processes::Process>> {
+    let content = match std::fs::read_to_string(pidfile) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("Failed reading {}", pidfile.display())),
+    };
+    let pid = content
+        .trim()
+        .parse()
+        .with_context(|| format!("No process ID in {}", pidfile.display()))?;
+    Ok(processes::read_process(pid).into_iter().collect())
+}
+
+fn matching(regex: &Regex) -> Result<Vec<processes::Process>> {
+    let own = std::process::id();
+    Ok(processes::pids()?
+        .into_iter()
+        .filter(|pid| *pid != own)
+        .filter_map(processes::read_process)
+        .filter(|process| match command_line(process.pid) {
+            Some(cmdline) if !cmdline.is_empty() => regex.is_match(&cmdline),
+            _ => regex.is_match(&process.name),
+        })
+        .collect())
+}
+
+impl Process {
+    /// The usage of all matching processes summed up
+    pub async fn produce(&mut self, key: &str) -> Result<ItemResult> {
+        let (regex, pidfile) = (&self.name_regex, &self.pidfile);
+        let found = match (regex, pidfile) {
+            (Some(regex), None) => {
+                processes::with_usage(&mut self.snapshot, || matching(regex)).await?
+            }
+            (None, Some(pidfile)) => {
+                processes::with_usage(&mut self.snapshot, || from_pidfile(pidfile)).await?
+            }
+            _ => bail!("Either name_regex or pidfile is needed"),
+        };
+        let fds = found
+            .iter()
+            .filter_map(|process| open_fds(process.pid))
+            .reduce(|a, b| a + b);
+        let value = |name: &str| Key::from(format!("{}.{}", key, name));
+        let mut values = HashMap::from([
+            (value("count"), found.len() as f64),
+            (value("cpu"), found.iter().map(|p| p.cpu).sum()),
+            (
+                value("rss"),
+                found.iter().map(|p| p.memory).sum::<u64>() as f64,
+            ),
+            (
+                value("threads"),
+                found.iter().map(|p| p.threads).sum::<u64>() as f64,
+            ),
+        ]);
+        if let Some(fds) = fds {
+            values.insert(value("fds"), fds as f64);
+        }
+        Ok(ItemResult {
+            time: now(),
+            key: key.into(),
+            raw: found
+                .iter()
+                .map(|process| process.pid.to_string())
+                .collect::<Vec<_>>()
+                .join(" "),
+            values,
+            tags: BTreeMap::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use regex::Regex;
+
+    use crate::item::process::{from_pidfile, matching, open_fds};
+
+    #[test]
+    fn select() {
+        let seconds = format!("1234.{}", std::process::id());
+        let mut child = std::process::Command::new("sleep")
+            .arg(&seconds)
+            .spawn()
+            .unwrap();
+        let regex = Regex::new(&format!("^sleep {}$", regex::escape(&seconds))).unwrap();
+        // Until the child executed sleep
+        let mut found = Vec::new();
+        for _ in 0..100 {
+            found = matching(&regex).unwrap();
+            if !found.is_empty() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].pid, child.id());
+        assert_eq!(found[0].threads, 1);
+        assert!(open_fds(child.id()).is_some());
+
+        let pidfile =
+            std::env::temp_dir().join(format!("antikoerper-pidfile-{}", std::process::id()));
+        std::fs::write(&pidfile, format!("{}\n", child.id())).unwrap();
+        assert_eq!(from_pidfile(&pidfile).unwrap()[0].name, "sleep");
+        child.kill().unwrap();
+        child.wait().unwrap();
+        assert!(from_pidfile(&pidfile).unwrap().is_empty());
+        std::fs::remove_file(&pidfile).unwrap();
+        assert!(from_pidfile(&pidfile).unwrap().is_empty());
+    }
+}
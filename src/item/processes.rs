@@ -0,0 +1,166 @@
+//! The top processes by CPU or memory usage, read from /proc
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use crate::item::{now, ItemResult};
+
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortBy {
+    #[default]
+    Cpu,
+    Memory,
+}
+
+/// CPU time of all processes at the last run, to calculate the usage in between
+#[derive(Debug, Default, Clone)]
+pub struct Snapshot {
+    taken: Option<Instant>,
+    ticks: HashMap<u32, u64>,
+}
+
+pub struct Process {
+    pub pid: u32,
+    pub name: String,
+    /// utime + stime, in clock ticks
+    ticks: u64,
+    /// Resident set size in bytes
+    pub memory: u64,
+    pub threads: u64,
+    /// Percent of a single CPU since the last snapshot
+    pub cpu: f64,
+}
+
+/// Parse /proc/<pid>/stat, the name is in parentheses and may contain anything
+fn parse_stat(pid: u32, stat: &str, page_size: u64) -> Option<Process> {
+    let start = stat.find('(')?;
+    let end = stat.rfind(')')?;
+    let name = stat[start + 1..end].to_owned();
+    // Fields after the name, starting with the state (field 3 in proc(5))
+    let fields = stat[end + 1..].split_whitespace().collect::<Vec<_>>();
+    let utime = fields.get(11)?.parse::<u64>().ok()?;
+    let stime = fields.get(12)?.parse::<u64>().ok()?;
+    let threads = fields.get(17)?.parse::<u64>().ok()?;
+    let rss = fields.get(21)?.parse::<u64>().ok()?;
+    Some(Process {
+        pid,
+        name,
+        ticks: utime + stime,
+        memory: rss * page_size,
+        threads,
+        cpu: 0.0,
+    })
+}
+
+/// The IDs of all running processes
+pub fn pids() -> Result<Vec<u32>> {
+    let mut pids = Vec::new();
+    for entry in std::fs::read_dir("/proc").context("Failed reading /proc")? {
+        pids.extend(
+            entry?
+                .file_name()
+                .to_str()
+                .and_then(|n| n.parse::<u32>().ok()),
+        );
+    }
+    Ok(pids)
+}
+
+/// `None` if the process exited, which it may do at any time
+pub fn read_process(pid: u32) -> Option<Process> {
+    // SAFETY: sysconf has no preconditions
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) }.max(1) as u64;
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    parse_stat(pid, &stat, page_size)
+}
+
+fn read_processes() -> Result<Vec<Process>> {
+    Ok(pids()?.into_iter().filter_map(read_process).collect())
+}
+
+/// The processes `read` returns, with their usage since the last snapshot. Without one, a
+/// second snapshot is taken after a second, so even the first run reports the current
+/// usage.
+pub async fn with_usage(
+    snapshot: &mut Snapshot,
+    read: impl Fn() -> Result<Vec<Process>>,
+) -> Result<Vec<Process>> {
+    if snapshot.taken.is_none() {
+        take(snapshot, &read()?);
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    // SAFETY: sysconf has no preconditions
+    let ticks_per_second = unsafe { libc::sysconf(libc::_SC_CLK_TCK) }.max(1) as f64;
+    let elapsed = snapshot
+        .taken
+        .map(|t| t.elapsed().as_secs_f64())
+        .unwrap_or(1.0);
+    let mut processes = read()?;
+    for process in processes.iter_mut() {
+        // New processes count from their start
+        let before = snapshot.ticks.get(&process.pid).copied().unwrap_or(0);
+        process.cpu =
+            process.ticks.saturating_sub(before) as f64 / ticks_per_second / elapsed * 100.0;
+    }
+    take(snapshot, &processes);
+    Ok(processes)
+}
+
+pub async fn produce(
+    key: &str,
+    top: usize,
+    sort_by: SortBy,
+    snapshot: &mut Snapshot,
+) -> Result<Vec<ItemResult>> {
+    let mut processes = with_usage(snapshot, read_processes).await?;
+    match sort_by {
+        SortBy::Cpu => processes.sort_by(|a, b| b.cpu.total_cmp(&a.cpu)),
+        SortBy::Memory => processes.sort_by_key(|p| std::cmp::Reverse(p.memory)),
+    }
+    let time = now();
+    Ok(processes
+        .into_iter()
+        .take(top)
+        .enumerate()
+        .map(|(i, process)| {
+            let key = format!("{}.{}", key, i + 1);
+            ItemResult {
+                time,
+                values: HashMap::from([
+                    (format!("{}.cpu", key).into(), process.cpu),
+                    (format!("{}.memory", key).into(), process.memory as f64),
+                ]),
+                key: key.into(),
+                tags: BTreeMap::from([
+                    (String::from("process"), process.name.clone()),
+                    (String::from("pid"), process.pid.to_string()),
+                ]),
+                raw: process.name,
+            }
+        })
+        .collect())
+}
+
+fn take(snapshot: &mut Snapshot, processes: &[Process]) {
+    snapshot.taken = Some(Instant::now());
+    snapshot.ticks = processes.iter().map(|p| (p.pid, p.ticks)).collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::item::processes::parse_stat;
+
+    #[test]
+    fn stat_with_spaces() {
+        let stat = "1234 (Web Content) S 1 1234 1234 0 -1 4194560 100 0 0 0 250 50 0 0 20 0 30 0 500 1000000 300 18446744073709551615";
+        let process = parse_stat(1234, stat, 4096).unwrap();
+        assert_eq!(process.name, "Web Content");
+        assert_eq!(process.ticks, 300);
+        assert_eq!(process.memory, 300 * 4096);
+        assert_eq!(process.threads, 30);
+    }
+}
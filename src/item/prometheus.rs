@@ -0,0 +1,180 @@
+//! Metrics scraped from a Prometheus exporter, e.g. node_exporter on another host
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::item::{now, ItemResult};
+use crate::keys::Key;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Scrape {
+    /// e.g. `http://host:9100/metrics`
+    url: String,
+    /// Matched against the names of metrics, all are kept if not given
+    #[serde(default, with = "serde_regex")]
+    metrics: Option<Regex>,
+    /// Seconds
+    #[serde(default = "timeout_default")]
+    timeout: u64,
+    #[serde(skip)]
+    client: reqwest::Client,
+}
+
+fn timeout_default() -> u64 {
+    10
+}
+
+/// A line of the text exposition format, `name{label="value",...} value [timestamp]`
+#[derive(Debug)]
+struct Sample {
+    name: String,
+    labels: BTreeMap<String, String>,
+    value: f64,
+}
+
+/// The quoted value of a label, after the opening quote, and the rest after the closing one
+fn label_value(text: &str) -> Result<(String, &str)> {
+    let mut value = String::new();
+    let mut chars = text.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((value, &text[i + 1..])),
+            '\\' => match chars.next() {
+                Some((_, 'n')) => value.push('\n'),
+                Some((_, c)) => value.push(c),
+                None => break,
+            },
+            c => value.push(c),
+        }
+    }
+    bail!("Unterminated label value")
+}
+
+fn parse_sample(line: &str) -> Result<Sample> {
+    let end = line
+        .find(|c: char| c == '{' || c.is_whitespace())
+        .ok_or_else(|| anyhow!("No value"))?;
+    let name = line[..end].to_owned();
+    let mut rest = &line[end..];
+    let mut labels = BTreeMap::new();
+    if let Some(mut inner) = rest.strip_prefix('{') {
+        loop {
+            inner = inner.trim_start_matches([' ', ',']);
+            if let Some(after) = inner.strip_prefix('}') {
+                rest = after;
+                break;
+            }
+            let (label, after) = inner
+                .split_once("=\"")
+                .ok_or_else(|| anyhow!("Expected label=\"value\""))?;
+            let (value, after) = label_value(after)?;
+            labels.insert(label.trim().to_owned(), value);
+            inner = after;
+        }
+    }
+    // The timestamp is ignored, exporters rarely give one
+    let value = rest
+        .split_whitespace()
+        .next()
+        .ok_or_else(|| anyhow!("No value"))?;
+    Ok(Sample {
+        name,
+        labels,
+        value: value
+            .parse()
+            .with_context(|| format!("Invalid value {}", value))?,
+    })
+}
+
+/// One result per set of labels, with the labels as tags and the values `<key>.<metric>`.
+/// NaN and infinite values are left out.
+fn results(key: &str, body: &str, metrics: Option<&Regex>) -> Result<Vec<ItemResult>> {
+    let time = now();
+    let mut sets = BTreeMap::<BTreeMap<String, String>, HashMap<Key, f64>>::new();
+    for line in body.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let sample = parse_sample(line).with_context(|| format!("Invalid sample {:?}", line))?;
+        if !sample.value.is_finite() || metrics.is_some_and(|m| !m.is_match(&sample.name)) {
+            continue;
+        }
+        sets.entry(sample.labels)
+            .or_default()
+            .insert(format!("{}.{}", key, sample.name).into(), sample.value);
+    }
+    Ok(sets
+        .into_iter()
+        .map(|(tags, values)| ItemResult {
+            time,
+            key: key.into(),
+            raw: String::new(),
+            values,
+            tags,
+        })
+        .collect())
+}
+
+impl Scrape {
+    pub async fn produce(&self, key: &str) -> Result<Vec<ItemResult>> {
+        let response = self
+            .client
+            .get(&self.url)
+            .header("Accept", "text/plain")
+            .timeout(Duration::from_secs(self.timeout))
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .with_context(|| format!("Failed scraping {}", self.url))?;
+        let body = response
+            .text()
+            .await
+            .with_context(|| format!("Failed reading the metrics of {}", self.url))?;
+        results(key, &body, self.metrics.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use regex::Regex;
+
+    use crate::item::prometheus::results;
+
+    #[test]
+    fn exposition() {
+        let body = r#"
+# HELP node_load1 1m load average.
+# TYPE node_load1 gauge
+node_load1 0.42
+node_cpu_seconds_total{cpu="0",mode="idle"} 1234.5
+node_cpu_seconds_total{cpu="0",mode="user"} 56
+node_cpu_guest_seconds_total{cpu="0",mode="user"} 0
+node_filesystem_avail_bytes{device="/dev/sda1",mountpoint="/a \"b\"\\c"} 1e+09 1700000000000
+node_scrape_collector_duration_seconds NaN
+"#;
+        let all = results("nx", body, None).unwrap();
+        assert_eq!(all.len(), 4);
+        assert_eq!(all[0].values["nx.node_load1"], 0.42);
+        assert!(all[0].tags.is_empty());
+        assert_eq!(all[0].values.len(), 1);
+        let user = all
+            .iter()
+            .find(|r| r.tags.get("mode").is_some_and(|m| m == "user"));
+        assert_eq!(user.unwrap().values.len(), 2);
+        let fs = all.iter().find(|r| r.tags.contains_key("device")).unwrap();
+        assert_eq!(fs.tags["mountpoint"], r#"/a "b"\c"#);
+        assert_eq!(fs.values["nx.node_filesystem_avail_bytes"], 1e9);
+
+        let cpu = Regex::new("^node_cpu_seconds").unwrap();
+        let filtered = results("nx", body, Some(&cpu)).unwrap();
+        assert_eq!(filtered.len(), 2);
+        assert!(filtered.iter().all(|r| r.values.len() == 1));
+
+        assert!(results("nx", "node_load1{cpu=\"0} 1", None).is_err());
+        assert!(results("nx", "node_load1 high", None).is_err());
+    }
+}
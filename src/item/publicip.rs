@@ -0,0 +1,152 @@
+//! The public IP address of the connection, e.g. of a home connection with a dynamic
+//! address, and for how long it has had it
+
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use log::warn;
+use serde::Deserialize;
+
+use crate::item::{now, ItemResult};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PublicIp {
+    /// Answers with the address in plain text, or as `ip` of a JSON object
+    #[serde(default = "url_default")]
+    url: String,
+    /// Seconds
+    #[serde(default = "timeout_default")]
+    timeout: u64,
+    #[serde(skip)]
+    client: reqwest::Client,
+    /// Where the current address and since when it is used are kept across restarts
+    #[serde(skip)]
+    state: Option<PathBuf>,
+    /// Seconds since the epoch
+    #[serde(skip)]
+    current: Option<(IpAddr, u64)>,
+}
+
+fn url_default() -> String {
+    String::from("https://api.ipify.org")
+}
+
+fn timeout_default() -> u64 {
+    10
+}
+
+/// Fields of JSON answers kept as tags, e.g. of ipinfo.io or ip-api.com
+const GEO_FIELDS: [&str; 6] = ["country", "countryCode", "region", "city", "org", "isp"];
+
+/// The address and the geographic information an endpoint answered with
+fn parse_answer(body: &str) -> Result<(IpAddr, BTreeMap<String, String>)> {
+    let body = body.trim();
+    if !body.starts_with('{') {
+        let ip = body
+            .parse()
+            .with_context(|| format!("Not an IP address: {}", body))?;
+        return Ok((ip, BTreeMap::new()));
+    }
+    let answer: serde_json::Map<String, serde_json::Value> = serde_json::from_str(body)?;
+    let ip = answer
+        .get("ip")
+        .or_else(|| answer.get("query"))
+        .and_then(|ip| ip.as_str())
+        .context("No ip in the answer")?
+        .parse()
+        .context("Invalid ip in the answer")?;
+    let geo = GEO_FIELDS
+        .iter()
+        .filter_map(|field| {
+            let value = answer.get(*field)?.as_str()?;
+            Some((field.to_string(), value.to_owned()))
+        })
+        .collect();
+    Ok((ip, geo))
+}
+
+impl PublicIp {
+    /// Continue with the address known before the restart
+    pub fn restore(&mut self, state: PathBuf) {
+        self.current = std::fs::read_to_string(&state).ok().and_then(|content| {
+            let (ip, since) = content.trim().split_once(' ')?;
+            Some((ip.parse().ok()?, since.parse().ok()?))
+        });
+        self.state = Some(state);
+    }
+
+    fn persist(&self) -> Result<()> {
+        let (Some(state), Some((ip, since))) = (&self.state, self.current) else {
+            return Ok(());
+        };
+        if let Some(dir) = state.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(state, format!("{} {}\n", ip, since))?;
+        Ok(())
+    }
+
+    pub async fn produce(&mut self, key: &str) -> Result<ItemResult> {
+        let body = self
+            .client
+            .get(&self.url)
+            .timeout(Duration::from_secs(self.timeout))
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .with_context(|| format!("Failed asking {}", self.url))?
+            .text()
+            .await
+            .with_context(|| format!("Failed reading the answer of {}", self.url))?;
+        let (ip, tags) = parse_answer(&body)?;
+        let time = now();
+        let since = match self.current {
+            Some((current, since)) if current == ip => since,
+            _ => {
+                self.current = Some((ip, time.as_secs()));
+                if let Err(e) = self.persist() {
+                    warn!("item {}: failed persisting the address: {}", key, e);
+                }
+                time.as_secs()
+            }
+        };
+        Ok(ItemResult {
+            time,
+            key: key.into(),
+            raw: ip.to_string(),
+            values: HashMap::from([(
+                format!("{}.age", key).into(),
+                time.as_secs().saturating_sub(since) as f64,
+            )]),
+            tags,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use crate::item::publicip::parse_answer;
+
+    #[test]
+    fn answers() {
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+        assert_eq!(parse_answer("203.0.113.7\n").unwrap().0, ip);
+        let (parsed, geo) = parse_answer(
+            r#"{"ip": "203.0.113.7", "city": "Berlin", "country": "DE", "loc": "52.5,13.4", "org": "AS3320 Deutsche Telekom AG"}"#,
+        )
+        .unwrap();
+        assert_eq!(parsed, ip);
+        assert_eq!(geo.len(), 3);
+        assert_eq!(geo["city"], "Berlin");
+        let (parsed, geo) =
+            parse_answer(r#"{"query": "2001:db8::1", "countryCode": "NL"}"#).unwrap();
+        assert_eq!(parsed, "2001:db8::1".parse::<IpAddr>().unwrap());
+        assert_eq!(geo["countryCode"], "NL");
+        assert!(parse_answer("<html>rate limited</html>").is_err());
+    }
+}
@@ -0,0 +1,171 @@
+//! Lines read from a serial device like an Arduino, a UPS or an energy meter, digested as
+//! they arrive
+
+use std::fs::File;
+use std::io::Read;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+use tokio::io::unix::AsyncFd;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Port {
+    /// e.g. `/dev/ttyUSB0`
+    pub device: PathBuf,
+    #[serde(default = "baud_default")]
+    baud: u32,
+    /// Ends every line, e.g. `\r\n`
+    #[serde(default = "line_terminator_default")]
+    line_terminator: String,
+}
+
+fn baud_default() -> u32 {
+    9600
+}
+
+fn line_terminator_default() -> String {
+    String::from("\n")
+}
+
+fn speed(baud: u32) -> Result<libc::speed_t> {
+    Ok(match baud {
+        1200 => libc::B1200,
+        2400 => libc::B2400,
+        4800 => libc::B4800,
+        9600 => libc::B9600,
+        19200 => libc::B19200,
+        38400 => libc::B38400,
+        57600 => libc::B57600,
+        115200 => libc::B115200,
+        230400 => libc::B230400,
+        460800 => libc::B460800,
+        500000 => libc::B500000,
+        921600 => libc::B921600,
+        1000000 => libc::B1000000,
+        baud => bail!("Unsupported baud rate {}", baud),
+    })
+}
+
+/// Raw mode, 8 data bits without parity, at the given speed
+fn configure(file: &File, speed: libc::speed_t) -> Result<()> {
+    let fd = file.as_raw_fd();
+    let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+    if unsafe { libc::tcgetattr(fd, &mut termios) } != 0 {
+        return Err(std::io::Error::last_os_error()).context("Not a serial device");
+    }
+    unsafe {
+        libc::cfmakeraw(&mut termios);
+        libc::cfsetispeed(&mut termios, speed);
+        libc::cfsetospeed(&mut termios, speed);
+    }
+    // Ignore modem control lines, most devices don't use them
+    termios.c_cflag |= libc::CLOCAL | libc::CREAD;
+    if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &termios) } != 0 {
+        return Err(std::io::Error::last_os_error()).context("Failed configuring the device");
+    }
+    Ok(())
+}
+
+/// An opened device, read without blocking a thread
+pub struct Connection {
+    fd: AsyncFd<File>,
+    lines: Lines,
+}
+
+impl Port {
+    pub fn open(&self) -> Result<Connection> {
+        let speed = speed(self.baud)?;
+        if self.line_terminator.is_empty() {
+            bail!("line_terminator must not be empty");
+        }
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NOCTTY | libc::O_NONBLOCK)
+            .open(&self.device)
+            .with_context(|| format!("Failed opening {}", self.device.display()))?;
+        configure(&file, speed).with_context(|| self.device.display().to_string())?;
+        Ok(Connection {
+            fd: AsyncFd::new(file)?,
+            lines: Lines::new(self.line_terminator.as_bytes()),
+        })
+    }
+}
+
+impl Connection {
+    /// The complete lines received next
+    pub async fn read(&mut self) -> Result<Vec<String>> {
+        let mut buffer = [0; 4096];
+        loop {
+            let mut guard = self.fd.readable().await?;
+            match guard.try_io(|fd| fd.get_ref().read(&mut buffer)) {
+                Ok(Ok(0)) => return Err(anyhow!("The device was closed")),
+                Ok(Ok(length)) => {
+                    let lines = self.lines.push(&buffer[..length]);
+                    if !lines.is_empty() {
+                        return Ok(lines);
+                    }
+                }
+                Ok(Err(e)) => return Err(e.into()),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+/// Splits the bytes received into lines
+pub struct Lines {
+    terminator: Vec<u8>,
+    pending: Vec<u8>,
+}
+
+/// Devices sending garbage without terminators would otherwise fill the memory
+const MAX_LINE: usize = 65536;
+
+impl Lines {
+    pub fn new(terminator: &[u8]) -> Self {
+        Self {
+            terminator: terminator.to_vec(),
+            pending: Vec::new(),
+        }
+    }
+
+    /// The lines completed by `bytes`
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<String> {
+        self.pending.extend_from_slice(bytes);
+        let mut lines = Vec::new();
+        while let Some(end) = self
+            .pending
+            .windows(self.terminator.len())
+            .position(|w| w == self.terminator)
+        {
+            let line = self
+                .pending
+                .drain(..end + self.terminator.len())
+                .take(end)
+                .collect::<Vec<_>>();
+            lines.push(String::from_utf8_lossy(&line).into_owned());
+        }
+        if self.pending.len() > MAX_LINE {
+            self.pending.clear();
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::item::serial::{speed, Lines};
+
+    #[test]
+    fn lines() {
+        let mut lines = Lines::new(b"\r\n");
+        assert!(lines.push(b"temp=21").is_empty());
+        assert_eq!(lines.push(b".5\r\nhum=40\r"), ["temp=21.5"]);
+        assert_eq!(lines.push(b"\n\r\n"), ["hum=40", ""]);
+        assert!(speed(115200).is_ok());
+        assert!(speed(1234).is_err());
+    }
+}
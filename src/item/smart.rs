@@ -0,0 +1,256 @@
+//! SMART health of disks, read with smartctl of smartmontools, to notice failing disks early
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use log::warn;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::item::{now, ItemResult};
+use crate::keys::Key;
+use crate::priority::Priority;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Smart {
+    /// e.g. `/dev/sda`, those `smartctl --scan` finds if empty
+    #[serde(default)]
+    devices: Vec<PathBuf>,
+    /// Also read disks that are spun down, waking them up
+    #[serde(default)]
+    wake: bool,
+    #[serde(default = "path_default")]
+    path: PathBuf,
+}
+
+fn path_default() -> PathBuf {
+    PathBuf::from("smartctl")
+}
+
+/// Bits of smartctl's exit status for failing to parse the command line or to open the
+/// device, other bits tell about the disk's health
+const FATAL: i32 = 0b11;
+
+/// Attributes of ATA disks by ID, their raw values are reported
+const ATTRIBUTES: [(u64, &str); 4] = [
+    (5, "reallocated_sectors"),
+    (197, "pending_sectors"),
+    (198, "uncorrectable_sectors"),
+    (199, "crc_errors"),
+];
+
+/// The values of a device from the output of `smartctl -j -a`
+fn parse(output: &Value) -> Vec<(&'static str, f64)> {
+    let number = |pointer: &str| output.pointer(pointer).and_then(Value::as_f64);
+    let mut values = vec![
+        (
+            "healthy",
+            output
+                .pointer("/smart_status/passed")
+                .and_then(Value::as_bool)
+                .map(|passed| if passed { 1.0 } else { 0.0 }),
+        ),
+        ("temperature", number("/temperature/current")),
+        ("power_on_hours", number("/power_on_time/hours")),
+        ("power_cycles", number("/power_cycle_count")),
+        // NVMe
+        (
+            "percentage_used",
+            number("/nvme_smart_health_information_log/percentage_used"),
+        ),
+        (
+            "available_spare",
+            number("/nvme_smart_health_information_log/available_spare"),
+        ),
+        (
+            "media_errors",
+            number("/nvme_smart_health_information_log/media_errors"),
+        ),
+        (
+            "critical_warning",
+            number("/nvme_smart_health_information_log/critical_warning"),
+        ),
+    ];
+    let table = output
+        .pointer("/ata_smart_attributes/table")
+        .and_then(Value::as_array);
+    for (id, name) in ATTRIBUTES {
+        let raw = table.and_then(|table| {
+            table
+                .iter()
+                .find(|attribute| attribute["id"].as_u64() == Some(id))
+                .and_then(|attribute| attribute.pointer("/raw/value"))
+                .and_then(Value::as_f64)
+        });
+        values.push((name, raw));
+    }
+    values
+        .into_iter()
+        .filter_map(|(name, value)| Some((name, value?)))
+        .collect()
+}
+
+/// The messages of smartctl, e.g. why it failed or that the disk is spun down
+fn messages(output: &Value) -> String {
+    output
+        .pointer("/smartctl/messages")
+        .and_then(Value::as_array)
+        .map(|messages| {
+            messages
+                .iter()
+                .filter_map(|message| message["string"].as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        })
+        .unwrap_or_default()
+}
+
+impl Smart {
+    /// The JSON output, and the exit status
+    async fn smartctl(&self, args: &[&str], priority: Priority) -> Result<(Value, i32)> {
+        let mut command = tokio::process::Command::new(&self.path);
+        priority.command(&mut command);
+        let output = command
+            .arg("-j")
+            .args(args)
+            .kill_on_drop(true)
+            .output()
+            .await
+            .with_context(|| format!("Failed running {}", self.path.display()))?;
+        let json = serde_json::from_slice(&output.stdout).with_context(|| {
+            format!(
+                "{} gave no JSON: {}",
+                self.path.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+        })?;
+        Ok((json, output.status.code().unwrap_or(FATAL)))
+    }
+
+    async fn devices(&self, priority: Priority) -> Result<Vec<PathBuf>> {
+        if !self.devices.is_empty() {
+            return Ok(self.devices.clone());
+        }
+        let (scan, _) = self.smartctl(&["--scan"], priority).await?;
+        let devices = scan["devices"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|device| device["name"].as_str().map(PathBuf::from))
+            .collect::<Vec<_>>();
+        if devices.is_empty() {
+            bail!("smartctl found no devices");
+        }
+        Ok(devices)
+    }
+
+    async fn device(&self, key: &str, device: &Path, priority: Priority) -> Result<ItemResult> {
+        let name = device
+            .file_name()
+            .unwrap_or(device.as_os_str())
+            .to_string_lossy()
+            .replace('.', "_");
+        let key = format!("{}.{}", key, name);
+        let device_arg = device.to_string_lossy();
+        // Exits with 0 and without data if the disk is spun down
+        let mut args = vec!["-a", &device_arg];
+        if !self.wake {
+            args.extend(["-n", "standby,0"]);
+        }
+        let (output, status) = self.smartctl(&args, priority).await?;
+        if status & FATAL != 0 {
+            bail!(
+                "smartctl failed for {}: {}",
+                device.display(),
+                messages(&output)
+            );
+        }
+        let mut tags = BTreeMap::from([(String::from("device"), device_arg.to_string())]);
+        for (tag, field) in [("model", "model_name"), ("serial", "serial_number")] {
+            if let Some(value) = output[field].as_str() {
+                tags.insert(String::from(tag), value.to_owned());
+            }
+        }
+        let mut values = parse(&output)
+            .into_iter()
+            .map(|(name, value)| (Key::from(format!("{}.{}", key, name)), value))
+            .collect::<HashMap<_, _>>();
+        let standby = messages(&output).to_lowercase().contains("standby mode");
+        values.insert(
+            format!("{}.standby", key).into(),
+            if standby { 1.0 } else { 0.0 },
+        );
+        Ok(ItemResult {
+            time: now(),
+            raw: messages(&output),
+            key: key.into(),
+            values,
+            tags,
+        })
+    }
+
+    /// One result per device, devices that cannot be read are logged and left out
+    pub async fn produce(&self, key: &str, priority: Priority) -> Result<Vec<ItemResult>> {
+        let mut results = Vec::new();
+        let mut failed = None;
+        for device in self.devices(priority).await? {
+            match self.device(key, &device, priority).await {
+                Ok(result) => results.push(result),
+                Err(e) => {
+                    warn!("item {}: {:#}", key, e);
+                    failed = Some(e);
+                }
+            }
+        }
+        match failed {
+            Some(e) if results.is_empty() => Err(e),
+            _ => Ok(results),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::item::smart::parse;
+
+    #[test]
+    fn smartctl_output() {
+        let ata = serde_json::json!({
+            "model_name": "WDC WD40EFRX-68N32N0",
+            "smart_status": {"passed": true},
+            "temperature": {"current": 34},
+            "power_on_time": {"hours": 31337},
+            "power_cycle_count": 120,
+            "ata_smart_attributes": {"table": [
+                {"id": 5, "name": "Reallocated_Sector_Ct", "raw": {"value": 8, "string": "8"}},
+                {"id": 9, "name": "Power_On_Hours", "raw": {"value": 31337, "string": "31337"}},
+                {"id": 197, "name": "Current_Pending_Sector", "raw": {"value": 0, "string": "0"}}
+            ]}
+        });
+        let values = parse(&ata);
+        assert_eq!(
+            values,
+            [
+                ("healthy", 1.0),
+                ("temperature", 34.0),
+                ("power_on_hours", 31337.0),
+                ("power_cycles", 120.0),
+                ("reallocated_sectors", 8.0),
+                ("pending_sectors", 0.0),
+            ]
+        );
+
+        let nvme = serde_json::json!({
+            "smart_status": {"passed": false},
+            "nvme_smart_health_information_log": {
+                "critical_warning": 4, "percentage_used": 97, "available_spare": 100,
+                "media_errors": 2
+            }
+        });
+        let values = parse(&nvme);
+        assert_eq!(values[0], ("healthy", 0.0));
+        assert!(values.contains(&("percentage_used", 97.0)));
+        assert!(values.contains(&("critical_warning", 4.0)));
+    }
+}
@@ -0,0 +1,665 @@
+//! Values of SNMP agents like routers, switches and UPSes, read with GET requests
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{anyhow, bail, Context, Result};
+use hmac::{Hmac, Mac};
+use log::warn;
+use md5::Md5;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
+use sha1::{Digest, Sha1};
+use tokio::net::UdpSocket;
+
+use crate::item::asn1::tlv;
+use crate::item::{now, ItemResult};
+use crate::secret::Secret;
+
+const GET_REQUEST: u8 = 0xa0;
+const REPORT: u8 = 0xa8;
+/// Length of the HMAC-MD5-96 and HMAC-SHA-96 authentication parameters
+const AUTH_LENGTH: usize = 12;
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+pub enum Version {
+    #[serde(rename = "1")]
+    V1,
+    #[default]
+    #[serde(rename = "2c")]
+    V2c,
+    #[serde(rename = "3")]
+    V3,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthProtocol {
+    Md5,
+    #[default]
+    Sha,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Agent {
+    host: String,
+    #[serde(default = "port_default")]
+    port: u16,
+    #[serde(default)]
+    version: Version,
+    /// Versions 1 and 2c
+    #[serde(default = "community_default")]
+    community: Secret,
+    /// Version 3, with the user-based security model
+    user: Option<String>,
+    #[serde(default)]
+    auth_protocol: AuthProtocol,
+    /// Messages are not authenticated without it
+    auth_password: Option<Secret>,
+    /// Encryption is not supported, configurations asking for it are rejected rather than
+    /// sending in plain text
+    #[serde(default, rename = "priv_password", deserialize_with = "no_privacy")]
+    _priv_password: (),
+    /// Labels of the values, and the OIDs to read them from
+    oids: BTreeMap<String, String>,
+    /// Seconds
+    #[serde(default = "timeout_default")]
+    timeout: u64,
+    /// Derived from `auth_password`, which takes a while
+    #[serde(skip)]
+    password_key: Arc<OnceLock<Vec<u8>>>,
+}
+
+fn port_default() -> u16 {
+    161
+}
+
+fn community_default() -> Secret {
+    Secret::new(String::from("public"))
+}
+
+fn timeout_default() -> u64 {
+    10
+}
+
+fn no_privacy<'de, D: Deserializer<'de>>(_: D) -> Result<(), D::Error> {
+    Err(D::Error::custom(
+        "encryption (priv_password) is not supported",
+    ))
+}
+
+/// A BER encoded tag, length and content
+fn encode(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut encoded = vec![tag];
+    if content.len() < 0x80 {
+        encoded.push(content.len() as u8);
+    } else {
+        let length = content.len().to_be_bytes();
+        let skip = length.iter().take_while(|b| **b == 0).count();
+        encoded.push(0x80 | (length.len() - skip) as u8);
+        encoded.extend_from_slice(&length[skip..]);
+    }
+    encoded.extend_from_slice(content);
+    encoded
+}
+
+fn integer(value: i64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    // Leading bytes only repeating the sign are left out
+    let skip = (0..7)
+        .take_while(|i| {
+            (bytes[*i] == 0 && bytes[i + 1] & 0x80 == 0)
+                || (bytes[*i] == 0xff && bytes[i + 1] & 0x80 != 0)
+        })
+        .count();
+    encode(0x02, &bytes[skip..])
+}
+
+fn parse_oid(oid: &str) -> Result<Vec<u32>> {
+    let arcs = oid
+        .trim_start_matches('.')
+        .split('.')
+        .map(str::parse)
+        .collect::<Result<Vec<u32>, _>>()
+        .with_context(|| format!("Invalid OID {}", oid))?;
+    if arcs.len() < 2 || arcs[0] > 2 || arcs[1] > u32::MAX - 80 || (arcs[0] < 2 && arcs[1] >= 40) {
+        bail!("Invalid OID {}", oid);
+    }
+    Ok(arcs)
+}
+
+fn encode_oid(arcs: &[u32]) -> Vec<u8> {
+    let mut content = Vec::new();
+    for arc in std::iter::once(arcs[0] * 40 + arcs[1]).chain(arcs[2..].iter().copied()) {
+        // Base 128, all but the last byte with the high bit set
+        let mut bytes = vec![(arc & 0x7f) as u8];
+        let mut rest = arc >> 7;
+        while rest > 0 {
+            bytes.push(0x80 | (rest & 0x7f) as u8);
+            rest >>= 7;
+        }
+        content.extend(bytes.iter().rev());
+    }
+    encode(0x06, &content)
+}
+
+fn decode_oid(content: &[u8]) -> String {
+    let mut arcs = Vec::new();
+    let mut arc = 0u64;
+    for byte in content {
+        arc = (arc << 7) | (byte & 0x7f) as u64;
+        if byte & 0x80 == 0 {
+            if arcs.is_empty() {
+                let first = (arc / 40).min(2);
+                arcs.push(first);
+                arcs.push(arc - first * 40);
+            } else {
+                arcs.push(arc);
+            }
+            arc = 0;
+        }
+    }
+    arcs.iter()
+        .map(|arc| arc.to_string())
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+fn decode_integer(content: &[u8]) -> i64 {
+    let sign = if content.first().is_some_and(|b| b & 0x80 != 0) {
+        -1
+    } else {
+        0
+    };
+    content
+        .iter()
+        .fold(sign, |value, b| (value << 8) | *b as i64)
+}
+
+/// Numbers, counters, gauges, time ticks, and strings containing a number
+fn decode_value(tag: u8, content: &[u8]) -> Option<f64> {
+    match tag {
+        0x02 => Some(decode_integer(content) as f64),
+        // Counter32, Gauge32, TimeTicks, Counter64
+        0x41..=0x43 | 0x46 => Some(
+            content
+                .iter()
+                .fold(0u64, |value, b| (value << 8) | *b as u64) as f64,
+        ),
+        0x04 => std::str::from_utf8(content).ok()?.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+fn get_request(request_id: i64, oids: &[Vec<u32>]) -> Vec<u8> {
+    let varbinds = oids
+        .iter()
+        .flat_map(|oid| encode(0x30, &[encode_oid(oid), vec![0x05, 0x00]].concat()))
+        .collect::<Vec<_>>();
+    encode(
+        GET_REQUEST,
+        &[
+            integer(request_id),
+            integer(0),
+            integer(0),
+            encode(0x30, &varbinds),
+        ]
+        .concat(),
+    )
+}
+
+#[derive(Debug)]
+struct Pdu {
+    tag: u8,
+    request_id: i64,
+    error_status: i64,
+    error_index: i64,
+    varbinds: Vec<(String, Option<f64>)>,
+}
+
+fn decode_pdu(input: &[u8]) -> Result<Pdu> {
+    let (tag, content, _) = tlv(input)?;
+    let (_, request_id, rest) = tlv(content)?;
+    let (_, error_status, rest) = tlv(rest)?;
+    let (_, error_index, rest) = tlv(rest)?;
+    let (_, mut list, _) = tlv(rest)?;
+    let mut varbinds = Vec::new();
+    while !list.is_empty() {
+        let (_, varbind, rest) = tlv(list)?;
+        list = rest;
+        let (_, oid, value) = tlv(varbind)?;
+        let (tag, value, _) = tlv(value)?;
+        varbinds.push((decode_oid(oid), decode_value(tag, value)));
+    }
+    Ok(Pdu {
+        tag,
+        request_id: decode_integer(request_id),
+        error_status: decode_integer(error_status),
+        error_index: decode_integer(error_index),
+        varbinds,
+    })
+}
+
+/// The PDU of a version 1 or 2c message
+fn decode_community(message: &[u8]) -> Result<Pdu> {
+    let (_, content, _) = tlv(message)?;
+    let (_, _version, rest) = tlv(content)?;
+    let (_, _community, rest) = tlv(rest)?;
+    decode_pdu(rest)
+}
+
+/// What an agent told about itself in its answer to a version 3 message
+#[derive(Debug, Default)]
+struct Engine {
+    id: Vec<u8>,
+    boots: i64,
+    time: i64,
+}
+
+/// A version 3 message, and where the authentication parameters are in it
+struct Secured {
+    message: Vec<u8>,
+    auth: Option<usize>,
+}
+
+fn encode_v3(message_id: i64, engine: &Engine, user: &str, auth: bool, pdu: &[u8]) -> Secured {
+    // Reportable, and authenticated if there is a password
+    let flags = if auth { 0x05 } else { 0x04 };
+    let head = [
+        integer(3),
+        encode(
+            0x30,
+            &[
+                integer(message_id),
+                integer(65507),
+                encode(0x04, &[flags]),
+                integer(3),
+            ]
+            .concat(),
+        ),
+    ]
+    .concat();
+    let before_auth = [
+        encode(0x04, &engine.id),
+        integer(engine.boots),
+        integer(engine.time),
+        encode(0x04, user.as_bytes()),
+    ]
+    .concat();
+    let auth_parameters = encode(0x04, &vec![0; if auth { AUTH_LENGTH } else { 0 }]);
+    let parameters = encode(
+        0x30,
+        &[
+            before_auth.clone(),
+            auth_parameters.clone(),
+            encode(0x04, &[]),
+        ]
+        .concat(),
+    );
+    let security = encode(0x04, &parameters);
+    let scoped = encode(
+        0x30,
+        &[encode(0x04, &engine.id), encode(0x04, &[]), pdu.to_vec()].concat(),
+    );
+    let content_length = head.len() + security.len() + scoped.len();
+    let message = encode(0x30, &[head.clone(), security.clone(), scoped].concat());
+    let auth = auth.then(|| {
+        // After the headers of the message, of the octet string and of the sequence
+        // of the security parameters, and after the header of the parameter itself
+        (message.len() - content_length)
+            + head.len()
+            + (security.len() - parameters.len())
+            + (parameters.len() - (before_auth.len() + auth_parameters.len() + 2))
+            + before_auth.len()
+            + 2
+    });
+    Secured { message, auth }
+}
+
+/// The engine of the agent, the offset and content of the authentication parameters,
+/// and the PDU of a version 3 message
+fn decode_v3(message: &[u8]) -> Result<(Engine, usize, &[u8], Pdu)> {
+    let (_, content, _) = tlv(message)?;
+    let (_, _version, rest) = tlv(content)?;
+    let (_, _global, rest) = tlv(rest)?;
+    let (_, security, scoped) = tlv(rest)?;
+    let (_, security, _) = tlv(security)?;
+    let (_, id, rest) = tlv(security)?;
+    let (_, boots, rest) = tlv(rest)?;
+    let (_, time, rest) = tlv(rest)?;
+    let (_, _user, rest) = tlv(rest)?;
+    let (_, auth, _) = tlv(rest)?;
+    let (tag, scoped, _) = tlv(scoped)?;
+    if tag != 0x30 {
+        bail!("Encrypted answers are not supported");
+    }
+    let (_, _context_engine, rest) = tlv(scoped)?;
+    let (_, _context_name, rest) = tlv(rest)?;
+    let engine = Engine {
+        id: id.to_vec(),
+        boots: decode_integer(boots),
+        time: decode_integer(time),
+    };
+    let offset = auth.as_ptr() as usize - message.as_ptr() as usize;
+    Ok((engine, offset, auth, decode_pdu(rest)?))
+}
+
+/// RFC 3414 A.2, the password repeated to a megabyte is hashed
+fn password_key<D: Digest>(password: &[u8]) -> Vec<u8> {
+    let mut digest = D::new();
+    let mut repeated = password.iter().cycle();
+    let mut chunk = [0; 64];
+    for _ in 0..(1 << 20) / chunk.len() {
+        for byte in chunk.iter_mut() {
+            *byte = *repeated.next().expect("password is not empty");
+        }
+        digest.update(chunk);
+    }
+    digest.finalize().to_vec()
+}
+
+/// The key for one agent
+fn localize<D: Digest>(key: &[u8], engine: &[u8]) -> Vec<u8> {
+    D::new()
+        .chain_update(key)
+        .chain_update(engine)
+        .chain_update(key)
+        .finalize()
+        .to_vec()
+}
+
+impl AuthProtocol {
+    fn password_key(self, password: &str) -> Vec<u8> {
+        match self {
+            AuthProtocol::Md5 => password_key::<Md5>(password.as_bytes()),
+            AuthProtocol::Sha => password_key::<Sha1>(password.as_bytes()),
+        }
+    }
+
+    fn localize(self, key: &[u8], engine: &[u8]) -> Vec<u8> {
+        match self {
+            AuthProtocol::Md5 => localize::<Md5>(key, engine),
+            AuthProtocol::Sha => localize::<Sha1>(key, engine),
+        }
+    }
+
+    /// HMAC-MD5-96 or HMAC-SHA-96 of a message with zeroed authentication parameters
+    fn sign(self, key: &[u8], message: &[u8]) -> Vec<u8> {
+        let mut mac = match self {
+            AuthProtocol::Md5 => {
+                let mut mac =
+                    Hmac::<Md5>::new_from_slice(key).expect("HMAC accepts keys of any length");
+                mac.update(message);
+                mac.finalize().into_bytes().to_vec()
+            }
+            AuthProtocol::Sha => {
+                let mut mac =
+                    Hmac::<Sha1>::new_from_slice(key).expect("HMAC accepts keys of any length");
+                mac.update(message);
+                mac.finalize().into_bytes().to_vec()
+            }
+        };
+        mac.truncate(AUTH_LENGTH);
+        mac
+    }
+}
+
+fn error_name(status: i64) -> String {
+    match status {
+        1 => String::from("tooBig"),
+        2 => String::from("noSuchName"),
+        3 => String::from("badValue"),
+        5 => String::from("genErr"),
+        status => format!("error status {}", status),
+    }
+}
+
+/// The reason given by the counter of a report, see RFC 3414
+fn report_reason(oid: &str) -> String {
+    match oid.strip_prefix("1.3.6.1.6.3.15.1.1.") {
+        Some("1.0") => String::from("unsupported security level"),
+        Some("2.0") => String::from("not in time window"),
+        Some("3.0") => String::from("unknown user name"),
+        Some("4.0") => String::from("unknown engine ID"),
+        Some("5.0") => String::from("wrong digest, check auth_protocol and auth_password"),
+        _ => format!("report {}", oid),
+    }
+}
+
+fn request_id() -> i64 {
+    // Positive and within 31 bits, as some agents expect
+    (SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos()
+        & 0x7fff_ffff) as i64
+}
+
+/// Send the request and decode answers until one is for it
+async fn exchange<T>(
+    socket: &UdpSocket,
+    request: &[u8],
+    decode: impl Fn(&[u8]) -> Result<Option<T>>,
+) -> Result<T> {
+    socket.send(request).await?;
+    let mut buffer = vec![0; 65535];
+    loop {
+        let read = socket.recv(&mut buffer).await?;
+        // Late answers to earlier requests are skipped
+        if let Some(answer) = decode(&buffer[..read])? {
+            return Ok(answer);
+        }
+    }
+}
+
+impl Agent {
+    /// `<key>.<label>` for each of the `oids`
+    pub async fn produce(&self, key: &str) -> Result<ItemResult> {
+        let oids = self
+            .oids
+            .values()
+            .map(|oid| parse_oid(oid))
+            .collect::<Result<Vec<_>>>()?;
+        let address = format!("{}:{}", self.host, self.port);
+        let socket = UdpSocket::bind("0.0.0.0:0").await?;
+        socket
+            .connect(&address)
+            .await
+            .with_context(|| format!("Failed connecting to {}", address))?;
+        let pdu = tokio::time::timeout(Duration::from_secs(self.timeout), self.get(&socket, &oids))
+            .await
+            .map_err(|_| anyhow!("{} did not answer within {}s", address, self.timeout))?
+            .map_err(|e| anyhow!("SNMP request to {} failed: {:#}", address, e))?;
+        if pdu.error_status != 0 {
+            let label = usize::try_from(pdu.error_index - 1)
+                .ok()
+                .and_then(|index| self.oids.keys().nth(index));
+            bail!(
+                "{} answered with {}{}",
+                address,
+                error_name(pdu.error_status),
+                label.map(|l| format!(" for {}", l)).unwrap_or_default()
+            );
+        }
+        let mut values = HashMap::new();
+        for ((label, oid), (_, value)) in self.oids.iter().zip(pdu.varbinds) {
+            match value {
+                Some(value) => {
+                    values.insert(format!("{}.{}", key, label).into(), value);
+                }
+                None => warn!("{}: {} ({}) has no numeric value", address, label, oid),
+            }
+        }
+        Ok(ItemResult {
+            time: now(),
+            key: key.into(),
+            raw: String::new(),
+            values,
+            tags: BTreeMap::new(),
+        })
+    }
+
+    async fn get(&self, socket: &UdpSocket, oids: &[Vec<u32>]) -> Result<Pdu> {
+        let version = match self.version {
+            Version::V1 => 0,
+            Version::V2c => 1,
+            Version::V3 => return self.get_v3(socket, oids).await,
+        };
+        let id = request_id();
+        let message = encode(
+            0x30,
+            &[
+                integer(version),
+                encode(0x04, self.community.expose().as_bytes()),
+                get_request(id, oids),
+            ]
+            .concat(),
+        );
+        exchange(socket, &message, |answer| {
+            let pdu = decode_community(answer)?;
+            Ok((pdu.request_id == id).then_some(pdu))
+        })
+        .await
+    }
+
+    async fn get_v3(&self, socket: &UdpSocket, oids: &[Vec<u32>]) -> Result<Pdu> {
+        let user = self
+            .user
+            .as_deref()
+            .ok_or_else(|| anyhow!("user is required for version 3"))?;
+        // The agent tells its engine in a report on an empty request
+        let id = request_id();
+        let discovery = encode_v3(id, &Engine::default(), "", false, &get_request(id, &[]));
+        let (engine, ..) = exchange(socket, &discovery.message, |answer| {
+            let (engine, _, _, pdu) = decode_v3(answer)?;
+            Ok((pdu.request_id == id).then_some((engine, pdu)))
+        })
+        .await
+        .context("Failed discovering the engine of the agent")?;
+
+        let id = id + 1;
+        let key = match &self.auth_password {
+            Some(password) if password.expose().len() < 8 => {
+                bail!("auth_password must have at least 8 characters")
+            }
+            Some(password) => Some(
+                self.auth_protocol.localize(
+                    self.password_key
+                        .get_or_init(|| self.auth_protocol.password_key(password.expose())),
+                    &engine.id,
+                ),
+            ),
+            None => None,
+        };
+        let mut secured = encode_v3(id, &engine, user, key.is_some(), &get_request(id, oids));
+        if let (Some(key), Some(offset)) = (&key, secured.auth) {
+            let signature = self.auth_protocol.sign(key, &secured.message);
+            secured.message[offset..offset + AUTH_LENGTH].copy_from_slice(&signature);
+        }
+        let pdu = exchange(socket, &secured.message, |answer| {
+            let (_, offset, auth, pdu) = decode_v3(answer)?;
+            if pdu.request_id != id {
+                return Ok(None);
+            }
+            // Reports on failed authentication are not authenticated themselves
+            if let Some(key) = key.as_ref().filter(|_| pdu.tag != REPORT) {
+                let mut unsigned = answer.to_vec();
+                unsigned[offset..offset + auth.len()].fill(0);
+                if self.auth_protocol.sign(key, &unsigned) != auth {
+                    bail!("The answer is not authenticated");
+                }
+            }
+            Ok(Some(pdu))
+        })
+        .await?;
+        if pdu.tag == REPORT {
+            let oid = pdu
+                .varbinds
+                .first()
+                .map(|(oid, _)| oid.as_str())
+                .unwrap_or_default();
+            bail!("The agent refused the request: {}", report_reason(oid));
+        }
+        Ok(pdu)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use md5::Md5;
+    use sha1::Sha1;
+
+    use crate::item::snmp::{
+        decode_community, encode, get_request, integer, localize, parse_oid, password_key, Agent,
+    };
+
+    #[test]
+    fn get() {
+        let request = get_request(1, &[parse_oid("1.3.6.1.2.1.1.3.0").unwrap()]);
+        assert_eq!(
+            request,
+            [
+                0xa0, 0x19, 0x02, 0x01, 0x01, 0x02, 0x01, 0x00, 0x02, 0x01, 0x00, 0x30, 0x0e, 0x30,
+                0x0c, 0x06, 0x08, 0x2b, 0x06, 0x01, 0x02, 0x01, 0x01, 0x03, 0x00, 0x05, 0x00
+            ]
+        );
+        assert_eq!(integer(128), [0x02, 0x02, 0x00, 0x80]);
+        assert_eq!(integer(-129), [0x02, 0x02, 0xff, 0x7f]);
+        assert!(parse_oid("1.3.six").is_err());
+
+        // The answer has the uptime in TimeTicks
+        let mut response = request.clone();
+        response[0] = 0xa2;
+        let len = response.len();
+        response.splice(len - 2.., [0x43, 0x02, 0x30, 0x39]);
+        response[1] += 2;
+        response[12] += 2;
+        response[14] += 2;
+        let message = encode(
+            0x30,
+            &[integer(1), encode(0x04, b"public"), response].concat(),
+        );
+        let pdu = decode_community(&message).unwrap();
+        assert_eq!(pdu.request_id, 1);
+        assert_eq!(
+            pdu.varbinds,
+            [(String::from("1.3.6.1.2.1.1.3.0"), Some(12345.0))]
+        );
+    }
+
+    #[test]
+    fn localized_keys() {
+        // RFC 3414 A.3
+        let engine = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2];
+        assert_eq!(
+            hex::encode(localize::<Md5>(
+                &password_key::<Md5>(b"maplesyrup"),
+                &engine
+            )),
+            "526f5eed9fcce26f8964c2930787d82b"
+        );
+        assert_eq!(
+            hex::encode(localize::<Sha1>(
+                &password_key::<Sha1>(b"maplesyrup"),
+                &engine
+            )),
+            "6695febc9288e36282235fc7151f128497b38f3f"
+        );
+    }
+
+    #[test]
+    fn no_encryption() {
+        let agent = r#"
+            host = "switch"
+            version = "3"
+            user = "monitor"
+            auth_password = "maplesyrup"
+            oids = { uptime = "1.3.6.1.2.1.1.3.0" }
+        "#;
+        assert!(toml::from_str::<Agent>(agent).is_ok());
+        let error =
+            toml::from_str::<Agent>(&format!("{}priv_password = \"secret\"", agent)).unwrap_err();
+        assert!(error.to_string().contains("not supported"), "{}", error);
+    }
+}
@@ -0,0 +1,214 @@
+//! Basic host metrics, without shell pipelines and regexes, on the systems supported by
+//! sysinfo
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::time::Instant;
+
+use anyhow::{anyhow, bail, Result};
+use log::debug;
+use serde::Deserialize;
+use sysinfo::{Disk, Disks, Networks, System};
+
+use crate::item::filesystems::percent;
+use crate::item::{now, ItemResult};
+use crate::keys::Key;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SystemMetric {
+    /// Percentage of CPU time spent
+    Cpu,
+    /// Memory and swap in bytes
+    Memory,
+    /// Space of the root filesystem in bytes
+    Disk,
+    /// Bytes per second received and sent, over all interfaces but loopback
+    Net,
+    /// Load averages over 1, 5 and 15 minutes
+    Loadavg,
+}
+
+const ALL: [SystemMetric; 5] = [
+    SystemMetric::Cpu,
+    SystemMetric::Memory,
+    SystemMetric::Disk,
+    SystemMetric::Net,
+    SystemMetric::Loadavg,
+];
+
+/// State kept between runs, to calculate usage and rates
+#[derive(Debug, Default)]
+pub struct Counters {
+    /// Tells the CPU usage since it was last refreshed
+    system: Option<Box<System>>,
+    net: Option<(u64, u64, Instant)>,
+}
+
+/// Items are cloned before they first run, a clone starts without state
+impl Clone for Counters {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+/// `lo` on Linux, `lo0` on BSDs and macOS
+fn is_loopback(interface: &str) -> bool {
+    interface
+        .strip_prefix("lo")
+        .is_some_and(|rest| rest.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// The root filesystem, or the first one listed where there is none, e.g. on Windows
+fn root(disks: &Disks) -> Option<&Disk> {
+    disks
+        .list()
+        .iter()
+        .find(|disk| disk.mount_point() == Path::new("/"))
+        .or_else(|| disks.list().first())
+}
+
+fn collect(
+    metric: SystemMetric,
+    key: &str,
+    counters: &mut Counters,
+    values: &mut HashMap<Key, f64>,
+) -> Result<()> {
+    match metric {
+        SystemMetric::Cpu => {
+            // Usage is known from the second run on
+            match &mut counters.system {
+                Some(system) => {
+                    system.refresh_cpu_usage();
+                    values.insert(
+                        format!("{}.cpu.used_percent", key).into(),
+                        system.global_cpu_usage() as f64,
+                    );
+                }
+                None => {
+                    let mut system = System::new();
+                    system.refresh_cpu_usage();
+                    counters.system = Some(Box::new(system));
+                }
+            }
+        }
+        SystemMetric::Memory => {
+            let mut system = System::new();
+            system.refresh_memory();
+            let total = system.total_memory() as f64;
+            if total == 0.0 {
+                bail!("Memory usage is not available");
+            }
+            let available = system.available_memory() as f64;
+            for (name, value) in [
+                ("total", total),
+                ("available", available),
+                ("used", total - available),
+                ("used_percent", percent(total - available, total)),
+                ("swap_total", system.total_swap() as f64),
+                ("swap_used", system.used_swap() as f64),
+            ] {
+                values.insert(format!("{}.memory.{}", key, name).into(), value);
+            }
+        }
+        SystemMetric::Disk => {
+            let disks = Disks::new_with_refreshed_list();
+            let disk = root(&disks).ok_or_else(|| anyhow!("No filesystem found"))?;
+            let size = disk.total_space() as f64;
+            let available = disk.available_space() as f64;
+            for (name, value) in [
+                ("size", size),
+                ("used", size - available),
+                ("available", available),
+                ("used_percent", percent(size - available, size)),
+            ] {
+                values.insert(format!("{}.disk.{}", key, name).into(), value);
+            }
+        }
+        SystemMetric::Net => {
+            let (rx, tx) = Networks::new_with_refreshed_list()
+                .list()
+                .iter()
+                .filter(|(interface, _)| !is_loopback(interface))
+                .fold((0, 0), |(rx, tx), (_, data)| {
+                    (rx + data.total_received(), tx + data.total_transmitted())
+                });
+            let now = Instant::now();
+            // Rates are known from the second run on
+            if let Some((previous_rx, previous_tx, at)) = counters.net {
+                let elapsed = now.duration_since(at).as_secs_f64();
+                if elapsed > 0.0 {
+                    values.insert(
+                        format!("{}.net.rx_bytes", key).into(),
+                        rx.saturating_sub(previous_rx) as f64 / elapsed,
+                    );
+                    values.insert(
+                        format!("{}.net.tx_bytes", key).into(),
+                        tx.saturating_sub(previous_tx) as f64 / elapsed,
+                    );
+                }
+            }
+            counters.net = Some((rx, tx, now));
+        }
+        SystemMetric::Loadavg => {
+            if cfg!(windows) {
+                bail!("Load averages are not available");
+            }
+            let load = System::load_average();
+            for (name, load) in [("1m", load.one), ("5m", load.five), ("15m", load.fifteen)] {
+                values.insert(format!("{}.loadavg.{}", key, name).into(), load);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Without selected metrics, all are collected and the ones not available on this system
+/// are skipped
+pub fn produce(key: &str, metrics: &[SystemMetric], counters: &mut Counters) -> Result<ItemResult> {
+    let mut values = HashMap::new();
+    if metrics.is_empty() {
+        for metric in ALL {
+            if let Err(e) = collect(metric, key, counters, &mut values) {
+                debug!("item {}: skipping {:?}: {:#}", key, metric, e);
+            }
+        }
+    } else {
+        for metric in metrics {
+            collect(*metric, key, counters, &mut values)?;
+        }
+    }
+    Ok(ItemResult {
+        time: now(),
+        key: key.into(),
+        raw: String::new(),
+        values,
+        tags: BTreeMap::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::item::system::{is_loopback, produce, Counters};
+
+    #[test]
+    fn loopback() {
+        assert!(is_loopback("lo"));
+        assert!(is_loopback("lo0"));
+        assert!(!is_loopback("eth0"));
+        assert!(!is_loopback("lowpan0"));
+    }
+
+    #[test]
+    fn collects() {
+        let mut counters = Counters::default();
+        let first = produce("os", &[], &mut counters).unwrap();
+        assert!(first.values["os.memory.total"] > 0.0);
+        assert!(first.values.contains_key("os.loadavg.1m"));
+        assert!(!first.values.contains_key("os.cpu.used_percent"));
+        std::thread::sleep(sysinfo::MINIMUM_CPU_UPDATE_INTERVAL);
+        let second = produce("os", &[], &mut counters).unwrap();
+        assert!((0.0..=100.0).contains(&second.values["os.cpu.used_percent"]));
+        assert!(second.values.contains_key("os.net.rx_bytes"));
+    }
+}
@@ -0,0 +1,184 @@
+//! State, restarts and memory of systemd units, queried over D-Bus
+
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::{anyhow, Context, Result};
+use log::debug;
+use serde::Deserialize;
+use zbus::zvariant::OwnedObjectPath;
+use zbus::{proxy::CacheProperties, Connection, Proxy};
+
+use crate::item::{now, ItemResult};
+
+const DESTINATION: &str = "org.freedesktop.systemd1";
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Units {
+    /// Unit names, `.service` if without suffix
+    units: Vec<String>,
+    /// The user's service manager instead of the system's
+    #[serde(default)]
+    user: bool,
+    #[serde(skip)]
+    connection: Option<Connection>,
+}
+
+/// `nginx.service` for `nginx`
+fn unit_name(unit: &str) -> String {
+    if unit.contains('.') {
+        unit.to_owned()
+    } else {
+        format!("{}.service", unit)
+    }
+}
+
+/// Part of keys, `nginx` for `nginx.service`, `backup_timer` for `backup.timer`
+fn unit_key(unit: &str) -> String {
+    unit.strip_suffix(".service")
+        .unwrap_or(unit)
+        .replace(['.', '@', '-'], "_")
+}
+
+/// Numbered in the order of systemd's own `UnitActiveState`, so 0 is active
+fn active_state(state: &str) -> Option<f64> {
+    [
+        "active",
+        "reloading",
+        "inactive",
+        "failed",
+        "activating",
+        "deactivating",
+        "maintenance",
+        "refreshing",
+    ]
+    .iter()
+    .position(|s| *s == state)
+    .map(|p| p as f64)
+}
+
+async fn properties(
+    connection: &Connection,
+    path: &OwnedObjectPath,
+    interface: &str,
+) -> Result<Proxy<'static>> {
+    // Properties are read once per run, caching would only subscribe to changes
+    Ok(zbus::proxy::Builder::<Proxy>::new(connection)
+        .destination(DESTINATION)?
+        .path(path.clone())?
+        .interface(interface.to_owned())?
+        .cache_properties(CacheProperties::No)
+        .build()
+        .await?)
+}
+
+async fn unit(
+    connection: &Connection,
+    manager: &Proxy<'_>,
+    key: &str,
+    unit: &str,
+) -> Result<ItemResult> {
+    // Also finds units that are not loaded, unlike GetUnit
+    let path: OwnedObjectPath = manager
+        .call("LoadUnit", &(unit,))
+        .await
+        .with_context(|| format!("Failed loading unit {}", unit))?;
+    let proxy = properties(connection, &path, "org.freedesktop.systemd1.Unit").await?;
+    let load_state: String = proxy.get_property("LoadState").await?;
+    let active: String = proxy.get_property("ActiveState").await?;
+    let sub: String = proxy.get_property("SubState").await?;
+    let key = format!("{}.{}", key, unit_key(unit));
+    let mut values = HashMap::new();
+    values.insert(
+        format!("{}.loaded", key).into(),
+        if load_state == "loaded" { 1.0 } else { 0.0 },
+    );
+    values.insert(
+        format!("{}.active", key).into(),
+        if active == "active" { 1.0 } else { 0.0 },
+    );
+    if let Some(state) = active_state(&active) {
+        values.insert(format!("{}.active_state", key).into(), state);
+    }
+    if unit.ends_with(".service") && load_state == "loaded" {
+        let service = properties(connection, &path, "org.freedesktop.systemd1.Service").await?;
+        match service.get_property::<u32>("NRestarts").await {
+            Ok(restarts) => {
+                values.insert(format!("{}.restarts", key).into(), restarts as f64);
+            }
+            // systemd before 235
+            Err(e) => debug!("systemd: no restarts of {}: {}", unit, e),
+        }
+        // u64::MAX without memory accounting
+        match service.get_property::<u64>("MemoryCurrent").await {
+            Ok(memory) if memory != u64::MAX => {
+                values.insert(format!("{}.memory", key).into(), memory as f64);
+            }
+            Ok(_) => {}
+            Err(e) => debug!("systemd: no memory of {}: {}", unit, e),
+        }
+    }
+    Ok(ItemResult {
+        time: now(),
+        key: key.into(),
+        raw: format!("{} ({})", active, sub),
+        values,
+        tags: BTreeMap::from([(String::from("unit"), unit.to_owned())]),
+    })
+}
+
+impl Units {
+    async fn poll(&self, connection: &Connection, key: &str) -> Result<Vec<ItemResult>> {
+        let manager = zbus::proxy::Builder::<Proxy>::new(connection)
+            .destination(DESTINATION)?
+            .path("/org/freedesktop/systemd1")?
+            .interface("org.freedesktop.systemd1.Manager")?
+            .cache_properties(CacheProperties::No)
+            .build()
+            .await?;
+        let mut results = Vec::new();
+        for name in &self.units {
+            results.push(unit(connection, &manager, key, &unit_name(name)).await?);
+        }
+        Ok(results)
+    }
+
+    /// One result per unit, `<key>.<unit>`
+    pub async fn produce(&mut self, key: &str) -> Result<Vec<ItemResult>> {
+        let connection = match &self.connection {
+            Some(connection) => connection.clone(),
+            None => {
+                let connection = if self.user {
+                    Connection::session().await
+                } else {
+                    Connection::system().await
+                }
+                .map_err(|e| anyhow!("Failed connecting to D-Bus: {}", e))?;
+                self.connection = Some(connection.clone());
+                connection
+            }
+        };
+        let results = self.poll(&connection, key).await;
+        if results.is_err() {
+            // Reconnect next time, e.g. after the bus was restarted
+            self.connection = None;
+        }
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::item::systemd::{active_state, unit_key, unit_name};
+
+    #[test]
+    fn units() {
+        assert_eq!(unit_name("nginx"), "nginx.service");
+        assert_eq!(unit_name("backup.timer"), "backup.timer");
+        assert_eq!(unit_key("nginx.service"), "nginx");
+        assert_eq!(unit_key("getty@tty1.service"), "getty_tty1");
+        assert_eq!(unit_key("backup.timer"), "backup_timer");
+        assert_eq!(active_state("active"), Some(0.0));
+        assert_eq!(active_state("failed"), Some(3.0));
+        assert_eq!(active_state("bogus"), None);
+    }
+}
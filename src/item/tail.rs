@@ -0,0 +1,195 @@
+//! Lines appended to a file, e.g. a log or a CSV feed, digested as they are written
+//! instead of reading the whole file on every run
+
+use std::ffi::CString;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::os::fd::{FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use tokio::io::unix::AsyncFd;
+
+use crate::item::serial::Lines;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Tail {
+    pub path: PathBuf,
+    /// Also the lines already in the file when starting
+    #[serde(default)]
+    from_beginning: bool,
+    /// The lines read at once are digested together, instead of one by one
+    #[serde(default)]
+    pub batch: bool,
+}
+
+/// The file is checked this often even without notifications, e.g. on network
+/// filesystems
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The file read, and how far
+struct Opened {
+    file: File,
+    inode: u64,
+    position: u64,
+}
+
+impl Opened {
+    fn open(path: &Path, at_end: bool) -> Result<Option<Self>> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Failed opening {}", path.display())),
+        };
+        let metadata = file.metadata()?;
+        Ok(Some(Self {
+            file,
+            inode: metadata.ino(),
+            position: if at_end { metadata.len() } else { 0 },
+        }))
+    }
+
+    /// The bytes appended since the last read, from the start if the file was truncated
+    fn read(&mut self) -> Result<Vec<u8>> {
+        if self.file.metadata()?.len() < self.position {
+            self.position = 0;
+        }
+        self.file.seek(SeekFrom::Start(self.position))?;
+        let mut bytes = Vec::new();
+        self.file.read_to_end(&mut bytes)?;
+        self.position += bytes.len() as u64;
+        Ok(bytes)
+    }
+}
+
+/// Follows the file by name, like `tail -F`, so it is read again once rotated
+pub struct Follower {
+    path: PathBuf,
+    /// Notifications about the directory of the file
+    watch: AsyncFd<File>,
+    opened: Option<Opened>,
+    lines: Lines,
+}
+
+impl Tail {
+    pub fn follow(&self) -> Result<Follower> {
+        let dir = match self.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        // SAFETY: no pointers involved
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error()).context("Failed setting up inotify");
+        }
+        // SAFETY: the descriptor was just created and is owned by nobody else
+        let watch = File::from(unsafe { OwnedFd::from_raw_fd(fd) });
+        let name = CString::new(dir.as_os_str().as_bytes())?;
+        let mask = libc::IN_MODIFY
+            | libc::IN_CREATE
+            | libc::IN_MOVED_TO
+            | libc::IN_MOVED_FROM
+            | libc::IN_DELETE;
+        // SAFETY: the name is a valid C string for the duration of the call
+        if unsafe { libc::inotify_add_watch(fd, name.as_ptr(), mask) } < 0 {
+            return Err(std::io::Error::last_os_error())
+                .with_context(|| format!("Failed watching {}", dir.display()));
+        }
+        Ok(Follower {
+            path: self.path.clone(),
+            watch: AsyncFd::new(watch)?,
+            opened: Opened::open(&self.path, !self.from_beginning)?,
+            lines: Lines::new(b"\n"),
+        })
+    }
+}
+
+impl Follower {
+    /// The lines appended since the last call, also those of a file that replaced it
+    fn read_new(&mut self) -> Result<Vec<String>> {
+        let mut lines = match &mut self.opened {
+            Some(opened) => self.lines.push(&opened.read()?),
+            None => Vec::new(),
+        };
+        let inode = std::fs::metadata(&self.path).ok().map(|m| m.ino());
+        if inode.is_some() && inode != self.opened.as_ref().map(|o| o.inode) {
+            // A line the old file did not finish is dropped
+            self.lines = Lines::new(b"\n");
+            self.opened = Opened::open(&self.path, false)?;
+            if let Some(opened) = &mut self.opened {
+                lines.extend(self.lines.push(&opened.read()?));
+            }
+        }
+        Ok(lines)
+    }
+
+    /// Wait for something to happen in the directory, the events are not looked at
+    async fn changed(&self) -> Result<()> {
+        let mut events = [0; 4096];
+        loop {
+            let mut guard = self.watch.readable().await?;
+            match guard.try_io(|watch| watch.get_ref().read(&mut events)) {
+                Ok(read) => return read.map(|_| ()).map_err(Into::into),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+
+    /// The lines appended next
+    pub async fn read(&mut self) -> Result<Vec<String>> {
+        loop {
+            let lines = self.read_new()?;
+            if !lines.is_empty() {
+                return Ok(lines);
+            }
+            tokio::select! {
+                changed = self.changed() => changed?,
+                _ = tokio::time::sleep(POLL_INTERVAL) => (),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use crate::item::tail::Tail;
+
+    #[tokio::test]
+    async fn follow() {
+        let dir = std::env::temp_dir().join(format!("antikoerper-tail-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("app.log");
+        std::fs::write(&path, "old\n").unwrap();
+        let tail = Tail {
+            path: path.clone(),
+            from_beginning: false,
+            batch: false,
+        };
+        let mut follower = tail.follow().unwrap();
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        file.write_all(b"first\nsec").unwrap();
+        assert_eq!(follower.read().await.unwrap(), ["first"]);
+        file.write_all(b"ond\n").unwrap();
+        assert_eq!(follower.read().await.unwrap(), ["second"]);
+
+        // Rotated, the remaining lines of the old file are read first
+        file.write_all(b"last\n").unwrap();
+        std::fs::rename(&path, dir.join("app.log.1")).unwrap();
+        std::fs::write(&path, "new\n").unwrap();
+        assert_eq!(follower.read().await.unwrap(), ["last", "new"]);
+
+        // Truncated
+        std::fs::write(&path, "re\n").unwrap();
+        assert_eq!(follower.read().await.unwrap(), ["re"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,64 @@
+//! Reachability of a TCP port
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+use log::warn;
+use serde::Deserialize;
+use tokio::net::TcpStream;
+
+use crate::item::{now, ItemResult};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Target {
+    host: String,
+    port: u16,
+    /// Seconds
+    #[serde(default = "timeout_default")]
+    timeout: u64,
+}
+
+fn timeout_default() -> u64 {
+    10
+}
+
+impl Target {
+    /// Never fails, unreachable ports are reported with `up` being 0
+    pub async fn produce(&self, key: &str) -> ItemResult {
+        let address = format!("{}:{}", self.host, self.port);
+        let start = Instant::now();
+        let connected = tokio::time::timeout(
+            Duration::from_secs(self.timeout),
+            TcpStream::connect(&address),
+        )
+        .await;
+        let mut values = HashMap::new();
+        match connected {
+            Ok(Ok(_)) => {
+                values.insert(format!("{}.up", key).into(), 1.0);
+                values.insert(
+                    format!("{}.connect", key).into(),
+                    start.elapsed().as_secs_f64(),
+                );
+            }
+            Ok(Err(e)) => {
+                warn!("Connecting to {} failed: {}", address, e);
+                values.insert(format!("{}.up", key).into(), 0.0);
+            }
+            Err(_) => {
+                warn!(
+                    "Connecting to {} timed out after {}s",
+                    address, self.timeout
+                );
+                values.insert(format!("{}.up", key).into(), 0.0);
+            }
+        }
+        ItemResult {
+            time: now(),
+            key: key.into(),
+            raw: String::new(),
+            values,
+            tags: BTreeMap::new(),
+        }
+    }
+}
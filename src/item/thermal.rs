@@ -0,0 +1,164 @@
+//! CPU frequencies, thermal throttling and thermal zone temperatures from sysfs
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+
+use anyhow::{bail, Result};
+
+use crate::item::{now, ItemResult};
+use crate::keys::Key;
+
+pub fn read_number(path: &Path) -> Option<f64> {
+    std::fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+/// Directories below `dir` named `<prefix><number>`, sorted by their number
+pub fn numbered(dir: &Path, prefix: &str) -> Vec<(u32, std::path::PathBuf)> {
+    let mut entries = std::fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| {
+                    let entry = entry.ok()?;
+                    let n = entry
+                        .file_name()
+                        .to_str()?
+                        .strip_prefix(prefix)?
+                        .parse()
+                        .ok()?;
+                    Some((n, entry.path()))
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    entries.sort_by_key(|(n, _)| *n);
+    entries
+}
+
+pub fn collect(sys: &Path, key: &str) -> HashMap<Key, f64> {
+    let mut values = HashMap::new();
+
+    let mut frequencies = Vec::new();
+    let mut core_throttles = 0.0;
+    let mut package_throttles = HashMap::new();
+    let mut throttle_counters = false;
+    for (n, cpu) in numbered(&sys.join("devices/system/cpu"), "cpu") {
+        // in kHz
+        if let Some(freq) = read_number(&cpu.join("cpufreq/scaling_cur_freq")) {
+            values.insert(format!("{}.frequency.cpu{}", key, n).into(), freq / 1000.0);
+            frequencies.push(freq / 1000.0);
+        }
+        if let Some(count) = read_number(&cpu.join("thermal_throttle/core_throttle_count")) {
+            core_throttles += count;
+            throttle_counters = true;
+        }
+        // Every CPU of a package reports the same package count
+        if let Some(count) = read_number(&cpu.join("thermal_throttle/package_throttle_count")) {
+            let package = read_number(&cpu.join("topology/physical_package_id")).unwrap_or(0.0);
+            package_throttles.insert(package as i64, count);
+        }
+    }
+    if !frequencies.is_empty() {
+        values.insert(
+            format!("{}.frequency.average", key).into(),
+            frequencies.iter().sum::<f64>() / frequencies.len() as f64,
+        );
+    }
+    if throttle_counters {
+        values.insert(format!("{}.throttles.core", key).into(), core_throttles);
+    }
+    if !package_throttles.is_empty() {
+        values.insert(
+            format!("{}.throttles.package", key).into(),
+            package_throttles.values().sum(),
+        );
+    }
+
+    let mut names = HashSet::new();
+    for (n, zone) in numbered(&sys.join("class/thermal"), "thermal_zone") {
+        // in millidegrees Celsius
+        let Some(temp) = read_number(&zone.join("temp")) else {
+            continue;
+        };
+        let kind = std::fs::read_to_string(zone.join("type"))
+            .map(|t| t.trim().replace(['.', ' ', '/'], "_"))
+            .unwrap_or_default();
+        // Types like acpitz repeat for several zones
+        let name = if kind.is_empty() || !names.insert(kind.clone()) {
+            format!("{}_{}", kind, n).trim_start_matches('_').to_owned()
+        } else {
+            kind
+        };
+        values.insert(
+            format!("{}.temperature.{}", key, name).into(),
+            temp / 1000.0,
+        );
+    }
+    values
+}
+
+pub fn produce(key: &str) -> Result<ItemResult> {
+    let values = collect(Path::new("/sys"), key);
+    if values.is_empty() {
+        bail!("Neither CPU frequencies nor thermal zones found in /sys");
+    }
+    Ok(ItemResult {
+        time: now(),
+        key: key.into(),
+        raw: String::new(),
+        values,
+        tags: BTreeMap::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::item::thermal::collect;
+
+    fn write(root: &Path, path: &str, content: &str) {
+        let path = root.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+
+    #[test]
+    fn sysfs() {
+        let root = std::env::temp_dir().join(format!("antikoerper-thermal-{}", std::process::id()));
+        for cpu in ["cpu0", "cpu1"] {
+            let dir = format!("devices/system/cpu/{}", cpu);
+            write(
+                &root,
+                &format!("{}/cpufreq/scaling_cur_freq", dir),
+                "2000000\n",
+            );
+            write(
+                &root,
+                &format!("{}/thermal_throttle/core_throttle_count", dir),
+                "3\n",
+            );
+            write(
+                &root,
+                &format!("{}/thermal_throttle/package_throttle_count", dir),
+                "7\n",
+            );
+            write(
+                &root,
+                &format!("{}/topology/physical_package_id", dir),
+                "0\n",
+            );
+        }
+        write(&root, "class/thermal/thermal_zone0/type", "acpitz\n");
+        write(&root, "class/thermal/thermal_zone0/temp", "45000\n");
+        write(&root, "class/thermal/thermal_zone1/type", "acpitz\n");
+        write(&root, "class/thermal/thermal_zone1/temp", "51500\n");
+        let values = collect(&root, "t");
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(values["t.frequency.cpu1"], 2000.0);
+        assert_eq!(values["t.frequency.average"], 2000.0);
+        assert_eq!(values["t.throttles.core"], 6.0);
+        assert_eq!(values["t.throttles.package"], 7.0);
+        assert_eq!(values["t.temperature.acpitz"], 45.0);
+        assert_eq!(values["t.temperature.acpitz_1"], 51.5);
+    }
+}
@@ -0,0 +1,183 @@
+//! Minutes per category tracked today, from Timewarrior or ActivityWatch
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone, Utc};
+use serde::Deserialize;
+
+use crate::item::{now, run_cmd_capture_output, ItemResult};
+use crate::priority::Priority;
+use crate::secret::Secret;
+
+/// Lowercase, with everything but letters and digits replaced, to be usable in keys
+fn category(name: &str) -> String {
+    let name = name
+        .trim()
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+    if name.is_empty() {
+        String::from("untagged")
+    } else {
+        name
+    }
+}
+
+fn start_of_day() -> DateTime<Utc> {
+    let today = Local::now().date_naive();
+    Local
+        .from_local_datetime(&today.and_hms_opt(0, 0, 0).expect("midnight exists"))
+        .earliest()
+        .map(|t| t.with_timezone(&Utc))
+        .unwrap_or_else(Utc::now)
+}
+
+fn result(key: &str, minutes: HashMap<String, f64>) -> ItemResult {
+    let mut values = minutes
+        .iter()
+        .map(|(category, minutes)| (format!("{}.{}", key, category).into(), *minutes))
+        .collect::<HashMap<_, _>>();
+    values.insert(format!("{}.total", key).into(), minutes.values().sum());
+    ItemResult {
+        time: now(),
+        key: key.into(),
+        raw: String::new(),
+        values,
+        tags: BTreeMap::new(),
+    }
+}
+
+#[derive(Deserialize)]
+struct Interval {
+    start: String,
+    end: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+fn parse_timew_time(time: &str) -> Result<DateTime<Utc>> {
+    Ok(NaiveDateTime::parse_from_str(time, "%Y%m%dT%H%M%SZ")
+        .with_context(|| format!("Unexpected time {}", time))?
+        .and_utc())
+}
+
+/// Minutes per tag of `timew export` output, intervals with several tags count for each.
+/// The running interval counts until `now`.
+fn timewarrior_minutes(
+    export: &str,
+    since: DateTime<Utc>,
+    now: DateTime<Utc>,
+) -> Result<HashMap<String, f64>> {
+    let intervals: Vec<Interval> =
+        serde_json::from_str(export).context("Unexpected output of timew export")?;
+    let mut minutes = HashMap::new();
+    for interval in intervals {
+        let start = parse_timew_time(&interval.start)?.max(since);
+        let end = match interval.end {
+            Some(end) => parse_timew_time(&end)?,
+            None => now,
+        };
+        let duration = (end - start).num_seconds().max(0) as f64 / 60.0;
+        let tags = if interval.tags.is_empty() {
+            vec![String::from("untagged")]
+        } else {
+            interval.tags
+        };
+        for tag in tags {
+            *minutes.entry(category(&tag)).or_default() += duration;
+        }
+    }
+    Ok(minutes)
+}
+
+pub async fn timewarrior(
+    key: &str,
+    path: &PathBuf,
+    priority: Priority,
+    env: &BTreeMap<String, Secret>,
+) -> Result<ItemResult> {
+    let export =
+        run_cmd_capture_output(path, &["export".into(), ":day".into()], priority, env).await?;
+    Ok(result(
+        key,
+        timewarrior_minutes(&export, start_of_day(), Utc::now())?,
+    ))
+}
+
+#[derive(Deserialize)]
+struct Event {
+    /// Seconds
+    duration: f64,
+    data: HashMap<String, serde_json::Value>,
+}
+
+/// Minutes per application today, from the events of a window watcher bucket
+pub async fn activitywatch(key: &str, url: &str, bucket: Option<&str>) -> Result<ItemResult> {
+    let client = reqwest::Client::new();
+    let url = url.trim_end_matches('/');
+    let bucket = match bucket {
+        Some(bucket) => bucket.to_owned(),
+        None => {
+            let buckets: HashMap<String, serde_json::Value> = client
+                .get(format!("{}/api/0/buckets/", url))
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            buckets
+                .into_keys()
+                .find(|id| id.starts_with("aw-watcher-window"))
+                .ok_or_else(|| anyhow!("No aw-watcher-window bucket found"))?
+        }
+    };
+    let response = client
+        .get(format!("{}/api/0/buckets/{}/events", url, bucket))
+        .query(&[
+            ("start", start_of_day().to_rfc3339()),
+            ("end", Utc::now().to_rfc3339()),
+            ("limit", String::from("-1")),
+        ])
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        bail!("ActivityWatch responded with {}", response.status());
+    }
+    let events: Vec<Event> = response.json().await?;
+    let mut minutes = HashMap::new();
+    for event in events {
+        let app = event
+            .data
+            .get("app")
+            .and_then(|app| app.as_str())
+            .unwrap_or_default();
+        *minutes.entry(category(app)).or_default() += event.duration / 60.0;
+    }
+    Ok(result(key, minutes))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::item::timetracking::{parse_timew_time, timewarrior_minutes};
+
+    #[test]
+    fn timewarrior() {
+        let export = r#"[
+            {"id":3,"start":"20240501T060000Z","end":"20240501T083000Z","tags":["Work","email"]},
+            {"id":2,"start":"20240501T090000Z","end":"20240501T093000Z"},
+            {"id":1,"start":"20240501T100000Z","tags":["work"]}
+        ]"#;
+        let minutes = timewarrior_minutes(
+            export,
+            parse_timew_time("20240501T080000Z").unwrap(),
+            parse_timew_time("20240501T101500Z").unwrap(),
+        )
+        .unwrap();
+        assert_eq!(minutes["work"], 45.0);
+        assert_eq!(minutes["email"], 30.0);
+        assert_eq!(minutes["untagged"], 30.0);
+    }
+}
@@ -0,0 +1,199 @@
+//! The Wi-Fi connection: network, signal, bitrates and whether the internet is reachable
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+
+use crate::item::{now, tcp, ItemResult};
+use crate::keys::Key;
+use crate::priority::Priority;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Wifi {
+    /// The first wireless interface if not given
+    interface: Option<String>,
+    /// Connected to on every run, e.g. `{ host = "1.1.1.1", port = 443 }`
+    check: Option<tcp::Target>,
+    #[serde(default = "path_default")]
+    path: PathBuf,
+}
+
+fn path_default() -> PathBuf {
+    PathBuf::from("iw")
+}
+
+/// What `iw dev <interface> link` tells about the connection
+#[derive(Debug, Default, PartialEq)]
+struct Link {
+    ssid: String,
+    /// MHz
+    frequency: Option<f64>,
+    /// dBm
+    signal: Option<f64>,
+    /// MBit/s
+    rx_bitrate: Option<f64>,
+    tx_bitrate: Option<f64>,
+}
+
+/// `None` if not connected
+fn parse_link(output: &str) -> Option<Link> {
+    if !output.trim_start().starts_with("Connected to") {
+        return None;
+    }
+    let mut link = Link::default();
+    for line in output.lines() {
+        let Some((field, value)) = line.trim().split_once(':') else {
+            continue;
+        };
+        let number = || {
+            value
+                .split_whitespace()
+                .next()
+                .and_then(|number| number.parse().ok())
+        };
+        match field {
+            "SSID" => link.ssid = value.trim().to_owned(),
+            "freq" => link.frequency = number(),
+            "signal" => link.signal = number(),
+            "rx bitrate" => link.rx_bitrate = number(),
+            "tx bitrate" => link.tx_bitrate = number(),
+            _ => (),
+        }
+    }
+    Some(link)
+}
+
+/// Link quality of the interface in `/proc/net/wireless`, in percent of the maximum of 70
+fn link_quality(wireless: &str, interface: &str) -> Option<f64> {
+    wireless.lines().skip(2).find_map(|line| {
+        let (name, fields) = line.split_once(':')?;
+        if name.trim() != interface {
+            return None;
+        }
+        let quality: f64 = fields
+            .split_whitespace()
+            .nth(1)?
+            .trim_end_matches('.')
+            .parse()
+            .ok()?;
+        Some(quality / 70.0 * 100.0)
+    })
+}
+
+fn first_interface() -> Result<String> {
+    let mut interfaces = std::fs::read_dir("/sys/class/net")
+        .context("Failed listing network interfaces")?
+        .flatten()
+        .filter(|entry| entry.path().join("wireless").exists())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+    interfaces.sort();
+    match interfaces.into_iter().next() {
+        Some(interface) => Ok(interface),
+        None => bail!("No wireless interface found"),
+    }
+}
+
+impl Wifi {
+    pub async fn produce(&self, key: &str, priority: Priority) -> Result<ItemResult> {
+        let interface = match &self.interface {
+            Some(interface) => interface.clone(),
+            None => first_interface()?,
+        };
+        let mut command = tokio::process::Command::new(&self.path);
+        priority.command(&mut command);
+        let output = command
+            .args(["dev", &interface, "link"])
+            .kill_on_drop(true)
+            .output()
+            .await
+            .with_context(|| format!("Failed running {}", self.path.display()))?;
+        if !output.status.success() {
+            bail!(
+                "{} failed: {}",
+                self.path.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        let link = parse_link(&String::from_utf8_lossy(&output.stdout));
+
+        let mut values = HashMap::<Key, f64>::new();
+        let mut tags = BTreeMap::from([(String::from("interface"), interface.clone())]);
+        let value = |name: &str| Key::from(format!("{}.{}", key, name));
+        values.insert(value("connected"), if link.is_some() { 1.0 } else { 0.0 });
+        let raw = match link {
+            Some(link) => {
+                let fields = [
+                    ("frequency", link.frequency),
+                    ("signal", link.signal),
+                    ("rx_bitrate", link.rx_bitrate),
+                    ("tx_bitrate", link.tx_bitrate),
+                ];
+                for (name, field) in fields {
+                    if let Some(field) = field {
+                        values.insert(value(name), field);
+                    }
+                }
+                let quality = std::fs::read_to_string("/proc/net/wireless")
+                    .ok()
+                    .and_then(|wireless| link_quality(&wireless, &interface));
+                if let Some(quality) = quality {
+                    values.insert(value("link_quality"), quality);
+                }
+                tags.insert(String::from("ssid"), link.ssid.clone());
+                link.ssid
+            }
+            None => String::from("not connected"),
+        };
+        if let Some(check) = &self.check {
+            values.extend(check.produce(&format!("{}.check", key)).await.values);
+        }
+        Ok(ItemResult {
+            time: now(),
+            key: key.into(),
+            raw,
+            values,
+            tags,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::item::wifi::{link_quality, parse_link, Link};
+
+    #[test]
+    fn link() {
+        let output = "Connected to aa:bb:cc:dd:ee:ff (on wlp3s0)
+	SSID: Home: 5G
+	freq: 5180
+	RX: 123456 bytes (789 packets)
+	TX: 23456 bytes (123 packets)
+	signal: -56 dBm
+	rx bitrate: 866.7 MBit/s VHT-MCS 9 80MHz short GI VHT-NSS 2
+	tx bitrate: 650.0 MBit/s VHT-MCS 7 80MHz short GI VHT-NSS 2
+	bss flags:	short-slot-time
+";
+        assert_eq!(
+            parse_link(output),
+            Some(Link {
+                ssid: String::from("Home: 5G"),
+                frequency: Some(5180.0),
+                signal: Some(-56.0),
+                rx_bitrate: Some(866.7),
+                tx_bitrate: Some(650.0),
+            })
+        );
+        assert_eq!(parse_link("Not connected.\n"), None);
+
+        let wireless =
+            "Inter-| sta-|   Quality        |   Discarded packets               | Missed | WE
+ face | tus | link level noise |  nwid  crypt   frag  retry   misc | beacon | 22
+wlp3s0: 0000   49.  -61.  -256        0      0      0      0     12        0
+";
+        assert_eq!(link_quality(wireless, "wlp3s0"), Some(70.0));
+        assert_eq!(link_quality(wireless, "wlan0"), None);
+    }
+}
@@ -0,0 +1,163 @@
+//! Handshakes and transfer of the peers of WireGuard interfaces, as told by `wg show all
+//! dump`
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+
+use crate::item::{now, ItemResult};
+use crate::keys::Key;
+use crate::priority::Priority;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct WireGuard {
+    /// e.g. `wg0`, all if empty
+    #[serde(default)]
+    interfaces: Vec<String>,
+    /// Names of peers used in keys by their public key, others are named by the start of
+    /// their public key
+    #[serde(default)]
+    names: HashMap<String, String>,
+    #[serde(default = "path_default")]
+    path: PathBuf,
+}
+
+fn path_default() -> PathBuf {
+    PathBuf::from("wg")
+}
+
+/// A peer line of the dump, tab separated: interface, public key, preshared key, endpoint,
+/// allowed IPs, latest handshake, bytes received, bytes sent and persistent keepalive
+struct Peer<'a> {
+    interface: &'a str,
+    public_key: &'a str,
+    endpoint: &'a str,
+    /// Seconds since the epoch, 0 if there was none
+    handshake: u64,
+    received: u64,
+    sent: u64,
+}
+
+/// The peers, interface lines have fewer fields and are left out, including the private
+/// key
+fn parse(dump: &str) -> Vec<Peer<'_>> {
+    dump.lines()
+        .filter_map(|line| {
+            let fields = line.split('\t').collect::<Vec<_>>();
+            let [interface, public_key, _, endpoint, _, handshake, received, sent, _] =
+                fields.as_slice()
+            else {
+                return None;
+            };
+            Some(Peer {
+                interface,
+                public_key,
+                endpoint,
+                handshake: handshake.parse().ok()?,
+                received: received.parse().ok()?,
+                sent: sent.parse().ok()?,
+            })
+        })
+        .collect()
+}
+
+impl WireGuard {
+    fn result(&self, key: &str, peer: &Peer, time: Duration) -> ItemResult {
+        let name = match self.names.get(peer.public_key) {
+            Some(name) => name.replace('.', "_"),
+            // Base64
+            None => peer
+                .public_key
+                .chars()
+                .filter(char::is_ascii_alphanumeric)
+                .take(8)
+                .collect(),
+        };
+        let key = format!("{}.{}.{}", key, peer.interface.replace('.', "_"), name);
+        let value = |name: &str| Key::from(format!("{}.{}", key, name));
+        let mut values = HashMap::from([
+            (value("rx_bytes"), peer.received as f64),
+            (value("tx_bytes"), peer.sent as f64),
+        ]);
+        if peer.handshake > 0 {
+            values.insert(
+                value("handshake_age"),
+                time.as_secs().saturating_sub(peer.handshake) as f64,
+            );
+        }
+        ItemResult {
+            time,
+            raw: peer.endpoint.to_owned(),
+            key: key.into(),
+            values,
+            tags: BTreeMap::from([
+                (String::from("interface"), peer.interface.to_owned()),
+                (String::from("peer"), peer.public_key.to_owned()),
+            ]),
+        }
+    }
+
+    /// One result per peer
+    pub async fn produce(&self, key: &str, priority: Priority) -> Result<Vec<ItemResult>> {
+        let mut command = tokio::process::Command::new(&self.path);
+        priority.command(&mut command);
+        let output = command
+            .args(["show", "all", "dump"])
+            .kill_on_drop(true)
+            .output()
+            .await
+            .with_context(|| format!("Failed running {}", self.path.display()))?;
+        // The output holds private keys, only the error is told
+        if !output.status.success() {
+            bail!(
+                "{} failed: {}",
+                self.path.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        let dump = String::from_utf8_lossy(&output.stdout);
+        let time = now();
+        Ok(parse(&dump)
+            .iter()
+            .filter(|peer| {
+                self.interfaces.is_empty() || self.interfaces.iter().any(|i| i == peer.interface)
+            })
+            .map(|peer| self.result(key, peer, time))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    use crate::item::wireguard::{parse, path_default, WireGuard};
+
+    #[test]
+    fn dump() {
+        let dump = "wg0\tcHJpdmF0ZQ==\tUGVlcg+public/key0=\t51820\toff
+wg0\tAbC+dEf/GhIjKlMn0=\t(none)\t203.0.113.5:51820\t10.0.0.2/32\t1700000000\t4096\t1024\t25
+wg0\tzZz9+yYy/xXxwWw0=\t(none)\t(none)\t10.0.0.3/32\t0\t0\t0\toff
+";
+        let peers = parse(dump);
+        assert_eq!(peers.len(), 2);
+        let wireguard = WireGuard {
+            interfaces: Vec::new(),
+            names: HashMap::from([(String::from("AbC+dEf/GhIjKlMn0="), String::from("laptop"))]),
+            path: path_default(),
+        };
+        let time = Duration::from_secs(1700000100);
+        let laptop = wireguard.result("vpn", &peers[0], time);
+        assert_eq!(&*laptop.key, "vpn.wg0.laptop");
+        assert_eq!(laptop.raw, "203.0.113.5:51820");
+        assert_eq!(laptop.values["vpn.wg0.laptop.handshake_age"], 100.0);
+        assert_eq!(laptop.values["vpn.wg0.laptop.rx_bytes"], 4096.0);
+        let never = wireguard.result("vpn", &peers[1], time);
+        assert_eq!(&*never.key, "vpn.wg0.zZz9yYyx");
+        assert_eq!(never.values.len(), 2);
+    }
+}
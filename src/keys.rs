@@ -0,0 +1,56 @@
+//! Keys of results and values of digests shared instead of formatted for every sample.
+//! Native inputs write few values per sample and format their keys themselves.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// The key of a result or a value, cheap to clone
+pub type Key = Arc<str>;
+
+/// Labels of monitoring plugins are not known in advance, past this many the keys are
+/// formatted every time
+const MAX_CACHED: usize = 1024;
+
+/// The keys of one item: its own, and `<key>.<name>` for the names of the values its
+/// digest parses
+#[derive(Debug, Clone, Default)]
+pub struct KeyCache {
+    item: Arc<OnceLock<Key>>,
+    values: Arc<Mutex<HashMap<String, Key>>>,
+}
+
+impl KeyCache {
+    /// Always called with the same key
+    pub fn item(&self, key: &str) -> Key {
+        self.item.get_or_init(|| key.into()).clone()
+    }
+
+    /// `<key>.<name>`, always called with the same item key
+    pub fn value(&self, key: &str, name: &str) -> Key {
+        let mut values = self.values.lock().expect("key cache lock poisoned");
+        if let Some(cached) = values.get(name) {
+            return cached.clone();
+        }
+        let value: Key = format!("{}.{}", key, name).into();
+        if values.len() < MAX_CACHED {
+            values.insert(name.to_owned(), value.clone());
+        }
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::keys::KeyCache;
+
+    #[test]
+    fn shared() {
+        let keys = KeyCache::default();
+        let first = keys.value("disk", "free");
+        assert_eq!(&*first, "disk.free");
+        assert!(Arc::ptr_eq(&first, &keys.value("disk", "free")));
+        assert!(Arc::ptr_eq(&keys.item("disk"), &keys.item("disk")));
+    }
+}
@@ -0,0 +1,26 @@
+//! Antikoerper is a simple and lightweight data aggregation and visualization tool. The
+//! library is what the daemon is built from, and lets tests run the whole pipeline.
+
+pub mod alert;
+pub mod app;
+pub mod chaos;
+pub mod check;
+pub mod clock;
+pub mod conf;
+pub mod control;
+pub mod dispatcher;
+pub mod event;
+pub mod item;
+pub mod keys;
+pub mod logging;
+pub mod output;
+pub mod priority;
+pub mod profile;
+pub mod secret;
+pub mod selftest;
+pub mod spool;
+pub mod state;
+pub mod template;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod workers;
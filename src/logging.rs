@@ -0,0 +1,179 @@
+//! Logging through env_logger, with warnings and errors repeated within an interval
+//! summarized instead of flooding the journal during long outages
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use log::{Level, Log, Metadata, Record};
+use tokio::time::Instant;
+
+/// Seconds, 0 disables throttling
+static INTERVAL: AtomicU64 = AtomicU64::new(300);
+
+/// Change how long repetitions are suppressed, e.g. once the configuration is read
+pub fn set_interval(seconds: u64) {
+    INTERVAL.store(seconds, Ordering::Relaxed);
+}
+
+/// When a message was first logged, and how often it was repeated since
+struct Seen {
+    since: Instant,
+    suppressed: u64,
+}
+
+/// Passes a warning or error only if it was not logged within the interval. Repetitions
+/// are counted and summarized with the next message logged after the interval.
+pub struct Throttled<L> {
+    inner: L,
+    seen: Mutex<HashMap<(Level, String, String), Seen>>,
+}
+
+impl<L: Log> Throttled<L> {
+    pub fn new(inner: L) -> Self {
+        Self {
+            inner,
+            seen: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<L: Log> Log for Throttled<L> {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.inner.enabled(record.metadata()) {
+            return;
+        }
+        let interval = Duration::from_secs(INTERVAL.load(Ordering::Relaxed));
+        if interval.is_zero() || record.level() > Level::Warn {
+            self.inner.log(record);
+            return;
+        }
+        let now = Instant::now();
+        let key = (
+            record.level(),
+            record.target().to_owned(),
+            record.args().to_string(),
+        );
+        let mut summaries = Vec::new();
+        let repeated = {
+            let mut seen = self.seen.lock().expect("log lock poisoned");
+            seen.retain(|(level, target, message), s| {
+                if now.duration_since(s.since) < interval {
+                    return true;
+                }
+                if s.suppressed > 0 {
+                    summaries.push((*level, target.clone(), message.clone(), s.suppressed));
+                }
+                false
+            });
+            match seen.get_mut(&key) {
+                Some(s) => {
+                    s.suppressed += 1;
+                    true
+                }
+                None => {
+                    seen.insert(
+                        key,
+                        Seen {
+                            since: now,
+                            suppressed: 0,
+                        },
+                    );
+                    false
+                }
+            }
+        };
+        for (level, target, message, suppressed) in summaries {
+            self.inner.log(
+                &Record::builder()
+                    .level(level)
+                    .target(&target)
+                    .args(format_args!(
+                        "{} (repeated {} more times in {}s)",
+                        message,
+                        suppressed,
+                        interval.as_secs()
+                    ))
+                    .build(),
+            );
+        }
+        if !repeated {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush()
+    }
+}
+
+/// env_logger configured by `RUST_LOG`, throttled
+pub fn init() {
+    let logger = env_logger::Builder::from_default_env().build();
+    let max_level = logger.filter();
+    if log::set_boxed_logger(Box::new(Throttled::new(logger))).is_ok() {
+        log::set_max_level(max_level);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use log::{Level, Log, Metadata, Record};
+
+    use crate::logging::Throttled;
+
+    #[derive(Clone, Default)]
+    struct Collect(Arc<Mutex<Vec<String>>>);
+
+    impl Log for Collect {
+        fn enabled(&self, _: &Metadata) -> bool {
+            true
+        }
+        fn log(&self, record: &Record) {
+            self.0.lock().unwrap().push(record.args().to_string());
+        }
+        fn flush(&self) {}
+    }
+
+    fn log(logger: &Throttled<Collect>, level: Level, message: &str) {
+        logger.log(
+            &Record::builder()
+                .level(level)
+                .args(format_args!("{}", message))
+                .build(),
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn repetitions() {
+        let collected = Collect::default();
+        let logger = Throttled::new(collected.clone());
+        for _ in 0..3 {
+            log(&logger, Level::Error, "down");
+            log(&logger, Level::Info, "retrying");
+        }
+        log(&logger, Level::Warn, "slow");
+        tokio::time::advance(Duration::from_secs(301)).await;
+        log(&logger, Level::Error, "down");
+        assert_eq!(
+            *collected.0.lock().unwrap(),
+            [
+                "down",
+                "retrying",
+                "retrying",
+                "retrying",
+                "slow",
+                "down (repeated 2 more times in 300s)",
+                "down"
+            ]
+        );
+    }
+}
@@ -1,38 +1,151 @@
 //! Antikoerper is a simple and lightweight data aggregation and visualization tool
 
-use std::io::Read;
 use std::path::PathBuf;
+use std::time::Duration;
 
-use anyhow::Result;
-use clap::Parser;
-use log::{error, info};
+use anyhow::{bail, Result};
+use clap::{Parser, Subcommand};
+use itertools::Itertools;
+use log::{error, info, warn};
 
-mod app;
-mod conf;
-mod item;
-mod output;
+use antikoerper::{app, check, conf, control, logging, selftest, spool};
 
 #[derive(Parser)]
 #[command(name = "Antikörper")]
 #[command(author, version, about, long_about = None)]
 struct Cli {
+    /// May be given several times, later files override earlier ones; defaults to
+    /// the paths in ANTIKOERPER_CONFIG separated by `:`
     #[arg(short, long, value_name = "CONFIG")]
-    config: Option<PathBuf>,
+    config: Vec<PathBuf>,
     #[arg(short, long)]
     daemonize: bool,
+    /// Randomly inject failures, slow outputs and bursts of results
+    #[arg(long, hide = true)]
+    chaos: bool,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Control the running daemon
+    Ctl {
+        #[command(subcommand)]
+        command: CtlCommand,
+    },
+    /// Inspect and manage results outputs buffered because they could not write them
+    Spool {
+        #[command(subcommand)]
+        command: SpoolCommand,
+    },
+    /// Send a manual data point to all outputs of the running daemon, e.g. for mood or
+    /// coffee count
+    Annotate {
+        key: String,
+        /// Written as value if numeric, otherwise only as raw value
+        value: String,
+        /// Attached as tag `note`, and sent as event
+        #[arg(long)]
+        note: Option<String>,
+    },
+    /// Write a probe to the configured outputs, to test their credentials and
+    /// permissions before running the daemon
+    OutputCheck {
+        /// Only this output
+        output: Option<String>,
+    },
+    /// Run every item once and digest its output, without writing to the outputs
+    Selftest {
+        /// How long each item may take
+        #[arg(long, value_parser = humantime::parse_duration, default_value = "10s")]
+        timeout: Duration,
+    },
+}
+
+#[derive(Subcommand)]
+enum CtlCommand {
+    /// Show the state of the daemon, including active silences
+    Status,
+    /// Suppress alerts for items matching a glob
+    Silence {
+        keys: String,
+        /// How long to silence, e.g. "2h" or "30min"
+        #[arg(long = "for", value_parser = humantime::parse_duration)]
+        duration: Duration,
+        #[arg(long)]
+        comment: Option<String>,
+    },
+    /// Remove a silence previously added with `ctl silence`
+    Unsilence { keys: String },
+}
+
+#[derive(Subcommand)]
+enum SpoolCommand {
+    /// Show how many results each output has buffered, and since when
+    Ls,
+    /// Write the buffered results now, while the daemon is stopped
+    Replay {
+        /// Only the buffer of this output
+        output: Option<String>,
+    },
+    /// Drop buffered results for good
+    Purge {
+        /// Only the buffer of this output, or a buffer of no configured output
+        output: Option<String>,
+    },
+}
+
+fn ctl_request(command: CtlCommand) -> control::Request {
+    match command {
+        CtlCommand::Status => control::Request::Status,
+        CtlCommand::Silence {
+            keys,
+            duration,
+            comment,
+        } => control::Request::Silence {
+            keys,
+            duration: duration.as_secs(),
+            comment: comment.unwrap_or_default(),
+        },
+        CtlCommand::Unsilence { keys } => control::Request::Unsilence { keys },
+    }
+}
+
+async fn ctl(config: &conf::Config, request: control::Request) -> Result<()> {
+    // Whoever can read the configuration may use its tokens
+    let access = &config.general.control;
+    let token = std::env::var("ANTIKOERPER_CONTROL_TOKEN").ok().or_else(|| {
+        access
+            .token
+            .as_ref()
+            .or(access.read_token.as_ref())
+            .map(|token| token.expose().to_owned())
+    });
+    let response =
+        control::request(&config.general.control_socket, request, token.as_deref()).await?;
+    if !response.ok {
+        bail!("{}", response.message);
+    }
+    println!("{}", response.message.trim_end());
+    Ok(())
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
 
-    env_logger::Builder::from_default_env().init();
+    logging::init();
 
-    let config_path = cli
-        .config
-        .unwrap_or_else(|| PathBuf::from("/etc/antikoerper/config.toml"));
+    let config_paths = if !cli.config.is_empty() {
+        cli.config
+    } else if let Some(paths) = std::env::var_os("ANTIKOERPER_CONFIG").filter(|p| !p.is_empty()) {
+        std::env::split_paths(&paths).collect()
+    } else {
+        vec![PathBuf::from("/etc/antikoerper/config.toml")]
+    };
 
-    if cli.daemonize {
+    if cli.daemonize && cli.command.is_none() {
         let mut child = std::process::Command::new(
             std::env::args()
                 .next()
@@ -54,21 +167,60 @@ async fn main() -> Result<()> {
         })?;
     }
 
-    info!("Config file used: {}", &config_path.display());
+    info!(
+        "Config files used: {}",
+        config_paths.iter().map(|path| path.display()).join(", ")
+    );
 
-    let mut file = std::fs::File::open(&config_path).map_err(|e| {
-        error!("Failed opening configuration file, {}", e);
+    let mut config = conf::load_files(&config_paths).map_err(|e| {
+        error!("Failed loading configuration, {:#}", e);
         e
     })?;
+    logging::set_interval(config.general.log_repeat_interval);
+    if cli.chaos {
+        warn!("Chaos mode: injecting failures, slow outputs and bursts of results");
+        config.general.chaos = true;
+    }
+
+    match cli.command {
+        Some(Command::Ctl { command }) => return ctl(&config, ctl_request(command)).await,
+        Some(Command::Annotate { key, value, note }) => {
+            return ctl(&config, control::Request::Annotate { key, value, note }).await
+        }
+        Some(Command::Spool { command }) => {
+            let command = match command {
+                SpoolCommand::Ls => spool::Command::List,
+                SpoolCommand::Replay { output } => spool::Command::Replay(output),
+                SpoolCommand::Purge { output } => spool::Command::Purge(output),
+            };
+            print!("{}", spool::run(config, command).await?);
+            return Ok(());
+        }
+        Some(Command::OutputCheck { output }) => {
+            print!("{}", check::run(config, output).await?);
+            return Ok(());
+        }
+        Some(Command::Selftest { timeout }) => {
+            print!("{}", selftest::run(config, timeout).await?);
+            return Ok(());
+        }
+        None => (),
+    }
 
-    let config = conf::load(&mut file as &mut dyn Read).map_err(|e| {
-        error!("Failed parsing configuration, {}", e);
+    let app = app::App::try_from(config).map_err(|e| {
+        error!("Failed setting up outputs, {}", e);
         e
     })?;
 
-    let app = app::App::from(config);
+    let chaos = cli.chaos;
+    let reload = move || {
+        let mut config = conf::load_files(&config_paths)?;
+        logging::set_interval(config.general.log_repeat_interval);
+        config.general.chaos = chaos;
+        Ok(config)
+    };
 
-    app.start().await.map_err(|e| {
+    app.run(reload).await.map_err(|e| {
         error!("Application startup failed for following reason:");
         error!("{}", e);
         e
@@ -1,28 +1,267 @@
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use async_trait::async_trait;
+use base64::Engine;
 use influxdb::{self, InfluxDbWriteable};
 use log::{debug, error, warn};
 use tokio::fs::{File, OpenOptions};
 use tokio::io::AsyncWriteExt;
-use tokio::sync::broadcast;
+use tokio::sync::{broadcast, watch};
+use zeroize::Zeroizing;
 
-use crate::conf::OutputKind;
+use crate::alert::{AlertFilter, Silences};
+use crate::chaos;
+use crate::conf::{
+    AggregateConfig, FileLayout, General, InfluxDBAuth, InfluxDBMode, OutputConfig, OutputKind,
+    Timestamps,
+};
+use crate::dispatcher::Dispatcher;
+use crate::event::{Event, Severity};
 use crate::item::ItemResult;
+use crate::keys::Key;
+use crate::template::{Template, TemplateContext};
+#[cfg(any(test, feature = "testing"))]
+use crate::testing::MockOutput;
+
+mod aggregate;
+mod arrow;
+mod breaker;
+pub mod buffer;
+mod cloudwatch;
+mod datadog;
+mod deadletter;
+mod dedup;
+mod exec;
+mod filter;
+mod icinga2;
+mod influxdb3;
+mod lineprotocol;
+mod mqtt;
+mod pool;
+mod push;
+mod questdb;
+mod rewrite;
+mod socket;
+mod sql;
+mod tls;
+mod webhook;
+mod zabbix;
+
+use arrow::ArrowOutput;
+use breaker::Breaker;
+use buffer::Buffer;
+use cloudwatch::CloudWatchOutput;
+use datadog::DatadogOutput;
+use deadletter::{DeadLetter, Rejected};
+use dedup::ChangeFilter;
+use exec::ExecOutput;
+use filter::{forward, until, KeyFilter};
+use icinga2::Icinga2Output;
+use influxdb3::InfluxDB3Output;
+use mqtt::MqttOutput;
+use push::{PushOutput, PushService};
+use questdb::QuestDBOutput;
+use rewrite::KeyRewrite;
+use socket::SocketOutput;
+use sql::SqlOutput;
+use webhook::WebhookOutput;
+use zabbix::ZabbixOutput;
 
 #[async_trait]
 pub trait AKOutput {
     fn prepare(&self) -> Result<()>;
-    async fn start(self, mut receiver: broadcast::Receiver<ItemResult>);
+    /// Runs until the receiver is closed, which only happens when antikoerper shuts
+    /// down. Reloads keep the receiver of an unchanged output open, and abort the tasks of
+    /// outputs that changed.
+    async fn start(self, mut receiver: broadcast::Receiver<Arc<ItemResult>>);
+}
+
+/// Outputs which are able to handle events, besides results
+#[async_trait]
+pub trait AKEventOutput {
+    async fn start_events(self, mut receiver: broadcast::Receiver<Event>);
+}
+
+/// An output, with the handling common to all kinds of outputs
+#[derive(Clone)]
+pub struct ConfiguredOutput {
+    pub name: String,
+    pub output: Output,
+    pub breaker: Breaker,
+    filter: KeyFilter,
+    rewrite: KeyRewrite,
+    aggregate: Option<AggregateConfig>,
+    changes: Option<ChangeFilter>,
+    concurrency: usize,
+    timestamps: Timestamps,
+    chaos: bool,
+}
+
+impl ConfiguredOutput {
+    pub fn new(
+        name: String,
+        config: OutputConfig,
+        general: &General,
+        silences: &Silences,
+    ) -> Result<Self> {
+        let breaker = Breaker::new(name.clone(), config.circuit_breaker);
+        if config.concurrency == 0 {
+            bail!("{}: concurrency must be at least 1", name);
+        }
+        if config.concurrency > 1 {
+            match &config.kind {
+                // Single files, processes or client ids
+                OutputKind::Arrow { .. } | OutputKind::Exec { .. } | OutputKind::Mqtt { .. } => {
+                    bail!(
+                        "{}: {} outputs do not support concurrency",
+                        name,
+                        config.kind.type_name()
+                    )
+                }
+                OutputKind::InfluxDB {
+                    buffer: Some(_), ..
+                }
+                | OutputKind::InfluxDB3 {
+                    buffer: Some(_), ..
+                } => bail!("{}: concurrency cannot be combined with buffer", name),
+                _ => {}
+            }
+        }
+        Ok(Self {
+            filter: KeyFilter::new(&config.include_keys, &config.exclude_keys)?,
+            rewrite: KeyRewrite::new(config.prefix, config.suffix, config.rename)?,
+            aggregate: config.aggregate,
+            changes: config
+                .write_on_change_only
+                .then(|| ChangeFilter::new(config.heartbeat)),
+            concurrency: config.concurrency,
+            timestamps: config.timestamps,
+            chaos: general.chaos,
+            output: Output::new(
+                &name,
+                config.kind,
+                general,
+                silences,
+                breaker.clone(),
+                config.timestamps,
+            )?,
+            breaker,
+            name,
+        })
+    }
+
+    /// The results this output is supposed to receive, until `stop` is told to
+    pub fn results(
+        &self,
+        dispatcher: &Dispatcher,
+        stop: &watch::Receiver<()>,
+    ) -> broadcast::Receiver<Arc<ItemResult>> {
+        let mut receiver = until(dispatcher.subscribe(), stop.clone());
+        if !self.filter.is_empty() || !self.rewrite.is_empty() {
+            let filter = self.filter.clone();
+            let rewrite = self.rewrite.clone();
+            receiver = forward(receiver, move |result: Arc<ItemResult>| {
+                filter.matches(&result.key).then(|| {
+                    if rewrite.is_empty() {
+                        result
+                    } else {
+                        Arc::new(rewrite.apply(Arc::unwrap_or_clone(result)))
+                    }
+                })
+            });
+        }
+        if let Some(config) = &self.aggregate {
+            receiver = aggregate::aggregate(receiver, config.clone());
+        }
+        if let Some(changes) = &self.changes {
+            let mut changes = changes.clone();
+            receiver = forward(receiver, move |result| changes.apply(result));
+        }
+        // Outputs holding results back, in batches or buffers, stamp them when writing them
+        if self.timestamps == Timestamps::Write && !self.output.stamps_on_write() {
+            let timestamps = self.timestamps;
+            receiver = forward(receiver, move |result| Some(timestamps.stamp(result)));
+        }
+        if self.chaos {
+            receiver = chaos::slow(receiver);
+        }
+        receiver
+    }
+
+    /// The buffer of results the output failed to write, if it has one
+    pub fn spool(&self) -> Option<&Buffer> {
+        match &self.output {
+            Output::InfluxDB(output) => output.buffer.as_ref(),
+            Output::InfluxDB3(output) => output.buffer(),
+            _ => None,
+        }
+    }
+
+    /// Write the buffered results now, up to the first one failing
+    pub async fn replay_spool(&self) -> Result<()> {
+        match &self.output {
+            Output::InfluxDB(output) => output.replay_buffer().await,
+            Output::InfluxDB3(output) => output.replay_buffer().await,
+            _ => Ok(()),
+        }
+    }
+
+    /// The results for each of the output's write tasks, until `stop` is told to
+    pub fn result_pool(
+        &self,
+        dispatcher: &Dispatcher,
+        stop: &watch::Receiver<()>,
+    ) -> Vec<broadcast::Receiver<Arc<ItemResult>>> {
+        let receiver = self.results(dispatcher, stop);
+        if self.concurrency > 1 {
+            pool::split(receiver, self.concurrency)
+        } else {
+            vec![receiver]
+        }
+    }
+
+    /// The events this output is supposed to receive, until `stop` is told to
+    pub fn events(
+        &self,
+        dispatcher: &Dispatcher,
+        stop: &watch::Receiver<()>,
+    ) -> broadcast::Receiver<Event> {
+        let receiver = until(dispatcher.subscribe_events(), stop.clone());
+        if self.filter.is_empty() {
+            return receiver;
+        }
+        let filter = self.filter.clone();
+        forward(receiver, move |event: Event| {
+            filter.matches(&event.key).then_some(event)
+        })
+    }
 }
 
+#[allow(clippy::large_enum_variant)]
 #[derive(Clone)]
 pub enum Output {
     File(FileOutput),
     InfluxDB(InfluxDBOutput),
+    InfluxDB3(InfluxDB3Output),
+    Exec(ExecOutput),
+    Arrow(ArrowOutput),
+    CloudWatch(CloudWatchOutput),
+    Datadog(DatadogOutput),
+    Icinga2(Icinga2Output),
+    Mqtt(MqttOutput),
+    Push(PushOutput),
+    QuestDB(QuestDBOutput),
+    Socket(SocketOutput),
+    Sql(SqlOutput),
+    Webhook(WebhookOutput),
+    Zabbix(ZabbixOutput),
+    #[cfg(any(test, feature = "testing"))]
+    Mock(MockOutput),
 }
 
 #[async_trait]
@@ -31,54 +270,432 @@ impl AKOutput for Output {
         match self {
             Self::File(output) => output.prepare(),
             Self::InfluxDB(output) => output.prepare(),
+            Self::InfluxDB3(output) => output.prepare(),
+            Self::Exec(output) => output.prepare(),
+            Self::Arrow(output) => output.prepare(),
+            Self::CloudWatch(output) => output.prepare(),
+            Self::Datadog(output) => output.prepare(),
+            Self::Icinga2(output) => output.prepare(),
+            Self::Mqtt(output) => output.prepare(),
+            Self::Push(output) => output.prepare(),
+            Self::QuestDB(output) => output.prepare(),
+            Self::Socket(output) => output.prepare(),
+            Self::Sql(output) => output.prepare(),
+            Self::Webhook(output) => output.prepare(),
+            Self::Zabbix(output) => output.prepare(),
+            #[cfg(any(test, feature = "testing"))]
+            Self::Mock(output) => output.prepare(),
         }
     }
-    async fn start(self, receiver: broadcast::Receiver<ItemResult>) {
+    async fn start(self, receiver: broadcast::Receiver<Arc<ItemResult>>) {
         match self {
             Self::File(output) => output.start(receiver).await,
             Self::InfluxDB(output) => output.start(receiver).await,
+            Self::InfluxDB3(output) => output.start(receiver).await,
+            Self::Exec(output) => output.start(receiver).await,
+            Self::Arrow(output) => output.start(receiver).await,
+            Self::CloudWatch(output) => output.start(receiver).await,
+            Self::Datadog(output) => output.start(receiver).await,
+            Self::Icinga2(output) => output.start(receiver).await,
+            Self::Mqtt(output) => output.start(receiver).await,
+            Self::Push(output) => output.start(receiver).await,
+            Self::QuestDB(output) => output.start(receiver).await,
+            Self::Socket(output) => output.start(receiver).await,
+            Self::Sql(output) => output.start(receiver).await,
+            Self::Webhook(output) => output.start(receiver).await,
+            Self::Zabbix(output) => output.start(receiver).await,
+            #[cfg(any(test, feature = "testing"))]
+            Self::Mock(output) => output.start(receiver).await,
         }
     }
 }
 
-impl From<OutputKind> for Output {
-    fn from(ok: OutputKind) -> Self {
-        match ok {
+#[async_trait]
+impl AKEventOutput for Output {
+    async fn start_events(self, receiver: broadcast::Receiver<Event>) {
+        match self {
+            Self::File(output) => output.start_events(receiver).await,
+            Self::Push(output) => output.start_events(receiver).await,
+            Self::Webhook(output) => output.start_events(receiver).await,
+            #[cfg(any(test, feature = "testing"))]
+            Self::Mock(output) => output.start_events(receiver).await,
+            _ => (),
+        }
+    }
+}
+
+impl Output {
+    /// Prepare the output and write a probe to it, what was done if it worked, for
+    /// `antikoerper output-check`
+    pub async fn check(&self) -> Result<String> {
+        self.prepare()?;
+        match self {
+            Self::File(output) => probe_file(&output.base_path),
+            Self::InfluxDB(output) => output.check().await,
+            Self::InfluxDB3(output) => output.check().await,
+            Self::Exec(output) => output.check().await,
+            Self::Arrow(output) => output.check(),
+            Self::CloudWatch(output) => output.check().await,
+            Self::Datadog(output) => output.check().await,
+            Self::Icinga2(output) => output.check().await,
+            Self::Mqtt(output) => output.check().await,
+            Self::Push(output) => output.check().await,
+            Self::QuestDB(output) => output.check().await,
+            Self::Socket(output) => output.check().await,
+            Self::Sql(output) => output.check().await,
+            Self::Webhook(output) => output.check().await,
+            Self::Zabbix(output) => output.check().await,
+            #[cfg(any(test, feature = "testing"))]
+            Self::Mock(_) => Ok(String::from("nothing to check")),
+        }
+    }
+
+    /// Whether the output applies `timestamps = "write"` itself, rather than getting the
+    /// results stamped on arrival
+    fn stamps_on_write(&self) -> bool {
+        matches!(
+            self,
+            Self::InfluxDB(_)
+                | Self::InfluxDB3(_)
+                | Self::Arrow(_)
+                | Self::CloudWatch(_)
+                | Self::Datadog(_)
+        )
+    }
+
+    /// Whether the output is configured to receive events
+    pub fn handles_events(&self) -> bool {
+        match self {
+            Self::File(output) => output.events.is_some(),
+            Self::Push(output) => output.handles_events(),
+            Self::Webhook(output) => output.handles_events(),
+            #[cfg(any(test, feature = "testing"))]
+            Self::Mock(_) => true,
+            _ => false,
+        }
+    }
+
+    pub fn new(
+        name: &str,
+        ok: OutputKind,
+        general: &General,
+        silences: &Silences,
+        breaker: Breaker,
+        timestamps: Timestamps,
+    ) -> Result<Self> {
+        Ok(match ok {
             OutputKind::File {
                 base_path,
                 always_write_raw,
+                layout,
+                events,
             } => Output::File(FileOutput {
                 base_path,
                 always_write_raw,
+                layout,
+                events,
             }),
             OutputKind::InfluxDB {
                 url,
+                urls,
+                mode,
                 database,
+                retention_policy,
                 auth,
                 use_raw_as_fallback,
                 always_write_raw,
+                batch_size,
+                flush_interval,
+                buffer,
+                tls,
+                dead_letter,
             } => {
-                let client = auth
-                    .as_ref()
-                    .map(|crate::conf::InfluxDBAuth { username, password }| {
-                        influxdb::Client::new(url.clone(), database.clone())
-                            .with_auth(username, password)
-                    })
-                    .unwrap_or_else(|| influxdb::Client::new(url, database));
+                // By the output's name, unique unlike its servers
+                let buffer = buffer.map(|config| Buffer::new(&config, &general.state_dir, name));
+                let mut http_client = tls::builder(tls.as_ref())?;
+                if let Some(auth) = &auth {
+                    // Basic authentication, the client's own puts the password into URLs,
+                    // which end up in error messages
+                    http_client = http_client.default_headers(influx_auth_header(auth)?);
+                }
+                let urls = if urls.is_empty() { vec![url] } else { urls };
                 Output::InfluxDB(InfluxDBOutput {
                     use_raw_as_fallback,
                     always_write_raw,
-                    client,
+                    batch_size: batch_size.max(1),
+                    flush_interval,
+                    urls,
+                    database,
+                    retention_policy,
+                    hostname: general.hostname.clone(),
+                    http_client: http_client.build()?,
+                    mode,
+                    active: Default::default(),
+                    buffer,
+                    dead_letter: dead_letter.map(DeadLetter::new),
+                    breaker,
+                    timestamps,
                 })
             }
-        }
+            OutputKind::Arrow {
+                base_path,
+                flush_interval,
+                rotate_interval,
+                use_raw_as_fallback,
+                always_write_raw,
+            } => Output::Arrow(ArrowOutput::new(
+                base_path,
+                flush_interval,
+                rotate_interval,
+                use_raw_as_fallback,
+                always_write_raw,
+                timestamps,
+            )),
+            OutputKind::CloudWatch {
+                region,
+                namespace,
+                endpoint,
+                credentials,
+                dimensions,
+                flush_interval,
+                http,
+            } => Output::CloudWatch(CloudWatchOutput::new(
+                region,
+                namespace,
+                endpoint,
+                credentials,
+                dimensions,
+                general.hostname.clone(),
+                flush_interval,
+                tls::http_client(&http)?,
+                breaker,
+                timestamps,
+            )),
+            OutputKind::InfluxDB3 {
+                url,
+                database,
+                token,
+                use_raw_as_fallback,
+                always_write_raw,
+                buffer,
+                tls,
+                dead_letter,
+            } => {
+                let buffer = buffer.map(|config| Buffer::new(&config, &general.state_dir, name));
+                Output::InfluxDB3(InfluxDB3Output::new(
+                    url,
+                    database,
+                    token,
+                    use_raw_as_fallback,
+                    always_write_raw,
+                    buffer,
+                    tls::client(tls.as_ref())?,
+                    dead_letter.map(DeadLetter::new),
+                    breaker,
+                    timestamps,
+                ))
+            }
+            OutputKind::Exec { path, args } => Output::Exec(ExecOutput::new(path, args)),
+            OutputKind::Datadog {
+                api_key,
+                site,
+                tags,
+                flush_interval,
+                http,
+            } => Output::Datadog(DatadogOutput::new(
+                api_key,
+                site,
+                tags,
+                general.hostname.clone(),
+                flush_interval,
+                tls::http_client(&http)?,
+                breaker,
+                timestamps,
+            )),
+            OutputKind::Icinga2 {
+                url,
+                username,
+                password,
+                host,
+                ca_file,
+            } => Output::Icinga2(Icinga2Output::new(
+                url,
+                username,
+                password,
+                host.unwrap_or_else(|| general.hostname.clone()),
+                ca_file,
+                general.hostname.clone(),
+                breaker,
+            )?),
+            OutputKind::Mqtt {
+                host,
+                port,
+                username,
+                password,
+                topic,
+                retain,
+                homeassistant,
+                use_raw_as_fallback,
+                always_write_raw,
+            } => Output::Mqtt(MqttOutput::new(
+                host,
+                port,
+                username,
+                password,
+                topic,
+                retain,
+                homeassistant,
+                general.hostname.clone(),
+                name,
+                use_raw_as_fallback,
+                always_write_raw,
+            )),
+            OutputKind::Ntfy {
+                url,
+                topic,
+                token,
+                template,
+                alert,
+                events,
+                http,
+            } => Output::Push(PushOutput::new(
+                url,
+                PushService::Ntfy { topic, token },
+                template,
+                AlertFilter::new(alert, silences.clone()),
+                events,
+                silences.clone(),
+                general.hostname.clone(),
+                tls::http_client(&http)?,
+            )),
+            OutputKind::Gotify {
+                url,
+                token,
+                template,
+                alert,
+                events,
+                http,
+            } => Output::Push(PushOutput::new(
+                url,
+                PushService::Gotify { token },
+                template,
+                AlertFilter::new(alert, silences.clone()),
+                events,
+                silences.clone(),
+                general.hostname.clone(),
+                tls::http_client(&http)?,
+            )),
+            OutputKind::QuestDB {
+                host,
+                port,
+                table,
+                auth,
+                use_raw_as_fallback,
+                always_write_raw,
+            } => Output::QuestDB(QuestDBOutput::new(
+                host,
+                port,
+                table,
+                general.hostname.clone(),
+                auth,
+                use_raw_as_fallback,
+                always_write_raw,
+                breaker,
+            )?),
+            OutputKind::Socket {
+                address,
+                format,
+                use_raw_as_fallback,
+                always_write_raw,
+            } => Output::Socket(SocketOutput::new(
+                address,
+                format,
+                use_raw_as_fallback,
+                always_write_raw,
+            )),
+            OutputKind::Sql {
+                url,
+                statement,
+                use_raw_as_fallback,
+                always_write_raw,
+            } => Output::Sql(SqlOutput::new(
+                url,
+                statement,
+                use_raw_as_fallback,
+                always_write_raw,
+                breaker,
+            )?),
+            OutputKind::Zabbix {
+                server,
+                port,
+                host,
+                use_raw_as_fallback,
+                always_write_raw,
+            } => Output::Zabbix(ZabbixOutput::new(
+                server,
+                port,
+                host.unwrap_or_else(|| general.hostname.clone()),
+                use_raw_as_fallback,
+                always_write_raw,
+                breaker,
+            )),
+            OutputKind::Webhook {
+                url,
+                method,
+                headers,
+                template,
+                alert,
+                events,
+                event_template,
+                http,
+            } => Output::Webhook(WebhookOutput::new(
+                url,
+                method,
+                headers,
+                template,
+                alert.map(|rule| AlertFilter::new(rule, silences.clone())),
+                events,
+                event_template,
+                silences.clone(),
+                general.hostname.clone(),
+                tls::http_client(&http)?,
+            )?),
+            #[cfg(any(test, feature = "testing"))]
+            OutputKind::Mock(output) => Output::Mock(output),
+        })
+    }
+}
+
+/// Key of the probe written by `antikoerper output-check`
+pub const PROBE: &str = "antikoerper.check";
+
+/// The result written by `antikoerper output-check`
+pub fn probe() -> ItemResult {
+    ItemResult {
+        time: crate::clock::now(),
+        key: PROBE.into(),
+        raw: String::from("antikoerper output-check"),
+        values: HashMap::from([(Key::from(PROBE), 1.0)]),
+        tags: BTreeMap::new(),
     }
 }
 
+/// Create and remove a file in the directory an output writes to
+fn probe_file(dir: &Path) -> Result<String> {
+    let path = dir.join(".antikoerper-check");
+    std::fs::write(&path, "antikoerper output-check\n")
+        .with_context(|| format!("Failed writing {}", path.display()))?;
+    std::fs::remove_file(&path).with_context(|| format!("Failed removing {}", path.display()))?;
+    Ok(format!("wrote and removed {}", path.display()))
+}
+
 #[derive(Clone)]
 pub struct FileOutput {
     base_path: PathBuf,
     always_write_raw: bool,
+    layout: FileLayout,
+    /// Minimum severity of events written to `events.log`
+    events: Option<Severity>,
 }
 
 impl FileOutput {
@@ -105,12 +722,33 @@ impl FileOutput {
             .await?;
         Ok(())
     }
-    async fn write_values(&self, values: &HashMap<String, f64>, time: &Duration) -> Result<()> {
+    async fn write_values(&self, values: &HashMap<Key, f64>, time: &Duration) -> Result<()> {
         for (key, value) in values.iter() {
             self.write_value(key, *value, time).await?;
         }
         Ok(())
     }
+    /// The values named below the item's key, sorted, and the raw output quoted as JSON
+    async fn write_result(&self, result: &ItemResult, raw: bool) -> Result<()> {
+        let prefix = format!("{}.", result.key);
+        let mut line = result
+            .values
+            .iter()
+            .map(|(key, value)| {
+                let name = key.strip_prefix(&prefix).unwrap_or(key);
+                format!("{}={}", name, value)
+            })
+            .collect::<Vec<_>>();
+        line.sort();
+        line.insert(0, result.time.as_secs().to_string());
+        if raw {
+            line.push(format!("raw={}", serde_json::to_string(&result.raw)?));
+        }
+        let mut file = self.open_file(&result.key).await?;
+        file.write_all(format!("{}\n", line.join(" ")).as_bytes())
+            .await?;
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -118,7 +756,7 @@ impl AKOutput for FileOutput {
     fn prepare(&self) -> Result<()> {
         std::fs::create_dir_all(self.base_path.clone()).map_err(anyhow::Error::from)
     }
-    async fn start(self, mut receiver: broadcast::Receiver<ItemResult>) {
+    async fn start(self, mut receiver: broadcast::Receiver<Arc<ItemResult>>) {
         debug!("FileOutput: Starting loop");
         loop {
             match receiver.recv().await {
@@ -131,7 +769,18 @@ impl AKOutput for FileOutput {
                 Ok(itemresult) => {
                     debug!("FileOutput: Received result for item {}", itemresult.key);
                     debug!("FileOutput: values: {:#?}", itemresult.values);
-                    if itemresult.values.is_empty() || self.always_write_raw {
+                    let raw = itemresult.values.is_empty() || self.always_write_raw;
+                    if self.layout == FileLayout::Item {
+                        if let Err(e) = self.write_result(&itemresult, raw).await {
+                            error!(
+                                "FileOutput: Failed writing data for Item {}",
+                                itemresult.key
+                            );
+                            error!("FileOutput: {}", e);
+                        }
+                        continue;
+                    }
+                    if raw {
                         if let Err(e) = self
                             .write_raw_value(
                                 &format!("{}.raw", itemresult.key),
@@ -165,40 +814,341 @@ impl AKOutput for FileOutput {
     }
 }
 
+#[async_trait]
+impl AKEventOutput for FileOutput {
+    async fn start_events(self, mut receiver: broadcast::Receiver<Event>) {
+        debug!("FileOutput: Starting event loop");
+        let Some(min_severity) = self.events else {
+            return;
+        };
+        loop {
+            match receiver.recv().await {
+                Err(recverr) => match recverr {
+                    broadcast::error::RecvError::Closed => break,
+                    broadcast::error::RecvError::Lagged(count) => {
+                        warn!("FileOutput is lagging behind, {} events skipped", count)
+                    }
+                },
+                Ok(event) if event.severity < min_severity => (),
+                Ok(event) => {
+                    let line = format!(
+                        "{} {} {}: {}\n",
+                        event.time.as_secs(),
+                        event.severity.as_str(),
+                        event.key,
+                        event.message.replace('\n', " ")
+                    );
+                    let written = match self.open_file("events.log").await {
+                        Ok(mut file) => file
+                            .write_all(line.as_bytes())
+                            .await
+                            .map_err(anyhow::Error::from),
+                        Err(e) => Err(e),
+                    };
+                    if let Err(e) = written {
+                        error!("FileOutput: Failed writing event for Item {}", event.key);
+                        error!("FileOutput: {}", e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn add_tags(query: influxdb::WriteQuery, tags: &BTreeMap<String, String>) -> influxdb::WriteQuery {
+    tags.iter().fold(query, |query, (name, value)| {
+        query.add_tag(name, value.as_str())
+    })
+}
+
+fn influx_auth_header(auth: &InfluxDBAuth) -> Result<reqwest::header::HeaderMap> {
+    let credentials = Zeroizing::new(format!("{}:{}", auth.username, auth.password.expose()));
+    let mut value = reqwest::header::HeaderValue::from_str(&format!(
+        "Basic {}",
+        base64::engine::general_purpose::STANDARD.encode(credentials.as_bytes())
+    ))?;
+    value.set_sensitive(true);
+    Ok(reqwest::header::HeaderMap::from_iter([(
+        reqwest::header::AUTHORIZATION,
+        value,
+    )]))
+}
+
+/// InfluxDB answers 400 for points it will never accept, e.g. because of field type
+/// conflicts, but the client only passes on the message
+fn influx_rejected(e: &influxdb::Error) -> bool {
+    matches!(e, influxdb::Error::DatabaseError { error }
+        if error.contains("partial write") || error.contains("unable to parse"))
+}
+
 #[derive(Clone)]
 pub struct InfluxDBOutput {
     use_raw_as_fallback: bool,
     always_write_raw: bool,
-    client: influxdb::Client,
+    batch_size: usize,
+    flush_interval: u64,
+    urls: Vec<String>,
+    database: String,
+    retention_policy: Option<Template>,
+    hostname: String,
+    http_client: reqwest::Client,
+    mode: InfluxDBMode,
+    /// Index of the client written to in failover mode
+    active: Arc<AtomicUsize>,
+    buffer: Option<Buffer>,
+    dead_letter: Option<DeadLetter>,
+    breaker: Breaker,
+    timestamps: Timestamps,
 }
 
 impl InfluxDBOutput {
-    async fn write_raw_value(&self, key: &str, value: &str, time: &Duration) -> Result<()> {
-        self.client
-            .query(
-                influxdb::Timestamp::Milliseconds(time.as_millis())
-                    .into_query(key)
-                    .add_field("value", value),
-            )
-            .await
-            .map(|_| ())
-            .map_err(anyhow::Error::from)
+    fn queries(&self, itemresult: &ItemResult) -> Vec<influxdb::WriteQuery> {
+        let timestamp = influxdb::Timestamp::Milliseconds(itemresult.time.as_millis());
+        let mut queries = itemresult
+            .values
+            .iter()
+            .map(|(key, value)| {
+                add_tags(
+                    timestamp.into_query(key.as_ref()).add_field("value", value),
+                    &itemresult.tags,
+                )
+            })
+            .collect::<Vec<influxdb::WriteQuery>>();
+        if itemresult.values.is_empty() && self.use_raw_as_fallback || self.always_write_raw {
+            queries.push(add_tags(
+                timestamp
+                    .into_query(format!("{}.raw", itemresult.key))
+                    .add_field("value", itemresult.raw.as_str()),
+                &itemresult.tags,
+            ));
+        }
+        queries
     }
-    async fn write_values(&self, values: &HashMap<String, f64>, time: &Duration) -> Result<()> {
-        self.client
-            .query(
-                values
-                    .iter()
-                    .map(|(key, value)| {
-                        influxdb::Timestamp::Milliseconds(time.as_millis())
-                            .into_query(key)
-                            .add_field("value", value)
-                    })
-                    .collect::<Vec<influxdb::WriteQuery>>(),
-            )
+
+    /// Write a probe point to every server and drop it again, which servers only allow
+    /// with the privileges for it
+    async fn check(&self) -> Result<String> {
+        let probe = probe();
+        let query = influxdb::Timestamp::Milliseconds(probe.time.as_millis())
+            .into_query(PROBE)
+            .add_field("value", 1.0);
+        let lines = influxdb::Query::build(&query)?.get();
+        let policy = self.retention_policy(&probe);
+        let mut kept = Vec::new();
+        for url in &self.urls {
+            self.post(url, &policy, &lines)
+                .await
+                .with_context(|| url.clone())?;
+            if let Err(e) = self.drop_probe(url).await {
+                kept.push(format!("{}: {:#}", url, e));
+            }
+        }
+        if kept.is_empty() {
+            Ok(format!("wrote and dropped a probe point {}", PROBE))
+        } else {
+            Ok(format!(
+                "wrote a probe point {}, failed dropping it: {}",
+                PROBE,
+                kept.join("; ")
+            ))
+        }
+    }
+
+    async fn drop_probe(&self, url: &str) -> Result<()> {
+        let response = self
+            .http_client
+            .post(format!("{}/query", url))
+            .query(&[
+                ("db", self.database.as_str()),
+                ("q", &format!("DROP SERIES FROM \"{}\"", PROBE)),
+            ])
+            .send()
+            .await?;
+        let status = response.status();
+        let text = response.text().await.unwrap_or_default();
+        // Failed statements are told in the body, with status 200
+        if !status.is_success() || text.contains("\"error\"") {
+            bail!("{}: {}", status, text.trim());
+        }
+        Ok(())
+    }
+
+    /// Write the buffered results now, for `antikoerper spool replay`
+    async fn replay_buffer(&self) -> Result<()> {
+        match self.buffer.clone() {
+            Some(mut buffer) => buffer.replay(&|r| self.write(r)).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn write(&self, itemresults: Vec<Arc<ItemResult>>) -> Result<()> {
+        let itemresults = itemresults
+            .into_iter()
+            .map(|itemresult| self.timestamps.stamp(itemresult))
+            .collect::<Vec<_>>();
+        let written = self.send(&itemresults).await;
+        match &self.dead_letter {
+            Some(dead_letter) => dead_letter.catch("InfluxDBOutput", &itemresults, written),
+            None => written,
+        }
+    }
+
+    /// The retention policy of a result, empty for the database's default
+    fn retention_policy(&self, itemresult: &ItemResult) -> String {
+        let Some(template) = &self.retention_policy else {
+            return String::new();
+        };
+        template
+            .render(&TemplateContext::new(itemresult, &self.hostname))
+            .map(|policy| policy.trim().to_owned())
+            .unwrap_or_else(|e| {
+                warn!(
+                    "InfluxDBOutput: no retention policy for item {}, using the default: {}",
+                    itemresult.key, e
+                );
+                String::new()
+            })
+    }
+
+    /// The raw values and all values of the results, in one request per retention policy
+    async fn send(&self, itemresults: &[Arc<ItemResult>]) -> Result<()> {
+        let mut policies = BTreeMap::<String, Vec<influxdb::WriteQuery>>::new();
+        for itemresult in itemresults {
+            policies
+                .entry(self.retention_policy(itemresult))
+                .or_default()
+                .extend(self.queries(itemresult));
+        }
+        let mut writes = Vec::new();
+        for (policy, queries) in policies {
+            if !queries.is_empty() {
+                writes.push((policy, influxdb::Query::build(&queries)?.get()));
+            }
+        }
+        if writes.is_empty() {
+            return Ok(());
+        }
+        match self.mode {
+            InfluxDBMode::Failover => self.write_failover(&writes).await,
+            InfluxDBMode::Mirror => self.write_mirror(&writes).await,
+        }
+    }
+
+    /// Write lines to the retention policy of the database on one server. The client
+    /// of the influxdb crate can't select retention policies.
+    async fn post(&self, url: &str, policy: &str, lines: &str) -> Result<(), influxdb::Error> {
+        let mut parameters = vec![("db", self.database.as_str()), ("precision", "ms")];
+        if !policy.is_empty() {
+            parameters.push(("rp", policy));
+        }
+        let response = self
+            .http_client
+            .post(format!("{}/write", url))
+            .query(&parameters)
+            .body(lines.to_owned())
+            .send()
             .await
-            .map(|_| ())
-            .map_err(anyhow::Error::from)
+            .map_err(|e| influxdb::Error::ConnectionError {
+                error: e.to_string(),
+            })?;
+        match response.status() {
+            reqwest::StatusCode::UNAUTHORIZED => Err(influxdb::Error::AuthorizationError),
+            reqwest::StatusCode::FORBIDDEN => Err(influxdb::Error::AuthenticationError),
+            status if status.is_success() => Ok(()),
+            status => Err(influxdb::Error::DatabaseError {
+                error: format!("{}: {}", status, response.text().await.unwrap_or_default()),
+            }),
+        }
+    }
+
+    /// All writes to one server, up to the first failing one
+    async fn post_all(
+        &self,
+        url: &str,
+        writes: &[(String, String)],
+    ) -> Result<(), influxdb::Error> {
+        for (policy, lines) in writes {
+            self.post(url, policy, lines).await?;
+        }
+        Ok(())
+    }
+
+    /// Starting with the server that worked last, the first one accepting the write
+    /// is kept
+    async fn write_failover(&self, writes: &[(String, String)]) -> Result<()> {
+        let active = self.active.load(Ordering::Relaxed);
+        let mut errors = Vec::new();
+        for offset in 0..self.urls.len() {
+            let index = (active + offset) % self.urls.len();
+            let url = &self.urls[index];
+            match self.post_all(url, writes).await {
+                Ok(_) => {
+                    if index != active {
+                        warn!("InfluxDBOutput: failed over to {}", url);
+                        self.active.store(index, Ordering::Relaxed);
+                    }
+                    return Ok(());
+                }
+                // The server is fine, failing over would not help
+                Err(e) if influx_rejected(&e) => {
+                    return Err(Rejected(format!("{}: {}", url, e)).into())
+                }
+                Err(e) => errors.push(format!("{}: {}", url, e)),
+            }
+        }
+        bail!("{}", errors.join("; "))
+    }
+
+    /// Fails if any server failed, writing again is harmless as InfluxDB overwrites
+    /// points with the same timestamp
+    async fn write_mirror(&self, writes: &[(String, String)]) -> Result<()> {
+        let mut errors = Vec::new();
+        let mut rejected = 0;
+        for url in &self.urls {
+            if let Err(e) = self.post_all(url, writes).await {
+                rejected += usize::from(influx_rejected(&e));
+                errors.push(format!("{}: {}", url, e));
+            }
+        }
+        if !errors.is_empty() && rejected == errors.len() {
+            return Err(Rejected(errors.join("; ")).into());
+        }
+        if !errors.is_empty() {
+            bail!("{}", errors.join("; "))
+        }
+        Ok(())
+    }
+
+    async fn flush(&self, buffer: &mut Option<Buffer>, pending: &mut Vec<Arc<ItemResult>>) {
+        if pending.is_empty() {
+            return;
+        }
+        let batch = std::mem::take(pending);
+        let keys = batch
+            .iter()
+            .map(|itemresult| itemresult.key.as_ref())
+            .collect::<Vec<_>>()
+            .join(", ");
+        debug!("InfluxDBOutput: writing {} results", batch.len());
+        let written = match buffer {
+            Some(buffer) if !self.breaker.allow() => {
+                buffer.push(&batch).await;
+                return;
+            }
+            Some(buffer) => {
+                let written = buffer.write(batch, |r| self.write(r)).await;
+                self.breaker.record(&written);
+                written
+            }
+            None => match self.breaker.call(self.write(batch)).await {
+                Some(written) => written,
+                None => return,
+            },
+        };
+        if let Err(e) = written {
+            error!("InfluxDBOutput: Failed writing data for Items {}", keys);
+            error!("InfluxDBOutput: {}", e)
+        }
     }
 }
 
@@ -207,57 +1157,118 @@ impl AKOutput for InfluxDBOutput {
     fn prepare(&self) -> Result<()> {
         Ok(())
     }
-    async fn start(self, mut receiver: broadcast::Receiver<ItemResult>) {
+    async fn start(mut self, mut receiver: broadcast::Receiver<Arc<ItemResult>>) {
         debug!("InfluxDBOutput: Starting loop");
+        let mut buffer = self.buffer.take();
+        let mut pending = Vec::new();
+        let mut flush = tokio::time::interval(Duration::from_secs(self.flush_interval.max(1)));
         loop {
-            match receiver.recv().await {
-                Err(recverr) => match recverr {
-                    broadcast::error::RecvError::Closed => break,
-                    broadcast::error::RecvError::Lagged(count) => {
+            tokio::select! {
+                _ = flush.tick() => self.flush(&mut buffer, &mut pending).await,
+                received = receiver.recv() => match received {
+                    Err(broadcast::error::RecvError::Closed) => {
+                        self.flush(&mut buffer, &mut pending).await;
+                        break;
+                    }
+                    Err(broadcast::error::RecvError::Lagged(count)) => {
                         warn!(
                             "InfluxDBOutput is lagging behind, {} results skipped",
                             count
                         )
                     }
-                },
-                Ok(itemresult) => {
-                    debug!(
-                        "InfluxDBOutput: Received result for item {}",
-                        itemresult.key
-                    );
-                    debug!("InfluxDBOutput: values: {:#?}", itemresult.values);
-                    if itemresult.values.is_empty() && self.use_raw_as_fallback
-                        || self.always_write_raw
-                    {
-                        if let Err(e) = self
-                            .write_raw_value(
-                                &format!("{}.raw", itemresult.key),
-                                &itemresult.raw,
-                                &itemresult.time,
-                            )
-                            .await
-                        {
-                            error!(
-                                "InfluxDBOutput: Failed writing raw data for Item {}",
-                                itemresult.key
-                            );
-                            error!("InfluxDBOutput: {}", e);
-                        }
-                    }
-                    if !itemresult.values.is_empty() {
-                        if let Err(e) = self
-                            .write_values(&itemresult.values, &itemresult.time)
-                            .await
-                        {
-                            error!(
-                                "InfluxDBOutout: Failed writing data for Item {}",
-                                itemresult.key
-                            );
-                            error!("InfluxDBOutput: {}", e)
+                    Ok(itemresult) => {
+                        debug!(
+                            "InfluxDBOutput: Received result for item {}",
+                            itemresult.key
+                        );
+                        debug!("InfluxDBOutput: values: {:#?}", itemresult.values);
+                        pending.push(itemresult);
+                        if pending.len() >= self.batch_size {
+                            self.flush(&mut buffer, &mut pending).await;
                         }
                     }
-                }
+                },
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+    use std::time::Duration;
+
+    use crate::app::App;
+    use crate::conf::FileLayout;
+    use crate::item::ItemResult;
+    use crate::output::FileOutput;
+    use crate::testing::ConfigBuilder;
+
+    #[tokio::test]
+    async fn item_layout() {
+        let dir = std::env::temp_dir().join(format!("antikoerper-file-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let output = FileOutput {
+            base_path: dir.clone(),
+            always_write_raw: true,
+            layout: FileLayout::Item,
+            events: None,
+        };
+        let result = ItemResult {
+            time: Duration::from_secs(1_000),
+            key: "os/load".into(),
+            raw: String::from("0.50 \"1.5\"\n"),
+            values: HashMap::from([
+                ("os/load.one".into(), 0.5),
+                ("os/load.five".into(), 1.5),
+                ("uptime".into(), 3.0),
+            ]),
+            tags: BTreeMap::new(),
+        };
+        output.write_result(&result, true).await.unwrap();
+        let written = std::fs::read_to_string(dir.join("os_load")).unwrap();
+        assert_eq!(
+            written,
+            "1000 five=1.5 one=0.5 uptime=3 raw=\"0.50 \\\"1.5\\\"\\n\"\n"
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn buffer_per_output() {
+        let influxdb = |urls: &str| {
+            toml::from_str(&format!(
+                "type = \"influxdb\"\nurls = {}\nbuffer = {{}}",
+                urls
+            ))
+            .unwrap()
+        };
+        let config = ConfigBuilder::new()
+            .item(
+                r#"
+                key = "load"
+                interval = 60
+                input = { type = "shell", script = "true" }
+                "#,
+            )
+            .output(influxdb(r#"["http://a:8086"]"#))
+            .output(influxdb(r#"["http://b:8086"]"#))
+            .build()
+            .unwrap();
+        let state_dir = config.general.state_dir.clone();
+        let app = App::try_from(config).unwrap();
+        let paths = app
+            .outputs()
+            .iter()
+            .map(|output| output.spool().unwrap().path().to_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [
+                state_dir.join("buffer/influxdb"),
+                state_dir.join("buffer/influxdb_1")
+            ]
+        );
+        std::fs::remove_dir_all(state_dir).unwrap();
+    }
+}
@@ -0,0 +1,180 @@
+//! Downsampling of results before they reach an output, e.g. one-minute rollups for a
+//! remote TSDB while a local file output gets every result
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::warn;
+use tokio::sync::broadcast;
+
+use crate::conf::{AggregateConfig, AggregateFunction};
+use crate::item::{now, ItemResult};
+use crate::keys::Key;
+
+#[derive(Debug, Clone, Copy)]
+struct Stats {
+    min: f64,
+    max: f64,
+    sum: f64,
+    count: u64,
+    last: f64,
+}
+
+impl Stats {
+    fn new(value: f64) -> Self {
+        Self {
+            min: value,
+            max: value,
+            sum: value,
+            count: 1,
+            last: value,
+        }
+    }
+
+    fn add(&mut self, value: f64) {
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.sum += value;
+        self.count += 1;
+        self.last = value;
+    }
+
+    fn get(&self, function: AggregateFunction) -> f64 {
+        match function {
+            AggregateFunction::Min => self.min,
+            AggregateFunction::Max => self.max,
+            AggregateFunction::Mean => self.sum / self.count as f64,
+            AggregateFunction::Last => self.last,
+        }
+    }
+}
+
+/// The results of one item within the current window
+#[derive(Debug, Default)]
+struct Item {
+    raw: String,
+    tags: BTreeMap<String, String>,
+    values: HashMap<Key, Stats>,
+}
+
+#[derive(Debug, Default)]
+pub struct Window {
+    items: BTreeMap<Key, Item>,
+}
+
+impl Window {
+    pub fn add(&mut self, result: &ItemResult) {
+        let item = self.items.entry(result.key.clone()).or_default();
+        item.raw.clone_from(&result.raw);
+        item.tags.clone_from(&result.tags);
+        for (key, value) in &result.values {
+            match item.values.get_mut(key) {
+                Some(stats) => stats.add(*value),
+                None => {
+                    item.values.insert(key.clone(), Stats::new(*value));
+                }
+            }
+        }
+    }
+
+    /// One result per item, with `<value key>.<function>` for every value
+    pub fn finish(&mut self, functions: &[AggregateFunction]) -> Vec<ItemResult> {
+        let time = now();
+        std::mem::take(&mut self.items)
+            .into_iter()
+            .map(|(key, item)| ItemResult {
+                time,
+                key,
+                raw: item.raw,
+                values: item
+                    .values
+                    .iter()
+                    .flat_map(|(key, stats)| {
+                        functions
+                            .iter()
+                            .map(move |f| (format!("{}.{}", key, f.as_str()).into(), stats.get(*f)))
+                    })
+                    .collect(),
+                tags: item.tags,
+            })
+            .collect()
+    }
+}
+
+pub fn aggregate(
+    mut receiver: broadcast::Receiver<Arc<ItemResult>>,
+    config: AggregateConfig,
+) -> broadcast::Receiver<Arc<ItemResult>> {
+    let (sender, aggregated) = broadcast::channel(100);
+    tokio::spawn(async move {
+        let mut window = Window::default();
+        let mut interval = tokio::time::interval(Duration::from_secs(config.window.max(1)));
+        // The first tick completes right away
+        interval.tick().await;
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    for result in window.finish(&config.functions) {
+                        let _ = sender.send(Arc::new(result));
+                    }
+                }
+                received = receiver.recv() => match received {
+                    Err(broadcast::error::RecvError::Closed) => {
+                        for result in window.finish(&config.functions) {
+                            let _ = sender.send(Arc::new(result));
+                        }
+                        break;
+                    }
+                    Err(broadcast::error::RecvError::Lagged(count)) => {
+                        warn!("Output aggregation is lagging behind, {} results skipped", count)
+                    }
+                    Ok(result) => window.add(&result),
+                },
+            }
+            if sender.receiver_count() == 0 {
+                // The output is gone
+                break;
+            }
+        }
+    });
+    aggregated
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+    use std::time::Duration;
+
+    use crate::conf::AggregateFunction;
+    use crate::item::ItemResult;
+    use crate::output::aggregate::Window;
+
+    #[test]
+    fn rollup() {
+        let mut window = Window::default();
+        for value in [3.0, 1.0, 2.0] {
+            window.add(&ItemResult {
+                time: Duration::from_secs(0),
+                key: "load".into(),
+                raw: value.to_string(),
+                values: HashMap::from([("load.1m".into(), value)]),
+                tags: BTreeMap::new(),
+            });
+        }
+        let results = window.finish(&[
+            AggregateFunction::Min,
+            AggregateFunction::Max,
+            AggregateFunction::Mean,
+            AggregateFunction::Last,
+        ]);
+        assert_eq!(results.len(), 1);
+        let values = &results[0].values;
+        assert_eq!(values["load.1m.min"], 1.0);
+        assert_eq!(values["load.1m.max"], 3.0);
+        assert_eq!(values["load.1m.mean"], 2.0);
+        assert_eq!(values["load.1m.last"], 2.0);
+        assert_eq!(results[0].raw, "2");
+        assert!(window.finish(&[AggregateFunction::Last]).is_empty());
+    }
+}
@@ -0,0 +1,218 @@
+//! Output writing Apache Arrow IPC streams, e.g. for reading with Polars or pandas
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Context, Result};
+use arrow_array::builder::{Float64Builder, StringBuilder, TimestampMillisecondBuilder};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_ipc::writer::StreamWriter;
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use async_trait::async_trait;
+use log::{debug, error, warn};
+use tokio::sync::broadcast;
+
+use crate::conf::Timestamps;
+use crate::item::ItemResult;
+use crate::output::{probe_file, AKOutput};
+
+#[derive(Clone)]
+pub struct ArrowOutput {
+    base_path: PathBuf,
+    schema: SchemaRef,
+    flush_interval: u64,
+    rotate_interval: u64,
+    use_raw_as_fallback: bool,
+    always_write_raw: bool,
+    timestamps: Timestamps,
+}
+
+/// Rows collected until the next flush
+struct Rows {
+    time: TimestampMillisecondBuilder,
+    item: StringBuilder,
+    key: StringBuilder,
+    value: Float64Builder,
+    raw: StringBuilder,
+    tags: StringBuilder,
+    len: usize,
+}
+
+impl Rows {
+    fn new() -> Self {
+        Self {
+            time: TimestampMillisecondBuilder::new().with_timezone("UTC"),
+            item: StringBuilder::new(),
+            key: StringBuilder::new(),
+            value: Float64Builder::new(),
+            raw: StringBuilder::new(),
+            tags: StringBuilder::new(),
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, itemresult: &ItemResult, key: &str, value: Option<f64>, raw: Option<&str>) {
+        self.time.append_value(itemresult.time.as_millis() as i64);
+        self.item.append_value(&itemresult.key);
+        self.key.append_value(key);
+        self.value.append_option(value);
+        self.raw.append_option(raw);
+        self.tags
+            .append_value(serde_json::to_string(&itemresult.tags).unwrap_or_default());
+        self.len += 1;
+    }
+
+    /// The record batch of the rows, all with the time `stamp` if given
+    fn finish(&mut self, schema: &SchemaRef, stamp: Option<Duration>) -> Result<RecordBatch> {
+        let mut time = self.time.finish();
+        if let Some(stamp) = stamp {
+            let mut stamped = TimestampMillisecondBuilder::new().with_timezone("UTC");
+            stamped.append_value_n(stamp.as_millis() as i64, self.len);
+            time = stamped.finish();
+        }
+        self.len = 0;
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(time),
+            Arc::new(self.item.finish()),
+            Arc::new(self.key.finish()),
+            Arc::new(self.value.finish()),
+            Arc::new(self.raw.finish()),
+            Arc::new(self.tags.finish()),
+        ];
+        RecordBatch::try_new(schema.clone(), columns).map_err(anyhow::Error::from)
+    }
+}
+
+/// The stream currently written to, and when it has to be rotated
+struct CurrentFile {
+    writer: StreamWriter<std::fs::File>,
+    until: SystemTime,
+}
+
+impl ArrowOutput {
+    pub fn new(
+        base_path: PathBuf,
+        flush_interval: u64,
+        rotate_interval: u64,
+        use_raw_as_fallback: bool,
+        always_write_raw: bool,
+        timestamps: Timestamps,
+    ) -> Self {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new(
+                "time",
+                DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())),
+                false,
+            ),
+            Field::new("item", DataType::Utf8, false),
+            Field::new("key", DataType::Utf8, false),
+            Field::new("value", DataType::Float64, true),
+            Field::new("raw", DataType::Utf8, true),
+            // JSON object
+            Field::new("tags", DataType::Utf8, false),
+        ]));
+        Self {
+            base_path,
+            schema,
+            flush_interval,
+            rotate_interval,
+            use_raw_as_fallback,
+            always_write_raw,
+            timestamps,
+        }
+    }
+
+    fn open(&self) -> Result<CurrentFile> {
+        let now = SystemTime::now();
+        let secs = now.duration_since(SystemTime::UNIX_EPOCH)?.as_secs();
+        let path = self.base_path.join(format!("antikoerper-{}.arrows", secs));
+        debug!("ArrowOutput: starting new file {}", path.display());
+        let file = std::fs::File::create(&path)
+            .with_context(|| format!("Failed creating {}", path.display()))?;
+        Ok(CurrentFile {
+            writer: StreamWriter::try_new(file, &self.schema)?,
+            until: now + Duration::from_secs(self.rotate_interval),
+        })
+    }
+
+    pub fn check(&self) -> Result<String> {
+        probe_file(&self.base_path)
+    }
+
+    /// Write the collected rows as one record batch, rotating the file if it is due
+    fn flush(&self, rows: &mut Rows, current: &mut Option<CurrentFile>) -> Result<()> {
+        if current
+            .as_ref()
+            .map(|c| c.until <= SystemTime::now())
+            .unwrap_or(false)
+        {
+            if let Some(mut c) = current.take() {
+                c.writer.finish()?;
+            }
+        }
+        if rows.len == 0 {
+            return Ok(());
+        }
+        let stamp = (self.timestamps == Timestamps::Write).then(crate::clock::now);
+        let batch = rows.finish(&self.schema, stamp)?;
+        if current.is_none() {
+            *current = Some(self.open()?);
+        }
+        if let Some(c) = current.as_mut() {
+            c.writer.write(&batch)?;
+            c.writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl AKOutput for ArrowOutput {
+    fn prepare(&self) -> Result<()> {
+        std::fs::create_dir_all(&self.base_path).map_err(anyhow::Error::from)
+    }
+    async fn start(self, mut receiver: broadcast::Receiver<Arc<ItemResult>>) {
+        debug!("ArrowOutput: Starting loop");
+        let mut rows = Rows::new();
+        let mut current = None;
+        let mut flush = tokio::time::interval(Duration::from_secs(self.flush_interval.max(1)));
+        loop {
+            tokio::select! {
+                _ = flush.tick() => {
+                    if let Err(e) = self.flush(&mut rows, &mut current) {
+                        error!("ArrowOutput: Failed writing record batch");
+                        error!("ArrowOutput: {}", e);
+                    }
+                }
+                received = receiver.recv() => match received {
+                    Err(broadcast::error::RecvError::Closed) => {
+                        if let Err(e) = self.flush(&mut rows, &mut current) {
+                            error!("ArrowOutput: Failed writing record batch");
+                            error!("ArrowOutput: {}", e);
+                        }
+                        if let Some(mut c) = current.take() {
+                            let _ = c.writer.finish();
+                        }
+                        break;
+                    }
+                    Err(broadcast::error::RecvError::Lagged(count)) => {
+                        warn!("ArrowOutput is lagging behind, {} results skipped", count)
+                    }
+                    Ok(itemresult) => {
+                        debug!("ArrowOutput: Received result for item {}", itemresult.key);
+                        for (key, value) in itemresult.values.iter() {
+                            rows.push(&itemresult, key, Some(*value), None);
+                        }
+                        if itemresult.values.is_empty() && self.use_raw_as_fallback
+                            || self.always_write_raw
+                        {
+                            let key = format!("{}.raw", itemresult.key);
+                            rows.push(&itemresult, &key, None, Some(&itemresult.raw));
+                        }
+                    }
+                },
+            }
+        }
+    }
+}
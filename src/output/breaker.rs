@@ -0,0 +1,165 @@
+//! Health of an output, and a circuit breaker pausing writes to a backend that keeps
+//! failing
+
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Result;
+use log::{debug, info, warn};
+use tokio::time::Instant;
+
+use crate::conf::BreakerConfig;
+use crate::item::{now, ItemResult};
+
+#[derive(Debug, Default)]
+struct State {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+    /// Writes skipped while the breaker was open
+    skipped: u64,
+}
+
+/// Shared by all tasks of an output. Without a configured breaker, only the health is
+/// tracked.
+#[derive(Debug, Clone)]
+pub struct Breaker {
+    name: String,
+    config: Option<BreakerConfig>,
+    state: Arc<Mutex<State>>,
+}
+
+impl Breaker {
+    pub fn new(name: String, config: Option<BreakerConfig>) -> Self {
+        Self {
+            name,
+            config,
+            state: Arc::new(Mutex::new(State::default())),
+        }
+    }
+
+    /// Whether a write should be attempted. After the cooldown, writes are attempted
+    /// again until the next failure.
+    pub fn allow(&self) -> bool {
+        let mut state = self.state.lock().expect("breaker lock poisoned");
+        match state.open_until {
+            Some(until) if Instant::now() < until => {
+                state.skipped += 1;
+                debug!("{}: circuit breaker open, skipping write", self.name);
+                false
+            }
+            _ => true,
+        }
+    }
+
+    pub fn record<T>(&self, result: &Result<T>) {
+        let mut state = self.state.lock().expect("breaker lock poisoned");
+        if result.is_ok() {
+            if state.open_until.take().is_some() {
+                info!("{}: writing succeeded again", self.name);
+            }
+            state.consecutive_failures = 0;
+            return;
+        }
+        state.consecutive_failures += 1;
+        if let Some(config) = &self.config {
+            if state.consecutive_failures >= config.failures {
+                warn!(
+                    "{}: {} consecutive failures, pausing writes for {} seconds",
+                    self.name, state.consecutive_failures, config.cooldown
+                );
+                state.open_until = Some(Instant::now() + Duration::from_secs(config.cooldown));
+            }
+        }
+    }
+
+    /// For the state dump, e.g. `healthy` or `3 consecutive failures, circuit open`
+    pub fn describe(&self) -> String {
+        let state = self.state.lock().expect("breaker lock poisoned");
+        if state.consecutive_failures == 0 {
+            return String::from("healthy");
+        }
+        let mut description = format!("{} consecutive failures", state.consecutive_failures);
+        if state.open_until.is_some_and(|until| Instant::now() < until) {
+            description.push_str(", circuit open");
+        }
+        description
+    }
+
+    /// Run the write unless the breaker is open, `None` if it was skipped
+    pub async fn call<T, F: Future<Output = Result<T>>>(&self, write: F) -> Option<Result<T>> {
+        if !self.allow() {
+            return None;
+        }
+        let result = write.await;
+        self.record(&result);
+        Some(result)
+    }
+
+    /// `antikoerper.output.<name>` with `.healthy`, `.consecutive_failures`, `.open` and
+    /// `.skipped`
+    pub fn health(&self) -> ItemResult {
+        let state = self.state.lock().expect("breaker lock poisoned");
+        let key = format!("antikoerper.output.{}", self.name);
+        let open = state.open_until.is_some_and(|until| Instant::now() < until);
+        let values = HashMap::from([
+            (
+                format!("{}.healthy", key).into(),
+                if state.consecutive_failures == 0 {
+                    1.0
+                } else {
+                    0.0
+                },
+            ),
+            (
+                format!("{}.consecutive_failures", key).into(),
+                state.consecutive_failures as f64,
+            ),
+            (format!("{}.open", key).into(), if open { 1.0 } else { 0.0 }),
+            (format!("{}.skipped", key).into(), state.skipped as f64),
+        ]);
+        ItemResult {
+            time: now(),
+            key: key.into(),
+            raw: String::new(),
+            values,
+            tags: BTreeMap::from([(String::from("output"), self.name.clone())]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+
+    use crate::conf::BreakerConfig;
+    use crate::output::breaker::Breaker;
+
+    #[tokio::test]
+    async fn opens_after_failures() {
+        let breaker = Breaker::new(
+            String::from("test"),
+            Some(BreakerConfig {
+                failures: 2,
+                cooldown: 3600,
+            }),
+        );
+        assert!(breaker
+            .call(async { Err::<(), _>(anyhow!("down")) })
+            .await
+            .is_some());
+        assert!(breaker
+            .call(async { Err::<(), _>(anyhow!("down")) })
+            .await
+            .is_some());
+        assert!(breaker.call(async { Ok(()) }).await.is_none());
+        let health = breaker.health();
+        assert_eq!(health.values["antikoerper.output.test.open"], 1.0);
+        assert_eq!(health.values["antikoerper.output.test.skipped"], 1.0);
+        assert_eq!(
+            health.values["antikoerper.output.test.consecutive_failures"],
+            2.0
+        );
+    }
+}
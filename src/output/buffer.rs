@@ -0,0 +1,416 @@
+//! Write-ahead buffer of results an output failed to write, so short outages of a backend
+//! don't leave gaps
+
+use std::borrow::Cow;
+use std::future::Future;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use log::{debug, error, info, warn};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use tokio::time::Instant;
+
+use crate::conf::{BufferConfig, SpoolFormat};
+use crate::item::{duration_millis, ItemResult};
+
+/// Buffered results written in one go when replaying
+const REPLAY_BATCH: usize = 500;
+
+/// zstd frames start with it, neither JSON nor MessagePack results do
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// zstd's default, fast while already shrinking results a lot
+const ZSTD_LEVEL: i32 = 3;
+
+/// Results are stored in `<state_dir>/buffer/<name>`, as JSON, one per line, or as
+/// MessagePack, optionally compressed with zstd
+#[derive(Debug, Clone)]
+pub struct Buffer {
+    path: PathBuf,
+    max_size: u64,
+    format: SpoolFormat,
+    zstd: bool,
+    retry_interval: Duration,
+    last_attempt: Option<Instant>,
+}
+
+impl Buffer {
+    pub fn new(config: &BufferConfig, state_dir: &Path, name: &str) -> Self {
+        let name = name
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect::<String>();
+        Self {
+            path: state_dir.join("buffer").join(name),
+            max_size: config.max_size,
+            format: config.format,
+            zstd: config.zstd,
+            retry_interval: Duration::from_secs(config.retry_interval),
+            last_attempt: None,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    async fn is_empty(&self) -> bool {
+        tokio::fs::metadata(&self.path)
+            .await
+            .map(|m| m.len() == 0)
+            .unwrap_or(true)
+    }
+
+    /// Run the file I/O on a thread of its own, rather than blocking the runtime while
+    /// reading and rewriting up to `max_size`
+    async fn blocking<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&Buffer) -> Result<T> + Send + 'static,
+    ) -> Result<T> {
+        let buffer = self.clone();
+        tokio::task::spawn_blocking(move || f(&buffer)).await?
+    }
+
+    /// Write the results, after the buffered results if it is time for another attempt.
+    /// While there are buffered results left, new results are appended to the buffer
+    /// to keep their order. A failed attempt is returned after buffering the results.
+    pub async fn write<F, Fut>(&mut self, results: Vec<Arc<ItemResult>>, write: F) -> Result<()>
+    where
+        F: Fn(Vec<Arc<ItemResult>>) -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        if !self.is_empty().await {
+            if self
+                .last_attempt
+                .is_some_and(|at| at.elapsed() < self.retry_interval)
+            {
+                debug!("buffer: adding {} results", results.len());
+                self.push(&results).await;
+                return Ok(());
+            }
+            if let Err(e) = self.replay(&write).await {
+                self.push(&results).await;
+                return Err(e);
+            }
+        }
+        if let Err(e) = write(results.clone()).await {
+            self.last_attempt = Some(Instant::now());
+            self.push(&results).await;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Write the buffered results in order, until one fails. They are passed with the time
+    /// they were collected at, outputs writing with the time of the write stamp them.
+    pub async fn replay<F, Fut>(&mut self, write: &F) -> Result<()>
+    where
+        F: Fn(Vec<Arc<ItemResult>>) -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        self.last_attempt = Some(Instant::now());
+        let pending = self.blocking(|buffer| buffer.read()).await?;
+        let mut written = 0;
+        let mut failed = Ok(());
+        for batch in pending.chunks(REPLAY_BATCH) {
+            if let Err(e) = write(batch.to_vec()).await {
+                failed = Err(e);
+                break;
+            }
+            written += batch.len();
+        }
+        if written > 0 {
+            info!(
+                "buffer: wrote {} buffered results, {} left in {}",
+                written,
+                pending.len() - written,
+                self.path.display()
+            );
+        }
+        let left = pending[written..].to_vec();
+        self.blocking(move |buffer| buffer.store(&left)).await?;
+        failed
+    }
+
+    pub async fn push(&self, results: &[Arc<ItemResult>]) {
+        let appended = results.to_vec();
+        if let Err(e) = self.blocking(move |buffer| buffer.append(&appended)).await {
+            error!("buffer: Failed buffering {} results", results.len());
+            error!("buffer: {:#}", e);
+        }
+    }
+
+    fn append(&self, results: &[Arc<ItemResult>]) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let stored = std::fs::metadata(&self.path).map_or(0, |m| m.len());
+        if stored > 0 && stored_as(&self.path)? != (self.format, self.zstd) {
+            // Stored before the format changed, converted instead of mixing formats
+            let mut all = self.read()?;
+            all.extend_from_slice(results);
+            self.store(&all)?;
+        } else {
+            let mut file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+                .with_context(|| format!("Failed opening {}", self.path.display()))?;
+            file.write_all(&encode(results, self.format, self.zstd)?)?;
+        }
+        if std::fs::metadata(&self.path)?.len() > self.max_size {
+            self.truncate()?;
+        }
+        Ok(())
+    }
+
+    /// Drop the oldest results, until the buffer is back to 90% of its maximum size.
+    /// Compressed, the results are assumed to shrink by the same ratio.
+    fn truncate(&self) -> Result<()> {
+        let stored = std::fs::metadata(&self.path)?.len() as f64;
+        let results = self.read()?;
+        let sizes = results
+            .iter()
+            .map(|result| Ok(encode(std::slice::from_ref(result), self.format, false)?.len()))
+            .collect::<Result<Vec<_>>>()?;
+        let ratio = stored / sizes.iter().sum::<usize>().max(1) as f64;
+        let target = (self.max_size / 10 * 9) as f64;
+        let mut size = stored;
+        let mut dropped = 0;
+        while size > target && dropped < sizes.len() {
+            size -= sizes[dropped] as f64 * ratio;
+            dropped += 1;
+        }
+        warn!(
+            "buffer: {} is full, dropped the {} oldest results",
+            self.path.display(),
+            dropped
+        );
+        self.store(&results[dropped..])
+    }
+
+    fn read(&self) -> Result<Vec<Arc<ItemResult>>> {
+        let content = std::fs::read(&self.path)
+            .with_context(|| format!("Failed reading {}", self.path.display()))?;
+        Ok(decode(&content)?.into_iter().map(Arc::new).collect())
+    }
+
+    fn store(&self, results: &[Arc<ItemResult>]) -> Result<()> {
+        std::fs::write(&self.path, encode(results, self.format, self.zstd)?)
+            .with_context(|| format!("Failed writing {}", self.path.display()))
+    }
+}
+
+/// Every append is a frame of its own, the frames are read as one
+fn encode(results: &[Arc<ItemResult>], format: SpoolFormat, zstd: bool) -> Result<Vec<u8>> {
+    let mut encoded = Vec::new();
+    for result in results {
+        match format {
+            SpoolFormat::Json => {
+                serde_json::to_writer(&mut encoded, &**result)?;
+                encoded.push(b'\n');
+            }
+            // With field names, so buffers stay readable when fields are added
+            SpoolFormat::MessagePack => rmp_serde::encode::write_named(&mut encoded, &**result)?,
+        }
+    }
+    if zstd && !encoded.is_empty() {
+        encoded = zstd::encode_all(encoded.as_slice(), ZSTD_LEVEL)?;
+    }
+    Ok(encoded)
+}
+
+/// The results of a buffer in any format, skipping those that cannot be read
+fn decode<T: DeserializeOwned>(content: &[u8]) -> Result<Vec<T>> {
+    let content = if content.starts_with(&ZSTD_MAGIC) {
+        Cow::Owned(zstd::decode_all(content).context("Failed decompressing")?)
+    } else {
+        Cow::Borrowed(content)
+    };
+    let mut results = Vec::new();
+    if content.first() == Some(&b'{') {
+        for line in content
+            .split(|b| *b == b'\n')
+            .filter(|line| !line.is_empty())
+        {
+            match serde_json::from_slice(line) {
+                Ok(result) => results.push(result),
+                Err(e) => warn!("buffer: skipping unreadable result: {}", e),
+            }
+        }
+        return Ok(results);
+    }
+    let mut rest = content.as_ref();
+    while !rest.is_empty() {
+        match rmp_serde::from_read(&mut rest) {
+            Ok(result) => results.push(result),
+            // Without line breaks, there is no telling where the next result starts
+            Err(e) => {
+                warn!("buffer: skipping the unreadable rest: {}", e);
+                break;
+            }
+        }
+    }
+    Ok(results)
+}
+
+/// The format and compression of the buffer at the path
+fn stored_as(path: &Path) -> Result<(SpoolFormat, bool)> {
+    let mut file = std::fs::File::open(path)?;
+    let mut start = [0; 4];
+    let read = file.read(&mut start)?;
+    let zstd = start[..read] == ZSTD_MAGIC;
+    let mut first = [0];
+    if zstd {
+        file.seek(SeekFrom::Start(0))?;
+        zstd::Decoder::new(file)?.read_exact(&mut first)?;
+    } else {
+        first[0] = start[0];
+    }
+    let format = if first[0] == b'{' {
+        SpoolFormat::Json
+    } else {
+        SpoolFormat::MessagePack
+    };
+    Ok((format, zstd))
+}
+
+/// What a buffer holds, for `antikoerper spool`
+#[derive(Debug, Default)]
+pub struct Contents {
+    pub results: usize,
+    pub bytes: u64,
+    pub oldest: Option<Duration>,
+    pub newest: Option<Duration>,
+}
+
+/// The contents of the buffer at the path, empty if there is none
+pub fn contents(path: &Path) -> Result<Contents> {
+    #[derive(Deserialize)]
+    struct Timed {
+        #[serde(with = "duration_millis")]
+        time: Duration,
+    }
+
+    let content = match std::fs::read(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Contents::default()),
+        Err(e) => return Err(e).with_context(|| format!("Failed reading {}", path.display())),
+    };
+    let mut contents = Contents {
+        bytes: content.len() as u64,
+        ..Default::default()
+    };
+    for Timed { time } in decode::<Timed>(&content)? {
+        contents.results += 1;
+        contents.oldest = Some(contents.oldest.map_or(time, |oldest| oldest.min(time)));
+        contents.newest = Some(contents.newest.map_or(time, |newest| newest.max(time)));
+    }
+    Ok(contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::conf::{BufferConfig, SpoolFormat};
+    use crate::item::ItemResult;
+    use crate::output::buffer::{contents, Buffer};
+
+    fn result(key: &str) -> Arc<ItemResult> {
+        Arc::new(ItemResult {
+            time: Duration::from_secs(0),
+            key: key.into(),
+            raw: String::new(),
+            values: HashMap::new(),
+            tags: BTreeMap::new(),
+        })
+    }
+
+    #[tokio::test]
+    async fn replay_in_order() {
+        let dir = std::env::temp_dir().join(format!("antikoerper-buffer-{}", std::process::id()));
+        let mut buffer = Buffer::new(
+            &BufferConfig {
+                max_size: 1024,
+                retry_interval: 0,
+                format: SpoolFormat::Json,
+                zstd: false,
+            },
+            &dir,
+            "test",
+        );
+        let up = AtomicBool::new(false);
+        let written = std::sync::Mutex::new(Vec::new());
+        let write = |results: Vec<Arc<ItemResult>>| {
+            let ok = up.load(Ordering::SeqCst);
+            if ok {
+                written
+                    .lock()
+                    .unwrap()
+                    .extend(results.into_iter().map(|r| r.key.to_string()));
+            }
+            async move {
+                if ok {
+                    Ok(())
+                } else {
+                    Err(anyhow::anyhow!("down"))
+                }
+            }
+        };
+        assert!(buffer.write(vec![result("a")], write).await.is_err());
+        assert!(buffer.write(vec![result("b")], write).await.is_err());
+        up.store(true, Ordering::SeqCst);
+        assert!(buffer.write(vec![result("c")], write).await.is_ok());
+        assert_eq!(*written.lock().unwrap(), vec!["a", "b", "c"]);
+        assert!(buffer.is_empty().await);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn formats() {
+        let dir = std::env::temp_dir().join(format!("antikoerper-spool-{}", std::process::id()));
+        let buffer = |format, zstd| {
+            let config = BufferConfig {
+                max_size: 2000,
+                retry_interval: 0,
+                format,
+                zstd,
+            };
+            Buffer::new(&config, &dir, "test")
+        };
+        let json = buffer(SpoolFormat::Json, false);
+        json.push(&[result("a"), result("b")]).await;
+        // Converted on the first push after the format changed
+        let compact = buffer(SpoolFormat::MessagePack, true);
+        compact.push(&[result("c")]).await;
+        compact.push(&[result("d")]).await;
+        let keys = |buffer: &Buffer| {
+            buffer
+                .read()
+                .unwrap()
+                .iter()
+                .map(|r| r.key.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(keys(&compact), ["a", "b", "c", "d"]);
+        assert_eq!(contents(compact.path()).unwrap().results, 4);
+
+        let uncompressed = buffer(SpoolFormat::MessagePack, false);
+        for i in 0..100 {
+            uncompressed.push(&[result(&i.to_string())]).await;
+        }
+        let left = keys(&uncompressed);
+        assert!(std::fs::metadata(uncompressed.path()).unwrap().len() <= 2000);
+        assert_eq!(left.last().unwrap(), "99");
+        assert!(left.len() < 100);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
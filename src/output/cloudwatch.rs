@@ -0,0 +1,525 @@
+//! Output to AWS CloudWatch, using the PutMetricData API
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use chrono::{TimeZone, Utc};
+use hmac::{Hmac, Mac};
+use log::{debug, error, warn};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tokio::sync::{broadcast, Mutex};
+use zeroize::Zeroizing;
+
+use crate::conf::Timestamps;
+use crate::item::ItemResult;
+use crate::output::breaker::Breaker;
+use crate::output::{AKOutput, PROBE};
+use crate::secret::Secret;
+
+/// PutMetricData accepts at most this many metrics per request
+const MAX_METRICS_PER_REQUEST: usize = 1000;
+/// PutMetricData accepts at most this many dimensions per metric
+const MAX_DIMENSIONS: usize = 30;
+
+const IMDS_URL: &str = "http://169.254.169.254/latest";
+
+#[derive(Clone)]
+pub struct CloudWatchOutput {
+    region: String,
+    namespace: String,
+    endpoint: String,
+    credentials: CredentialSource,
+    dimensions: Vec<(String, String)>,
+    flush_interval: u64,
+    client: reqwest::Client,
+    breaker: Breaker,
+    timestamps: Timestamps,
+}
+
+/// A single value waiting to be sent
+struct Datum {
+    name: String,
+    item: String,
+    value: f64,
+    time: Duration,
+}
+
+#[derive(Clone)]
+struct Credentials {
+    access_key_id: String,
+    secret_access_key: Secret,
+    session_token: Option<Secret>,
+}
+
+type CachedCredentials = Option<(Credentials, chrono::DateTime<Utc>)>;
+
+/// Where the credentials for signing requests come from
+#[derive(Clone)]
+enum CredentialSource {
+    /// Given in the configuration or the environment
+    Static(Credentials),
+    /// Fetched from the EC2 instance metadata service, cached until shortly before they expire
+    InstanceProfile(std::sync::Arc<Mutex<CachedCredentials>>),
+}
+
+impl CredentialSource {
+    fn new(configured: Option<crate::conf::AwsCredentials>) -> Self {
+        if let Some(c) = configured {
+            return Self::Static(Credentials {
+                access_key_id: c.access_key_id,
+                secret_access_key: c.secret_access_key,
+                session_token: c.session_token,
+            });
+        }
+        match (
+            std::env::var("AWS_ACCESS_KEY_ID"),
+            std::env::var("AWS_SECRET_ACCESS_KEY"),
+        ) {
+            (Ok(access_key_id), Ok(secret_access_key)) => Self::Static(Credentials {
+                access_key_id,
+                secret_access_key: Secret::new(secret_access_key),
+                session_token: std::env::var("AWS_SESSION_TOKEN").ok().map(Secret::new),
+            }),
+            _ => Self::InstanceProfile(Default::default()),
+        }
+    }
+
+    async fn get(&self, client: &reqwest::Client) -> Result<Credentials> {
+        match self {
+            Self::Static(c) => Ok(c.clone()),
+            Self::InstanceProfile(cache) => {
+                let mut cache = cache.lock().await;
+                if let Some((c, expiration)) = cache.as_ref() {
+                    if *expiration - chrono::Duration::minutes(5) > Utc::now() {
+                        return Ok(c.clone());
+                    }
+                }
+                let (c, expiration) = fetch_instance_credentials(client).await?;
+                *cache = Some((c.clone(), expiration));
+                Ok(c)
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct InstanceCredentials {
+    access_key_id: String,
+    secret_access_key: Secret,
+    token: Secret,
+    expiration: chrono::DateTime<Utc>,
+}
+
+/// Fetch temporary credentials of the instance role via IMDSv2
+async fn fetch_instance_credentials(
+    client: &reqwest::Client,
+) -> Result<(Credentials, chrono::DateTime<Utc>)> {
+    debug!("CloudWatchOutput: fetching credentials from instance metadata");
+    let token = client
+        .put(format!("{}/api/token", IMDS_URL))
+        .header("X-aws-ec2-metadata-token-ttl-seconds", "21600")
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .context("No credentials configured and instance metadata is unreachable")?
+        .text()
+        .await?;
+    let role = client
+        .get(format!("{}/meta-data/iam/security-credentials/", IMDS_URL))
+        .header("X-aws-ec2-metadata-token", &token)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .context("Instance has no IAM role attached")?
+        .text()
+        .await?;
+    let role = match role.lines().next() {
+        Some(r) => r.trim().to_owned(),
+        None => bail!("Instance has no IAM role attached"),
+    };
+    let creds: InstanceCredentials = client
+        .get(format!(
+            "{}/meta-data/iam/security-credentials/{}",
+            IMDS_URL, role
+        ))
+        .header("X-aws-ec2-metadata-token", &token)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())?
+        .json()
+        .await?;
+    Ok((
+        Credentials {
+            access_key_id: creds.access_key_id,
+            secret_access_key: creds.secret_access_key,
+            session_token: Some(creds.token),
+        },
+        creds.expiration,
+    ))
+}
+
+impl CloudWatchOutput {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        region: String,
+        namespace: String,
+        endpoint: Option<String>,
+        credentials: Option<crate::conf::AwsCredentials>,
+        dimensions: BTreeMap<String, String>,
+        hostname: String,
+        flush_interval: u64,
+        client: reqwest::Client,
+        breaker: Breaker,
+        timestamps: Timestamps,
+    ) -> Self {
+        let endpoint =
+            endpoint.unwrap_or_else(|| format!("https://monitoring.{}.amazonaws.com", region));
+        let mut dims = vec![(String::from("Host"), hostname)];
+        dims.extend(dimensions);
+        Self {
+            region,
+            namespace,
+            endpoint,
+            credentials: CredentialSource::new(credentials),
+            dimensions: dims,
+            flush_interval,
+            client,
+            breaker,
+            timestamps,
+        }
+    }
+
+    /// Build the form encoded body of a PutMetricData request
+    fn request_body(&self, data: &[Datum]) -> Result<String> {
+        let mut params = vec![
+            (String::from("Action"), String::from("PutMetricData")),
+            (String::from("Version"), String::from("2010-08-01")),
+            (String::from("Namespace"), self.namespace.clone()),
+        ];
+        for (i, datum) in data.iter().enumerate() {
+            let prefix = format!("MetricData.member.{}", i + 1);
+            let time = Utc
+                .timestamp_millis_opt(datum.time.as_millis() as i64)
+                .single()
+                .context("Result timestamp out of range")?;
+            params.push((format!("{}.MetricName", prefix), datum.name.clone()));
+            params.push((format!("{}.Value", prefix), datum.value.to_string()));
+            params.push((
+                format!("{}.Timestamp", prefix),
+                time.to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            ));
+            let dimensions = self
+                .dimensions
+                .iter()
+                .chain(std::iter::once(&(String::from("Item"), datum.item.clone())))
+                .take(MAX_DIMENSIONS)
+                .cloned()
+                .collect::<Vec<_>>();
+            for (j, (name, value)) in dimensions.into_iter().enumerate() {
+                params.push((format!("{}.Dimensions.member.{}.Name", prefix, j + 1), name));
+                params.push((
+                    format!("{}.Dimensions.member.{}.Value", prefix, j + 1),
+                    value,
+                ));
+            }
+        }
+        serde_urlencoded::to_string(params).map_err(anyhow::Error::from)
+    }
+
+    async fn put_metric_data(&self, data: &[Datum]) -> Result<()> {
+        let body = self.request_body(data)?;
+        let credentials = self.credentials.get(&self.client).await?;
+        let host = self
+            .endpoint
+            .split("://")
+            .last()
+            .unwrap_or(&self.endpoint)
+            .trim_end_matches('/')
+            .to_owned();
+        let now = Utc::now();
+        let headers = sign(
+            &credentials,
+            &self.region,
+            &host,
+            &body,
+            &now.format("%Y%m%dT%H%M%SZ").to_string(),
+        );
+        let mut request = self.client.post(&self.endpoint).body(body);
+        for (name, value) in headers {
+            request = request.header(name, value);
+        }
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            bail!("PutMetricData failed with {}: {}", status, text);
+        }
+        Ok(())
+    }
+
+    /// CloudWatch can't delete metrics, the probe expires like all others
+    pub async fn check(&self) -> Result<String> {
+        self.put_metric_data(&[Datum {
+            name: PROBE.to_owned(),
+            item: PROBE.to_owned(),
+            value: 1.0,
+            time: crate::clock::now(),
+        }])
+        .await?;
+        Ok(format!(
+            "sent a probe metric {} to {}",
+            PROBE, self.namespace
+        ))
+    }
+
+    async fn flush(&self, pending: &mut Vec<Datum>) {
+        while !pending.is_empty() {
+            let mut batch = pending
+                .drain(..pending.len().min(MAX_METRICS_PER_REQUEST))
+                .collect::<Vec<_>>();
+            if self.timestamps == Timestamps::Write {
+                let now = crate::clock::now();
+                batch.iter_mut().for_each(|datum| datum.time = now);
+            }
+            debug!("CloudWatchOutput: sending {} metrics", batch.len());
+            if let Some(Err(e)) = self.breaker.call(self.put_metric_data(&batch)).await {
+                error!("CloudWatchOutput: Failed sending {} metrics", batch.len());
+                error!("CloudWatchOutput: {}", e);
+            }
+        }
+    }
+}
+
+type HmacSha256 = Hmac<Sha256>;
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Sign a form encoded POST request to the monitoring service using AWS Signature Version 4,
+/// returns all headers that have to be sent with the request
+fn sign(
+    credentials: &Credentials,
+    region: &str,
+    host: &str,
+    body: &str,
+    amz_date: &str,
+) -> Vec<(&'static str, String)> {
+    let content_type = "application/x-www-form-urlencoded; charset=utf-8";
+    let mut headers = vec![
+        ("content-type", content_type.to_owned()),
+        ("host", host.to_owned()),
+        ("x-amz-date", amz_date.to_owned()),
+    ];
+    if let Some(token) = &credentials.session_token {
+        headers.push(("x-amz-security-token", token.expose().to_owned()));
+    }
+    let authorization = authorization(credentials, "monitoring", region, &headers, body, amz_date);
+    headers.retain(|(name, _)| *name != "host");
+    headers.push(("authorization", authorization));
+    headers
+}
+
+/// The authorization header of a POST request to `/` of `service`, signing `headers`,
+/// which are lowercase and sorted by name
+fn authorization(
+    credentials: &Credentials,
+    service: &str,
+    region: &str,
+    headers: &[(&str, String)],
+    body: &str,
+    amz_date: &str,
+) -> String {
+    let date = &amz_date[..8];
+    let canonical_headers = headers
+        .iter()
+        .map(|(name, value)| format!("{}:{}\n", name, value.trim()))
+        .collect::<String>();
+    let signed_headers = headers
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(";");
+    let canonical_request = format!(
+        "POST\n/\n\n{}\n{}\n{}",
+        canonical_headers,
+        signed_headers,
+        hex::encode(Sha256::digest(body.as_bytes()))
+    );
+    let scope = format!("{}/{}/{}/aws4_request", date, region, service);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        hex::encode(Sha256::digest(canonical_request.as_bytes()))
+    );
+    let key = hmac(
+        Zeroizing::new(format!("AWS4{}", credentials.secret_access_key.expose())).as_bytes(),
+        date,
+    );
+    let key = hmac(&key, region);
+    let key = hmac(&key, service);
+    let key = hmac(&key, "aws4_request");
+    let signature = hex::encode(hmac(&key, &string_to_sign));
+    format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        credentials.access_key_id, scope, signed_headers, signature
+    )
+}
+
+#[async_trait]
+impl AKOutput for CloudWatchOutput {
+    fn prepare(&self) -> Result<()> {
+        Ok(())
+    }
+    async fn start(self, mut receiver: broadcast::Receiver<Arc<ItemResult>>) {
+        debug!("CloudWatchOutput: Starting loop");
+        let mut pending = Vec::<Datum>::new();
+        let mut flush = tokio::time::interval(Duration::from_secs(self.flush_interval.max(1)));
+        loop {
+            tokio::select! {
+                _ = flush.tick() => self.flush(&mut pending).await,
+                received = receiver.recv() => match received {
+                    Err(broadcast::error::RecvError::Closed) => {
+                        self.flush(&mut pending).await;
+                        break;
+                    }
+                    Err(broadcast::error::RecvError::Lagged(count)) => {
+                        warn!("CloudWatchOutput is lagging behind, {} results skipped", count)
+                    }
+                    Ok(itemresult) => {
+                        debug!("CloudWatchOutput: Received result for item {}", itemresult.key);
+                        for (key, value) in itemresult.values.iter() {
+                            // CloudWatch rejects NaN and infinite values
+                            if !value.is_finite() {
+                                continue;
+                            }
+                            pending.push(Datum {
+                                name: key.to_string(),
+                                item: itemresult.key.to_string(),
+                                value: *value,
+                                time: itemresult.time,
+                            });
+                        }
+                        if pending.len() >= MAX_METRICS_PER_REQUEST {
+                            self.flush(&mut pending).await;
+                        }
+                    }
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::conf::Timestamps;
+    use crate::output::breaker::Breaker;
+    use crate::output::cloudwatch::{authorization, sign, CloudWatchOutput, Credentials, Datum};
+    use crate::secret::Secret;
+
+    /// Of the examples in the AWS documentation
+    fn credentials() -> Credentials {
+        Credentials {
+            access_key_id: String::from("AKIDEXAMPLE"),
+            secret_access_key: Secret::new(String::from(
+                "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            )),
+            session_token: None,
+        }
+    }
+
+    #[test]
+    fn signature() {
+        // post-x-www-form-urlencoded of the AWS Signature Version 4 test suite
+        let headers = [
+            (
+                "content-type",
+                String::from("application/x-www-form-urlencoded"),
+            ),
+            ("host", String::from("example.amazonaws.com")),
+            ("x-amz-date", String::from("20150830T123600Z")),
+        ];
+        assert_eq!(
+            authorization(
+                &credentials(),
+                "service",
+                "us-east-1",
+                &headers,
+                "Param1=value1",
+                "20150830T123600Z"
+            ),
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, \
+             SignedHeaders=content-type;host;x-amz-date, \
+             Signature=ff11897932ad3f4e8b18135d722051e5ac45fc38421b1da7b9d196a0fe09473a"
+        );
+
+        let mut credentials = credentials();
+        credentials.session_token = Some(Secret::new(String::from("token")));
+        let headers = sign(
+            &credentials,
+            "eu-central-1",
+            "monitoring.eu-central-1.amazonaws.com",
+            "Action=PutMetricData",
+            "20150830T123600Z",
+        );
+        let names = headers.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "content-type",
+                "x-amz-date",
+                "x-amz-security-token",
+                "authorization"
+            ]
+        );
+        assert!(headers[3].1.starts_with(
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/eu-central-1/monitoring/aws4_request, \
+             SignedHeaders=content-type;host;x-amz-date;x-amz-security-token, Signature="
+        ));
+    }
+
+    #[test]
+    fn request_body() {
+        let output = CloudWatchOutput::new(
+            String::from("eu-central-1"),
+            String::from("Antikoerper"),
+            None,
+            None,
+            Default::default(),
+            String::from("host1"),
+            0,
+            reqwest::Client::new(),
+            Breaker::new(String::from("cloudwatch"), None),
+            Timestamps::Collection,
+        );
+        let body = output
+            .request_body(&[Datum {
+                name: String::from("os.load.load1m"),
+                item: String::from("os.load"),
+                value: 0.5,
+                time: Duration::from_millis(1440000000000),
+            }])
+            .unwrap();
+        assert_eq!(
+            body,
+            "Action=PutMetricData&Version=2010-08-01&Namespace=Antikoerper\
+             &MetricData.member.1.MetricName=os.load.load1m\
+             &MetricData.member.1.Value=0.5\
+             &MetricData.member.1.Timestamp=2015-08-19T16%3A00%3A00.000Z\
+             &MetricData.member.1.Dimensions.member.1.Name=Host\
+             &MetricData.member.1.Dimensions.member.1.Value=host1\
+             &MetricData.member.1.Dimensions.member.2.Name=Item\
+             &MetricData.member.1.Dimensions.member.2.Value=os.load"
+        );
+    }
+}
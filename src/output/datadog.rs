@@ -0,0 +1,191 @@
+//! Output to the Datadog metrics API
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use log::{debug, error, warn};
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::conf::Timestamps;
+use crate::item::ItemResult;
+use crate::output::breaker::Breaker;
+use crate::output::{probe, AKOutput, PROBE};
+use crate::secret::Secret;
+
+/// Number of series sent with a single request, keeps the payload well below the API limits
+const MAX_SERIES_PER_REQUEST: usize = 1000;
+
+/// Metric type "gauge" in the v2 series API
+const GAUGE: u8 = 3;
+
+#[derive(Clone)]
+pub struct DatadogOutput {
+    url: String,
+    api_key: Secret,
+    hostname: String,
+    tags: Vec<String>,
+    flush_interval: u64,
+    client: reqwest::Client,
+    breaker: Breaker,
+    timestamps: Timestamps,
+}
+
+#[derive(Serialize)]
+struct Point {
+    timestamp: u64,
+    value: f64,
+}
+
+#[derive(Serialize)]
+struct Resource {
+    name: String,
+    #[serde(rename = "type")]
+    kind: &'static str,
+}
+
+#[derive(Serialize)]
+struct Series {
+    metric: String,
+    #[serde(rename = "type")]
+    kind: u8,
+    points: [Point; 1],
+    tags: Vec<String>,
+    resources: [Resource; 1],
+}
+
+#[derive(Serialize)]
+struct Payload<'a> {
+    series: &'a [Series],
+}
+
+fn format_tags(tags: &BTreeMap<String, String>) -> impl Iterator<Item = String> + '_ {
+    tags.iter()
+        .map(|(name, value)| format!("{}:{}", name, value))
+}
+
+impl DatadogOutput {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        api_key: Secret,
+        site: String,
+        tags: BTreeMap<String, String>,
+        hostname: String,
+        flush_interval: u64,
+        client: reqwest::Client,
+        breaker: Breaker,
+        timestamps: Timestamps,
+    ) -> Self {
+        Self {
+            url: format!("https://api.{}/api/v2/series", site),
+            api_key,
+            hostname,
+            tags: format_tags(&tags).collect(),
+            flush_interval,
+            client,
+            breaker,
+            timestamps,
+        }
+    }
+
+    async fn submit(&self, series: &[Series]) -> Result<()> {
+        let response = self
+            .client
+            .post(&self.url)
+            .header("DD-API-KEY", self.api_key.expose())
+            .json(&Payload { series })
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            bail!("Datadog responded with {}: {}", status, text);
+        }
+        Ok(())
+    }
+
+    pub async fn check(&self) -> Result<String> {
+        self.submit(&[self.series(&probe(), PROBE, 1.0)]).await?;
+        Ok(format!("sent a probe series {}", PROBE))
+    }
+
+    async fn flush(&self, pending: &mut Vec<Series>) {
+        while !pending.is_empty() {
+            let mut batch = pending
+                .drain(..pending.len().min(MAX_SERIES_PER_REQUEST))
+                .collect::<Vec<_>>();
+            if self.timestamps == Timestamps::Write {
+                let now = crate::clock::now().as_secs();
+                for series in &mut batch {
+                    series.points[0].timestamp = now;
+                }
+            }
+            debug!("DatadogOutput: sending {} series", batch.len());
+            if let Some(Err(e)) = self.breaker.call(self.submit(&batch)).await {
+                error!("DatadogOutput: Failed sending {} series", batch.len());
+                error!("DatadogOutput: {}", e);
+            }
+        }
+    }
+
+    fn series(&self, itemresult: &ItemResult, key: &str, value: f64) -> Series {
+        let mut tags = self.tags.clone();
+        tags.push(format!("item:{}", itemresult.key));
+        tags.extend(format_tags(&itemresult.tags));
+        Series {
+            metric: key.to_owned(),
+            kind: GAUGE,
+            points: [Point {
+                timestamp: itemresult.time.as_secs(),
+                value,
+            }],
+            tags,
+            resources: [Resource {
+                name: self.hostname.clone(),
+                kind: "host",
+            }],
+        }
+    }
+}
+
+#[async_trait]
+impl AKOutput for DatadogOutput {
+    fn prepare(&self) -> Result<()> {
+        Ok(())
+    }
+    async fn start(self, mut receiver: broadcast::Receiver<Arc<ItemResult>>) {
+        debug!("DatadogOutput: Starting loop");
+        let mut pending = Vec::<Series>::new();
+        let mut flush = tokio::time::interval(Duration::from_secs(self.flush_interval.max(1)));
+        loop {
+            tokio::select! {
+                _ = flush.tick() => self.flush(&mut pending).await,
+                received = receiver.recv() => match received {
+                    Err(broadcast::error::RecvError::Closed) => {
+                        self.flush(&mut pending).await;
+                        break;
+                    }
+                    Err(broadcast::error::RecvError::Lagged(count)) => {
+                        warn!("DatadogOutput is lagging behind, {} results skipped", count)
+                    }
+                    Ok(itemresult) => {
+                        debug!("DatadogOutput: Received result for item {}", itemresult.key);
+                        for (key, value) in itemresult.values.iter() {
+                            // JSON has no representation for NaN and infinite values
+                            if !value.is_finite() {
+                                continue;
+                            }
+                            pending.push(self.series(&itemresult, key, *value));
+                        }
+                        if pending.len() >= MAX_SERIES_PER_REQUEST {
+                            self.flush(&mut pending).await;
+                        }
+                    }
+                },
+            }
+        }
+    }
+}
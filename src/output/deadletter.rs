@@ -0,0 +1,121 @@
+//! Results a backend refused for good, e.g. because of a schema conflict, kept for
+//! inspection and re-import instead of being lost
+
+use std::fmt;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use log::error;
+
+use crate::item::ItemResult;
+
+/// A write the backend will never accept, writing it again would fail again
+#[derive(Debug)]
+pub struct Rejected(pub String);
+
+impl fmt::Display for Rejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rejected: {}", self.0)
+    }
+}
+
+impl std::error::Error for Rejected {}
+
+/// Results are appended as JSON, one per line, like in the buffer
+#[derive(Debug, Clone)]
+pub struct DeadLetter {
+    path: PathBuf,
+}
+
+impl DeadLetter {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// Rejected results are appended to the file and count as written, so they neither
+    /// trip the circuit breaker nor stay in the buffer forever
+    pub fn catch(
+        &self,
+        name: &str,
+        results: &[Arc<ItemResult>],
+        written: Result<()>,
+    ) -> Result<()> {
+        match written {
+            Err(e) if e.is::<Rejected>() => {
+                error!(
+                    "{}: {}, appending {} results to {}",
+                    name,
+                    e,
+                    results.len(),
+                    self.path.display()
+                );
+                if let Err(e) = self.append(results) {
+                    error!("{}: Failed writing dead letters", name);
+                    error!("{}: {:#}", name, e);
+                }
+                Ok(())
+            }
+            written => written,
+        }
+    }
+
+    fn append(&self, results: &[Arc<ItemResult>]) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut lines = Vec::new();
+        for result in results {
+            serde_json::to_writer(&mut lines, &**result)?;
+            lines.push(b'\n');
+        }
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("Failed opening {}", self.path.display()))?
+            .write_all(&lines)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use anyhow::anyhow;
+
+    use crate::item::ItemResult;
+    use crate::output::deadletter::{DeadLetter, Rejected};
+
+    #[test]
+    fn only_rejected() {
+        let path = std::env::temp_dir().join(format!("antikoerper-dead-{}", std::process::id()));
+        let dead_letter = DeadLetter::new(path.clone());
+        let results = [Arc::new(ItemResult {
+            time: Duration::from_secs(0),
+            key: "temp".into(),
+            raw: String::new(),
+            values: HashMap::from([("temp.parsed".into(), 21.5)]),
+            tags: BTreeMap::new(),
+        })];
+        assert!(dead_letter
+            .catch("test", &results, Err(anyhow!("connection refused")))
+            .is_err());
+        assert!(!path.exists());
+        assert!(dead_letter
+            .catch(
+                "test",
+                &results,
+                Err(Rejected(String::from("field type conflict")).into())
+            )
+            .is_ok());
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(content.lines().count(), 1);
+        assert!(content.contains("temp.parsed"));
+    }
+}
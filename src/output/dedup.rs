@@ -0,0 +1,109 @@
+//! Suppression of values that did not change since they were last written
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::item::ItemResult;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Written {
+    Value(f64),
+    Raw(String),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ChangeFilter {
+    /// Unchanged values are written again after this long anyway
+    heartbeat: Option<Duration>,
+    last: HashMap<String, (Written, Instant)>,
+}
+
+impl ChangeFilter {
+    pub fn new(heartbeat: Option<u64>) -> Self {
+        Self {
+            heartbeat: heartbeat.map(Duration::from_secs),
+            last: HashMap::new(),
+        }
+    }
+
+    fn changed(&mut self, key: &str, written: Written, now: Instant) -> bool {
+        let changed = match self.last.get(key) {
+            Some((last, at)) => {
+                *last != written || self.heartbeat.is_some_and(|h| now.duration_since(*at) >= h)
+            }
+            None => true,
+        };
+        if changed {
+            self.last.insert(key.to_owned(), (written, now));
+        }
+        changed
+    }
+
+    /// The result with only the changed values, `None` if nothing changed. Results
+    /// without values are compared by their raw value. Only results with some unchanged
+    /// values are copied.
+    pub fn apply(&mut self, result: Arc<ItemResult>) -> Option<Arc<ItemResult>> {
+        let now = Instant::now();
+        if result.values.is_empty() {
+            let key = format!("{}.raw", result.key);
+            return self
+                .changed(&key, Written::Raw(result.raw.clone()), now)
+                .then_some(result);
+        }
+        let changed = result
+            .values
+            .iter()
+            .filter(|(key, value)| self.changed(key, Written::Value(**value), now))
+            .map(|(key, _)| key.clone())
+            .collect::<HashSet<_>>();
+        if changed.is_empty() {
+            return None;
+        }
+        if changed.len() == result.values.len() {
+            return Some(result);
+        }
+        let mut result = Arc::unwrap_or_clone(result);
+        result.values.retain(|key, _| changed.contains(key));
+        Some(Arc::new(result))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::item::ItemResult;
+    use crate::output::dedup::ChangeFilter;
+
+    fn result(packages: f64, updates: f64) -> Arc<ItemResult> {
+        Arc::new(ItemResult {
+            time: Duration::from_secs(0),
+            key: "pkg".into(),
+            raw: String::new(),
+            values: HashMap::from([
+                ("pkg.installed".into(), packages),
+                ("pkg.updates".into(), updates),
+            ]),
+            tags: BTreeMap::new(),
+        })
+    }
+
+    #[test]
+    fn only_changes() {
+        let mut filter = ChangeFilter::new(None);
+        assert_eq!(filter.apply(result(1000.0, 3.0)).unwrap().values.len(), 2);
+        assert!(filter.apply(result(1000.0, 3.0)).is_none());
+        let changed = filter.apply(result(1000.0, 0.0)).unwrap();
+        assert_eq!(changed.values.len(), 1);
+        assert_eq!(changed.values["pkg.updates"], 0.0);
+
+        let mut heartbeat = ChangeFilter::new(Some(0));
+        assert!(heartbeat.apply(result(1000.0, 3.0)).is_some());
+        assert!(heartbeat.apply(result(1000.0, 3.0)).is_some());
+    }
+}
@@ -0,0 +1,145 @@
+//! Output streaming results as JSON lines to a long-running external command
+
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use log::{debug, error, info, warn};
+use tokio::io::AsyncWriteExt;
+use tokio::process::{Child, ChildStdin, Command};
+use tokio::sync::broadcast;
+
+use crate::item::ItemResult;
+use crate::output::AKOutput;
+
+/// Time to wait before starting the command again after it died
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Clone)]
+pub struct ExecOutput {
+    path: PathBuf,
+    args: Vec<String>,
+}
+
+/// The running command
+struct Running {
+    child: Child,
+    stdin: ChildStdin,
+}
+
+impl ExecOutput {
+    pub fn new(path: PathBuf, args: Vec<String>) -> Self {
+        Self { path, args }
+    }
+
+    fn spawn(&self) -> Result<Running> {
+        info!(
+            "ExecOutput: starting {} {}",
+            self.path.display(),
+            self.args.join(" ")
+        );
+        let mut child = Command::new(&self.path)
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("Failed starting {}", self.path.display()))?;
+        let stdin = child
+            .stdin
+            .take()
+            .context("stdin of the command is not available")?;
+        Ok(Running { child, stdin })
+    }
+
+    /// Write a line to the command, starting it again once if it is not running anymore
+    async fn write(&self, running: &mut Option<Running>, line: &[u8]) -> Result<()> {
+        for attempt in 0..2 {
+            if running.is_none() {
+                if attempt > 0 {
+                    tokio::time::sleep(RESTART_DELAY).await;
+                }
+                *running = Some(self.spawn()?);
+            }
+            if let Some(r) = running.as_mut() {
+                match r.stdin.write_all(line).await {
+                    Ok(()) => return r.stdin.flush().await.map_err(anyhow::Error::from),
+                    Err(e) => {
+                        let status = r.child.try_wait().ok().flatten();
+                        warn!(
+                            "ExecOutput: {} is not accepting input anymore ({}), exit status: {:?}",
+                            self.path.display(),
+                            e,
+                            status
+                        );
+                        *running = None;
+                    }
+                }
+            }
+        }
+        bail!("{} could not be restarted", self.path.display())
+    }
+
+    /// Start the command without writing to it, it is killed if it does not finish on
+    /// the closed stdin
+    pub async fn check(&self) -> Result<String> {
+        let Running { mut child, stdin } = self.spawn()?;
+        drop(stdin);
+        if let Ok(status) = tokio::time::timeout(Duration::from_secs(5), child.wait()).await {
+            let status = status?;
+            if !status.success() {
+                bail!("{} exited with {}", self.path.display(), status);
+            }
+        }
+        Ok(format!("started {}", self.path.display()))
+    }
+}
+
+#[async_trait]
+impl AKOutput for ExecOutput {
+    fn prepare(&self) -> Result<()> {
+        Ok(())
+    }
+    async fn start(self, mut receiver: broadcast::Receiver<Arc<ItemResult>>) {
+        debug!("ExecOutput: Starting loop");
+        let mut running = None;
+        loop {
+            match receiver.recv().await {
+                Err(recverr) => match recverr {
+                    broadcast::error::RecvError::Closed => break,
+                    broadcast::error::RecvError::Lagged(count) => {
+                        warn!("ExecOutput is lagging behind, {} results skipped", count)
+                    }
+                },
+                Ok(itemresult) => {
+                    debug!("ExecOutput: Received result for item {}", itemresult.key);
+                    let line = match serde_json::to_vec(&itemresult) {
+                        Ok(mut line) => {
+                            line.push(b'\n');
+                            line
+                        }
+                        Err(e) => {
+                            error!("ExecOutput: Failed serializing Item {}", itemresult.key);
+                            error!("ExecOutput: {}", e);
+                            continue;
+                        }
+                    };
+                    if let Err(e) = self.write(&mut running, &line).await {
+                        error!(
+                            "ExecOutput: Failed writing data for Item {}",
+                            itemresult.key
+                        );
+                        error!("ExecOutput: {}", e);
+                    }
+                }
+            }
+        }
+        // Closing stdin lets the command finish on its own
+        if let Some(mut r) = running.take() {
+            drop(r.stdin);
+            let _ = r.child.wait().await;
+        }
+    }
+}
@@ -0,0 +1,189 @@
+//! Routing of results and events to outputs, by the key of the item
+
+use anyhow::{Context, Result};
+use log::warn;
+use tokio::sync::{broadcast, watch};
+
+/// Capacity of the channel between the filter and the output
+const CAPACITY: usize = 100;
+
+#[derive(Debug, Clone)]
+enum KeyPattern {
+    Glob(glob::Pattern),
+    Regex(regex::Regex),
+}
+
+impl KeyPattern {
+    /// Patterns enclosed in slashes are regular expressions, all others globs
+    fn new(pattern: &str) -> Result<Self> {
+        match pattern.strip_prefix('/').and_then(|p| p.strip_suffix('/')) {
+            Some(regex) => Ok(Self::Regex(
+                regex::Regex::new(regex).with_context(|| format!("Invalid regex {}", pattern))?,
+            )),
+            None => Ok(Self::Glob(
+                glob::Pattern::new(pattern).with_context(|| format!("Invalid glob {}", pattern))?,
+            )),
+        }
+    }
+
+    fn matches(&self, key: &str) -> bool {
+        match self {
+            Self::Glob(glob) => glob.matches(key),
+            Self::Regex(regex) => regex.is_match(key),
+        }
+    }
+}
+
+/// Which items an output receives results and events of
+#[derive(Debug, Clone, Default)]
+pub struct KeyFilter {
+    include: Vec<KeyPattern>,
+    exclude: Vec<KeyPattern>,
+}
+
+impl KeyFilter {
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Self> {
+        Ok(Self {
+            include: include
+                .iter()
+                .map(|p| KeyPattern::new(p))
+                .collect::<Result<_>>()?,
+            exclude: exclude
+                .iter()
+                .map(|p| KeyPattern::new(p))
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    /// Without includes, everything not excluded matches
+    pub fn matches(&self, key: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|p| p.matches(key)))
+            && !self.exclude.iter().any(|p| p.matches(key))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+}
+
+/// Forward messages to a new channel, dropping or changing them on the way. The receiver
+/// of the new channel sees it closed once the original channel is closed.
+pub fn forward<T, F>(mut receiver: broadcast::Receiver<T>, mut f: F) -> broadcast::Receiver<T>
+where
+    T: Clone + Send + 'static,
+    F: FnMut(T) -> Option<T> + Send + 'static,
+{
+    let (sender, forwarded) = broadcast::channel(CAPACITY);
+    tokio::spawn(async move {
+        loop {
+            match receiver.recv().await {
+                Err(broadcast::error::RecvError::Closed) => break,
+                Err(broadcast::error::RecvError::Lagged(count)) => {
+                    warn!("Output filter is lagging behind, {} skipped", count)
+                }
+                Ok(message) => {
+                    if let Some(message) = f(message) {
+                        if sender.send(message).is_err() {
+                            // The output is gone
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    });
+    forwarded
+}
+
+/// Forward messages until `stop` is told to, then those already queued, and close the
+/// new channel. Dropping the sender of `stop` does not stop forwarding.
+pub fn until<T>(
+    mut receiver: broadcast::Receiver<T>,
+    mut stop: watch::Receiver<()>,
+) -> broadcast::Receiver<T>
+where
+    T: Clone + Send + 'static,
+{
+    let (sender, forwarded) = broadcast::channel(CAPACITY);
+    tokio::spawn(async move {
+        let mut stoppable = true;
+        loop {
+            tokio::select! {
+                changed = stop.changed(), if stoppable => match changed {
+                    Ok(()) => break,
+                    Err(_) => stoppable = false,
+                },
+                received = receiver.recv() => match received {
+                    Err(broadcast::error::RecvError::Closed) => return,
+                    Err(broadcast::error::RecvError::Lagged(count)) => {
+                        warn!("Output is lagging behind, {} skipped", count)
+                    }
+                    Ok(message) => {
+                        if sender.send(message).is_err() {
+                            // The output is gone
+                            return;
+                        }
+                    }
+                },
+            }
+        }
+        loop {
+            match receiver.try_recv() {
+                Ok(message) => {
+                    let _ = sender.send(message);
+                }
+                Err(broadcast::error::TryRecvError::Lagged(_)) => continue,
+                Err(_) => break,
+            }
+        }
+    });
+    forwarded
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::{broadcast, watch};
+
+    use crate::output::filter::{until, KeyFilter};
+
+    #[test]
+    fn include_exclude() {
+        let filter = KeyFilter::new(
+            &[String::from("os.*"), String::from("/^net\\.(rx|tx)$/")],
+            &[String::from("os.*.raw")],
+        )
+        .unwrap();
+        assert!(filter.matches("os.load"));
+        assert!(filter.matches("net.rx"));
+        assert!(!filter.matches("net.rx2"));
+        assert!(!filter.matches("os.load.raw"));
+        assert!(KeyFilter::default().matches("anything"));
+        assert!(KeyFilter::new(&[String::from("/(/")], &[]).is_err());
+    }
+
+    #[tokio::test]
+    async fn until_stopped() {
+        let (sender, receiver) = broadcast::channel(8);
+        let (stop, stopped) = watch::channel(());
+        let mut forwarded = until(receiver, stopped);
+        sender.send(1).unwrap();
+        assert_eq!(forwarded.recv().await.unwrap(), 1);
+        sender.send(2).unwrap();
+        sender.send(3).unwrap();
+        stop.send_replace(());
+        assert_eq!(forwarded.recv().await.unwrap(), 2);
+        assert_eq!(forwarded.recv().await.unwrap(), 3);
+        assert!(matches!(
+            forwarded.recv().await,
+            Err(broadcast::error::RecvError::Closed)
+        ));
+
+        let (sender, receiver) = broadcast::channel(8);
+        let (stop, stopped) = watch::channel(());
+        let mut forwarded = until(receiver, stopped);
+        drop(stop);
+        tokio::task::yield_now().await;
+        sender.send(4).unwrap();
+        assert_eq!(forwarded.recv().await.unwrap(), 4);
+    }
+}
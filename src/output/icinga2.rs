@@ -0,0 +1,177 @@
+//! Output submitting passive check results to Icinga2, using its REST API
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use log::{debug, error, warn};
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::alert::Status;
+use crate::conf::TlsConfig;
+use crate::item::ItemResult;
+use crate::output::breaker::Breaker;
+use crate::output::{probe, tls, AKOutput, PROBE};
+use crate::secret::Secret;
+
+#[derive(Clone)]
+pub struct Icinga2Output {
+    url: String,
+    username: String,
+    password: Secret,
+    host: String,
+    hostname: String,
+    client: reqwest::Client,
+    breaker: Breaker,
+}
+
+#[derive(Serialize)]
+struct CheckResult<'a> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    filter: &'static str,
+    filter_vars: FilterVars<'a>,
+    exit_status: u8,
+    plugin_output: &'a str,
+    performance_data: Vec<&'a str>,
+    check_source: &'a str,
+}
+
+#[derive(Serialize)]
+struct FilterVars<'a> {
+    host: &'a str,
+    service: &'a str,
+}
+
+impl Icinga2Output {
+    pub fn new(
+        url: String,
+        username: String,
+        password: Secret,
+        host: String,
+        ca_file: Option<PathBuf>,
+        hostname: String,
+        breaker: Breaker,
+    ) -> Result<Self> {
+        let client = tls::client(Some(&TlsConfig {
+            ca_file,
+            ..Default::default()
+        }))?;
+        Ok(Self {
+            url: format!(
+                "{}/v1/actions/process-check-result",
+                url.trim_end_matches('/')
+            ),
+            username,
+            password,
+            host,
+            hostname,
+            client,
+            breaker,
+        })
+    }
+
+    /// The raw output is split like monitoring plugin output: the message, then a pipe,
+    /// then the performance data
+    fn check_result<'a>(&'a self, itemresult: &'a ItemResult, status: Status) -> CheckResult<'a> {
+        let (output, perfdata) = itemresult
+            .raw
+            .split_once('|')
+            .unwrap_or((&itemresult.raw, ""));
+        CheckResult {
+            kind: "Service",
+            filter: "host.name==host && service.name==service",
+            filter_vars: FilterVars {
+                host: &self.host,
+                service: &itemresult.key,
+            },
+            exit_status: status as u8,
+            plugin_output: output.trim(),
+            performance_data: perfdata.split_whitespace().collect(),
+            check_source: &self.hostname,
+        }
+    }
+
+    fn request(&self, check_result: &CheckResult<'_>) -> reqwest::RequestBuilder {
+        self.client
+            .post(&self.url)
+            .basic_auth(&self.username, Some(self.password.expose()))
+            .header(reqwest::header::ACCEPT, "application/json")
+            .json(check_result)
+    }
+
+    async fn send(&self, check_result: &CheckResult<'_>) -> Result<()> {
+        let response = self.request(check_result).send().await?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            bail!("Icinga2 responded with {}: {}", status, text);
+        }
+        Ok(())
+    }
+
+    /// Submit a check result for the service `antikoerper.check`, which usually does not
+    /// exist, Icinga2 tells so after accepting the credentials
+    pub async fn check(&self) -> Result<String> {
+        let probe = probe();
+        let response = self
+            .request(&self.check_result(&probe, Status::Ok))
+            .send()
+            .await?;
+        match response.status() {
+            status if status.is_success() => Ok(format!(
+                "submitted a check result for service {} of host {}",
+                PROBE, self.host
+            )),
+            reqwest::StatusCode::NOT_FOUND => Ok(format!(
+                "credentials accepted, no service {} on host {}",
+                PROBE, self.host
+            )),
+            status => bail!(
+                "Icinga2 responded with {}: {}",
+                status,
+                response.text().await.unwrap_or_default()
+            ),
+        }
+    }
+}
+
+#[async_trait]
+impl AKOutput for Icinga2Output {
+    fn prepare(&self) -> Result<()> {
+        Ok(())
+    }
+    async fn start(self, mut receiver: broadcast::Receiver<Arc<ItemResult>>) {
+        debug!("Icinga2Output: Starting loop");
+        loop {
+            match receiver.recv().await {
+                Err(recverr) => match recverr {
+                    broadcast::error::RecvError::Closed => break,
+                    broadcast::error::RecvError::Lagged(count) => {
+                        warn!("Icinga2Output is lagging behind, {} results skipped", count)
+                    }
+                },
+                Ok(itemresult) => {
+                    debug!("Icinga2Output: Received result for item {}", itemresult.key);
+                    // Only results of the monitoring-plugin digest are check results
+                    let Some(status) = Status::of(&itemresult) else {
+                        continue;
+                    };
+                    if let Some(Err(e)) = self
+                        .breaker
+                        .call(self.send(&self.check_result(&itemresult, status)))
+                        .await
+                    {
+                        error!(
+                            "Icinga2Output: Failed sending check result for Item {}",
+                            itemresult.key
+                        );
+                        error!("Icinga2Output: {}", e);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,260 @@
+//! Output to InfluxDB 3, using line protocol on its v3 write endpoint
+
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use log::{debug, error, warn};
+use reqwest::StatusCode;
+use tokio::sync::broadcast;
+
+use crate::conf::Timestamps;
+use crate::item::ItemResult;
+use crate::output::breaker::Breaker;
+use crate::output::buffer::Buffer;
+use crate::output::deadletter::{DeadLetter, Rejected};
+use crate::output::lineprotocol::Line;
+use crate::output::{probe, AKOutput, PROBE};
+use crate::secret::Secret;
+
+#[derive(Clone)]
+pub struct InfluxDB3Output {
+    url: String,
+    database: String,
+    token: Option<Secret>,
+    use_raw_as_fallback: bool,
+    always_write_raw: bool,
+    client: reqwest::Client,
+    buffer: Option<Buffer>,
+    dead_letter: Option<DeadLetter>,
+    breaker: Breaker,
+    timestamps: Timestamps,
+}
+
+impl InfluxDB3Output {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        url: String,
+        database: String,
+        token: Option<Secret>,
+        use_raw_as_fallback: bool,
+        always_write_raw: bool,
+        buffer: Option<Buffer>,
+        client: reqwest::Client,
+        dead_letter: Option<DeadLetter>,
+        breaker: Breaker,
+        timestamps: Timestamps,
+    ) -> Self {
+        Self {
+            url: format!("{}/api/v3/write_lp", url.trim_end_matches('/')),
+            database,
+            token,
+            use_raw_as_fallback,
+            always_write_raw,
+            client,
+            buffer,
+            dead_letter,
+            breaker,
+            timestamps,
+        }
+    }
+
+    /// Same layout as the influxdb output: one measurement per key, the value in the field
+    /// `value`, the item's tags as tags
+    fn lines(&self, itemresult: &ItemResult) -> String {
+        let line = |key: &str| {
+            itemresult
+                .tags
+                .iter()
+                .fold(Line::new(key), |line, (name, value)| line.tag(name, value))
+        };
+        let mut lines = itemresult
+            .values
+            .iter()
+            .filter(|(_, value)| value.is_finite())
+            .map(|(key, value)| line(key).float("value", *value).finish(&itemresult.time))
+            .collect::<String>();
+        if itemresult.values.is_empty() && self.use_raw_as_fallback || self.always_write_raw {
+            lines.push_str(
+                &line(&format!("{}.raw", itemresult.key))
+                    .string("value", &itemresult.raw)
+                    .finish(&itemresult.time),
+            );
+        }
+        lines
+    }
+
+    pub fn buffer(&self) -> Option<&Buffer> {
+        self.buffer.as_ref()
+    }
+
+    /// Write the buffered results now, for `antikoerper spool replay`
+    pub async fn replay_buffer(&self) -> Result<()> {
+        match self.buffer.clone() {
+            Some(mut buffer) => buffer.replay(&|r| self.write(r)).await,
+            None => Ok(()),
+        }
+    }
+
+    /// InfluxDB 3 can't delete single points, the probe is kept
+    pub async fn check(&self) -> Result<String> {
+        self.send(&[Arc::new(probe())]).await?;
+        Ok(format!("wrote a probe point {}", PROBE))
+    }
+
+    async fn write(&self, itemresults: Vec<Arc<ItemResult>>) -> Result<()> {
+        let itemresults = itemresults
+            .into_iter()
+            .map(|itemresult| self.timestamps.stamp(itemresult))
+            .collect::<Vec<_>>();
+        let written = self.send(&itemresults).await;
+        match &self.dead_letter {
+            Some(dead_letter) => dead_letter.catch("InfluxDB3Output", &itemresults, written),
+            None => written,
+        }
+    }
+
+    async fn send(&self, itemresults: &[Arc<ItemResult>]) -> Result<()> {
+        let lines = itemresults
+            .iter()
+            .map(|itemresult| self.lines(itemresult))
+            .collect::<String>();
+        if lines.is_empty() {
+            return Ok(());
+        }
+        let mut request = self
+            .client
+            .post(&self.url)
+            .query(&[("db", self.database.as_str()), ("precision", "nanosecond")])
+            .body(lines);
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token.expose());
+        }
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            // Unparsable lines or schema conflicts
+            if status == StatusCode::BAD_REQUEST || status == StatusCode::UNPROCESSABLE_ENTITY {
+                return Err(
+                    Rejected(format!("InfluxDB responded with {}: {}", status, text)).into(),
+                );
+            }
+            bail!("InfluxDB responded with {}: {}", status, text);
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl AKOutput for InfluxDB3Output {
+    fn prepare(&self) -> Result<()> {
+        Ok(())
+    }
+    async fn start(mut self, mut receiver: broadcast::Receiver<Arc<ItemResult>>) {
+        debug!("InfluxDB3Output: Starting loop");
+        let mut buffer = self.buffer.take();
+        loop {
+            match receiver.recv().await {
+                Err(recverr) => match recverr {
+                    broadcast::error::RecvError::Closed => break,
+                    broadcast::error::RecvError::Lagged(count) => {
+                        warn!(
+                            "InfluxDB3Output is lagging behind, {} results skipped",
+                            count
+                        )
+                    }
+                },
+                Ok(itemresult) => {
+                    debug!(
+                        "InfluxDB3Output: Received result for item {}",
+                        itemresult.key
+                    );
+                    let key = itemresult.key.clone();
+                    let written = match &mut buffer {
+                        Some(buffer) if !self.breaker.allow() => {
+                            buffer.push(&[itemresult]).await;
+                            continue;
+                        }
+                        Some(buffer) => {
+                            let written = buffer.write(vec![itemresult], |r| self.write(r)).await;
+                            self.breaker.record(&written);
+                            written
+                        }
+                        None => match self.breaker.call(self.write(vec![itemresult])).await {
+                            Some(written) => written,
+                            None => continue,
+                        },
+                    };
+                    if let Err(e) = written {
+                        error!("InfluxDB3Output: Failed writing data for Item {}", key);
+                        error!("InfluxDB3Output: {}", e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use tokio::net::TcpListener;
+
+    use crate::clock::{self, TokioClock};
+    use crate::conf::{BufferConfig, SpoolFormat, Timestamps};
+    use crate::item::ItemResult;
+    use crate::output::breaker::Breaker;
+    use crate::output::buffer::Buffer;
+    use crate::output::influxdb3::InfluxDB3Output;
+    use crate::testing::receive_request;
+
+    #[tokio::test]
+    async fn replay_with_write_time() {
+        clock::set(TokioClock::new(Duration::from_secs(1_000_000)));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let dir =
+            std::env::temp_dir().join(format!("antikoerper-influxdb3-{}", std::process::id()));
+        let buffer = Buffer::new(
+            &BufferConfig {
+                max_size: 1024,
+                retry_interval: 0,
+                format: SpoolFormat::Json,
+                zstd: false,
+            },
+            &dir,
+            "test",
+        );
+        buffer
+            .push(&[Arc::new(ItemResult {
+                time: Duration::from_secs(10),
+                key: "load".into(),
+                raw: String::new(),
+                values: HashMap::from([("load.one".into(), 1.5)]),
+                tags: BTreeMap::new(),
+            })])
+            .await;
+        let output = InfluxDB3Output::new(
+            url,
+            String::from("test"),
+            None,
+            false,
+            false,
+            Some(buffer.clone()),
+            reqwest::Client::new(),
+            None,
+            Breaker::new(String::from("influxdb3"), None),
+            Timestamps::Write,
+        );
+        let (body, replayed) = tokio::join!(receive_request(&listener), output.replay_buffer());
+        replayed.unwrap();
+        let (line, time) = body.trim_end().rsplit_once(' ').unwrap();
+        assert_eq!(line, "load.one value=1.5");
+        assert_eq!(time.parse::<u64>().unwrap() / 1_000_000_000, 1_000_000);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
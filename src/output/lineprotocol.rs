@@ -0,0 +1,109 @@
+//! Formatting of the InfluxDB line protocol, as spoken by many time series databases
+//!
+//! See https://docs.influxdata.com/influxdb/v1/write_protocols/line_protocol_reference/
+
+use std::fmt::Write;
+use std::time::Duration;
+
+/// A single line, built up by adding tags, then fields, then the timestamp
+pub struct Line {
+    buffer: String,
+    has_fields: bool,
+}
+
+fn escape(buffer: &mut String, s: &str, special: &[char]) {
+    for c in s.chars() {
+        if c == '\\' || special.contains(&c) {
+            buffer.push('\\');
+        }
+        match c {
+            // Newlines would end the line, they can't be escaped
+            '\n' | '\r' => buffer.push(' '),
+            c => buffer.push(c),
+        }
+    }
+}
+
+impl Line {
+    pub fn new(measurement: &str) -> Self {
+        let mut buffer = String::new();
+        escape(&mut buffer, measurement, &[',', ' ']);
+        Self {
+            buffer,
+            has_fields: false,
+        }
+    }
+
+    /// Tags have to be added before any field
+    pub fn tag(mut self, name: &str, value: &str) -> Self {
+        debug_assert!(!self.has_fields);
+        // Empty tag values are not allowed
+        if value.is_empty() {
+            return self;
+        }
+        self.buffer.push(',');
+        escape(&mut self.buffer, name, &[',', '=', ' ']);
+        self.buffer.push('=');
+        escape(&mut self.buffer, value, &[',', '=', ' ']);
+        self
+    }
+
+    fn field_name(&mut self, name: &str) {
+        self.buffer.push(if self.has_fields { ',' } else { ' ' });
+        self.has_fields = true;
+        escape(&mut self.buffer, name, &[',', '=', ' ']);
+        self.buffer.push('=');
+    }
+
+    pub fn float(mut self, name: &str, value: f64) -> Self {
+        self.field_name(name);
+        // Debug formatting always includes a decimal point or exponent, so the value
+        // is never mistaken for an integer
+        let _ = write!(self.buffer, "{:?}", value);
+        self
+    }
+
+    pub fn string(mut self, name: &str, value: &str) -> Self {
+        self.field_name(name);
+        self.buffer.push('"');
+        for c in value.chars() {
+            match c {
+                '"' | '\\' => {
+                    self.buffer.push('\\');
+                    self.buffer.push(c);
+                }
+                '\n' | '\r' => self.buffer.push(' '),
+                c => self.buffer.push(c),
+            }
+        }
+        self.buffer.push('"');
+        self
+    }
+
+    /// Finish the line with a timestamp in nanoseconds, including the trailing newline
+    pub fn finish(mut self, time: &Duration) -> String {
+        let _ = writeln!(self.buffer, " {}", time.as_nanos());
+        self.buffer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::output::lineprotocol::Line;
+
+    #[test]
+    fn escaping() {
+        let line = Line::new("my measurement")
+            .tag("host", "a,b=c")
+            .tag("empty", "")
+            .float("value", 1.0)
+            .string("raw", "say \"hi\"\nthere")
+            .finish(&Duration::from_secs(1));
+        assert_eq!(
+            line,
+            "my\\ measurement,host=a\\,b\\=c value=1.0,raw=\"say \\\"hi\\\" there\" 1000000000\n"
+        );
+    }
+}
@@ -0,0 +1,299 @@
+//! Output publishing values to an MQTT broker, optionally announcing them to Home Assistant
+//! using MQTT discovery
+
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use log::{debug, error, info, warn};
+use rumqttc::{AsyncClient, Event, EventLoop, Incoming, LastWill, MqttOptions, QoS};
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::conf::HomeAssistant;
+use crate::item::ItemResult;
+use crate::output::{AKOutput, PROBE};
+use crate::secret::Secret;
+
+/// Requests queued for the broker, results are dropped if the broker does not keep up
+const QUEUE_LENGTH: usize = 1000;
+
+#[derive(Clone)]
+pub struct MqttOutput {
+    options: MqttOptions,
+    topic: String,
+    retain: bool,
+    hostname: String,
+    homeassistant: Option<HomeAssistant>,
+    use_raw_as_fallback: bool,
+    always_write_raw: bool,
+}
+
+/// The discovery config of a single sensor, see
+/// https://www.home-assistant.io/integrations/sensor.mqtt/
+#[derive(Serialize)]
+struct SensorConfig<'a> {
+    name: &'a str,
+    unique_id: String,
+    state_topic: String,
+    availability_topic: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unit_of_measurement: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    device_class: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    state_class: Option<&'static str>,
+    device: Device<'a>,
+}
+
+#[derive(Serialize)]
+struct Device<'a> {
+    identifiers: [String; 1],
+    name: &'a str,
+    manufacturer: &'static str,
+    sw_version: &'static str,
+}
+
+/// Home Assistant only allows these characters in node and object ids
+fn object_id(s: &str) -> String {
+    s.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+impl MqttOutput {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        host: String,
+        port: u16,
+        username: Option<String>,
+        password: Option<Secret>,
+        topic: String,
+        retain: bool,
+        homeassistant: Option<HomeAssistant>,
+        hostname: String,
+        name: &str,
+        use_raw_as_fallback: bool,
+        always_write_raw: bool,
+    ) -> Self {
+        let topic = format!("{}/{}", topic.trim_end_matches('/'), hostname);
+        // Unique per output, brokers disconnect clients when another one connects with the
+        // same id
+        let client_id = format!("antikoerper-{}-{}", hostname, name);
+        let mut options = MqttOptions::new(client_id, host, port);
+        options.set_keep_alive(Duration::from_secs(30));
+        options.set_last_will(LastWill::new(
+            format!("{}/status", topic),
+            "offline",
+            QoS::AtLeastOnce,
+            true,
+        ));
+        if let Some(username) = username {
+            options.set_credentials(
+                username,
+                password.map(|p| p.expose().to_owned()).unwrap_or_default(),
+            );
+        }
+        Self {
+            options,
+            topic,
+            retain,
+            hostname,
+            homeassistant,
+            use_raw_as_fallback,
+            always_write_raw,
+        }
+    }
+
+    fn state_topic(&self, key: &str) -> String {
+        format!("{}/{}", self.topic, key)
+    }
+
+    /// Announce a key to Home Assistant, numeric values are sensors with a state class so
+    /// Home Assistant keeps statistics for them
+    fn announce(
+        &self,
+        client: &AsyncClient,
+        homeassistant: &HomeAssistant,
+        key: &str,
+        numeric: bool,
+    ) {
+        let node_id = format!("antikoerper_{}", object_id(&self.hostname));
+        let config = SensorConfig {
+            name: homeassistant
+                .names
+                .get(key)
+                .map(String::as_str)
+                .unwrap_or(key),
+            unique_id: format!("{}_{}", node_id, object_id(key)),
+            state_topic: self.state_topic(key),
+            availability_topic: format!("{}/status", self.topic),
+            unit_of_measurement: homeassistant.units.get(key).map(String::as_str),
+            device_class: homeassistant.device_classes.get(key).map(String::as_str),
+            state_class: numeric.then_some("measurement"),
+            device: Device {
+                identifiers: [node_id.clone()],
+                name: &self.hostname,
+                manufacturer: "antikoerper",
+                sw_version: env!("CARGO_PKG_VERSION"),
+            },
+        };
+        let topic = format!(
+            "{}/sensor/{}/{}/config",
+            homeassistant.discovery_prefix,
+            node_id,
+            object_id(key)
+        );
+        debug!("MqttOutput: announcing {} on {}", key, topic);
+        match serde_json::to_vec(&config) {
+            Ok(payload) => {
+                if let Err(e) = client.try_publish(topic, QoS::AtLeastOnce, true, payload) {
+                    error!("MqttOutput: Failed announcing {}: {}", key, e);
+                }
+            }
+            Err(e) => error!("MqttOutput: Failed announcing {}: {}", key, e),
+        }
+    }
+
+    /// Publish a probe message, with a client id of its own not to take over the session
+    /// of the daemon
+    pub async fn check(&self) -> Result<String> {
+        let (host, port) = self.options.broker_address();
+        let mut options =
+            MqttOptions::new(format!("{}-check", self.options.client_id()), host, port);
+        if let Some((username, password)) = self.options.credentials() {
+            options.set_credentials(username, password);
+        }
+        let (client, mut eventloop) = AsyncClient::new(options, 10);
+        let topic = self.state_topic(PROBE);
+        client.publish(&topic, QoS::AtLeastOnce, false, "1").await?;
+        let acknowledged = async {
+            loop {
+                if let Event::Incoming(Incoming::PubAck(_)) = eventloop.poll().await? {
+                    return Ok::<_, rumqttc::ConnectionError>(());
+                }
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(10), acknowledged)
+            .await
+            .context("Timed out waiting for the broker")??;
+        // Without a last will, closing the connection is enough
+        Ok(format!("published a probe message on {}", topic))
+    }
+
+    /// Drive the connection to the broker, announcing availability on every (re)connect
+    async fn event_loop(
+        self,
+        mut eventloop: EventLoop,
+        client: AsyncClient,
+        announced: Arc<Mutex<HashSet<String>>>,
+    ) {
+        let birth_topic = self
+            .homeassistant
+            .as_ref()
+            .map(|ha| format!("{}/status", ha.discovery_prefix));
+        loop {
+            match eventloop.poll().await {
+                Ok(Event::Incoming(Incoming::ConnAck(_))) => {
+                    info!("MqttOutput: connected to the broker");
+                    let _ = client.try_publish(
+                        format!("{}/status", self.topic),
+                        QoS::AtLeastOnce,
+                        true,
+                        "online",
+                    );
+                    if let Some(birth_topic) = &birth_topic {
+                        let _ = client.try_subscribe(birth_topic, QoS::AtLeastOnce);
+                    }
+                }
+                Ok(Event::Incoming(Incoming::Publish(publish)))
+                    if Some(&publish.topic) == birth_topic.as_ref()
+                        && publish.payload.as_ref() == b"online" =>
+                {
+                    // Home Assistant restarted, it might have lost the retained configs
+                    debug!("MqttOutput: Home Assistant is online, announcing again");
+                    announced.lock().expect("announced lock poisoned").clear();
+                }
+                Ok(_) => (),
+                Err(e) => {
+                    warn!("MqttOutput: connection to the broker failed: {}", e);
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl AKOutput for MqttOutput {
+    fn prepare(&self) -> Result<()> {
+        Ok(())
+    }
+    async fn start(self, mut receiver: broadcast::Receiver<Arc<ItemResult>>) {
+        debug!("MqttOutput: Starting loop");
+        let (client, eventloop) = AsyncClient::new(self.options.clone(), QUEUE_LENGTH);
+        let announced = Arc::new(Mutex::new(HashSet::new()));
+        let event_loop = tokio::spawn(self.clone().event_loop(
+            eventloop,
+            client.clone(),
+            announced.clone(),
+        ));
+        loop {
+            match receiver.recv().await {
+                Err(recverr) => match recverr {
+                    broadcast::error::RecvError::Closed => break,
+                    broadcast::error::RecvError::Lagged(count) => {
+                        warn!("MqttOutput is lagging behind, {} results skipped", count)
+                    }
+                },
+                Ok(itemresult) => {
+                    debug!("MqttOutput: Received result for item {}", itemresult.key);
+                    let raw_key = format!("{}.raw", itemresult.key);
+                    let mut messages = itemresult
+                        .values
+                        .iter()
+                        .map(|(key, value)| (key.as_ref(), value.to_string(), true))
+                        .collect::<Vec<_>>();
+                    if itemresult.values.is_empty() && self.use_raw_as_fallback
+                        || self.always_write_raw
+                    {
+                        messages.push((&raw_key, itemresult.raw.clone(), false));
+                    }
+                    for (key, payload, numeric) in messages {
+                        if let Some(homeassistant) = &self.homeassistant {
+                            let new = announced
+                                .lock()
+                                .expect("announced lock poisoned")
+                                .insert(key.to_owned());
+                            if new {
+                                self.announce(&client, homeassistant, key, numeric);
+                            }
+                        }
+                        if let Err(e) = client.try_publish(
+                            self.state_topic(key),
+                            QoS::AtMostOnce,
+                            self.retain,
+                            payload,
+                        ) {
+                            error!(
+                                "MqttOutput: Failed writing data for Item {}",
+                                itemresult.key
+                            );
+                            error!("MqttOutput: {}", e);
+                        }
+                    }
+                }
+            }
+        }
+        let _ = client.disconnect().await;
+        event_loop.abort();
+    }
+}
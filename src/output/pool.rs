@@ -0,0 +1,59 @@
+//! Several tasks writing the results of one output, so a slow write does not hold up
+//! all results behind it. Results of the same key always go to the same task, keeping
+//! their order.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+use log::warn;
+use tokio::sync::broadcast;
+
+use crate::item::ItemResult;
+
+const CAPACITY: usize = 100;
+
+fn task_of(key: &str, tasks: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    (hasher.finish() % tasks as u64) as usize
+}
+
+/// One receiver per task, all closed once the original channel is closed
+pub fn split(
+    mut receiver: broadcast::Receiver<Arc<ItemResult>>,
+    tasks: usize,
+) -> Vec<broadcast::Receiver<Arc<ItemResult>>> {
+    let (senders, receivers): (Vec<_>, Vec<_>) =
+        (0..tasks).map(|_| broadcast::channel(CAPACITY)).unzip();
+    tokio::spawn(async move {
+        loop {
+            match receiver.recv().await {
+                Err(broadcast::error::RecvError::Closed) => break,
+                Err(broadcast::error::RecvError::Lagged(count)) => {
+                    warn!("Output pool is lagging behind, {} results skipped", count)
+                }
+                Ok(result) => {
+                    let task = task_of(&result.key, senders.len());
+                    if senders[task].send(result).is_err() {
+                        // The output is gone
+                        break;
+                    }
+                }
+            }
+        }
+    });
+    receivers
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::output::pool::task_of;
+
+    #[test]
+    fn same_key_same_task() {
+        assert_eq!(task_of("os.load", 4), task_of("os.load", 4));
+        assert!((0..100).all(|i| task_of(&format!("key{}", i), 4) < 4));
+        assert_eq!(task_of("os.load", 1), 0);
+    }
+}
@@ -0,0 +1,224 @@
+//! Output sending push notifications via ntfy or Gotify, for alerts and events
+
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use log::{debug, error, warn};
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::alert::{AlertFilter, Silences, Status};
+use crate::event::{Event, Severity};
+use crate::item::ItemResult;
+use crate::output::{AKEventOutput, AKOutput};
+use crate::secret::Secret;
+use crate::template::{Template, TemplateContext};
+
+#[derive(Clone)]
+pub enum PushService {
+    Ntfy {
+        topic: String,
+        token: Option<Secret>,
+    },
+    Gotify {
+        token: Secret,
+    },
+}
+
+#[derive(Clone)]
+pub struct PushOutput {
+    url: String,
+    service: PushService,
+    template: Option<Template>,
+    alert: AlertFilter,
+    events: Option<Severity>,
+    silences: Silences,
+    hostname: String,
+    client: reqwest::Client,
+}
+
+/// A single notification, independent of the service
+struct Notification {
+    title: String,
+    message: String,
+    severity: Severity,
+}
+
+#[derive(Serialize)]
+struct GotifyMessage<'a> {
+    title: &'a str,
+    message: &'a str,
+    priority: u8,
+}
+
+impl PushOutput {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        url: String,
+        service: PushService,
+        template: Option<Template>,
+        alert: AlertFilter,
+        events: Option<Severity>,
+        silences: Silences,
+        hostname: String,
+        client: reqwest::Client,
+    ) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_owned(),
+            service,
+            template,
+            alert,
+            events,
+            silences,
+            hostname,
+            client,
+        }
+    }
+
+    pub fn handles_events(&self) -> bool {
+        self.events.is_some()
+    }
+
+    /// The message is the rendered template, or the output of the monitoring plugin
+    fn notification(&self, itemresult: &ItemResult) -> Result<Notification> {
+        let status = Status::of(itemresult).unwrap_or(Status::Unknown);
+        let message = match &self.template {
+            Some(template) => template.render(&TemplateContext::new(itemresult, &self.hostname))?,
+            None => itemresult
+                .raw
+                .split('|')
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_owned(),
+        };
+        Ok(Notification {
+            title: format!("{}: {} {}", self.hostname, itemresult.key, status.as_str()),
+            message,
+            severity: status.into(),
+        })
+    }
+
+    pub async fn check(&self) -> Result<String> {
+        self.send(&Notification {
+            title: format!("{}: output check", self.hostname),
+            message: String::from("Test notification of antikoerper output-check"),
+            severity: Severity::Info,
+        })
+        .await?;
+        Ok(String::from("sent a test notification"))
+    }
+
+    async fn send(&self, notification: &Notification) -> Result<()> {
+        let request = match &self.service {
+            PushService::Ntfy { topic, token } => {
+                let (priority, tags) = match notification.severity {
+                    Severity::Info => ("default", "white_check_mark"),
+                    Severity::Warning => ("high", "warning"),
+                    Severity::Critical => ("urgent", "rotating_light"),
+                };
+                let mut request = self
+                    .client
+                    .post(format!("{}/{}", self.url, topic))
+                    .header("Title", &notification.title)
+                    .header("Priority", priority)
+                    .header("Tags", tags)
+                    .body(notification.message.clone());
+                if let Some(token) = token {
+                    request = request.bearer_auth(token.expose());
+                }
+                request
+            }
+            PushService::Gotify { token } => self
+                .client
+                .post(format!("{}/message", self.url))
+                .header("X-Gotify-Key", token.expose())
+                .json(&GotifyMessage {
+                    title: &notification.title,
+                    message: &notification.message,
+                    priority: match notification.severity {
+                        Severity::Info => 2,
+                        Severity::Warning => 5,
+                        Severity::Critical => 8,
+                    },
+                }),
+        };
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            bail!("Push service responded with {}", response.status());
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl AKOutput for PushOutput {
+    fn prepare(&self) -> Result<()> {
+        Ok(())
+    }
+    async fn start(mut self, mut receiver: broadcast::Receiver<Arc<ItemResult>>) {
+        debug!("PushOutput: Starting loop");
+        loop {
+            match receiver.recv().await {
+                Err(recverr) => match recverr {
+                    broadcast::error::RecvError::Closed => break,
+                    broadcast::error::RecvError::Lagged(count) => {
+                        warn!("PushOutput is lagging behind, {} results skipped", count)
+                    }
+                },
+                Ok(itemresult) => {
+                    debug!("PushOutput: Received result for item {}", itemresult.key);
+                    if !self.alert.should_notify(&itemresult) {
+                        continue;
+                    }
+                    let sent = match self.notification(&itemresult) {
+                        Ok(notification) => self.send(&notification).await,
+                        Err(e) => Err(e),
+                    };
+                    if let Err(e) = sent {
+                        error!(
+                            "PushOutput: Failed sending notification for Item {}",
+                            itemresult.key
+                        );
+                        error!("PushOutput: {}", e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl AKEventOutput for PushOutput {
+    async fn start_events(self, mut receiver: broadcast::Receiver<Event>) {
+        debug!("PushOutput: Starting event loop");
+        let Some(min_severity) = self.events else {
+            return;
+        };
+        loop {
+            match receiver.recv().await {
+                Err(recverr) => match recverr {
+                    broadcast::error::RecvError::Closed => break,
+                    broadcast::error::RecvError::Lagged(count) => {
+                        warn!("PushOutput is lagging behind, {} events skipped", count)
+                    }
+                },
+                Ok(event) => {
+                    if event.severity < min_severity || self.silences.is_silenced(&event.key) {
+                        continue;
+                    }
+                    let notification = Notification {
+                        title: format!("{}: {}", self.hostname, event.key),
+                        message: event.message.clone(),
+                        severity: event.severity,
+                    };
+                    if let Err(e) = self.send(&notification).await {
+                        error!("PushOutput: Failed sending event for Item {}", event.key);
+                        error!("PushOutput: {}", e);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,201 @@
+//! Output to QuestDB, using InfluxDB line protocol over its TCP ingestion port
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use base64::Engine;
+use log::{debug, error, info, warn};
+use p256::ecdsa::signature::Signer;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::broadcast;
+
+use crate::conf::QuestDBAuth;
+use crate::item::ItemResult;
+use crate::output::breaker::Breaker;
+use crate::output::lineprotocol::Line;
+use crate::output::{probe, AKOutput, PROBE};
+
+#[derive(Clone)]
+pub struct QuestDBOutput {
+    address: String,
+    table: String,
+    hostname: String,
+    auth: Option<QuestDBAuth>,
+    use_raw_as_fallback: bool,
+    always_write_raw: bool,
+    breaker: Breaker,
+}
+
+impl QuestDBOutput {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        host: String,
+        port: u16,
+        table: String,
+        hostname: String,
+        auth: Option<QuestDBAuth>,
+        use_raw_as_fallback: bool,
+        always_write_raw: bool,
+        breaker: Breaker,
+    ) -> Result<Self> {
+        if let Some(auth) = &auth {
+            signing_key(auth)?;
+        }
+        Ok(Self {
+            address: format!("{}:{}", host, port),
+            table,
+            hostname,
+            auth,
+            use_raw_as_fallback,
+            always_write_raw,
+            breaker,
+        })
+    }
+
+    async fn connect(&self) -> Result<TcpStream> {
+        debug!("QuestDBOutput: connecting to {}", self.address);
+        let stream =
+            tokio::time::timeout(Duration::from_secs(10), TcpStream::connect(&self.address))
+                .await
+                .context("Timed out connecting to QuestDB")??;
+        match &self.auth {
+            None => Ok(stream),
+            Some(auth) => authenticate(stream, auth).await,
+        }
+    }
+
+    /// QuestDB never answers on the port, errors like rejected credentials close the
+    /// connection
+    pub async fn check(&self) -> Result<String> {
+        let mut stream = self.connect().await?;
+        stream.write_all(self.lines(&probe()).as_bytes()).await?;
+        stream.flush().await?;
+        let mut byte = [0u8; 1];
+        if let Ok(read) = tokio::time::timeout(Duration::from_secs(1), stream.read(&mut byte)).await
+        {
+            if read? == 0 {
+                bail!("QuestDB closed the connection");
+            }
+        }
+        Ok(format!("wrote a probe row {} to {}", PROBE, self.table))
+    }
+
+    fn lines(&self, itemresult: &ItemResult) -> String {
+        let line = |key: &str| {
+            itemresult.tags.iter().fold(
+                Line::new(&self.table)
+                    .tag("host", &self.hostname)
+                    .tag("item", &itemresult.key)
+                    .tag("key", key),
+                |line, (name, value)| line.tag(name, value),
+            )
+        };
+        let mut lines = itemresult
+            .values
+            .iter()
+            .filter(|(_, value)| value.is_finite())
+            .map(|(key, value)| line(key).float("value", *value).finish(&itemresult.time))
+            .collect::<String>();
+        if itemresult.values.is_empty() && self.use_raw_as_fallback || self.always_write_raw {
+            lines.push_str(
+                &line(&format!("{}.raw", itemresult.key))
+                    .string("raw", &itemresult.raw)
+                    .finish(&itemresult.time),
+            );
+        }
+        lines
+    }
+}
+
+fn signing_key(auth: &QuestDBAuth) -> Result<p256::ecdsa::SigningKey> {
+    let d = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(auth.private_key.expose().trim_end_matches('='))
+        .context("QuestDB private_key is not valid base64url")?;
+    p256::ecdsa::SigningKey::from_slice(&d).context("QuestDB private_key is not a P-256 key")
+}
+
+/// QuestDB's challenge-response authentication: send the key id, sign the challenge
+async fn authenticate(stream: TcpStream, auth: &QuestDBAuth) -> Result<TcpStream> {
+    let mut stream = BufReader::new(stream);
+    stream
+        .get_mut()
+        .write_all(format!("{}\n", auth.key_id).as_bytes())
+        .await?;
+    let mut challenge = String::new();
+    stream.read_line(&mut challenge).await?;
+    let challenge = challenge.trim_end_matches('\n');
+    if challenge.is_empty() {
+        bail!("QuestDB closed the connection during authentication");
+    }
+    let signature: p256::ecdsa::Signature = signing_key(auth)?.sign(challenge.as_bytes());
+    let signature = base64::engine::general_purpose::STANDARD.encode(signature.to_bytes());
+    stream
+        .get_mut()
+        .write_all(format!("{}\n", signature).as_bytes())
+        .await?;
+    Ok(stream.into_inner())
+}
+
+#[async_trait]
+impl AKOutput for QuestDBOutput {
+    fn prepare(&self) -> Result<()> {
+        Ok(())
+    }
+    async fn start(self, mut receiver: broadcast::Receiver<Arc<ItemResult>>) {
+        debug!("QuestDBOutput: Starting loop");
+        let mut connection: Option<TcpStream> = None;
+        loop {
+            match receiver.recv().await {
+                Err(recverr) => match recverr {
+                    broadcast::error::RecvError::Closed => break,
+                    broadcast::error::RecvError::Lagged(count) => {
+                        warn!("QuestDBOutput is lagging behind, {} results skipped", count)
+                    }
+                },
+                Ok(itemresult) => {
+                    debug!("QuestDBOutput: Received result for item {}", itemresult.key);
+                    let lines = self.lines(&itemresult);
+                    if lines.is_empty() {
+                        continue;
+                    }
+                    if connection.is_none() {
+                        match self.breaker.call(self.connect()).await {
+                            None => continue,
+                            Some(Ok(c)) => {
+                                info!("QuestDBOutput: connected to {}", self.address);
+                                connection = Some(c);
+                            }
+                            Some(Err(e)) => {
+                                error!(
+                                    "QuestDBOutput: Failed writing data for Item {}",
+                                    itemresult.key
+                                );
+                                error!("QuestDBOutput: {}", e);
+                                continue;
+                            }
+                        }
+                    }
+                    if let Some(stream) = connection.as_mut() {
+                        let written = stream
+                            .write_all(lines.as_bytes())
+                            .await
+                            .map_err(anyhow::Error::from);
+                        self.breaker.record(&written);
+                        if let Err(e) = written {
+                            error!(
+                                "QuestDBOutput: Failed writing data for Item {}",
+                                itemresult.key
+                            );
+                            error!("QuestDBOutput: {}", e);
+                            // QuestDB closes the connection on errors, reconnect next time
+                            connection = None;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
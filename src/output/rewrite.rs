@@ -0,0 +1,83 @@
+//! Renaming of keys per output, for backends with their own naming conventions
+
+use anyhow::{Context, Result};
+use regex::Regex;
+
+use crate::conf::Rename;
+use crate::item::ItemResult;
+
+#[derive(Debug, Clone, Default)]
+pub struct KeyRewrite {
+    prefix: String,
+    suffix: String,
+    rename: Vec<(Regex, String)>,
+}
+
+impl KeyRewrite {
+    pub fn new(prefix: String, suffix: String, rename: Vec<Rename>) -> Result<Self> {
+        Ok(Self {
+            prefix,
+            suffix,
+            rename: rename
+                .into_iter()
+                .map(|r| {
+                    Regex::new(&r.regex)
+                        .with_context(|| format!("Invalid regex {}", r.regex))
+                        .map(|regex| (regex, r.replace))
+                })
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.prefix.is_empty() && self.suffix.is_empty() && self.rename.is_empty()
+    }
+
+    /// All renames in order, then prefix and suffix
+    pub fn key(&self, key: &str) -> String {
+        let renamed = self
+            .rename
+            .iter()
+            .fold(key.to_owned(), |key, (regex, replace)| {
+                regex.replace_all(&key, replace.as_str()).into_owned()
+            });
+        format!("{}{}{}", self.prefix, renamed, self.suffix)
+    }
+
+    /// Rewrite the keys of all values, and the key of the item the raw value is written with
+    pub fn apply(&self, mut result: ItemResult) -> ItemResult {
+        result.key = self.key(&result.key).into();
+        result.values = result
+            .values
+            .into_iter()
+            .map(|(key, value)| (self.key(&key).into(), value))
+            .collect();
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::conf::Rename;
+    use crate::output::rewrite::KeyRewrite;
+
+    #[test]
+    fn rename() {
+        let rewrite = KeyRewrite::new(
+            String::from("host1_"),
+            String::from("_total"),
+            vec![
+                Rename {
+                    regex: String::from(r"^os\."),
+                    replace: String::new(),
+                },
+                Rename {
+                    regex: String::from(r"\."),
+                    replace: String::from("_"),
+                },
+            ],
+        )
+        .unwrap();
+        assert_eq!(rewrite.key("os.load.load1m"), "host1_load_load1m_total");
+    }
+}
@@ -0,0 +1,250 @@
+//! Output writing lines to a Unix or TCP socket, for collectors like Vector, fluent-bit or
+//! telegraf's socket_listener
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use log::{debug, error, info, warn};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, UnixStream};
+use tokio::sync::broadcast;
+use tokio::time::Instant;
+
+use crate::conf::SocketFormat;
+use crate::item::ItemResult;
+use crate::output::lineprotocol::Line;
+use crate::output::{probe, AKOutput, PROBE};
+
+/// Waiting time after the first failed connection attempt, doubled with every further one
+const BACKOFF_MIN: Duration = Duration::from_secs(1);
+const BACKOFF_MAX: Duration = Duration::from_secs(60);
+
+type Connection = Box<dyn AsyncWrite + Send + Unpin>;
+
+#[derive(Clone)]
+pub struct SocketOutput {
+    address: String,
+    format: SocketFormat,
+    use_raw_as_fallback: bool,
+    always_write_raw: bool,
+}
+
+/// Graphite paths can't contain whitespace, and `;` separates tags
+fn graphite_escape(s: &str) -> String {
+    s.chars()
+        .map(|c| {
+            if c.is_whitespace() || c == ';' {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+impl SocketOutput {
+    pub fn new(
+        address: String,
+        format: SocketFormat,
+        use_raw_as_fallback: bool,
+        always_write_raw: bool,
+    ) -> Self {
+        Self {
+            address,
+            format,
+            use_raw_as_fallback,
+            always_write_raw,
+        }
+    }
+
+    async fn connect(&self) -> Result<Connection> {
+        debug!("SocketOutput: connecting to {}", self.address);
+        let connect = async {
+            Ok::<Connection, std::io::Error>(match self.address.strip_prefix("unix:") {
+                Some(path) => Box::new(UnixStream::connect(path).await?),
+                None => Box::new(TcpStream::connect(&self.address).await?),
+            })
+        };
+        tokio::time::timeout(Duration::from_secs(10), connect)
+            .await
+            .with_context(|| format!("Timed out connecting to {}", self.address))?
+            .with_context(|| format!("Failed connecting to {}", self.address))
+    }
+
+    pub async fn check(&self) -> Result<String> {
+        let mut connection = self.connect().await?;
+        connection
+            .write_all(self.lines(&probe())?.as_bytes())
+            .await?;
+        connection.flush().await?;
+        Ok(format!("wrote a probe {} to {}", PROBE, self.address))
+    }
+
+    fn write_raw(&self, itemresult: &ItemResult) -> bool {
+        itemresult.values.is_empty() && self.use_raw_as_fallback || self.always_write_raw
+    }
+
+    fn lines(&self, itemresult: &ItemResult) -> Result<String> {
+        let raw_key = format!("{}.raw", itemresult.key);
+        Ok(match self.format {
+            SocketFormat::Json => {
+                let mut line = serde_json::to_string(itemresult)?;
+                line.push('\n');
+                line
+            }
+            SocketFormat::Influx => {
+                let line = |key: &str| {
+                    itemresult
+                        .tags
+                        .iter()
+                        .fold(Line::new(key), |line, (name, value)| line.tag(name, value))
+                };
+                let mut lines = itemresult
+                    .values
+                    .iter()
+                    .filter(|(_, value)| value.is_finite())
+                    .map(|(key, value)| line(key).float("value", *value).finish(&itemresult.time))
+                    .collect::<String>();
+                if self.write_raw(itemresult) {
+                    lines.push_str(
+                        &line(&raw_key)
+                            .string("value", &itemresult.raw)
+                            .finish(&itemresult.time),
+                    );
+                }
+                lines
+            }
+            // Graphite only knows numbers, raw results are never written
+            SocketFormat::Graphite => {
+                let tags = itemresult
+                    .tags
+                    .iter()
+                    .map(|(name, value)| {
+                        format!(";{}={}", graphite_escape(name), graphite_escape(value))
+                    })
+                    .collect::<String>();
+                itemresult
+                    .values
+                    .iter()
+                    .filter(|(_, value)| value.is_finite())
+                    .map(|(key, value)| {
+                        format!(
+                            "{}{} {} {}\n",
+                            graphite_escape(key),
+                            tags,
+                            value,
+                            itemresult.time.as_secs()
+                        )
+                    })
+                    .collect()
+            }
+        })
+    }
+}
+
+#[async_trait]
+impl AKOutput for SocketOutput {
+    fn prepare(&self) -> Result<()> {
+        Ok(())
+    }
+    async fn start(self, mut receiver: broadcast::Receiver<Arc<ItemResult>>) {
+        debug!("SocketOutput: Starting loop");
+        let mut connection: Option<Connection> = None;
+        let mut backoff = BACKOFF_MIN;
+        let mut retry_at = Instant::now();
+        loop {
+            match receiver.recv().await {
+                Err(recverr) => match recverr {
+                    broadcast::error::RecvError::Closed => break,
+                    broadcast::error::RecvError::Lagged(count) => {
+                        warn!("SocketOutput is lagging behind, {} results skipped", count)
+                    }
+                },
+                Ok(itemresult) => {
+                    debug!("SocketOutput: Received result for item {}", itemresult.key);
+                    let lines = match self.lines(&itemresult) {
+                        Ok(lines) if lines.is_empty() => continue,
+                        Ok(lines) => lines,
+                        Err(e) => {
+                            error!(
+                                "SocketOutput: Failed formatting data for Item {}",
+                                itemresult.key
+                            );
+                            error!("SocketOutput: {}", e);
+                            continue;
+                        }
+                    };
+                    if connection.is_none() {
+                        if Instant::now() < retry_at {
+                            debug!(
+                                "SocketOutput: not connected, dropping result for item {}",
+                                itemresult.key
+                            );
+                            continue;
+                        }
+                        match self.connect().await {
+                            Ok(c) => {
+                                info!("SocketOutput: connected to {}", self.address);
+                                connection = Some(c);
+                                backoff = BACKOFF_MIN;
+                            }
+                            Err(e) => {
+                                error!(
+                                    "SocketOutput: Failed writing data for Item {}",
+                                    itemresult.key
+                                );
+                                error!("SocketOutput: {:#}, retrying in {:?}", e, backoff);
+                                retry_at = Instant::now() + backoff;
+                                backoff = (backoff * 2).min(BACKOFF_MAX);
+                                continue;
+                            }
+                        }
+                    }
+                    if let Some(stream) = connection.as_mut() {
+                        let written = match stream.write_all(lines.as_bytes()).await {
+                            Ok(()) => stream.flush().await,
+                            Err(e) => Err(e),
+                        };
+                        if let Err(e) = written {
+                            error!(
+                                "SocketOutput: Failed writing data for Item {}",
+                                itemresult.key
+                            );
+                            error!("SocketOutput: {}", e);
+                            // Reconnect with the next result
+                            connection = None;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+    use std::time::Duration;
+
+    use crate::conf::SocketFormat;
+    use crate::item::ItemResult;
+    use crate::output::socket::SocketOutput;
+
+    #[test]
+    fn graphite() {
+        let output = SocketOutput::new(String::new(), SocketFormat::Graphite, false, true);
+        let result = ItemResult {
+            time: Duration::from_secs(100),
+            key: "os.load".into(),
+            raw: String::from("0.5"),
+            values: HashMap::from([("os.load.1 min".into(), 0.5)]),
+            tags: BTreeMap::from([("room".into(), String::from("attic"))]),
+        };
+        assert_eq!(
+            output.lines(&result).unwrap(),
+            "os.load.1_min;room=attic 0.5 100\n"
+        );
+    }
+}
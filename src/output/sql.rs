@@ -0,0 +1,227 @@
+//! Output to SQL databases (PostgreSQL, MySQL, SQLite), using a user-defined INSERT statement
+
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use log::{debug, error, warn};
+use sqlx::any::AnyPoolOptions;
+use sqlx::AnyPool;
+use tokio::sync::broadcast;
+
+use crate::item::ItemResult;
+use crate::output::breaker::Breaker;
+use crate::output::{probe, AKOutput, PROBE};
+use crate::secret::Secret;
+
+/// The placeholders usable in the statement
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Param {
+    /// Seconds since the UNIX epoch
+    Time,
+    /// Milliseconds since the UNIX epoch
+    TimeMs,
+    /// Key of the value
+    Key,
+    /// Key of the item that produced the value
+    Item,
+    /// The value, NULL for raw results
+    Value,
+    /// The raw result, NULL for values
+    Raw,
+    /// The tags of the item, as a JSON object
+    Tags,
+}
+
+/// A single row to insert
+struct Row<'a> {
+    key: &'a str,
+    value: Option<f64>,
+    raw: Option<&'a str>,
+}
+
+#[derive(Clone)]
+pub struct SqlOutput {
+    pool: AnyPool,
+    statement: String,
+    params: Vec<Param>,
+    use_raw_as_fallback: bool,
+    always_write_raw: bool,
+    breaker: Breaker,
+}
+
+/// Replace the `{name}` placeholders in the statement with bind parameters of the database,
+/// `$1`, `$2`... for PostgreSQL, `?` for all others.
+fn parse_statement(statement: &str, numbered: bool) -> Result<(String, Vec<Param>)> {
+    let mut query = String::with_capacity(statement.len());
+    let mut params = Vec::new();
+    let mut rest = statement;
+    while let Some(start) = rest.find('{') {
+        query.push_str(&rest[..start]);
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => bail!("Unclosed placeholder in statement: {}", statement),
+        };
+        let param = match &rest[start + 1..end] {
+            "time" => Param::Time,
+            "time_ms" => Param::TimeMs,
+            "key" => Param::Key,
+            "item" => Param::Item,
+            "value" => Param::Value,
+            "raw" => Param::Raw,
+            "tags" => Param::Tags,
+            other => bail!("Unknown placeholder {{{}}} in statement", other),
+        };
+        params.push(param);
+        if numbered {
+            query.push_str(&format!("${}", params.len()));
+        } else {
+            query.push('?');
+        }
+        rest = &rest[end + 1..];
+    }
+    query.push_str(rest);
+    Ok((query, params))
+}
+
+impl SqlOutput {
+    pub fn new(
+        url: Secret,
+        statement: String,
+        use_raw_as_fallback: bool,
+        always_write_raw: bool,
+        breaker: Breaker,
+    ) -> Result<Self> {
+        sqlx::any::install_default_drivers();
+        let (statement, params) =
+            parse_statement(&statement, url.expose().starts_with("postgres"))?;
+        let pool = AnyPoolOptions::new()
+            .max_connections(2)
+            .connect_lazy(url.expose())?;
+        Ok(Self {
+            pool,
+            statement,
+            params,
+            use_raw_as_fallback,
+            always_write_raw,
+            breaker,
+        })
+    }
+
+    async fn insert(&self, itemresult: &ItemResult, rows: &[Row<'_>]) -> Result<()> {
+        let mut transaction = self.pool.begin().await?;
+        self.execute(&mut transaction, itemresult, rows).await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    /// Insert a probe row and roll it back, leaving the table as it was
+    pub async fn check(&self) -> Result<String> {
+        let mut transaction = self.pool.begin().await?;
+        let row = Row {
+            key: PROBE,
+            value: Some(1.0),
+            raw: None,
+        };
+        self.execute(&mut transaction, &probe(), &[row]).await?;
+        transaction.rollback().await?;
+        Ok(String::from("inserted a probe row and rolled it back"))
+    }
+
+    async fn execute(
+        &self,
+        transaction: &mut sqlx::Transaction<'_, sqlx::Any>,
+        itemresult: &ItemResult,
+        rows: &[Row<'_>],
+    ) -> Result<()> {
+        let tags = serde_json::to_string(&itemresult.tags)?;
+        for row in rows {
+            let mut query = sqlx::query(&self.statement);
+            for param in self.params.iter() {
+                query = match param {
+                    Param::Time => query.bind(itemresult.time.as_secs() as i64),
+                    Param::TimeMs => query.bind(itemresult.time.as_millis() as i64),
+                    Param::Key => query.bind(row.key.to_owned()),
+                    Param::Item => query.bind(itemresult.key.to_string()),
+                    Param::Value => query.bind(row.value),
+                    Param::Raw => query.bind(row.raw.map(String::from)),
+                    Param::Tags => query.bind(tags.clone()),
+                };
+            }
+            query.execute(&mut **transaction).await?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl AKOutput for SqlOutput {
+    fn prepare(&self) -> Result<()> {
+        Ok(())
+    }
+    async fn start(self, mut receiver: broadcast::Receiver<Arc<ItemResult>>) {
+        debug!("SqlOutput: Starting loop");
+        loop {
+            match receiver.recv().await {
+                Err(recverr) => match recverr {
+                    broadcast::error::RecvError::Closed => break,
+                    broadcast::error::RecvError::Lagged(count) => {
+                        warn!("SqlOutput is lagging behind, {} results skipped", count)
+                    }
+                },
+                Ok(itemresult) => {
+                    debug!("SqlOutput: Received result for item {}", itemresult.key);
+                    let raw_key = format!("{}.raw", itemresult.key);
+                    let mut rows = itemresult
+                        .values
+                        .iter()
+                        .map(|(key, value)| Row {
+                            key,
+                            value: Some(*value),
+                            raw: None,
+                        })
+                        .collect::<Vec<_>>();
+                    if itemresult.values.is_empty() && self.use_raw_as_fallback
+                        || self.always_write_raw
+                    {
+                        rows.push(Row {
+                            key: &raw_key,
+                            value: None,
+                            raw: Some(&itemresult.raw),
+                        });
+                    }
+                    if rows.is_empty() {
+                        continue;
+                    }
+                    if let Some(Err(e)) = self.breaker.call(self.insert(&itemresult, &rows)).await {
+                        error!("SqlOutput: Failed writing data for Item {}", itemresult.key);
+                        error!("SqlOutput: {}", e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::output::sql::{parse_statement, Param};
+
+    #[test]
+    fn statement_placeholders() {
+        let statement =
+            "INSERT INTO metrics (ts, name, value) VALUES (to_timestamp({time}), {key}, {value})";
+        let (query, params) = parse_statement(statement, true).unwrap();
+        assert_eq!(
+            query,
+            "INSERT INTO metrics (ts, name, value) VALUES (to_timestamp($1), $2, $3)"
+        );
+        assert_eq!(params, vec![Param::Time, Param::Key, Param::Value]);
+        let (query, _) = parse_statement(statement, false).unwrap();
+        assert_eq!(
+            query,
+            "INSERT INTO metrics (ts, name, value) VALUES (to_timestamp(?), ?, ?)"
+        );
+        assert!(parse_statement("INSERT {nope}", false).is_err());
+    }
+}
@@ -0,0 +1,58 @@
+//! HTTP clients according to the TLS settings of an output
+
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use log::warn;
+
+use crate::conf::{HttpConfig, TlsConfig};
+
+fn read(path: &Path) -> Result<Vec<u8>> {
+    std::fs::read(path).with_context(|| format!("Failed reading {}", path.display()))
+}
+
+pub fn client(config: Option<&TlsConfig>) -> Result<reqwest::Client> {
+    Ok(builder(config)?.build()?)
+}
+
+/// For outputs talking to web APIs, not waiting forever for hung servers
+pub fn http_client(config: &HttpConfig) -> Result<reqwest::Client> {
+    Ok(builder(config.tls.as_ref())?
+        .timeout(Duration::from_secs(config.timeout.max(1)))
+        .build()?)
+}
+
+/// For clients needing further settings
+pub fn builder(config: Option<&TlsConfig>) -> Result<reqwest::ClientBuilder> {
+    let mut client = reqwest::Client::builder();
+    let Some(config) = config else {
+        return Ok(client);
+    };
+    if let Some(ca_file) = &config.ca_file {
+        client = client.add_root_certificate(
+            reqwest::Certificate::from_pem(&read(ca_file)?)
+                .with_context(|| format!("{} is not a PEM certificate", ca_file.display()))?,
+        );
+    }
+    match (&config.client_cert, &config.client_key) {
+        (Some(cert), key) => {
+            let mut pem = read(cert)?;
+            if let Some(key) = key {
+                pem.push(b'\n');
+                pem.extend(read(key)?);
+            }
+            client = client.identity(
+                reqwest::Identity::from_pem(&pem)
+                    .context("Client certificate or key are not valid PEM")?,
+            );
+        }
+        (None, Some(_)) => bail!("tls.client_key requires tls.client_cert"),
+        (None, None) => {}
+    }
+    if config.insecure_skip_verify {
+        warn!("TLS certificate verification is disabled for an output");
+        client = client.danger_accept_invalid_certs(true);
+    }
+    Ok(client)
+}
@@ -0,0 +1,176 @@
+//! Output sending every result as an HTTP request, e.g. to chat or ticket systems
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use log::{debug, error, warn};
+use tokio::sync::broadcast;
+
+use crate::alert::{AlertFilter, Silences};
+use crate::event::{Event, Severity};
+use crate::item::ItemResult;
+use crate::output::{probe, AKEventOutput, AKOutput};
+use crate::template::{Template, TemplateContext};
+
+#[derive(Clone)]
+pub struct WebhookOutput {
+    url: String,
+    method: reqwest::Method,
+    headers: BTreeMap<String, String>,
+    template: Option<Template>,
+    alert: Option<AlertFilter>,
+    events: Option<Severity>,
+    event_template: Option<Template>,
+    silences: Silences,
+    hostname: String,
+    client: reqwest::Client,
+}
+
+impl WebhookOutput {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        url: String,
+        method: String,
+        headers: BTreeMap<String, String>,
+        template: Option<Template>,
+        alert: Option<AlertFilter>,
+        events: Option<Severity>,
+        event_template: Option<Template>,
+        silences: Silences,
+        hostname: String,
+        client: reqwest::Client,
+    ) -> Result<Self> {
+        let method = reqwest::Method::from_bytes(method.to_uppercase().as_bytes())?;
+        Ok(Self {
+            url,
+            method,
+            headers,
+            template,
+            alert,
+            events,
+            event_template,
+            silences,
+            hostname,
+            client,
+        })
+    }
+
+    pub fn handles_events(&self) -> bool {
+        self.events.is_some()
+    }
+
+    /// Render the request body, without a template the whole context is sent as JSON
+    fn body(&self, itemresult: &ItemResult) -> Result<String> {
+        let context = TemplateContext::new(itemresult, &self.hostname);
+        match &self.template {
+            Some(template) => template.render(&context),
+            None => serde_json::to_string(&context).map_err(anyhow::Error::from),
+        }
+    }
+
+    fn event_body(&self, event: &Event) -> Result<String> {
+        match &self.event_template {
+            Some(template) => template.render(event),
+            None => serde_json::to_string(event).map_err(anyhow::Error::from),
+        }
+    }
+
+    /// The probe is sent like a result, URLs often hold tokens and are not told
+    pub async fn check(&self) -> Result<String> {
+        self.send(self.body(&probe())?, self.template.is_none())
+            .await?;
+        Ok(String::from("sent a probe request"))
+    }
+
+    async fn send(&self, body: String, json: bool) -> Result<()> {
+        let mut request = self
+            .client
+            .request(self.method.clone(), &self.url)
+            .body(body);
+        if json {
+            request = request.header(reqwest::header::CONTENT_TYPE, "application/json");
+        }
+        for (name, value) in self.headers.iter() {
+            request = request.header(name, value);
+        }
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            bail!("Webhook responded with {}", response.status());
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl AKOutput for WebhookOutput {
+    fn prepare(&self) -> Result<()> {
+        Ok(())
+    }
+    async fn start(mut self, mut receiver: broadcast::Receiver<Arc<ItemResult>>) {
+        debug!("WebhookOutput: Starting loop");
+        loop {
+            match receiver.recv().await {
+                Err(recverr) => match recverr {
+                    broadcast::error::RecvError::Closed => break,
+                    broadcast::error::RecvError::Lagged(count) => {
+                        warn!("WebhookOutput is lagging behind, {} results skipped", count)
+                    }
+                },
+                Ok(itemresult) => {
+                    debug!("WebhookOutput: Received result for item {}", itemresult.key);
+                    if let Some(alert) = self.alert.as_mut() {
+                        if !alert.should_notify(&itemresult) {
+                            continue;
+                        }
+                    }
+                    let sent = match self.body(&itemresult) {
+                        Ok(body) => self.send(body, self.template.is_none()).await,
+                        Err(e) => Err(e),
+                    };
+                    if let Err(e) = sent {
+                        error!(
+                            "WebhookOutput: Failed sending data for Item {}",
+                            itemresult.key
+                        );
+                        error!("WebhookOutput: {}", e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl AKEventOutput for WebhookOutput {
+    async fn start_events(self, mut receiver: broadcast::Receiver<Event>) {
+        debug!("WebhookOutput: Starting event loop");
+        let Some(min_severity) = self.events else {
+            return;
+        };
+        loop {
+            match receiver.recv().await {
+                Err(recverr) => match recverr {
+                    broadcast::error::RecvError::Closed => break,
+                    broadcast::error::RecvError::Lagged(count) => {
+                        warn!("WebhookOutput is lagging behind, {} events skipped", count)
+                    }
+                },
+                Ok(event) => {
+                    if event.severity < min_severity || self.silences.is_silenced(&event.key) {
+                        continue;
+                    }
+                    let sent = match self.event_body(&event) {
+                        Ok(body) => self.send(body, self.event_template.is_none()).await,
+                        Err(e) => Err(e),
+                    };
+                    if let Err(e) = sent {
+                        error!("WebhookOutput: Failed sending event for Item {}", event.key);
+                        error!("WebhookOutput: {}", e);
+                    }
+                }
+            }
+        }
+    }
+}
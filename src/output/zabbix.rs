@@ -0,0 +1,279 @@
+//! Output using the Zabbix sender (trapper) protocol
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use log::{debug, error, warn};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::broadcast;
+
+use crate::item::ItemResult;
+use crate::output::breaker::Breaker;
+use crate::output::{AKOutput, PROBE};
+
+const HEADER: &[u8; 5] = b"ZBXD\x01";
+/// Responses of the server are small, anything bigger is not a Zabbix server
+const MAX_RESPONSE_LENGTH: u64 = 64 * 1024;
+/// Seconds to wait for connecting, and for the server's response
+const TIMEOUT: u64 = 10;
+
+#[derive(Clone)]
+pub struct ZabbixOutput {
+    address: String,
+    host: String,
+    use_raw_as_fallback: bool,
+    always_write_raw: bool,
+    breaker: Breaker,
+}
+
+#[derive(Serialize)]
+struct Value<'a> {
+    host: &'a str,
+    key: &'a str,
+    value: String,
+    clock: u64,
+    ns: u32,
+}
+
+#[derive(Serialize)]
+struct SenderData<'a> {
+    request: &'static str,
+    data: Vec<Value<'a>>,
+}
+
+#[derive(Deserialize)]
+struct ServerResponse {
+    response: String,
+    #[serde(default)]
+    info: String,
+}
+
+/// Wrap a JSON payload into a Zabbix protocol packet
+fn packet(payload: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(HEADER.len() + 8 + payload.len());
+    packet.extend_from_slice(HEADER);
+    packet.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+    packet.extend_from_slice(payload);
+    packet
+}
+
+/// The payload of a Zabbix protocol packet
+async fn read_packet(stream: &mut (impl AsyncRead + Unpin)) -> Result<Vec<u8>> {
+    let mut header = [0u8; 13];
+    stream.read_exact(&mut header).await?;
+    if &header[..5] != HEADER {
+        bail!("Invalid header");
+    }
+    let length = u64::from_le_bytes(header[5..].try_into()?);
+    if length > MAX_RESPONSE_LENGTH {
+        bail!("Packet of {} bytes is too long", length);
+    }
+    let mut payload = vec![0u8; length as usize];
+    stream.read_exact(&mut payload).await?;
+    Ok(payload)
+}
+
+impl ZabbixOutput {
+    pub fn new(
+        server: String,
+        port: u16,
+        host: String,
+        use_raw_as_fallback: bool,
+        always_write_raw: bool,
+        breaker: Breaker,
+    ) -> Self {
+        Self {
+            address: format!("{}:{}", server, port),
+            host,
+            use_raw_as_fallback,
+            always_write_raw,
+            breaker,
+        }
+    }
+
+    /// The server's info on the values it processed
+    async fn send(&self, data: Vec<Value<'_>>) -> Result<String> {
+        let payload = serde_json::to_vec(&SenderData {
+            request: "sender data",
+            data,
+        })?;
+        let timeout = Duration::from_secs(TIMEOUT);
+        let mut stream = tokio::time::timeout(timeout, TcpStream::connect(&self.address))
+            .await
+            .context("Timed out connecting to the Zabbix server")??;
+        stream.write_all(&packet(&payload)).await?;
+
+        let body = tokio::time::timeout(timeout, read_packet(&mut stream))
+            .await
+            .context("Timed out waiting for the Zabbix server's response")?
+            .with_context(|| format!("Invalid response from {}", self.address))?;
+        let response: ServerResponse = serde_json::from_slice(&body)?;
+        if response.response != "success" {
+            bail!("Zabbix server responded with {}", response.response);
+        }
+        // "processed: 1; failed: 0; total: 1; seconds spent: 0.000055"
+        if !response.info.contains("failed: 0;") {
+            warn!(
+                "ZabbixOutput: Not all values were accepted, are the items configured as trapper items? {}",
+                response.info
+            );
+        }
+        Ok(response.info)
+    }
+
+    /// Without a trapper item `antikoerper.check` on the host the server tells it failed
+    /// processing the probe, after accepting the connection
+    pub async fn check(&self) -> Result<String> {
+        let time = crate::clock::now();
+        let info = self
+            .send(vec![Value {
+                host: &self.host,
+                key: PROBE,
+                value: String::from("1"),
+                clock: time.as_secs(),
+                ns: time.subsec_nanos(),
+            }])
+            .await?;
+        Ok(format!(
+            "sent a probe value {} for host {}: {}",
+            PROBE, self.host, info
+        ))
+    }
+
+    fn values<'a>(&'a self, itemresult: &'a ItemResult, raw_key: &'a str) -> Vec<Value<'a>> {
+        let clock = itemresult.time.as_secs();
+        let ns = itemresult.time.subsec_nanos();
+        let mut data = itemresult
+            .values
+            .iter()
+            .map(|(key, value)| Value {
+                host: &self.host,
+                key,
+                value: value.to_string(),
+                clock,
+                ns,
+            })
+            .collect::<Vec<_>>();
+        if itemresult.values.is_empty() && self.use_raw_as_fallback || self.always_write_raw {
+            data.push(Value {
+                host: &self.host,
+                key: raw_key,
+                value: itemresult.raw.clone(),
+                clock,
+                ns,
+            });
+        }
+        data
+    }
+}
+
+#[async_trait]
+impl AKOutput for ZabbixOutput {
+    fn prepare(&self) -> Result<()> {
+        Ok(())
+    }
+    async fn start(self, mut receiver: broadcast::Receiver<Arc<ItemResult>>) {
+        debug!("ZabbixOutput: Starting loop");
+        loop {
+            match receiver.recv().await {
+                Err(recverr) => match recverr {
+                    broadcast::error::RecvError::Closed => break,
+                    broadcast::error::RecvError::Lagged(count) => {
+                        warn!("ZabbixOutput is lagging behind, {} results skipped", count)
+                    }
+                },
+                Ok(itemresult) => {
+                    debug!("ZabbixOutput: Received result for item {}", itemresult.key);
+                    let raw_key = format!("{}.raw", itemresult.key);
+                    let data = self.values(&itemresult, &raw_key);
+                    if data.is_empty() {
+                        continue;
+                    }
+                    if let Some(Err(e)) = self.breaker.call(self.send(data)).await {
+                        error!(
+                            "ZabbixOutput: Failed sending data for Item {}",
+                            itemresult.key
+                        );
+                        error!("ZabbixOutput: {}", e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use crate::output::breaker::Breaker;
+    use crate::output::zabbix::{packet, read_packet, Value, ZabbixOutput, HEADER};
+
+    #[tokio::test]
+    async fn framing() {
+        let payload = br#"{"request":"sender data","data":[]}"#;
+        let framed = packet(payload);
+        assert_eq!(&framed[..5], HEADER);
+        assert_eq!(framed[5..13], (payload.len() as u64).to_le_bytes());
+        assert_eq!(read_packet(&mut &framed[..]).await.unwrap(), payload);
+
+        let mut invalid = framed.clone();
+        invalid[0] = b'X';
+        assert!(read_packet(&mut &invalid[..]).await.is_err());
+        let mut too_long = framed.clone();
+        too_long[5..13].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(read_packet(&mut &too_long[..]).await.is_err());
+        assert!(read_packet(&mut &framed[..framed.len() - 1]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn response() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let request = read_packet(&mut stream).await.unwrap();
+            stream
+                .write_all(&packet(
+                    br#"{"response":"success","info":"processed: 1; failed: 0; total: 1"}"#,
+                ))
+                .await
+                .unwrap();
+            // The server doesn't close the connection before the client
+            let _ = stream.read(&mut [0; 1]).await;
+            request
+        });
+        let output = ZabbixOutput::new(
+            address.ip().to_string(),
+            address.port(),
+            String::from("host"),
+            false,
+            false,
+            Breaker::new(String::from("zabbix"), None),
+        );
+        let info = output
+            .send(vec![Value {
+                host: "host",
+                key: "load.one",
+                value: String::from("0.5"),
+                clock: 1,
+                ns: 2,
+            }])
+            .await
+            .unwrap();
+        assert_eq!(info, "processed: 1; failed: 0; total: 1");
+        let request: serde_json::Value = serde_json::from_slice(&server.await.unwrap()).unwrap();
+        assert_eq!(
+            request,
+            serde_json::json!({
+                "request": "sender data",
+                "data": [{"host": "host", "key": "load.one", "value": "0.5", "clock": 1, "ns": 2}],
+            })
+        );
+    }
+}
@@ -0,0 +1,141 @@
+//! CPU and I/O priority of the daemon and of the commands items run, so heavy collection
+//! does not compete with interactive work
+
+use anyhow::{bail, Context, Result};
+use log::debug;
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Priority {
+    /// From -20 (the highest priority) to 19, lower than the current one needs privileges
+    nice: Option<i32>,
+    ionice: Option<IoNice>,
+}
+
+/// Scheduling class of I/O, with a level from 0 (the highest priority) to 7
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum IoNice {
+    /// Needs privileges
+    Realtime(u8),
+    BestEffort(u8),
+    /// Only when no other process uses the disk
+    Idle,
+}
+
+impl TryFrom<String> for IoNice {
+    type Error = anyhow::Error;
+
+    /// `idle`, `best-effort` or `realtime`, optionally with the level, e.g. `best-effort:7`
+    fn try_from(ionice: String) -> Result<Self> {
+        let (class, level) = match ionice.split_once(':') {
+            Some((class, level)) => (
+                class,
+                level
+                    .parse()
+                    .ok()
+                    .filter(|level| *level <= 7)
+                    .with_context(|| format!("Invalid level in ionice {}, 0 to 7", ionice))?,
+            ),
+            // The kernel's default
+            None => (ionice.as_str(), 4),
+        };
+        Ok(match class {
+            "realtime" => IoNice::Realtime(level),
+            "best-effort" => IoNice::BestEffort(level),
+            "idle" => IoNice::Idle,
+            class => bail!(
+                "Unknown ionice class {}, expected realtime, best-effort or idle",
+                class
+            ),
+        })
+    }
+}
+
+const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+
+impl IoNice {
+    fn value(self) -> libc::c_int {
+        let (class, level) = match self {
+            IoNice::Realtime(level) => (1, level),
+            IoNice::BestEffort(level) => (2, level),
+            IoNice::Idle => (3, 0),
+        };
+        class << IOPRIO_CLASS_SHIFT | libc::c_int::from(level)
+    }
+}
+
+impl Priority {
+    /// Of a thread, 0 for the calling one. Only async-signal-safe calls, as this also
+    /// runs in forked children.
+    fn apply_to(&self, thread: libc::id_t) -> std::io::Result<()> {
+        if let Some(nice) = self.nice {
+            // SAFETY: no pointers involved
+            if unsafe { libc::setpriority(libc::PRIO_PROCESS, thread, nice) } != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+        if let Some(ionice) = self.ionice {
+            // SAFETY: no pointers involved
+            let ret = unsafe {
+                libc::syscall(
+                    libc::SYS_ioprio_set,
+                    IOPRIO_WHO_PROCESS,
+                    thread,
+                    ionice.value(),
+                )
+            };
+            if ret != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    /// Of every thread of the daemon, as Linux keeps priorities per thread. Threads
+    /// started later inherit them.
+    pub fn apply(&self) -> Result<()> {
+        if *self == Priority::default() {
+            return Ok(());
+        }
+        let threads = std::fs::read_dir("/proc/self/task").context("Failed listing threads")?;
+        for thread in threads {
+            let thread = thread?.file_name();
+            if let Some(id) = thread.to_str().and_then(|id| id.parse().ok()) {
+                self.apply_to(id)
+                    .with_context(|| format!("Failed setting the priority to {:?}", self))?;
+            }
+        }
+        debug!("Priority set to {:?}", self);
+        Ok(())
+    }
+
+    /// For a command to be started, starting it fails if the priority cannot be set
+    pub fn command(self, command: &mut tokio::process::Command) {
+        if self == Priority::default() {
+            return;
+        }
+        // SAFETY: `apply_to` only makes async-signal-safe calls
+        unsafe {
+            command.pre_exec(move || self.apply_to(0));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::priority::IoNice;
+
+    #[test]
+    fn ionice() {
+        let parse = |s: &str| IoNice::try_from(s.to_owned());
+        assert_eq!(parse("idle").unwrap(), IoNice::Idle);
+        assert_eq!(parse("best-effort").unwrap(), IoNice::BestEffort(4));
+        assert_eq!(parse("realtime:0").unwrap(), IoNice::Realtime(0));
+        assert_eq!(parse("best-effort:7").unwrap().value(), 2 << 13 | 7);
+        assert!(parse("best-effort:8").is_err());
+        assert!(parse("low").is_err());
+    }
+}
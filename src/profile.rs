@@ -0,0 +1,113 @@
+//! Built-in sets of items for common kinds of hosts, so `profile = "laptop"` is all a
+//! useful first configuration needs
+
+use anyhow::{bail, Context, Result};
+use itertools::Itertools;
+
+use crate::conf::merge;
+
+const PROFILES: [(&str, &str); 3] = [
+    ("laptop", include_str!("profiles/laptop.toml")),
+    ("server", include_str!("profiles/server.toml")),
+    ("raspberry-pi", include_str!("profiles/raspberry-pi.toml")),
+];
+
+fn key(item: &toml::Value) -> Option<&str> {
+    item.get("key").and_then(|key| key.as_str())
+}
+
+fn items(config: &mut toml::Value) -> Vec<toml::Value> {
+    match config
+        .as_table_mut()
+        .and_then(|table| table.remove("items"))
+    {
+        Some(toml::Value::Array(items)) => items,
+        _ => Vec::new(),
+    }
+}
+
+/// Add the items of the profiles the configuration selects. Its own items with the key
+/// of an item of a profile change the fields they give, e.g. the interval.
+pub fn expand(config: &mut toml::Value) -> Result<()> {
+    let names = match config.get("profile") {
+        None => return Ok(()),
+        Some(toml::Value::String(name)) => vec![name.clone()],
+        Some(toml::Value::Array(names)) => names
+            .iter()
+            .map(|name| name.as_str().map(String::from))
+            .collect::<Option<_>>()
+            .context("profile must be a name or a list of names")?,
+        Some(_) => bail!("profile must be a name or a list of names"),
+    };
+    let mut expanded: Vec<toml::Value> = Vec::new();
+    for name in names {
+        let Some((_, profile)) = PROFILES.iter().find(|(n, _)| *n == name) else {
+            bail!(
+                "Unknown profile {}, expected one of {}",
+                name,
+                PROFILES.iter().map(|(n, _)| n).join(", ")
+            );
+        };
+        let mut profile: toml::Value =
+            toml::from_str(profile).expect("built-in profiles are valid TOML");
+        // Items several profiles have are only added once
+        for item in items(&mut profile) {
+            if !expanded.iter().any(|e| key(e) == key(&item)) {
+                expanded.push(item);
+            }
+        }
+    }
+    for item in items(config) {
+        match expanded.iter_mut().find(|e| key(e) == key(&item)) {
+            Some(existing) => merge(existing, item),
+            None => expanded.push(item),
+        }
+    }
+    if let Some(table) = config.as_table_mut() {
+        table.insert(String::from("items"), toml::Value::Array(expanded));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::conf;
+    use crate::item::ItemKind;
+    use crate::profile::PROFILES;
+
+    #[test]
+    fn profiles() {
+        for (name, _) in PROFILES {
+            let data = format!("profile = {:?}\n[general]\n", name);
+            let config = conf::load(&mut data.as_bytes()).unwrap();
+            assert!(!config.items.is_empty(), "{}", name);
+        }
+
+        let data = r#"
+            profile = ["server", "raspberry-pi"]
+            [general]
+            [[items]]
+            key = "os.system"
+            interval = 120
+            [[items]]
+            key = "uptime"
+            interval = 60
+            input = { type = "file", path = "/proc/uptime" }
+        "#;
+        let config = conf::load(&mut data.as_bytes()).unwrap();
+        let system = config.items.iter().find(|i| i.key == "os.system").unwrap();
+        assert_eq!(system.interval, 120);
+        assert!(matches!(system.kind, ItemKind::System { .. }));
+        assert_eq!(
+            config
+                .items
+                .iter()
+                .filter(|i| i.key == "os.thermal")
+                .count(),
+            1
+        );
+        assert_eq!(config.items.last().unwrap().key, "uptime");
+
+        assert!(conf::load(&mut "profile = \"desktop\"\n[general]\n".as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,216 @@
+//! Credentials from the configuration, zeroed when dropped and hidden from debug output,
+//! decryption of the secrets the configuration refers to, and redaction of other values
+//! that must not be logged
+
+use std::fmt;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Deserializer};
+use zeroize::Zeroizing;
+
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Secret(Zeroizing<String>);
+
+impl Secret {
+    pub fn new(secret: String) -> Self {
+        Self(Zeroizing::new(secret))
+    }
+
+    /// The credential itself, only to be handed to the backend
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("\"***\"")
+    }
+}
+
+impl<'de> Deserialize<'de> for Secret {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Secret::new)
+    }
+}
+
+/// A TOML table of secrets encrypted with age, referred to as `{ secret = "<name>" }`
+/// anywhere in the configuration
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Secrets {
+    /// Armored or binary
+    file: PathBuf,
+    /// The age identity file with the key decrypting `file`
+    identity: Option<PathBuf>,
+    /// Without `identity`, the systemd credential holding it, see `LoadCredential=`
+    #[serde(default = "credential_default")]
+    credential: String,
+}
+
+fn credential_default() -> String {
+    String::from("age-identity")
+}
+
+impl Secrets {
+    fn identity(&self) -> Result<PathBuf> {
+        if let Some(identity) = &self.identity {
+            return Ok(identity.clone());
+        }
+        match std::env::var_os("CREDENTIALS_DIRECTORY") {
+            Some(dir) => Ok(Path::new(&dir).join(&self.credential)),
+            None => {
+                bail!("No identity to decrypt the secrets, and not run with systemd credentials")
+            }
+        }
+    }
+
+    /// The decrypted table
+    fn decrypt(&self) -> Result<toml::value::Table> {
+        let path = self.identity()?;
+        let identities = std::fs::File::open(&path)
+            .map(std::io::BufReader::new)
+            .and_then(age::IdentityFile::from_buffer)
+            .with_context(|| format!("Failed reading the identity {}", path.display()))?
+            .into_identities()
+            .with_context(|| format!("No usable identity in {}", path.display()))?;
+        let file = std::fs::File::open(&self.file)
+            .with_context(|| format!("Failed opening {}", self.file.display()))?;
+        let mut content = Zeroizing::new(String::new());
+        age::Decryptor::new(age::armor::ArmoredReader::new(file))
+            .and_then(|decryptor| {
+                decryptor.decrypt(identities.iter().map(|identity| identity.as_ref()))
+            })
+            .map_err(anyhow::Error::from)
+            .and_then(|mut reader| Ok(reader.read_to_string(&mut content)?))
+            .with_context(|| format!("Failed decrypting {}", self.file.display()))?;
+        toml::from_str(&content)
+            .with_context(|| format!("{} is no TOML table", self.file.display()))
+    }
+}
+
+/// Replace the references to secrets in the configuration with the decrypted values
+pub fn resolve(config: &mut toml::Value) -> Result<()> {
+    let secrets = match config.get("secrets") {
+        Some(secrets) => Some(
+            Secrets::deserialize(secrets.clone())
+                .context("Invalid section secrets")?
+                .decrypt()?,
+        ),
+        None => None,
+    };
+    replace(config, secrets.as_ref())
+}
+
+fn replace(value: &mut toml::Value, secrets: Option<&toml::value::Table>) -> Result<()> {
+    match value {
+        toml::Value::Table(table) => {
+            if let (1, Some(toml::Value::String(name))) = (table.len(), table.get("secret")) {
+                let Some(secrets) = secrets else {
+                    bail!(
+                        "The secret {} is referred to without a section secrets",
+                        name
+                    );
+                };
+                match secrets.get(name) {
+                    Some(secret) => *value = secret.clone(),
+                    None => bail!("No secret {} in the secrets", name),
+                }
+                return Ok(());
+            }
+            for (_, value) in table.iter_mut() {
+                replace(value, secrets)?;
+            }
+        }
+        toml::Value::Array(values) => {
+            for value in values {
+                replace(value, secrets)?;
+            }
+        }
+        _ => (),
+    }
+    Ok(())
+}
+
+/// A value in log messages, replaced by `<redacted>` if `hidden`, e.g. the output of
+/// sensitive items
+pub struct Redacted<'a, T: ?Sized> {
+    value: &'a T,
+    hidden: bool,
+}
+
+impl<'a, T: ?Sized> Redacted<'a, T> {
+    pub fn new(value: &'a T, hidden: bool) -> Self {
+        Self { value, hidden }
+    }
+}
+
+impl<T: fmt::Display + ?Sized> fmt::Display for Redacted<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.hidden {
+            f.write_str("<redacted>")
+        } else {
+            self.value.fmt(f)
+        }
+    }
+}
+
+impl<T: fmt::Debug + ?Sized> fmt::Debug for Redacted<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.hidden {
+            f.write_str("<redacted>")
+        } else {
+            self.value.fmt(f)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use age::secrecy::ExposeSecret;
+
+    use crate::secret::{resolve, Redacted, Secret};
+
+    #[test]
+    fn hidden() {
+        let secret = Secret::new(String::from("hunter2"));
+        assert_eq!(format!("{:?}", Some(&secret)), "Some(\"***\")");
+        assert_eq!(secret.expose(), "hunter2");
+        assert_eq!(format!("{}", Redacted::new("hunter2", true)), "<redacted>");
+        assert_eq!(format!("{:?}", Redacted::new("out", false)), "\"out\"");
+    }
+
+    #[test]
+    fn encrypted() {
+        let dir = std::env::temp_dir().join(format!("antikoerper-secrets-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let identity = age::x25519::Identity::generate();
+        std::fs::write(dir.join("key.txt"), identity.to_string().expose_secret()).unwrap();
+        let encrypted =
+            age::encrypt_and_armor(&identity.to_public(), b"influx = \"hunter2\"\n").unwrap();
+        std::fs::write(dir.join("secrets.toml.age"), encrypted).unwrap();
+
+        let mut config: toml::Value = toml::from_str(&format!(
+            r#"
+            secrets = {{ file = "{0}/secrets.toml.age", identity = "{0}/key.txt" }}
+            [[output]]
+            type = "influxdb"
+            password = {{ secret = "influx" }}
+            "#,
+            dir.display()
+        ))
+        .unwrap();
+        resolve(&mut config).unwrap();
+        assert_eq!(config["output"][0]["password"].as_str(), Some("hunter2"));
+
+        let reference = |name: &str| toml::from_str(&format!("secret = \"{}\"", name)).unwrap();
+        config["output"][0]["password"] = reference("other");
+        assert!(resolve(&mut config.clone()).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+        // The files are gone
+        config["output"][0]["password"] = reference("influx");
+        assert!(resolve(&mut config).is_err());
+    }
+}
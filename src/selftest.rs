@@ -0,0 +1,142 @@
+//! `antikoerper selftest`, running every item once and digesting its output to verify a
+//! configuration before rolling it out, without writing to the outputs
+
+use std::fmt::Write;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use tokio::task::JoinSet;
+
+use crate::conf::{Config, General};
+use crate::item::{Item, ItemResult};
+
+enum Outcome {
+    Ok(usize),
+    /// Results without values, or values that are not numbers
+    Warning(String),
+    Failed(String),
+    /// Pushed inputs have nothing to run
+    Skipped,
+}
+
+impl Outcome {
+    fn of(results: &[ItemResult]) -> Self {
+        let values = results
+            .iter()
+            .flat_map(|result| result.values.iter())
+            .collect::<Vec<_>>();
+        let mut nan = values
+            .iter()
+            .filter(|(_, value)| value.is_nan())
+            .map(|(key, _)| key.to_string())
+            .collect::<Vec<_>>();
+        nan.sort();
+        if values.is_empty() {
+            Self::Warning(String::from("no values"))
+        } else if !nan.is_empty() {
+            Self::Warning(format!("not a number: {}", nan.join(", ")))
+        } else {
+            Self::Ok(values.len())
+        }
+    }
+
+    fn status(&self) -> &'static str {
+        match self {
+            Self::Ok(_) => "ok",
+            Self::Warning(_) => "warning",
+            Self::Failed(_) => "failed",
+            Self::Skipped => "skipped",
+        }
+    }
+
+    fn detail(&self) -> String {
+        match self {
+            Self::Ok(1) => String::from("1 value"),
+            Self::Ok(values) => format!("{} values", values),
+            Self::Warning(detail) | Self::Failed(detail) => detail.clone(),
+            Self::Skipped => String::from("pushed, nothing to run"),
+        }
+    }
+}
+
+async fn check(item: Item, general: General, timeout: Duration) -> Outcome {
+    if item.kind.is_pushed() {
+        return Outcome::Skipped;
+    }
+    match tokio::time::timeout(timeout, item.run_once(&general)).await {
+        Ok(Ok(results)) => Outcome::of(&results),
+        Ok(Err(e)) => Outcome::Failed(format!("{:#}", e)),
+        Err(_) => Outcome::Failed(format!("timed out after {:?}", timeout)),
+    }
+}
+
+/// A table of the items and how their run went, or an error with it if any failed
+pub async fn run(config: Config, timeout: Duration) -> Result<String> {
+    let mut tasks = JoinSet::new();
+    for (index, item) in config.items.iter().enumerate() {
+        let run = check(item.clone(), config.general.clone(), timeout);
+        tasks.spawn(async move { (index, run.await) });
+    }
+    let mut outcomes = tasks.join_all().await;
+    outcomes.sort_by_key(|(index, _)| *index);
+    let width = config
+        .items
+        .iter()
+        .map(|item| item.key.len())
+        .chain([4])
+        .max()
+        .unwrap_or_default();
+    let mut out = format!("{:<width$}  {:<7}  DETAIL\n", "ITEM", "STATUS");
+    for (index, outcome) in &outcomes {
+        writeln!(
+            out,
+            "{:<width$}  {:<7}  {}",
+            config.items[*index].key,
+            outcome.status(),
+            outcome.detail()
+        )?;
+    }
+    if outcomes
+        .iter()
+        .any(|(_, outcome)| matches!(outcome, Outcome::Failed(_)))
+    {
+        bail!("{}", out.trim_end());
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+    use std::time::Duration;
+
+    use crate::item::ItemResult;
+    use crate::keys::Key;
+    use crate::selftest::Outcome;
+
+    fn result(values: &[(&str, f64)]) -> ItemResult {
+        ItemResult {
+            time: Duration::ZERO,
+            key: "disk".into(),
+            raw: String::new(),
+            values: values
+                .iter()
+                .map(|(key, value)| (Key::from(*key), *value))
+                .collect::<HashMap<_, _>>(),
+            tags: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn outcomes() {
+        let ok = Outcome::of(&[result(&[("disk.used", 1.0), ("disk.free", 2.0)])]);
+        assert_eq!((ok.status(), ok.detail().as_str()), ("ok", "2 values"));
+        let empty = Outcome::of(&[result(&[])]);
+        assert_eq!(empty.detail(), "no values");
+        let nan = Outcome::of(&[result(&[("disk.used", f64::NAN), ("disk.free", 2.0)])]);
+        assert_eq!(
+            (nan.status(), nan.detail().as_str()),
+            ("warning", "not a number: disk.used")
+        );
+    }
+}
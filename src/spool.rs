@@ -0,0 +1,312 @@
+//! `antikoerper spool`, inspecting and managing the results outputs buffered because they
+//! could not write them, e.g. after an extended outage of a backend
+
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
+
+use crate::app::App;
+use crate::conf::Config;
+use crate::control;
+use crate::output::buffer::{contents, Contents};
+
+pub enum Command {
+    List,
+    /// Of one output or all outputs
+    Replay(Option<String>),
+    /// Of one output or all outputs, also buffers no output uses any more by file name
+    Purge(Option<String>),
+}
+
+/// A buffer, of an output or left behind by one removed from the configuration
+struct Spool {
+    name: String,
+    path: PathBuf,
+    configured: bool,
+}
+
+fn spools(app: &App, state_dir: &Path) -> Vec<Spool> {
+    let mut spools = app
+        .outputs()
+        .iter()
+        .filter_map(|output| {
+            output.spool().map(|buffer| Spool {
+                name: output.name.clone(),
+                path: buffer.path().to_owned(),
+                configured: true,
+            })
+        })
+        .collect::<Vec<_>>();
+    let entries = std::fs::read_dir(state_dir.join("buffer"))
+        .into_iter()
+        .flatten()
+        .flatten();
+    for entry in entries {
+        if !spools.iter().any(|spool| spool.path == entry.path()) {
+            spools.push(Spool {
+                name: entry.file_name().to_string_lossy().into_owned(),
+                path: entry.path(),
+                configured: false,
+            });
+        }
+    }
+    spools
+}
+
+fn time(time: Option<Duration>) -> String {
+    time.and_then(|time| DateTime::<Utc>::from_timestamp_millis(time.as_millis() as i64))
+        .map(|time| time.format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_else(|| String::from("unknown"))
+}
+
+fn describe(contents: &Contents) -> String {
+    if contents.results == 0 {
+        return String::from("empty");
+    }
+    format!(
+        "{} results, {} bytes, from {} to {}",
+        contents.results,
+        contents.bytes,
+        time(contents.oldest),
+        time(contents.newest)
+    )
+}
+
+fn selected<'a>(spools: &'a [Spool], name: Option<&str>) -> Result<Vec<&'a Spool>> {
+    let selected = spools
+        .iter()
+        .filter(|spool| name.is_none_or(|name| spool.name == name))
+        .collect::<Vec<_>>();
+    if let (Some(name), true) = (name, selected.is_empty()) {
+        bail!("No output {} with a buffer", name);
+    }
+    Ok(selected)
+}
+
+/// What to print, or an error once everything possible was done
+pub async fn run(config: Config, command: Command) -> Result<String> {
+    let state_dir = config.general.state_dir.clone();
+    let socket = config.general.control_socket.clone();
+    let app = App::try_from(config)?;
+    let spools = spools(&app, &state_dir);
+    let mut out = String::new();
+    match command {
+        Command::List => {
+            if spools.is_empty() {
+                out.push_str("No outputs with a buffer\n");
+            }
+            for spool in &spools {
+                writeln!(
+                    out,
+                    "{}: {}{}",
+                    spool.name,
+                    describe(&contents(&spool.path)?),
+                    if spool.configured {
+                        ""
+                    } else {
+                        ", of no configured output"
+                    }
+                )?;
+            }
+        }
+        Command::Replay(name) => {
+            // Both writing the same buffer would lose or duplicate results
+            if control::request(&socket, control::Request::Status, None)
+                .await
+                .is_ok()
+            {
+                bail!(
+                    "The daemon is running and replays its buffers itself, stop it to replay them now"
+                );
+            }
+            let mut failed = false;
+            for spool in selected(&spools, name.as_deref())? {
+                let Some(output) = app.outputs().iter().find(|o| o.name == spool.name) else {
+                    writeln!(out, "{}: skipped, of no configured output", spool.name)?;
+                    continue;
+                };
+                let before = contents(&spool.path)?.results;
+                let replayed = output.replay_spool().await;
+                let left = contents(&spool.path)?.results;
+                match replayed {
+                    Ok(()) => writeln!(out, "{}: wrote {} results", spool.name, before - left)?,
+                    Err(e) => {
+                        failed = true;
+                        writeln!(
+                            out,
+                            "{}: wrote {} results, {} left: {:#}",
+                            spool.name,
+                            before - left,
+                            left,
+                            e
+                        )?
+                    }
+                }
+            }
+            if failed {
+                bail!("{}", out.trim_end());
+            }
+        }
+        Command::Purge(name) => {
+            for spool in selected(&spools, name.as_deref())? {
+                let results = contents(&spool.path)?.results;
+                match std::fs::remove_file(&spool.path) {
+                    Ok(()) => writeln!(out, "{}: dropped {} results", spool.name, results)?,
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                        writeln!(out, "{}: empty", spool.name)?
+                    }
+                    Err(e) => bail!("Failed removing {}: {}", spool.path.display(), e),
+                }
+            }
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+    use std::path::Path;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::UnixListener;
+
+    use crate::conf::{BufferConfig, Config, SpoolFormat};
+    use crate::item::ItemResult;
+    use crate::output::buffer::Buffer;
+    use crate::spool::{run, Command};
+    use crate::testing::ConfigBuilder;
+
+    const BUFFER: BufferConfig = BufferConfig {
+        max_size: 1024,
+        retry_interval: 0,
+        format: SpoolFormat::Json,
+        zstd: false,
+    };
+
+    fn builder() -> ConfigBuilder {
+        ConfigBuilder::new().item(
+            r#"
+            key = "load"
+            interval = 60
+            input = { type = "shell", script = "true" }
+            "#,
+        )
+    }
+
+    /// An InfluxDB 3 output with a buffer, its server refusing connections
+    fn config() -> Config {
+        builder()
+            .output(
+                toml::from_str(
+                    r#"
+                    type = "influxdb3"
+                    url = "http://127.0.0.1:1"
+                    buffer = { retry_interval = 0 }
+                    "#,
+                )
+                .unwrap(),
+            )
+            .build()
+            .unwrap()
+    }
+
+    /// Buffers `count` results as the output with the buffer `name` would
+    async fn buffer(state_dir: &Path, name: &str, count: u64) {
+        let buffer = Buffer::new(&BUFFER, state_dir, name);
+        for time in 0..count {
+            buffer
+                .push(&[Arc::new(ItemResult {
+                    time: Duration::from_secs(time),
+                    key: "load".into(),
+                    raw: String::new(),
+                    values: HashMap::from([("load.one".into(), 0.5)]),
+                    tags: BTreeMap::new(),
+                })])
+                .await;
+        }
+    }
+
+    #[tokio::test]
+    async fn list_and_purge() {
+        let empty = builder().build().unwrap();
+        assert_eq!(
+            run(empty, Command::List).await.unwrap(),
+            "No outputs with a buffer\n"
+        );
+
+        let state_dir = config().general.state_dir;
+        buffer(&state_dir, "influxdb3", 2).await;
+        buffer(&state_dir, "old", 1).await;
+        let with_state_dir = || {
+            let mut config = config();
+            config.general.state_dir = state_dir.clone();
+            config
+        };
+        assert_eq!(
+            run(with_state_dir(), Command::List).await.unwrap(),
+            "influxdb3: 2 results, 141 bytes, from 1970-01-01 00:00:00 to 1970-01-01 00:00:01\n\
+             old: 1 results, 69 bytes, from 1970-01-01 00:00:00 to 1970-01-01 00:00:00, \
+             of no configured output\n"
+        );
+        assert_eq!(
+            run(with_state_dir(), Command::Purge(Some(String::from("none"))))
+                .await
+                .unwrap_err()
+                .to_string(),
+            "No output none with a buffer"
+        );
+        assert_eq!(
+            run(with_state_dir(), Command::Purge(None)).await.unwrap(),
+            "influxdb3: dropped 2 results\nold: dropped 1 results\n"
+        );
+        assert_eq!(
+            run(with_state_dir(), Command::List).await.unwrap(),
+            "influxdb3: empty\n"
+        );
+        std::fs::remove_dir_all(state_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn replay() {
+        let config = config();
+        let state_dir = config.general.state_dir.clone();
+        buffer(&state_dir, "influxdb3", 2).await;
+        buffer(&state_dir, "old", 1).await;
+        let failed = run(config, Command::Replay(None)).await.unwrap_err();
+        assert!(failed
+            .to_string()
+            .starts_with("influxdb3: wrote 0 results, 2 left: "));
+        assert!(failed
+            .to_string()
+            .ends_with("\nold: skipped, of no configured output"));
+
+        let mut config = self::config();
+        config.general.state_dir = state_dir.clone();
+        let socket = config.general.control_socket.clone();
+        std::fs::create_dir_all(socket.parent().unwrap()).unwrap();
+        let listener = UnixListener::bind(&socket).unwrap();
+        let daemon = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (read, mut write) = stream.into_split();
+            BufReader::new(read).lines().next_line().await.unwrap();
+            write
+                .write_all(b"{\"ok\":true,\"message\":\"running\"}\n")
+                .await
+                .unwrap();
+        });
+        assert!(run(config, Command::Replay(None))
+            .await
+            .unwrap_err()
+            .to_string()
+            .starts_with("The daemon is running"));
+        daemon.await.unwrap();
+        std::fs::remove_dir_all(socket.parent().unwrap()).unwrap();
+        std::fs::remove_dir_all(state_dir).unwrap();
+    }
+}
@@ -0,0 +1,68 @@
+//! What the items did so far, kept for the state dump on SIGUSR1
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+#[derive(Debug, Default)]
+struct Run {
+    runs: u64,
+    failures: u64,
+    last: Option<DateTime<Utc>>,
+    /// Not known for inputs pushing their output
+    next: Option<DateTime<Utc>>,
+    last_error: Option<(DateTime<Utc>, String)>,
+}
+
+/// Shared by all item tasks
+#[derive(Debug, Clone, Default)]
+pub struct Runs(Arc<Mutex<BTreeMap<String, Run>>>);
+
+impl Runs {
+    /// A run of the item finished, or a pushed result arrived; `next` is when the next
+    /// run is due
+    pub fn finished(&self, key: &str, error: Option<String>, next: Option<Duration>) {
+        let now = Utc::now();
+        let mut runs = self.0.lock().expect("runs lock poisoned");
+        let run = runs.entry(key.to_owned()).or_default();
+        run.runs += 1;
+        run.last = Some(now);
+        run.next = next.and_then(|next| chrono::Duration::from_std(next).ok().map(|d| now + d));
+        if let Some(error) = error {
+            run.failures += 1;
+            run.last_error = Some((now, error));
+        }
+    }
+
+    /// One line per item, in the order of `keys`; items that did not run yet are
+    /// listed as well
+    pub fn describe(&self, keys: &[String]) -> Vec<String> {
+        let runs = self.0.lock().expect("runs lock poisoned");
+        let time = |time: DateTime<Utc>| time.format("%Y-%m-%d %H:%M:%S").to_string();
+        keys.iter()
+            .map(|key| {
+                let Some(run) = runs.get(key) else {
+                    return format!("item {}: no runs yet", key);
+                };
+                let mut line = format!("item {}: {} runs", key, run.runs);
+                if let Some(last) = run.last {
+                    line.push_str(&format!(", last {}", time(last)));
+                }
+                if let Some(next) = run.next {
+                    line.push_str(&format!(", next {}", time(next)));
+                }
+                if let Some((at, error)) = &run.last_error {
+                    line.push_str(&format!(
+                        ", {} failed, last at {}: {}",
+                        run.failures,
+                        time(*at),
+                        error
+                    ));
+                }
+                line
+            })
+            .collect()
+    }
+}
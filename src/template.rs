@@ -0,0 +1,162 @@
+//! Templates for outputs producing human-readable content
+
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use handlebars::Handlebars;
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::alert::Status;
+use crate::item::ItemResult;
+
+const NAME: &str = "template";
+
+/// A handlebars template, compiled when the configuration is loaded
+#[derive(Clone)]
+pub struct Template {
+    registry: Handlebars<'static>,
+    source: String,
+}
+
+impl std::fmt::Debug for Template {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Template").field(&self.source).finish()
+    }
+}
+
+impl Template {
+    pub fn new(source: &str) -> Result<Self> {
+        let mut registry = Handlebars::new();
+        // Most templates produce JSON or plain text, HTML escaping would only get in the way
+        registry.register_escape_fn(handlebars::no_escape);
+        registry.register_helper("json", Box::new(json_helper));
+        registry.register_template_string(NAME, source)?;
+        Ok(Self {
+            registry,
+            source: source.to_owned(),
+        })
+    }
+
+    /// Render with a TemplateContext for results, or an Event
+    pub fn render<T: Serialize>(&self, context: &T) -> Result<String> {
+        self.registry
+            .render(NAME, context)
+            .map_err(anyhow::Error::from)
+    }
+}
+
+impl<'de> Deserialize<'de> for Template {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let source = String::deserialize(deserializer)?;
+        Template::new(&source).map_err(serde::de::Error::custom)
+    }
+}
+
+/// `{{json value}}` renders any value as JSON, e.g. strings including their quotes
+fn json_helper(
+    h: &handlebars::Helper,
+    _: &Handlebars,
+    _: &handlebars::Context,
+    _: &mut handlebars::RenderContext,
+    out: &mut dyn handlebars::Output,
+) -> handlebars::HelperResult {
+    let value = h
+        .param(0)
+        .map(|p| p.value().clone())
+        .unwrap_or(serde_json::Value::Null);
+    out.write(&value.to_string())?;
+    Ok(())
+}
+
+/// The warning and critical ranges and min/max values of a performance metric,
+/// as reported by the monitoring-plugin digest
+#[derive(Default, Serialize)]
+pub struct Thresholds {
+    warn: Option<f64>,
+    crit: Option<f64>,
+    min: Option<f64>,
+    max: Option<f64>,
+}
+
+/// Everything a template has access to
+#[derive(Serialize)]
+pub struct TemplateContext<'a> {
+    /// Seconds since the UNIX epoch
+    pub time: u64,
+    pub key: &'a str,
+    pub host: &'a str,
+    pub raw: &'a str,
+    /// OK, WARNING, CRITICAL or UNKNOWN, if the item has a monitoring-plugin status
+    pub status: Option<&'static str>,
+    pub values: BTreeMap<&'a str, f64>,
+    pub tags: &'a BTreeMap<String, String>,
+    pub thresholds: BTreeMap<&'a str, Thresholds>,
+}
+
+impl<'a> TemplateContext<'a> {
+    pub fn new(result: &'a ItemResult, host: &'a str) -> Self {
+        let mut values = BTreeMap::new();
+        let mut thresholds = BTreeMap::<&str, Thresholds>::new();
+        for (key, value) in result.values.iter() {
+            let threshold = key.rsplit_once('.').and_then(|(metric, extra)| {
+                let t = match extra {
+                    "warn" | "crit" | "min" | "max" => thresholds.entry(metric).or_default(),
+                    _ => return None,
+                };
+                match extra {
+                    "warn" => t.warn = Some(*value),
+                    "crit" => t.crit = Some(*value),
+                    "min" => t.min = Some(*value),
+                    _ => t.max = Some(*value),
+                }
+                Some(())
+            });
+            if threshold.is_none() {
+                values.insert(key.as_ref(), *value);
+            }
+        }
+        Self {
+            time: result.time.as_secs(),
+            key: &result.key,
+            host,
+            raw: &result.raw,
+            status: Status::of(result).map(|s| s.as_str()),
+            values,
+            tags: &result.tags,
+            thresholds,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+    use std::time::Duration;
+
+    use crate::item::ItemResult;
+    use crate::template::{Template, TemplateContext};
+
+    #[test]
+    fn render() {
+        let result = ItemResult {
+            time: Duration::from_secs(1234),
+            key: "os.procs".into(),
+            raw: String::from("PROCS OK: 12 processes"),
+            values: HashMap::from([
+                ("os.procs.procs".into(), 12.0),
+                ("os.procs.procs.warn".into(), 100.0),
+            ]),
+            tags: BTreeMap::from([("room".into(), String::from("attic"))]),
+        };
+        let template = Template::new(
+            r#"{"text": {{json raw}}, "room": "{{tags.room}}", "value": {{lookup values "os.procs.procs"}}, "warn": {{lookup (lookup thresholds "os.procs.procs") "warn"}}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            template
+                .render(&TemplateContext::new(&result, "host"))
+                .unwrap(),
+            r#"{"text": "PROCS OK: 12 processes", "room": "attic", "value": 12.0, "warn": 100.0}"#
+        );
+    }
+}
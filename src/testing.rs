@@ -0,0 +1,208 @@
+//! Helpers for end-to-end tests, from a configuration through items and digests to the
+//! outputs, without touching the network or files outside a temporary directory. Only
+//! built for the crate's own tests and with the `testing` feature.
+
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::{broadcast, Notify};
+
+use crate::conf::{self, Config, OutputConfig, OutputKind};
+use crate::event::Event;
+use crate::item::ItemResult;
+use crate::output::{AKEventOutput, AKOutput};
+
+/// Collects the results and events it receives in memory, clones share them
+#[derive(Clone, Default)]
+pub struct MockOutput {
+    results: Arc<Mutex<Vec<Arc<ItemResult>>>>,
+    events: Arc<Mutex<Vec<Event>>>,
+    received: Arc<Notify>,
+}
+
+impl fmt::Debug for MockOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MockOutput")
+            .field("results", &self.results().len())
+            .field("events", &self.events().len())
+            .finish()
+    }
+}
+
+impl MockOutput {
+    pub fn results(&self) -> Vec<Arc<ItemResult>> {
+        self.results.lock().expect("mock lock poisoned").clone()
+    }
+
+    pub fn events(&self) -> Vec<Event> {
+        self.events.lock().expect("mock lock poisoned").clone()
+    }
+
+    /// The results, once there are at least `count`
+    pub async fn wait_for(&self, count: usize, timeout: Duration) -> Result<Vec<Arc<ItemResult>>> {
+        let wait = async {
+            loop {
+                let received = self.received.notified();
+                let results = self.results();
+                if results.len() >= count {
+                    return results;
+                }
+                received.await;
+            }
+        };
+        match tokio::time::timeout(timeout, wait).await {
+            Ok(results) => Ok(results),
+            Err(_) => bail!(
+                "Received {} results instead of {} within {:?}",
+                self.results().len(),
+                count,
+                timeout
+            ),
+        }
+    }
+}
+
+#[async_trait]
+impl AKOutput for MockOutput {
+    fn prepare(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn start(self, mut receiver: broadcast::Receiver<Arc<ItemResult>>) {
+        loop {
+            match receiver.recv().await {
+                Ok(result) => {
+                    self.results
+                        .lock()
+                        .expect("mock lock poisoned")
+                        .push(result);
+                    self.received.notify_waiters();
+                }
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl AKEventOutput for MockOutput {
+    async fn start_events(self, mut receiver: broadcast::Receiver<Event>) {
+        loop {
+            match receiver.recv().await {
+                Ok(event) => self.events.lock().expect("mock lock poisoned").push(event),
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    }
+}
+
+/// Builds a validated configuration from snippets of TOML. The state directory and the
+/// control socket are always in a new temporary directory.
+#[derive(Debug, Default)]
+pub struct ConfigBuilder {
+    general: String,
+    items: Vec<String>,
+    /// Part of what the configuration is read from, to be kept running across reloads
+    configured_outputs: Vec<String>,
+    outputs: Vec<OutputConfig>,
+}
+
+impl ConfigBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Options as in `[general]`
+    pub fn general(mut self, toml: &str) -> Self {
+        self.general = toml.to_owned();
+        self
+    }
+
+    /// An item as in `[[items]]`
+    pub fn item(mut self, toml: &str) -> Self {
+        self.items.push(toml.to_owned());
+        self
+    }
+
+    /// An output as in `[[output]]`
+    pub fn output_toml(mut self, toml: &str) -> Self {
+        self.configured_outputs.push(toml.to_owned());
+        self
+    }
+
+    /// An output with the common options set on the `OutputConfig`
+    pub fn output(mut self, config: OutputConfig) -> Self {
+        self.outputs.push(config);
+        self
+    }
+
+    /// A `MockOutput` receiving everything
+    pub fn mock(self, mock: &MockOutput) -> Self {
+        self.output(OutputConfig::new(OutputKind::Mock(mock.clone())))
+    }
+
+    pub fn build(self) -> Result<Config> {
+        static BUILT: AtomicUsize = AtomicUsize::new(0);
+        let mut toml = if self.configured_outputs.is_empty() {
+            String::from("output = []\n")
+        } else {
+            String::new()
+        };
+        toml.push_str(&format!("[general]\n{}\n", self.general));
+        for item in &self.items {
+            toml.push_str(&format!("[[items]]\n{}\n", item));
+        }
+        for output in &self.configured_outputs {
+            toml.push_str(&format!("[[output]]\n{}\n", output));
+        }
+        let mut config: Config = toml::from_str(&toml)?;
+        config.source = Some(conf::fingerprint(toml::from_str(&toml)?));
+        let dir = std::env::temp_dir().join(format!(
+            "antikoerper-test-{}-{}",
+            std::process::id(),
+            BUILT.fetch_add(1, Ordering::Relaxed)
+        ));
+        config.general.control_socket = dir.join("control.sock");
+        config.general.state_dir = dir;
+        config.output.extend(self.outputs);
+        conf::validate(&config)?;
+        Ok(config)
+    }
+}
+
+/// Answers the next HTTP request on `listener` with 204 and returns its body, in place of
+/// the server of an output
+pub async fn receive_request(listener: &TcpListener) -> String {
+    let (mut stream, _) = listener.accept().await.expect("accepting a connection");
+    let mut request = Vec::new();
+    let mut chunk = [0; 4096];
+    loop {
+        let read = stream.read(&mut chunk).await.expect("reading a request");
+        request.extend_from_slice(&chunk[..read]);
+        let text = String::from_utf8_lossy(&request);
+        if let Some((head, body)) = text.split_once("\r\n\r\n") {
+            let length = head
+                .lines()
+                .find_map(|line| {
+                    line.to_lowercase()
+                        .strip_prefix("content-length:")
+                        .and_then(|length| length.trim().parse::<usize>().ok())
+                })
+                .unwrap_or(0);
+            if body.len() >= length || read == 0 {
+                let _ = stream
+                    .write_all(b"HTTP/1.1 204 No Content\r\nContent-Length: 0\r\n\r\n")
+                    .await;
+                return body.to_string();
+            }
+        }
+    }
+}
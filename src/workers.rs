@@ -0,0 +1,68 @@
+//! Digesting on blocking threads, so heavy regexes over large outputs do not stall the
+//! items sharing the async executor
+
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use tokio::sync::Semaphore;
+
+/// At most as many jobs as workers run at once, the others wait for a free worker
+#[derive(Debug, Clone)]
+pub struct Workers {
+    permits: Arc<Semaphore>,
+}
+
+impl Workers {
+    pub fn new(count: usize) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(count)),
+        }
+    }
+
+    /// Run `job` on a blocking thread once a worker is free
+    pub async fn run<T, F>(&self, job: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let _permit = self.permits.acquire().await?;
+        tokio::task::spawn_blocking(job)
+            .await
+            .context("Worker failed")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::workers::Workers;
+
+    #[tokio::test]
+    async fn bounded() {
+        let workers = Workers::new(2);
+        let running = Arc::new(AtomicUsize::new(0));
+        let most = Arc::new(AtomicUsize::new(0));
+        let jobs = (0..6)
+            .map(|_| {
+                let (workers, running, most) = (workers.clone(), running.clone(), most.clone());
+                tokio::spawn(async move {
+                    workers
+                        .run(move || {
+                            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                            most.fetch_max(now, Ordering::SeqCst);
+                            std::thread::sleep(Duration::from_millis(20));
+                            running.fetch_sub(1, Ordering::SeqCst);
+                        })
+                        .await
+                })
+            })
+            .collect::<Vec<_>>();
+        for job in jobs {
+            job.await.unwrap().unwrap();
+        }
+        assert_eq!(most.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,31 @@
+use std::time::Duration;
+
+use antikoerper::app::App;
+use antikoerper::conf::{OutputConfig, OutputKind};
+use antikoerper::testing::{ConfigBuilder, MockOutput};
+
+#[tokio::test]
+async fn config_to_output() {
+    let mock = MockOutput::default();
+    let mut output = OutputConfig::new(OutputKind::Mock(mock.clone()));
+    output.prefix = String::from("test.");
+    let config = ConfigBuilder::new()
+        .general("health_interval = 0")
+        .item(
+            r#"
+            key = "load"
+            interval = 60
+            input = { type = "shell", script = "echo 'load 0.5'" }
+            digest = { type = "regex", regex = 'load (?P<one>[\d.]+)' }
+            "#,
+        )
+        .output(output)
+        .build()
+        .unwrap();
+    let app = App::try_from(config).unwrap();
+    let running = tokio::spawn(async move { app.start().await });
+    let results = mock.wait_for(1, Duration::from_secs(10)).await.unwrap();
+    running.abort();
+    assert_eq!(&*results[0].key, "test.load");
+    assert_eq!(results[0].values["test.load.one"], 0.5);
+}